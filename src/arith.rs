@@ -0,0 +1,197 @@
+//! Arithmetic evaluation, e.g., for integer variables (`declare -i`)
+//!
+//! Integers are `i64`. Supported operators, from the lowest precedence: `||`, `&&`, `==` `!=`,
+//! `<` `<=` `>` `>=`, `+` `-`, `*` `/` `%` and the unary `+` `-` `!`. Variable names evaluate to
+//! their values, and unset or empty variables are zero.
+
+/// Nesting limit of variables whose values are expressions themselves
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Var(String),
+    Op(&'static str),
+}
+
+/// Operators, longer ones first
+const OPS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")",
+];
+
+/// Evaluates an arithmetic expression, looking up variables with `var`
+pub(crate) fn eval(expr: &str, var: &dyn Fn(&str) -> Option<String>) -> Result<i64, String> {
+    self::eval_nested(expr, var, 0)
+}
+
+fn eval_nested(
+    expr: &str,
+    var: &dyn Fn(&str) -> Option<String>,
+    depth: usize,
+) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("{expr}: expression recursion level exceeded"));
+    }
+
+    let tokens = self::tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        var,
+        depth,
+    };
+
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(token) => Err(format!("{expr}: syntax error near `{}`", self::show(token))),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let num = rest[..len]
+                .parse()
+                .map_err(|_| format!("{}: invalid number", &rest[..len]))?;
+            tokens.push(Token::Num(num));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Var(rest[..len].to_string()));
+            len
+        } else if let Some(&op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(format!("{expr}: syntax error near `{c}`"));
+        };
+
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn show(token: &Token) -> String {
+    match token {
+        Token::Num(num) => num.to_string(),
+        Token::Var(name) => name.clone(),
+        Token::Op(op) => op.to_string(),
+    }
+}
+
+/// Binary operators by precedence level
+const LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    var: &'a dyn Fn(&str) -> Option<String>,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+
+            lhs = match op {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err("division by 0".to_string()),
+                "/" => lhs.wrapping_div(rhs),
+                "%" => lhs.wrapping_rem(rhs),
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek_op() {
+            Some(op @ ("+" | "-" | "!")) => {
+                self.pos += 1;
+                let value = self.unary()?;
+                Ok(match op {
+                    "+" => value,
+                    "-" => value.wrapping_neg(),
+                    _ => (value == 0) as i64,
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Num(num)) => Ok(num),
+            Some(Token::Var(name)) => {
+                let value = (self.var)(&name).unwrap_or_default();
+                match value.trim().parse() {
+                    Ok(num) => Ok(num),
+                    // the value is an expression itself
+                    Err(_) => self::eval_nested(&value, self.var, self.depth + 1),
+                }
+            }
+            Some(Token::Op("(")) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Op(")")) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            Some(token) => Err(format!("syntax error near `{}`", self::show(&token))),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Running a command line with its output captured, for integration tests (`capture` feature)
+
+use nix::{
+    libc,
+    sys::wait::{self, WaitStatus},
+    unistd::{self, ForkResult},
+};
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::io::{FromRawFd, RawFd},
+    thread,
+};
+
+use crate::{util, Ast, Shell};
+
+/// Output of [`Shell::run_capture`]. Bytes that are not valid UTF-8, e.g., of binary data, are
+/// replaced with U+FFFD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, or 128 plus the signal number if the shell was killed
+    pub code: i32,
+}
+
+impl Shell {
+    /// Runs a command line like [`Shell::run_c`] in a forked shell process and returns its
+    /// output. Pipelines and external commands write to the same pipes as builtins. The standard
+    /// input is empty.
+    pub fn run_capture(&self, cmd: &str) -> io::Result<CommandOutput> {
+        self.capture("", || self.run_c(cmd))
+    }
+
+    /// Like [`Shell::run_capture`], but the standard input is a pipe that `input` is written to
+    pub fn run_capture_with_input(&self, cmd: &str, input: &str) -> io::Result<CommandOutput> {
+        self.capture(input, || self.run_c(cmd))
+    }
+
+    /// Runs a syntax tree like [`Shell::execute`] in a forked shell process and returns its
+    /// output, as [`Shell::run_capture`] does
+    pub fn execute_capture(&self, ast: &Ast) -> io::Result<CommandOutput> {
+        self.capture("", || self.execute(ast))
+    }
+
+    /// Runs the shell in a forked process with the standard streams connected to pipes
+    fn capture(&self, input: &str, run: impl FnOnce() -> i32) -> io::Result<CommandOutput> {
+        let stdin = util::run_syscall(unistd::pipe)?;
+        let stdout = util::run_syscall(unistd::pipe)?;
+        let stderr = util::run_syscall(unistd::pipe)?;
+
+        match util::run_syscall(|| unsafe { unistd::fork() })? {
+            ForkResult::Child => {
+                let _ = util::run_syscall(|| unistd::dup2(stdin.0, libc::STDIN_FILENO));
+                let _ = unistd::close(stdin.0);
+                let _ = unistd::close(stdin.1);
+                for ((read, write), fd) in
+                    [(stdout, libc::STDOUT_FILENO), (stderr, libc::STDERR_FILENO)]
+                {
+                    let _ = util::run_syscall(|| unistd::dup2(write, fd));
+                    let _ = unistd::close(read);
+                    let _ = unistd::close(write);
+                }
+
+                let code = run();
+                let _ = io::stdout().flush();
+                unsafe { libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                let _ = unistd::close(stdin.0);
+                let _ = unistd::close(stdout.1);
+                let _ = unistd::close(stderr.1);
+
+                // written and read all at once, or the child may block on a full pipe
+                let input = input.to_string();
+                let writer = thread::spawn(move || {
+                    let mut file = unsafe { File::from_raw_fd(stdin.1) };
+                    // the shell may exit without reading it
+                    let _ = file.write_all(input.as_bytes());
+                });
+                let stderr = thread::spawn(move || self::read_all(stderr.0));
+                let stdout = self::read_all(stdout.0);
+                let stderr = stderr.join().unwrap_or_default();
+                let _ = writer.join();
+
+                let code = match util::run_syscall(|| wait::waitpid(child, None))? {
+                    WaitStatus::Exited(_, code) => code,
+                    WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                    _ => 1,
+                };
+
+                Ok(CommandOutput {
+                    stdout,
+                    stderr,
+                    code,
+                })
+            }
+        }
+    }
+}
+
+/// Reads a pipe to the end and closes it
+fn read_all(fd: RawFd) -> String {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut buf = Vec::new();
+    let _ = file.read_to_end(&mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+}
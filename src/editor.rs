@@ -0,0 +1,1257 @@
+//! Line editor configuration
+//!
+//! Custom key bindings use the readline notation: `\C-x` (Ctrl+x), `\M-x` (Alt+x), `\e` (Esc),
+//! `\t` (Tab), `\n` or `\r` (Enter) and `\\`. Other characters stand for themselves, e.g.,
+//! `\C-xg` is Ctrl+x followed by `g`. Keys can also be bound to editing commands by their readline
+//! names, e.g., `kill-line`.
+//!
+//! The readline bindings Ctrl+A, Ctrl+E, Ctrl+K, Ctrl+U, Ctrl+W and Alt+D work in the vi insert
+//! mode as in the emacs mode, unless they are bound to something else.
+//!
+//! The input line is highlighted while typing: known commands in green, quoted strings in yellow,
+//! operators in cyan and an unterminated part in red. The characters that would close the
+//! unterminated part are shown as a hint, which the right arrow key inserts. Otherwise, the rest
+//! of the latest history line starting with the input can be shown as a hint like fish, which the
+//! End key inserts, too.
+//!
+//! Tab completes file names. The argument of `cd` is completed with directories only, including
+//! the ones in `CDPATH` (of the environment, as the `main` thread doesn't see shell variables), and
+//! a leading `~` is replaced with the home directory. Arguments of builtins are completed with
+//! the option flags in their usage, signal names (`kill` and `trap`), option names (`set -o`) or
+//! variable names (`declare`). The `complete` builtin sets words, file names or directory names
+//! for other commands, which take precedence. A command of `complete -C` is run by `/bin/sh`, as
+//! the shell is busy in the `worker`, with the command name, the word and the word before it as
+//! the arguments like bash, and `COMP_LINE` and `COMP_POINT` set to the line and the cursor.
+//! `COMP_WORDS` has the words up to the cursor, one per line as `/bin/sh` has no arrays, and
+//! `COMP_CWORD` is the index of the word being completed. The `worker` reaps only the processes of
+//! its jobs, so it doesn't take the status of the command. Its output lines are the candidates,
+//! and it's killed if it takes longer than [`COMP_COMMAND_TIMEOUT`]. A name after `$` or `${` is
+//! completed with the variables of the environment and the shell, which the `worker` sends when
+//! they change, and `}` is added after `${NAME`.
+//!
+//! `ZEROSH_RPROMPT` is a prompt shown at the right end of the input line. It's drawn with the
+//! prompt, as rustyline has no right prompts, and disappears when the input reaches it or the
+//! terminal is too narrow. With the asynchronous prompt, its git branch (`\g`) is found by a
+//! thread, and a placeholder is shown until then. The thread redraws the prompt with the external
+//! printer, which prints nothing but moving up a line, as it prints a line above the prompt.
+
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::{Hinter, HistoryHinter},
+    validate::{ValidationContext, ValidationResult, Validator},
+    At, Cmd, ConditionalEventHandler, EditMode, Event, EventContext, EventHandler, InputMode,
+    KeyCode, KeyEvent, Modifiers, Movement, RepeatCount, Word,
+};
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    io::{self, Write},
+    os::unix::process::CommandExt,
+    process::{self, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    expand,
+    parser::{self, TokenKind},
+    util::{self, Color},
+    worker,
+};
+
+/// Key sequence bound to text or an editing command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeyBinding {
+    pub keys: Vec<KeyEvent>,
+    pub action: KeyAction,
+}
+
+/// What a [`KeyBinding`] does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum KeyAction {
+    /// Text inserted at the cursor. If it ends with a newline, the line is executed.
+    Text(String),
+    Command(EditCommand),
+}
+
+impl KeyBinding {
+    /// Parses a binding such as `("\C-g", "git status\n")`
+    pub fn parse(keys: &str, text: &str) -> Result<Self, String> {
+        Ok(Self {
+            keys: self::parse_key_seq(keys)?,
+            action: KeyAction::Text(self::unescape(text)),
+        })
+    }
+
+    /// Parses a binding to an editing command such as `("\C-w", "backward-kill-word")`
+    pub fn parse_command(keys: &str, name: &str) -> Result<Self, String> {
+        let command = EditCommand::from_name(name)
+            .ok_or_else(|| format!("{name}: unknown editing command"))?;
+        Ok(Self {
+            keys: self::parse_key_seq(keys)?,
+            action: KeyAction::Command(command),
+        })
+    }
+}
+
+/// Editing command of readline that keys can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EditCommand {
+    BeginningOfLine,
+    EndOfLine,
+    ForwardWord,
+    BackwardWord,
+    KillLine,
+    UnixLineDiscard,
+    KillWholeLine,
+    KillWord,
+    BackwardKillWord,
+    UnixWordRubout,
+    ClearScreen,
+}
+
+/// Readline bindings that the vi insert mode of rustyline lacks. They are the defaults of the
+/// emacs mode.
+const VI_INSERT_BINDINGS: &[(&str, EditCommand)] = &[
+    ("\\C-a", EditCommand::BeginningOfLine),
+    ("\\C-e", EditCommand::EndOfLine),
+    ("\\C-k", EditCommand::KillLine),
+    ("\\C-u", EditCommand::UnixLineDiscard),
+    ("\\C-w", EditCommand::UnixWordRubout),
+    ("\\M-d", EditCommand::KillWord),
+];
+
+impl EditCommand {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("beginning-of-line", Self::BeginningOfLine),
+        ("end-of-line", Self::EndOfLine),
+        ("forward-word", Self::ForwardWord),
+        ("backward-word", Self::BackwardWord),
+        ("kill-line", Self::KillLine),
+        ("unix-line-discard", Self::UnixLineDiscard),
+        ("kill-whole-line", Self::KillWholeLine),
+        ("kill-word", Self::KillWord),
+        ("backward-kill-word", Self::BackwardKillWord),
+        ("unix-word-rubout", Self::UnixWordRubout),
+        ("clear-screen", Self::ClearScreen),
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|&&(candidate, _)| candidate == name)
+            .map(|&(_, command)| command)
+    }
+
+    /// Returns the command of rustyline, repeated `n` times if it takes a count
+    fn cmd(self, n: RepeatCount) -> Cmd {
+        match self {
+            Self::BeginningOfLine => Cmd::Move(Movement::BeginningOfLine),
+            Self::EndOfLine => Cmd::Move(Movement::EndOfLine),
+            Self::ForwardWord => Cmd::Move(Movement::ForwardWord(n, At::AfterEnd, Word::Emacs)),
+            Self::BackwardWord => Cmd::Move(Movement::BackwardWord(n, Word::Emacs)),
+            Self::KillLine => Cmd::Kill(Movement::EndOfLine),
+            Self::UnixLineDiscard => Cmd::Kill(Movement::BeginningOfLine),
+            Self::KillWholeLine => Cmd::Kill(Movement::WholeLine),
+            Self::KillWord => Cmd::Kill(Movement::ForwardWord(n, At::AfterEnd, Word::Emacs)),
+            Self::BackwardKillWord => Cmd::Kill(Movement::BackwardWord(n, Word::Emacs)),
+            // whitespace-delimited like readline
+            Self::UnixWordRubout => Cmd::Kill(Movement::BackwardWord(n, Word::Big)),
+            Self::ClearScreen => Cmd::ClearScreen,
+        }
+    }
+}
+
+/// Parses a non-empty key sequence of a binding
+fn parse_key_seq(notation: &str) -> Result<Vec<KeyEvent>, String> {
+    let keys = self::parse_keys(notation).map_err(|msg| format!("{notation}: {msg}"))?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(keys)
+}
+
+/// Parses a key sequence in the readline notation
+pub(crate) fn parse_keys(notation: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut keys = Vec::new();
+    let mut chars = notation.chars().peekable();
+
+    while chars.peek().is_some() {
+        let mut mods = Modifiers::NONE;
+
+        let key = loop {
+            let c = match chars.next() {
+                Some('\\') => chars.next(),
+                Some(c) => break KeyEvent::new(c, mods),
+                None => None,
+            };
+
+            match c {
+                Some(prefix @ ('C' | 'M')) if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    mods |= if prefix == 'C' {
+                        Modifiers::CTRL
+                    } else {
+                        Modifiers::ALT
+                    };
+                }
+                Some('e') => break KeyEvent(KeyCode::Esc, mods),
+                Some('t') => break KeyEvent(KeyCode::Tab, mods),
+                Some('n' | 'r') => break KeyEvent(KeyCode::Enter, mods),
+                Some(c) => break KeyEvent::new(c, mods),
+                None => return Err("incomplete key sequence".to_string()),
+            }
+        };
+
+        keys.push(KeyEvent::normalize(key));
+    }
+
+    Ok(keys)
+}
+
+/// Replaces `\n`, `\t`, `\e`, `\"` and `\\` in binding text
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('e') => out.push('\x1b'),
+            Some(c @ ('"' | '\\')) => out.push(c),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// Line submitted by a key binding. It replaces the line returned by `readline`.
+pub(crate) type PendingLine = Arc<Mutex<Option<String>>>;
+
+/// Time of the last key press, for `TMOUT`. `None` until a key is pressed.
+pub(crate) type LastKey = Arc<Mutex<Option<Instant>>>;
+
+/// `!` history references and `^OLD^NEW` are expanded in the input lines (`set -o histexpand`).
+/// The `main` thread expands them, and the `worker` sets the option.
+pub(crate) type HistExpand = Arc<AtomicBool>;
+
+/// State of the line editor shared with the `worker` thread
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Shared {
+    pub comp_specs: CompSpecs,
+    pub last_key: LastKey,
+    pub histexpand: HistExpand,
+}
+
+/// Reads the input lines with the line editor, or as plain lines of the standard input if the
+/// line editor can't be created. Without a TTY, the line editor reads plain lines by itself.
+#[derive(Debug)]
+pub(crate) enum LineReader {
+    Editor(Box<rustyline::Editor<ShellHelper>>),
+    /// Prints the prompt and reads a line, keeping the history only in memory
+    Plain(Vec<String>),
+}
+
+impl LineReader {
+    pub fn readline(&mut self, prompt: &str) -> rustyline::Result<String> {
+        if let Self::Editor(editor) = self {
+            return editor.readline(prompt);
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(prompt.as_bytes())?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Err(ReadlineError::Eof);
+        }
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Returns the history lines, the oldest first
+    pub fn history(&self) -> Vec<&str> {
+        match self {
+            Self::Editor(editor) => editor.history().iter().map(String::as_str).collect(),
+            Self::Plain(history) => history.iter().map(String::as_str).collect(),
+        }
+    }
+
+    pub fn add_history_entry(&mut self, line: &str) {
+        match self {
+            Self::Editor(editor) => {
+                editor.add_history_entry(line);
+            }
+            Self::Plain(history) => history.push(line.to_string()),
+        }
+    }
+
+    /// Appends the new history lines to the file. Nothing is saved without the line editor.
+    pub fn append_history(&mut self, path: &str) -> rustyline::Result<()> {
+        match self {
+            Self::Editor(editor) => editor.append_history(path),
+            Self::Plain(_) => Ok(()),
+        }
+    }
+
+    pub fn helper_mut(&mut self) -> Option<&mut ShellHelper> {
+        match self {
+            Self::Editor(editor) => editor.helper_mut(),
+            Self::Plain(_) => None,
+        }
+    }
+
+    pub fn set_edit_mode(&mut self, mode: EditMode) {
+        use rustyline::config::Configurer;
+        if let Self::Editor(editor) = self {
+            editor.set_edit_mode(mode);
+        }
+    }
+}
+
+/// Registers key bindings to the editor. Every key press is recorded in `last_key`.
+pub(crate) fn bind_keys<H: rustyline::Helper>(
+    editor: &mut rustyline::Editor<H>,
+    bindings: &[KeyBinding],
+    pending: &PendingLine,
+    last_key: &LastKey,
+) {
+    let mut bind = |keys: Vec<KeyEvent>, handler: Option<EventHandler>| {
+        let event = if keys.is_empty() {
+            Event::Any
+        } else {
+            Event::KeySeq(keys)
+        };
+        let handler = RecordKey {
+            handler,
+            last_key: Arc::clone(last_key),
+        };
+        editor.bind_sequence(event, EventHandler::Conditional(Box::new(handler)));
+    };
+
+    // keys without bindings
+    bind(Vec::new(), None);
+
+    // Same as the `clear` builtin, but keeps the current input line. Bound explicitly so that
+    // it works in every edit mode.
+    let clear = EventHandler::Simple(Cmd::ClearScreen);
+    bind(vec![KeyEvent::ctrl('L')], Some(clear));
+
+    // accepts a hint at the end of the line like the right arrow key
+    let end = EventHandler::Conditional(Box::new(AcceptHint));
+    bind(vec![KeyEvent(KeyCode::End, Modifiers::NONE)], Some(end));
+
+    // registered first so that custom bindings replace them
+    for &(keys, command) in VI_INSERT_BINDINGS {
+        let keys = self::parse_keys(keys).unwrap();
+        let handler = EventHandler::Conditional(Box::new(ViInsertCommand(command)));
+        bind(keys, Some(handler));
+    }
+
+    for binding in bindings {
+        let handler = match &binding.action {
+            KeyAction::Text(text) => match text.strip_suffix('\n') {
+                // `Cmd::Insert` can't accept the line, so the handler builds the line by itself
+                Some(text) => EventHandler::Conditional(Box::new(ExecuteText {
+                    text: text.to_string(),
+                    pending: Arc::clone(pending),
+                })),
+                None => EventHandler::Simple(Cmd::Insert(1, text.clone())),
+            },
+            KeyAction::Command(command) => {
+                EventHandler::Conditional(Box::new(RunCommand(*command)))
+            }
+        };
+
+        bind(binding.keys.clone(), Some(handler));
+    }
+}
+
+/// Records the time of a key press, and then handles the key with the handler of the binding
+/// (or as usual without one)
+struct RecordKey {
+    handler: Option<EventHandler>,
+    last_key: LastKey,
+}
+
+impl ConditionalEventHandler for RecordKey {
+    fn handle(
+        &self,
+        evt: &Event,
+        n: RepeatCount,
+        positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        *self.last_key.lock().unwrap() = Some(Instant::now());
+        match self.handler.as_ref()? {
+            EventHandler::Simple(cmd) => Some(cmd.clone()),
+            EventHandler::Conditional(handler) => handler.handle(evt, n, positive, ctx),
+        }
+    }
+}
+
+/// Inserts the hint if the cursor is at the end of the line, or moves the cursor as usual
+struct AcceptHint;
+
+impl ConditionalEventHandler for AcceptHint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
+    }
+}
+
+/// Runs an editing command in the vi insert mode. The other modes handle the key by themselves.
+struct ViInsertCommand(EditCommand);
+
+impl ConditionalEventHandler for ViInsertCommand {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let is_insert = ctx.mode() == EditMode::Vi && ctx.input_mode() == InputMode::Insert;
+        is_insert.then(|| self.0.cmd(1))
+    }
+}
+
+/// Runs an editing command with the repeat count
+struct RunCommand(EditCommand);
+
+impl ConditionalEventHandler for RunCommand {
+    fn handle(&self, _: &Event, n: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        // the vi insert mode gives zero
+        Some(self.0.cmd(n.max(1)))
+    }
+}
+
+/// Inserts text at the cursor and accepts the line
+struct ExecuteText {
+    text: String,
+    pending: PendingLine,
+}
+
+impl ConditionalEventHandler for ExecuteText {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let (before, after) = ctx.line().split_at(ctx.pos());
+        let line = format!("{before}{}{after}", self.text);
+        *self.pending.lock().unwrap() = Some(line);
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// How the arguments of a command are completed, set by the `complete` builtin
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CompSpec {
+    /// Candidate words (`-W`)
+    pub words: Vec<String>,
+    /// File names (`-f`)
+    pub files: bool,
+    /// Directory names (`-d`)
+    pub dirs: bool,
+    /// Command printing the candidates (`-C`)
+    pub command: Option<String>,
+}
+
+/// Command names to their [`CompSpec`]s, shared by the `worker` thread and the completer
+pub(crate) type CompSpecs = Arc<Mutex<BTreeMap<String, CompSpec>>>;
+
+/// Helper of the line editor, which highlights and completes the input line
+#[derive(Debug, Default)]
+pub(crate) struct ShellHelper {
+    pub closing_hints: bool,
+    pub history_hints: bool,
+    pub comp_specs: CompSpecs,
+    /// Names of the shell variables, sent by the `worker`
+    pub var_names: Vec<String>,
+    /// Prompt shown at the right end of the input line (`ZEROSH_RPROMPT`)
+    pub rprompt: Option<Rprompt>,
+    /// Columns the first line of the input can take before it reaches the right prompt, `None`
+    /// if the right prompt is not shown. Set when the prompt is drawn, just before the input.
+    pub rprompt_room: Cell<Option<usize>>,
+    /// Input line and its history hint last shown, for the highlighter, which doesn't see the
+    /// history
+    pub history_hint: RefCell<Option<(String, String)>>,
+    /// Whether command names are builtins or found in `PATH`, cached for the line being edited,
+    /// as the highlighter runs on each key. Cleared before each line.
+    pub known_commands: RefCell<BTreeMap<String, bool>>,
+}
+
+impl rustyline::Helper for ShellHelper {}
+
+/// Right prompt, rendered or waiting for a segment
+#[derive(Debug)]
+pub(crate) enum Rprompt {
+    Text(String),
+    Async(Arc<AsyncRprompt>),
+}
+
+impl Rprompt {
+    fn text(&self) -> Cow<'_, str> {
+        match self {
+            Self::Text(text) => Cow::Borrowed(text),
+            Self::Async(rprompt) => Cow::Owned(rprompt.text()),
+        }
+    }
+}
+
+/// Stands for the segment of an [`AsyncRprompt`] in the rendered prompt. Environment variables,
+/// and so the templates, can't have it.
+pub(crate) const ASYNC_SEGMENT: char = '\0';
+
+/// Redraws the prompt through the external printer, which moves the cursor to the next line after
+/// the text
+const REDRAW: &str = "\x1b[A";
+
+/// Right prompt with a slow segment found by another thread, e.g., the git branch
+#[derive(Debug)]
+pub(crate) struct AsyncRprompt {
+    /// The prompt with [`ASYNC_SEGMENT`] in place of the segment
+    rendered: String,
+    segment: Mutex<Option<String>>,
+    found: Condvar,
+    /// The placeholder was drawn, so the prompt needs redrawing
+    shown: AtomicBool,
+    /// The line was accepted, after which nothing is redrawn
+    finished: AtomicBool,
+}
+
+impl AsyncRprompt {
+    pub fn new(rendered: String) -> Self {
+        Self {
+            rendered,
+            segment: Mutex::new(None),
+            found: Condvar::new(),
+            shown: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the rendered prompt has the segment at all
+    pub fn needs_segment(&self) -> bool {
+        self.rendered.contains(ASYNC_SEGMENT)
+    }
+
+    /// Sets the segment, and redraws the prompt if the placeholder is on the input line
+    pub fn set_segment(&self, segment: String, printer: &mut impl rustyline::ExternalPrinter) {
+        *self.segment.lock().unwrap() = Some(segment);
+        self.found.notify_all();
+        if self.shown.load(Ordering::SeqCst) && !self.finished.load(Ordering::SeqCst) {
+            let _ = printer.print(REDRAW.to_string());
+        }
+    }
+
+    /// Waits for the segment for a while, so that a fast one doesn't show the placeholder
+    pub fn wait(&self, timeout: Duration) {
+        let segment = self.segment.lock().unwrap();
+        let _ = self
+            .found
+            .wait_timeout_while(segment, timeout, |segment| segment.is_none());
+    }
+
+    /// Stops redrawing, as the line is accepted. Outside of `readline`, the external printer
+    /// would print to the terminal as it is.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    fn text(&self) -> String {
+        let segment = self.segment.lock().unwrap();
+        let segment = match &*segment {
+            Some(segment) => segment.as_str(),
+            None => {
+                self.shown.store(true, Ordering::SeqCst);
+                if util::ascii_only() {
+                    "..."
+                } else {
+                    "…"
+                }
+            }
+        };
+        self.rendered.replace(ASYNC_SEGMENT, segment)
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if let Some(res) = self.complete_var(&line[..pos]) {
+            return Ok(res);
+        }
+
+        if let Some((args, start)) = self::command_words(&line[..pos]) {
+            let word = &line[start..pos];
+            let plain = !word.contains(['\'', '"', '$']);
+
+            let spec = args.first().and_then(|name| {
+                let specs = self.comp_specs.lock().unwrap();
+                specs.get(name).cloned()
+            });
+            if let Some(spec) = spec.filter(|_| plain) {
+                let mut pairs = spec
+                    .words
+                    .iter()
+                    .filter(|candidate| candidate.starts_with(word))
+                    .map(|candidate| Pair {
+                        display: candidate.clone(),
+                        replacement: candidate.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                if spec.files || spec.dirs {
+                    pairs.extend(self::complete_paths(word, !spec.files, false));
+                }
+                if let Some(command) = &spec.command {
+                    let candidates = self::run_comp_command(command, &args, word, line, pos);
+                    pairs.extend(candidates.into_iter().map(|candidate| Pair {
+                        display: candidate.clone(),
+                        replacement: candidate,
+                    }));
+                }
+                return Ok((start, pairs));
+            }
+
+            if self::is_cd_arg(&args) && plain {
+                return Ok((start, self::complete_paths(word, true, true)));
+            }
+            if let Some(candidates) = worker::complete_builtin(&args, word) {
+                let pairs = candidates
+                    .into_iter()
+                    .map(|candidate| Pair {
+                        display: candidate.clone(),
+                        replacement: candidate,
+                    })
+                    .collect();
+                return Ok((start, pairs));
+            }
+        }
+
+        FilenameCompleter::new().complete_path(line, pos)
+    }
+}
+
+impl ShellHelper {
+    /// Completes a variable name after `$` or `${`, returning the start of the name and the
+    /// candidates. `None` if the text doesn't end in one.
+    fn complete_var(&self, text: &str) -> Option<(usize, Vec<Pair>)> {
+        let start = text
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let (before, prefix) = text.split_at(start);
+        let (before, suffix) = match before.strip_suffix("${") {
+            Some(before) => (before, "}"),
+            None => (before.strip_suffix('$')?, ""),
+        };
+        // `\$` is not an expansion, and neither is `$` in single quotes
+        if before.ends_with('\\')
+            || parser::tokenize(text).is_err_and(|err| err.msg == "unterminated single quote")
+        {
+            return None;
+        }
+
+        let mut names = env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .chain(self.var_names.iter().cloned())
+            .filter(|name| name.starts_with(prefix) && parser::is_name(name))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        let pairs = names
+            .into_iter()
+            .map(|name| Pair {
+                replacement: format!("{name}{suffix}"),
+                display: name,
+            })
+            .collect();
+        Some((start, pairs))
+    }
+}
+
+/// Returns the unquoted words of the simple command before the word being completed, and the
+/// start of that word. `None` if the text ends in an unterminated quote or after a redirection.
+fn command_words(text: &str) -> Option<(Vec<String>, usize)> {
+    let mut tokens = parser::tokenize(text).ok()?;
+
+    // the word being completed, which may be empty
+    let start = match tokens.last() {
+        Some(token) if token.span.1 == text.len() && matches!(token.kind, TokenKind::Word(_)) => {
+            tokens.pop().unwrap().span.0
+        }
+        _ => text.len(),
+    };
+
+    let mut args = Vec::new();
+    for token in tokens {
+        match token.kind {
+            TokenKind::Word(word) => args.push(expand::unquote(&word)),
+            TokenKind::Redirect(..) => args.push(String::new()),
+            _ => args.clear(),
+        }
+    }
+    // the target of a redirection is a file
+    if args.last().is_some_and(String::is_empty) {
+        return None;
+    }
+
+    Some((args, start))
+}
+
+/// Longest time a command of `complete -C` may take, after which it's killed and gives no
+/// candidates
+const COMP_COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs a command of `complete -C` and returns its output lines starting with the word. `args`
+/// are the words before the word being completed, the command name first.
+fn run_comp_command(
+    command: &str,
+    args: &[String],
+    word: &str,
+    line: &str,
+    pos: usize,
+) -> Vec<String> {
+    let name = args.first().map_or("", String::as_str);
+    let prev = args.last().map_or("", String::as_str);
+    let mut words = args.join("\n");
+    if !args.is_empty() {
+        words.push('\n');
+    }
+    words.push_str(word);
+    let child = process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .args(["sh", name, word, prev])
+        .env("COMP_LINE", line)
+        .env("COMP_POINT", pos.to_string())
+        .env("COMP_WORDS", words)
+        .env("COMP_CWORD", args.len().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // killed with its children on the timeout
+        .process_group(0)
+        .spawn();
+    let Ok(child) = child else {
+        return Vec::new();
+    };
+
+    // the output is read on another thread so that a hung command can be given up
+    let pid = child.id();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    let output = match rx.recv_timeout(COMP_COMMAND_TIMEOUT) {
+        Ok(Ok(output)) => output,
+        Ok(Err(_)) => return Vec::new(),
+        Err(_) => {
+            let pid = nix::unistd::Pid::from_raw(pid as i32);
+            let _ = nix::sys::signal::killpg(pid, nix::sys::signal::Signal::SIGKILL);
+            return Vec::new();
+        }
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|candidate| !candidate.is_empty() && candidate.starts_with(word))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns if the word being completed is the directory of `cd`
+fn is_cd_arg(args: &[String]) -> bool {
+    matches!(args, [cd] | [cd, _] if cd == "cd" && args[1..].iter().all(|arg| arg == "--"))
+}
+
+/// Completes a file name, or only a directory name, e.g., for `cd`. The candidates replace the
+/// whole word, and directories end with `/`. A leading `~` is replaced with the home directory,
+/// and relative names are also searched in `CDPATH` if `cdpath`.
+fn complete_paths(word: &str, dirs_only: bool, cdpath: bool) -> Vec<Pair> {
+    // the shell itself doesn't expand `~`
+    let word = match word.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = env::var("HOME").unwrap_or_default();
+            format!("{}{}", self::escape(home.trim_end_matches('/')), rest)
+        }
+        _ => word.to_string(),
+    };
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word.as_str()),
+    };
+    let (dir_path, prefix) = (expand::unquote(dir), expand::unquote(prefix));
+
+    let mut bases = vec![dir_path.clone()];
+    if cdpath && !word.starts_with(['/', '.']) {
+        if let Ok(cdpath) = env::var("CDPATH") {
+            bases.extend(
+                cdpath
+                    .split(':')
+                    // the current directory is already searched
+                    .filter(|base| !base.is_empty() && *base != ".")
+                    .map(|base| format!("{}/{dir_path}", base.trim_end_matches('/'))),
+            );
+        }
+    }
+
+    let mut names = BTreeSet::new();
+    for base in &bases {
+        let base = if base.is_empty() { "." } else { base };
+        let Ok(entries) = fs::read_dir(base) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // hidden files only if asked for
+            if !name.starts_with(&prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                continue;
+            }
+            // follows symbolic links
+            let is_dir = entry.path().is_dir();
+            if is_dir || !dirs_only {
+                names.insert((name, is_dir));
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|(name, is_dir)| {
+            let slash = if is_dir { "/" } else { "" };
+            Pair {
+                display: format!("{name}{slash}"),
+                replacement: format!("{dir}{}{slash}", self::escape(&name)),
+            }
+        })
+        .collect()
+}
+
+/// Escapes the characters of a file name that are special in the shell with backslashes
+fn escape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for (i, c) in name.char_indices() {
+        // `~` is the home directory at the start of a word
+        if c.is_whitespace() || "\\'\"`$&|;<>()*?[#!{}".contains(c) || (i == 0 && c == '~') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<String> {
+        if let Some(hint) = self.closing_hint(line, pos) {
+            return Some(hint);
+        }
+        if !self.history_hints {
+            return None;
+        }
+
+        let hint = HistoryHinter {}.hint(line, pos, ctx);
+        *self.history_hint.borrow_mut() = hint.clone().map(|hint| (line.to_string(), hint));
+        hint
+    }
+}
+
+impl ShellHelper {
+    /// Returns the characters that would close the unterminated part of the line
+    fn closing_hint(&self, line: &str, pos: usize) -> Option<String> {
+        // hints are shown at the end of the line
+        if !self.closing_hints || pos < line.len() {
+            return None;
+        }
+
+        // the error position may be inside an outer quote, so scan the whole line
+        parser::tokenize(line).err()?;
+        let closing = self::closing(line);
+        (!closing.is_empty()).then_some(closing)
+    }
+
+    /// Returns if the first line of the input and its hint reach the right prompt, which is then
+    /// cleared
+    fn covers_rprompt(&self, line: &str, pos: usize) -> bool {
+        let Some(room) = self.rprompt_room.get() else {
+            return false;
+        };
+        let width = match line.split_once('\n') {
+            Some((first, _)) => util::display_width(first),
+            None => {
+                let history_hint = self.history_hint.borrow();
+                let hint = match &*history_hint {
+                    Some((input, hint)) if input == line && pos == line.len() => Some(hint.clone()),
+                    _ => self.closing_hint(line, pos),
+                };
+                util::display_width(line) + util::display_width(&hint.unwrap_or_default())
+            }
+        };
+        width > room
+    }
+}
+
+impl Validator for ShellHelper {
+    fn validate(&self, _ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        self.finish_rprompt();
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let out = if util::color_enabled() {
+            Cow::Owned(self::highlight(line, |name| self.is_known_command(name)))
+        } else {
+            Cow::Borrowed(line)
+        };
+        if !self.covers_rprompt(line, pos) {
+            return out;
+        }
+
+        // clears the rest of the right prompt after the first line
+        let mut out = out.into_owned();
+        let end = out.find('\n').unwrap_or(out.len());
+        out.insert_str(end, "\x1b[K");
+        Cow::Owned(out)
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        self.rprompt_room.set(None);
+        // not for the prompts of searches
+        let Some(rprompt) = self.rprompt.as_ref().filter(|_| default).map(Rprompt::text) else {
+            return Cow::Borrowed(prompt);
+        };
+        if rprompt.is_empty() {
+            return Cow::Borrowed(prompt);
+        }
+        let Some((columns, _)) = util::terminal_size() else {
+            return Cow::Borrowed(prompt);
+        };
+
+        // the input starts on the last line of the prompt
+        let (upper, last) = match prompt.rfind('\n') {
+            Some(i) => prompt.split_at(i + 1),
+            None => ("", prompt),
+        };
+        let width = util::display_width(last);
+        let Some(col) = self::rprompt_column(columns.into(), width, util::display_width(&rprompt))
+        else {
+            return Cow::Borrowed(prompt);
+        };
+        self.rprompt_room.set(Some(col - width - 1));
+
+        // the right prompt is drawn first, and then the left one from the start of the line
+        Cow::Owned(format!("{upper}\x1b[{}G{rprompt}\r{last}", col + 1))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if util::color_enabled() {
+            Cow::Owned(format!("\x1b[2m{hint}\x1b[0m"))
+        } else {
+            Cow::Borrowed(hint)
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // any character can change the colors, e.g., a command name becomes known
+        true
+    }
+}
+
+impl ShellHelper {
+    /// Stops redrawing the asynchronous right prompt once the line is accepted or `readline`
+    /// returns otherwise
+    pub fn finish_rprompt(&self) {
+        if let Some(Rprompt::Async(rprompt)) = &self.rprompt {
+            rprompt.finish();
+        }
+    }
+
+    fn is_known_command(&self, name: &str) -> bool {
+        if let Some(&known) = self.known_commands.borrow().get(name) {
+            return known;
+        }
+        let known = worker::is_builtin(name) || util::find_in_path(name).is_some();
+        self.known_commands
+            .borrow_mut()
+            .insert(name.to_string(), known);
+        known
+    }
+}
+
+/// Returns the column the right prompt starts at, leaving the last column empty so that the
+/// terminal doesn't wrap. `None` if it doesn't fit with a space after the left prompt and room
+/// for the cursor.
+fn rprompt_column(columns: usize, prompt_width: usize, rprompt_width: usize) -> Option<usize> {
+    let col = columns.checked_sub(rprompt_width + 1)?;
+    (col > prompt_width + 1).then_some(col)
+}
+
+/// Colors the tokens of a line, with the command names for which `is_known` returns `true` in
+/// green
+fn highlight(line: &str, mut is_known: impl FnMut(&str) -> bool) -> String {
+    // the part from an unterminated quote or expansion is red
+    let (tokens, end) = match parser::tokenize(line) {
+        Ok(tokens) => (tokens, line.len()),
+        Err(err) => (
+            parser::tokenize(&line[..err.pos]).unwrap_or_default(),
+            err.pos,
+        ),
+    };
+
+    let mut out = String::with_capacity(line.len());
+    let mut last = 0;
+    // the next word is a command name
+    let mut command = true;
+    let mut redirect = false;
+
+    for token in tokens {
+        out.push_str(&line[last..token.span.0]);
+        let text = &line[token.span.0..token.span.1];
+
+        match &token.kind {
+            TokenKind::Word(word) if command && !redirect && parser::assignment(word).is_none() => {
+                let name = expand::unquote(word);
+                if is_known(&name) {
+                    out.push_str(&util::paint(Color::Green, text));
+                } else {
+                    self::highlight_quotes(text, &mut out);
+                }
+                command = false;
+            }
+            TokenKind::Word(_) => {
+                self::highlight_quotes(text, &mut out);
+                redirect = false;
+            }
+            TokenKind::Redirect(..) => {
+                out.push_str(&util::paint(Color::Cyan, text));
+                redirect = true;
+            }
+            _ => {
+                out.push_str(&util::paint(Color::Cyan, text));
+                command = true;
+            }
+        }
+
+        last = token.span.1;
+    }
+
+    out.push_str(&line[last..end]);
+    if end < line.len() {
+        out.push_str(&util::paint(Color::Red, &line[end..]));
+    }
+
+    out
+}
+
+/// Colors the quoted parts of a word
+fn highlight_quotes(word: &str, out: &mut String) {
+    let mut chars = word.char_indices();
+    let mut plain = 0;
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '\\' => {
+                chars.next();
+                continue;
+            }
+            '\'' => chars.find(|&(_, c)| c == '\''),
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '\\')) => {
+                        chars.next();
+                    }
+                    Some((j, '"')) => break Some((j, '"')),
+                    Some(_) => {}
+                    None => break None,
+                }
+            },
+            _ => continue,
+        };
+
+        let end = end.map_or(word.len(), |(j, _)| j + 1);
+        out.push_str(&word[plain..i]);
+        out.push_str(&util::paint(Color::Yellow, &word[i..end]));
+        plain = end;
+    }
+
+    out.push_str(&word[plain..]);
+}
+
+/// Returns the characters closing the quotes and expansions left open in the text, innermost
+/// first, e.g., `)"` for `"$(echo`
+fn closing(text: &str) -> String {
+    // expected closing characters
+    let mut stack = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (stack.last(), c) {
+            (Some('\''), '\'') => {
+                stack.pop();
+            }
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(&close), c) if c == close => {
+                stack.pop();
+            }
+            (_, '$') => match chars.peek() {
+                Some('(') => {
+                    chars.next();
+                    stack.push(')');
+                }
+                Some('{') => {
+                    chars.next();
+                    stack.push('}');
+                }
+                _ => {}
+            },
+            (Some('"'), _) => {}
+            (_, '\'' | '"') => stack.push(c),
+            // nested parentheses or braces, as counted by the tokenizer
+            (Some(')'), '(') => stack.push(')'),
+            (Some('}'), '{') => stack.push('}'),
+            _ => {}
+        }
+    }
+
+    stack.into_iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_looks_up_command_names() {
+        let mut names = Vec::new();
+        let line = "ls -l | grep x; a=1 'cat' < in > out && echo";
+        let out = highlight(line, |name| {
+            names.push(name.to_string());
+            true
+        });
+        assert_eq!(names, ["ls", "grep", "cat", "echo"]);
+        // no colors without a terminal
+        if !util::color_enabled() {
+            assert_eq!(out, line);
+        }
+    }
+
+    #[test]
+    fn known_commands_are_cached() {
+        let helper = ShellHelper::default();
+        assert!(helper.is_known_command("cd"));
+        assert!(!helper.is_known_command("zerosh-no-such-command"));
+        assert_eq!(
+            *helper.known_commands.borrow(),
+            BTreeMap::from([
+                ("cd".to_string(), true),
+                ("zerosh-no-such-command".to_string(), false),
+            ])
+        );
+
+        // not looked up again while the line is edited
+        helper
+            .known_commands
+            .borrow_mut()
+            .insert("zerosh-no-such-command".to_string(), true);
+        assert!(helper.is_known_command("zerosh-no-such-command"));
+    }
+
+    #[test]
+    fn closing_characters_innermost_first() {
+        assert_eq!(closing("echo 'a"), "'");
+        assert_eq!(closing("echo \"$(ls"), ")\"");
+        assert_eq!(closing("echo ${a"), "}");
+        assert_eq!(closing("echo $(a (b"), "))");
+        assert_eq!(closing("echo \"a $(b 'c"), "')\"");
+        // closed, escaped or quoted ones
+        assert_eq!(closing("echo 'a' \"b\" $(c) ${d}"), "");
+        assert_eq!(closing("echo \\' \"\\\""), "\"");
+        assert_eq!(closing("echo '$(\"'"), "");
+    }
+
+    #[test]
+    fn hints_close_quotes_or_complete_from_the_history() {
+        let mut history = rustyline::history::History::new();
+        history.add("echo hello");
+        let ctx = rustyline::Context::new(&history);
+        let mut helper = ShellHelper::default();
+        assert_eq!(helper.hint("ec", 2, &ctx), None);
+
+        helper.history_hints = true;
+        assert_eq!(helper.hint("ec", 2, &ctx).as_deref(), Some("ho hello"));
+        // remembered for the right prompt
+        assert_eq!(
+            *helper.history_hint.borrow(),
+            Some(("ec".to_string(), "ho hello".to_string()))
+        );
+        // only at the end of the line
+        assert_eq!(helper.hint("ec", 1, &ctx), None);
+        assert_eq!(helper.hint("ls", 2, &ctx), None);
+
+        // closing a quote takes precedence
+        history.add("echo 'a' b");
+        let ctx = rustyline::Context::new(&history);
+        helper.closing_hints = true;
+        assert_eq!(helper.hint("echo 'a", 7, &ctx).as_deref(), Some("'"));
+        assert_eq!(helper.hint("echo 'a", 6, &ctx), None);
+        assert_eq!(helper.hint("echo 'a'", 8, &ctx).as_deref(), Some(" b"));
+    }
+
+    #[test]
+    fn rprompt_column_leaves_room() {
+        // `%> ` at 0-2, the cursor at 3, a space at 4, and an empty column at the end
+        assert_eq!(rprompt_column(80, 3, 10), Some(69));
+        assert_eq!(rprompt_column(16, 3, 10), Some(5));
+        assert_eq!(rprompt_column(15, 3, 10), None);
+        // no left prompt
+        assert_eq!(rprompt_column(4, 0, 1), Some(2));
+        assert_eq!(rprompt_column(3, 0, 1), None);
+        // wider than the terminal
+        assert_eq!(rprompt_column(10, 3, 20), None);
+    }
+
+    #[test]
+    fn input_reaching_the_rprompt_clears_it() {
+        let mut helper = ShellHelper::default();
+        assert!(!helper.covers_rprompt(&"x".repeat(100), 0));
+
+        helper.rprompt_room.set(Some(5));
+        assert!(!helper.covers_rprompt("ls -l", 0));
+        assert!(helper.covers_rprompt("ls -la", 0));
+        // in columns, and only the first line
+        assert!(helper.covers_rprompt("ls 日本", 0));
+        assert!(!helper.covers_rprompt("ls \\\n-la --color", 0));
+        // with the hint closing a quote
+        assert!(!helper.covers_rprompt("ls 'a", 5));
+        helper.closing_hints = true;
+        assert!(helper.covers_rprompt("ls 'a", 5));
+    }
+}
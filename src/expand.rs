@@ -0,0 +1,661 @@
+//! Word expansion
+//!
+//! Turns raw words from the [`parser`](crate::parser) into command arguments.
+
+use std::fs;
+
+use crate::{arith, glob, parser, util};
+
+/// Shell state used by the expansion
+pub(crate) trait Context {
+    /// Returns the value of a variable, including special ones such as `?`
+    fn var(&self, name: &str) -> Option<String>;
+
+    /// Returns the elements of an array variable. Other variables are arrays of one element.
+    fn array(&self, name: &str) -> Option<Vec<String>>;
+
+    /// Assigns a value to a variable
+    fn set_var(&mut self, name: &str, value: String) -> Result<(), String>;
+
+    /// Runs a command line and returns its standard output. Sets the exit code of the command
+    /// substitution.
+    fn run_subst(&mut self, cmd: &str) -> String;
+
+    /// Sets the exit code of the last command substitution
+    fn set_subst_code(&mut self, code: i32);
+
+    /// Pathname expansion is disabled (`set -f`)
+    fn noglob(&self) -> bool;
+}
+
+/// Expands a raw word into arguments. Variables and `$(...)` are replaced with their values and
+/// split into fields unless they're quoted. Fields with unquoted `*`, `?` or `[` are replaced with
+/// the sorted paths matching them, if any. Quotes and backslash escapes are removed.
+pub(crate) fn expand_word(word: &str, ctx: &mut dyn Context) -> Result<Vec<String>, String> {
+    self::expand(word, ctx, true)
+}
+
+/// Expands a raw word into a string without field splitting, e.g., the value of an assignment
+pub(crate) fn expand_value(word: &str, ctx: &mut dyn Context) -> Result<String, String> {
+    Ok(self::expand(word, ctx, false)?.concat())
+}
+
+fn expand(word: &str, ctx: &mut dyn Context, split: bool) -> Result<Vec<String>, String> {
+    // `"${NAME[@]}"` (or `"$@"`) makes no argument for an empty array
+    if split {
+        let quoted = word
+            .strip_prefix("\"$")
+            .and_then(|rest| rest.strip_suffix('"'));
+        if let Some((name, len)) = quoted.and_then(self::all_elements) {
+            if len == quoted.unwrap_or_default().len() {
+                return Ok(ctx.array(name).unwrap_or_default());
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    // `None` until something is added, so that an unquoted empty expansion makes no argument
+    let mut field: Option<Field> = None;
+    let mut pos = 0;
+
+    while let Some(c) = self::next_char(word, &mut pos) {
+        match c {
+            '\\' => {
+                let field = field.get_or_insert_with(Field::default);
+                if let Some(c) = self::next_char(word, &mut pos) {
+                    field.push_quoted(c);
+                }
+            }
+            '\'' => {
+                let field = field.get_or_insert_with(Field::default);
+                while let Some(c) = self::next_char(word, &mut pos).filter(|&c| c != '\'') {
+                    field.push_quoted(c);
+                }
+            }
+            '"' => {
+                field.get_or_insert_with(Field::default);
+                while let Some(c) = self::next_char(word, &mut pos) {
+                    let current = field.get_or_insert_with(Field::default);
+                    match c {
+                        '"' => break,
+                        // inside double quotes, backslash only escapes these
+                        '\\' => match self::next_char(word, &mut pos) {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => current.push_quoted(c),
+                            Some(c) => {
+                                current.push_quoted('\\');
+                                current.push_quoted(c);
+                            }
+                            None => current.push_quoted('\\'),
+                        },
+                        // `"${NAME[@]}"` and `"$@"` are a field per element
+                        '$' if split && self::all_elements(&word[pos..]).is_some() => {
+                            let (name, len) = self::all_elements(&word[pos..]).unwrap();
+                            pos += len;
+
+                            let elements = ctx.array(name).unwrap_or_default();
+                            for (i, element) in elements.into_iter().enumerate() {
+                                if i > 0 {
+                                    fields.extend(field.take());
+                                }
+                                let field = field.get_or_insert_with(Field::default);
+                                element.chars().for_each(|c| field.push_quoted(c));
+                            }
+                        }
+                        '$' => match self::dollar(word, &mut pos, ctx)? {
+                            Some(value) => value.chars().for_each(|c| current.push_quoted(c)),
+                            None => current.push_quoted('$'),
+                        },
+                        c => current.push_quoted(c),
+                    }
+                }
+            }
+            '$' => match self::dollar(word, &mut pos, ctx)? {
+                Some(value) if !split => {
+                    let field = field.get_or_insert_with(Field::default);
+                    value.chars().for_each(|c| field.push(c));
+                }
+                Some(value) => {
+                    let ifs = ctx.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+                    self::split_fields(&value, &ifs, &mut fields, &mut field);
+                }
+                None => field.get_or_insert_with(Field::default).push('$'),
+            },
+            c => field.get_or_insert_with(Field::default).push(c),
+        }
+    }
+
+    fields.extend(field);
+    // pathname expansion of the fields with unquoted `*`, `?` or `[`, unless `set -f`
+    let glob = split && !ctx.noglob();
+    let mut args = Vec::with_capacity(fields.len());
+    for field in fields {
+        let paths = match field.glob && glob {
+            true => glob::expand_paths(&field.pattern),
+            false => Vec::new(),
+        };
+        match paths.is_empty() {
+            // a pattern matching nothing is kept as it is
+            true => args.push(field.text),
+            false => args.extend(paths),
+        }
+    }
+    Ok(args)
+}
+
+/// Field being expanded by [`expand`], and the glob pattern of it for pathname expansion
+#[derive(Debug, Default)]
+struct Field {
+    text: String,
+    /// The text with the quoted characters escaped, so that they match literally
+    pattern: String,
+    /// Has an unquoted `*`, `?` or `[`
+    glob: bool,
+}
+
+impl Field {
+    /// Adds an unquoted character, which is special in the pattern
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.pattern.push(c);
+        self.glob |= matches!(c, '*' | '?' | '[');
+    }
+
+    /// Adds a quoted or escaped character
+    fn push_quoted(&mut self, c: char) {
+        self.text.push(c);
+        if "*?[]\\".contains(c) {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+}
+
+/// Returns the name and the length if the text (after `$`) starts with `{NAME[@]}`, or `@` or
+/// `{@}` for the positional parameters
+fn all_elements(text: &str) -> Option<(&str, usize)> {
+    if text.starts_with('@') {
+        return Some(("@", 1));
+    }
+    let body = text.strip_prefix('{')?;
+    let len = parser::param_len(body)?;
+    if &body[..len] == "@" {
+        return Some(("@", len + 2));
+    }
+    let name = body[..len].strip_suffix("[@]")?;
+    parser::is_name(name).then_some((name, len + 2))
+}
+
+/// Returns `true` if the name is a special parameter like `?`, or a positional parameter
+fn is_special(name: &str) -> bool {
+    matches!(name, "?" | "$" | "!" | "-" | "#" | "@" | "*")
+        || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Field separators used if `IFS` is unset
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Splits an expanded value with the `IFS` characters, continuing the current field. Runs of IFS
+/// whitespace separate fields, while each other IFS character ends a field, possibly an empty one.
+/// An empty `IFS` disables splitting.
+fn split_fields(value: &str, ifs: &str, fields: &mut Vec<Field>, field: &mut Option<Field>) {
+    // a field ended with whitespace, which joins the following delimiter, e.g., `a : b`
+    let mut ws_ended = false;
+
+    for c in value.chars() {
+        if !ifs.contains(c) {
+            field.get_or_insert_with(Field::default).push(c);
+            ws_ended = false;
+        } else if c.is_ascii_whitespace() {
+            if let Some(done) = field.take() {
+                fields.push(done);
+                ws_ended = true;
+            }
+        } else {
+            match field.take() {
+                Some(done) => fields.push(done),
+                None if !ws_ended => fields.push(Field::default()),
+                None => {}
+            }
+            ws_ended = false;
+        }
+    }
+}
+
+/// Expands a raw word into a glob pattern, e.g., of `case`. Quoted characters and the values of
+/// quoted expansions are escaped, so that they match literally.
+pub(crate) fn expand_pattern(word: &str, ctx: &mut dyn Context) -> Result<String, String> {
+    let mut pattern = String::with_capacity(word.len());
+    let literal = |pattern: &mut String, c: char| {
+        if "*?[]\\".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    };
+    let mut pos = 0;
+
+    while let Some(c) = self::next_char(word, &mut pos) {
+        match c {
+            '\\' => {
+                if let Some(c) = self::next_char(word, &mut pos) {
+                    literal(&mut pattern, c);
+                }
+            }
+            '\'' => {
+                while let Some(c) = self::next_char(word, &mut pos).filter(|&c| c != '\'') {
+                    literal(&mut pattern, c);
+                }
+            }
+            '"' => {
+                while let Some(c) = self::next_char(word, &mut pos).filter(|&c| c != '"') {
+                    match c {
+                        '\\' => match self::next_char(word, &mut pos) {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => literal(&mut pattern, c),
+                            Some(c) => {
+                                literal(&mut pattern, '\\');
+                                literal(&mut pattern, c);
+                            }
+                            None => literal(&mut pattern, '\\'),
+                        },
+                        '$' => match self::dollar(word, &mut pos, ctx)? {
+                            Some(value) => value.chars().for_each(|c| literal(&mut pattern, c)),
+                            None => pattern.push('$'),
+                        },
+                        c => literal(&mut pattern, c),
+                    }
+                }
+            }
+            // unquoted expansions are patterns themselves
+            '$' => match self::dollar(word, &mut pos, ctx)? {
+                Some(value) => pattern.push_str(&value),
+                None => pattern.push('$'),
+            },
+            c => pattern.push(c),
+        }
+    }
+
+    Ok(pattern)
+}
+
+/// Removes quotes and backslash escapes, leaving `$` expansions as they are
+pub(crate) fn unquote(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            '\'' => out.extend(chars.by_ref().take_while(|&c| c != '\'')),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => out.push(c),
+                            Some(c) => {
+                                out.push('\\');
+                                out.push(c);
+                            }
+                            None => out.push('\\'),
+                        },
+                        c => out.push(c),
+                    }
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn next_char(word: &str, pos: &mut usize) -> Option<char> {
+    let c = word[*pos..].chars().next()?;
+    *pos += c.len_utf8();
+    Some(c)
+}
+
+/// Expands `$(...)`, `${NAME}`, `$NAME`, a special parameter like `$?` or a positional one like
+/// `$1` at `pos` (right after `$`), moving `pos` past it. `$10` is `$1` followed by `0`.
+/// Returns `None` if the `$` doesn't start an expansion.
+fn dollar(word: &str, pos: &mut usize, ctx: &mut dyn Context) -> Result<Option<String>, String> {
+    let rest = &word[*pos..];
+
+    if let Some(body) = rest.strip_prefix('(') {
+        // the tokenizer has checked the parentheses
+        let len = parser::subst_len(body).unwrap_or(body.len());
+        *pos = (*pos + 1 + len + 1).min(word.len());
+        return Ok(Some(self::substitute(&body[..len], ctx)));
+    }
+
+    if let Some(body) = rest.strip_prefix('{') {
+        let len = parser::param_len(body).unwrap_or(body.len());
+        *pos = (*pos + 1 + len + 1).min(word.len());
+        return self::param(&body[..len], ctx).map(Some);
+    }
+
+    if let Some(name) = rest.get(..1).filter(|c| self::is_special(c)) {
+        *pos += 1;
+        return Ok(Some(ctx.var(name).unwrap_or_default()));
+    }
+
+    let len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+    if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    *pos += len;
+    Ok(Some(ctx.var(&rest[..len]).unwrap_or_default()))
+}
+
+/// Expands `${PARAM}`. `PARAM` is one of:
+///
+/// - `NAME`: the value
+/// - `NAME:-WORD`: `WORD` if the variable is unset or empty
+/// - `NAME:=WORD`: same as `:-`, but also assigns `WORD` to the variable
+/// - `NAME:+WORD`: `WORD` if the variable is set and not empty
+/// - `NAME:?MESSAGE`: error with the message if the variable is unset or empty
+///
+/// Without the colon, the operators only check if the variable is unset.
+///
+/// `NAME[INDEX]` in place of `NAME` is an element of an array, where `INDEX` is an arithmetic
+/// expression (negative ones count from the end), and `NAME[@]` or `NAME[*]` is all the elements.
+/// `#NAME[@]` is the number of elements. In double quotes, `${NAME[@]}` is a field per element.
+///
+/// - `#NAME`: the length of the value
+/// - `NAME#PATTERN` or `NAME##PATTERN`: the value without the shortest or longest prefix matching
+///   the pattern
+/// - `NAME%PATTERN` or `NAME%%PATTERN`: the value without the shortest or longest matching suffix
+/// - `NAME/PATTERN/STRING`: the value with the first longest match of the pattern replaced with
+///   the string. `//` replaces all the matches, and `/#` and `/%` match only at the beginning or
+///   the end.
+fn param(param: &str, ctx: &mut dyn Context) -> Result<String, String> {
+    if let Some(name) = param.strip_prefix('#') {
+        // the number of the positional parameters
+        if matches!(name, "@" | "*") {
+            return Ok(ctx
+                .array(name)
+                .map_or(0, |elements| elements.len())
+                .to_string());
+        }
+        if self::is_special(name) || parser::is_name(name) {
+            let value = ctx.var(name).unwrap_or_default();
+            return Ok(value.chars().count().to_string());
+        }
+
+        // `${#NAME[@]}` is the number of elements, and `${#NAME[INDEX]}` is the length of one
+        if let Some((name, subscript)) = name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+            if parser::is_name(name) {
+                let len = match subscript {
+                    "@" | "*" => ctx.array(name).map_or(0, |elements| elements.len()),
+                    _ => self::element(name, subscript, ctx)?.map_or(0, |e| e.chars().count()),
+                };
+                return Ok(len.to_string());
+            }
+        }
+    }
+
+    let name_len = if param.starts_with(['?', '$', '!', '-', '#', '@', '*']) {
+        1
+    } else if param.starts_with(|c: char| c.is_ascii_digit()) {
+        param
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(param.len())
+    } else {
+        param
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(param.len())
+    };
+
+    let (name, rest) = param.split_at(name_len);
+    if !(self::is_special(name) || parser::is_name(name)) {
+        return Err(format!("${{{param}}}: bad substitution"));
+    }
+
+    let (subscript, rest) = match rest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((subscript, rest)) => (Some(subscript), rest),
+        None => (None, rest),
+    };
+
+    let value = match subscript {
+        Some(subscript) => self::element(name, subscript, ctx)?,
+        None => ctx.var(name),
+    };
+    if rest.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+
+    if let Some(op @ ('#' | '%')) = rest.chars().next() {
+        let longest = rest[1..].starts_with(op);
+        let pattern = self::expand_value(&rest[if longest { 2 } else { 1 }..], ctx)?;
+        let value = value.unwrap_or_default();
+        return Ok(self::remove_affix(&value, &pattern, op == '#', longest).to_string());
+    }
+
+    if let Some(rest) = rest.strip_prefix('/') {
+        let (mode, rest) = match rest.chars().next() {
+            Some(mode @ ('/' | '#' | '%')) => (Some(mode), &rest[1..]),
+            _ => (None, rest),
+        };
+
+        let (pattern, repl) = match self::find_slash(rest) {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+
+        let pattern = self::expand_value(pattern, ctx)?;
+        let repl = self::expand_value(repl, ctx)?;
+        let value = value.unwrap_or_default();
+        return Ok(self::replace(&value, &pattern, &repl, mode));
+    }
+
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    let Some(op) = rest.chars().next().filter(|c| "-=+?".contains(*c)) else {
+        return Err(format!("${{{param}}}: bad substitution"));
+    };
+    let word = &rest[1..];
+
+    // `:` also treats an empty value as unset
+    let value = value.filter(|value| !(colon && value.is_empty()));
+
+    match (op, value) {
+        ('-', None) => self::expand_value(word, ctx),
+        ('=', None) if subscript.is_some() => Err(format!("{param}: cannot assign in this way")),
+        ('=', None) => {
+            let word = self::expand_value(word, ctx)?;
+            ctx.set_var(name, word.clone())?;
+            Ok(word)
+        }
+        ('+', Some(_)) => self::expand_value(word, ctx),
+        ('+', None) => Ok(String::new()),
+        ('?', None) => {
+            let msg = self::expand_value(word, ctx)?;
+            if msg.is_empty() && colon {
+                Err(format!("{name}: parameter null or not set"))
+            } else if msg.is_empty() {
+                Err(format!("{name}: parameter not set"))
+            } else {
+                Err(format!("{name}: {msg}"))
+            }
+        }
+        (_, Some(value)) => Ok(value),
+        _ => unreachable!(),
+    }
+}
+
+/// Returns `NAME[SUBSCRIPT]`: all the elements joined with spaces for `@` and `*`, or an element
+fn element(name: &str, subscript: &str, ctx: &mut dyn Context) -> Result<Option<String>, String> {
+    let Some(elements) = ctx.array(name) else {
+        return Ok(None);
+    };
+
+    if subscript == "@" || subscript == "*" {
+        return Ok(Some(elements.join(" ")));
+    }
+
+    let index = arith::eval(subscript, &|name| ctx.var(name))?;
+    let index = if index < 0 {
+        elements.len() as i64 + index
+    } else {
+        index
+    };
+    if index < 0 {
+        return Err(format!("{name}[{subscript}]: bad array subscript"));
+    }
+
+    Ok(elements.into_iter().nth(index as usize))
+}
+
+/// Removes the shortest or longest prefix (or suffix) matching the pattern
+fn remove_affix<'a>(value: &'a str, pattern: &str, prefix: bool, longest: bool) -> &'a str {
+    let mut bounds = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([value.len()])
+        .collect::<Vec<_>>();
+
+    // shortest first
+    if prefix == longest {
+        bounds.reverse();
+    }
+
+    for i in bounds {
+        if prefix && glob::matches(pattern, &value[..i]) {
+            return &value[i..];
+        }
+        if !prefix && glob::matches(pattern, &value[i..]) {
+            return &value[..i];
+        }
+    }
+
+    value
+}
+
+/// Finds the `/` separating the pattern and the string, skipping quoted ones
+fn find_slash(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    let mut quote = None;
+
+    while let Some((i, c)) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                chars.next();
+            }
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('/', None) => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Replaces the longest matches of the pattern. `mode` is `/` (all the matches), `#` (prefix), `%`
+/// (suffix) or `None` (the first match).
+fn replace(value: &str, pattern: &str, repl: &str, mode: Option<char>) -> String {
+    let bounds = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([value.len()])
+        .collect::<Vec<_>>();
+
+    match mode {
+        Some('#') => {
+            let end = bounds
+                .iter()
+                .rev()
+                .find(|&&j| glob::matches(pattern, &value[..j]));
+            match end {
+                Some(&j) => format!("{repl}{}", &value[j..]),
+                None => value.to_string(),
+            }
+        }
+        Some('%') => {
+            let start = bounds
+                .iter()
+                .find(|&&i| glob::matches(pattern, &value[i..]));
+            match start {
+                Some(&i) => format!("{}{repl}", &value[..i]),
+                None => value.to_string(),
+            }
+        }
+        _ if pattern.is_empty() => value.to_string(),
+        _ => {
+            let all = mode == Some('/');
+            let mut out = String::with_capacity(value.len());
+            let mut k = 0;
+
+            while k < bounds.len() {
+                let i = bounds[k];
+                // the longest non-empty match starting at `i`
+                let end = bounds[k + 1..]
+                    .iter()
+                    .rposition(|&j| glob::matches(pattern, &value[i..j]));
+
+                match end {
+                    Some(n) => {
+                        out.push_str(repl);
+                        k += n + 1;
+                        if !all {
+                            out.push_str(&value[bounds[k]..]);
+                            return out;
+                        }
+                    }
+                    None => {
+                        out.extend(value[i..].chars().next());
+                        k += 1;
+                    }
+                }
+            }
+
+            out
+        }
+    }
+}
+
+/// Runs a command substitution. Trailing newlines are removed from the output, and NUL bytes are
+/// dropped like bash, as arguments and environment variables can't have them.
+fn substitute(cmd: &str, ctx: &mut dyn Context) -> String {
+    let mut output = match cmd.trim().strip_prefix('<') {
+        // `$(<FILE)` reads the file without forking `cat`
+        Some(file) => self::read_file(file.trim(), ctx),
+        None => ctx.run_subst(cmd),
+    };
+
+    if output.contains('\0') {
+        util::print_warning("command substitution: ignored NUL byte in the output");
+        output.retain(|c| c != '\0');
+    }
+
+    output.truncate(output.trim_end_matches('\n').len());
+    output
+}
+
+fn read_file(file: &str, ctx: &mut dyn Context) -> String {
+    let path = match self::expand_word(file, ctx) {
+        Ok(fields) => fields.join(" "),
+        Err(err) => {
+            util::print_error(err);
+            ctx.set_subst_code(1);
+            return String::new();
+        }
+    };
+
+    match fs::read(&path) {
+        Ok(bytes) => {
+            ctx.set_subst_code(0);
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        Err(err) => {
+            util::print_error(format!("{path}: {err}"));
+            ctx.set_subst_code(1);
+            String::new()
+        }
+    }
+}
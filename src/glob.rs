@@ -0,0 +1,149 @@
+//! Glob-style pattern matching and pathname expansion
+//!
+//! `*` matches any string, `?` matches any character and `[...]` matches one of the characters
+//! (`[!...]` or `[^...]` negates it, and `a-z` is a range). A backslash makes the next character
+//! literal.
+//!
+//! In pathname expansion, each component of a path is a pattern matched against the names in the
+//! directory, and a leading `.` of a name must be matched explicitly like bash.
+
+use std::{fs, path::Path};
+
+/// Returns `true` if the pattern matches the whole text
+pub(crate) fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    self::matches_chars(&pattern, &text)
+}
+
+/// Returns the paths matching a pattern, sorted. Empty if none matches.
+pub(crate) fn expand_paths(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components = rest.split('/').collect::<Vec<_>>();
+    for (i, component) in components.iter().enumerate() {
+        let dir_sep = if i + 1 < components.len() { "/" } else { "" };
+        if !self::has_magic(component) {
+            let name = self::unescape(component);
+            for path in &mut paths {
+                path.push_str(&name);
+                path.push_str(dir_sep);
+            }
+            continue;
+        }
+
+        let hidden = component.starts_with('.') || component.starts_with("\\.");
+        let mut next = Vec::new();
+        for dir in &paths {
+            let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                if (hidden || !name.starts_with('.')) && self::matches(component, &name) {
+                    next.push(format!("{dir}{name}{dir_sep}"));
+                }
+            }
+        }
+        paths = next;
+    }
+
+    // the literal components may not exist
+    paths.retain(|path| Path::new(path).symlink_metadata().is_ok());
+    paths.sort();
+    paths
+}
+
+/// Returns `true` if the pattern has an unescaped `*`, `?` or `[`
+fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the backslashes escaping characters
+fn unescape(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    let Some((&p, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+
+    match p {
+        // try the shortest match first
+        '*' => (0..=text.len()).any(|i| self::matches_chars(rest, &text[i..])),
+        '?' => !text.is_empty() && self::matches_chars(rest, &text[1..]),
+        '[' => match (self::bracket_len(rest), text.first()) {
+            (Some(len), Some(&c)) => {
+                self::bracket_matches(&rest[..len], c)
+                    && self::matches_chars(&rest[len + 1..], &text[1..])
+            }
+            (Some(_), None) => false,
+            // `[` without `]` is a literal
+            (None, _) => text.first() == Some(&'[') && self::matches_chars(rest, &text[1..]),
+        },
+        '\\' if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && self::matches_chars(&rest[1..], &text[1..])
+        }
+        p => text.first() == Some(&p) && self::matches_chars(rest, &text[1..]),
+    }
+}
+
+/// Returns the offset of the `]` closing a bracket expression. `body` is the pattern after `[`.
+fn bracket_len(body: &[char]) -> Option<usize> {
+    // `]` right after `[`, `[!` or `[^` is a member
+    let start = match body.first() {
+        Some('!' | '^') => 2,
+        _ => 1,
+    };
+    body.iter()
+        .skip(start)
+        .position(|&c| c == ']')
+        .map(|i| i + start)
+}
+
+fn bracket_matches(body: &[char], c: char) -> bool {
+    let (negate, mut set) = match body.split_first() {
+        Some((&('!' | '^'), rest)) => (true, rest),
+        _ => (false, body),
+    };
+
+    let mut found = false;
+    while let Some((&first, rest)) = set.split_first() {
+        match rest {
+            ['-', last, rest @ ..] => {
+                found |= (first..=*last).contains(&c);
+                set = rest;
+            }
+            _ => {
+                found |= first == c;
+                set = rest;
+            }
+        }
+    }
+
+    found != negate
+}
@@ -0,0 +1,234 @@
+//! History file and the `fc` builtin
+//!
+//! History lives on the `main` thread, so `fc` is run there instead of the `worker` thread. It
+//! returns the command lines to execute.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::util;
+
+/// History file in the home directory, used if `$XDG_STATE_HOME` is not set
+const HISTORY_FILE: &str = ".zerosh_history";
+
+/// Returns the history file: `$ZEROSH_HISTFILE`, the given file, `$XDG_STATE_HOME/zerosh/history`
+/// or `~/.zerosh_history`, in this order. The parent directory is created if missing.
+pub(crate) fn history_file(log_file: Option<String>) -> String {
+    let file = env::var("ZEROSH_HISTFILE")
+        .ok()
+        .filter(|file| !file.is_empty())
+        .or(log_file);
+
+    if let Some(file) = file {
+        self::create_parent(Path::new(&file));
+        return file;
+    }
+
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute());
+
+    if let Some(dir) = state_dir {
+        let file = dir.join("zerosh").join("history");
+        if self::create_parent(&file) {
+            return file.to_string_lossy().into_owned();
+        }
+    }
+
+    match dirs::home_dir() {
+        Some(home) => home.join(HISTORY_FILE).to_string_lossy().into_owned(),
+        None => HISTORY_FILE.to_string(),
+    }
+}
+
+/// Creates the directory of a file. Returns `false` with a warning on failure.
+fn create_parent(file: &Path) -> bool {
+    let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+        return true;
+    };
+
+    match fs::create_dir_all(dir) {
+        Ok(()) => true,
+        Err(err) => {
+            util::print_warning(format!("unable to create {}: {err}", dir.display()));
+            false
+        }
+    }
+}
+
+/// Parsed `fc` arguments
+#[derive(Debug, Default)]
+struct FcArgs<'a> {
+    list: bool,
+    no_numbers: bool,
+    reverse: bool,
+    /// `-s`: re-execute without editing
+    substitute: bool,
+    editor: Option<&'a str>,
+    operands: Vec<&'a str>,
+}
+
+/// `fc [-e EDITOR] [-lnr] [FIRST [LAST]]` or `fc -s [OLD=NEW] [FIRST]`
+///
+/// - `fc -l` lists the history (the last 16 entries by default).
+/// - `fc -s` returns a history entry with `OLD` replaced with `NEW`.
+/// - Otherwise, the entries are written to a temporary file and opened in `$FCEDIT`, `$EDITOR` or
+///   `vi`. The edited lines are returned.
+///
+/// `FIRST` and `LAST` are a history number, a negative offset from the current entry or a prefix.
+pub(crate) fn fc(args: &[String], history: &[&str]) -> Result<Vec<String>, String> {
+    let args = self::parse_args(args)?;
+    if history.is_empty() {
+        return if args.list {
+            Ok(Vec::new())
+        } else {
+            Err("no command found".to_string())
+        };
+    }
+
+    if args.substitute {
+        let (subs, specs): (Vec<_>, Vec<_>) = args.operands.iter().partition(|op| op.contains('='));
+        let index = self::find_entry(specs.first().copied(), history)?;
+
+        let mut line = history[index].to_string();
+        for sub in subs {
+            let (old, new) = sub.split_once('=').unwrap();
+            if !old.is_empty() {
+                line = line.replace(old, new);
+            }
+        }
+
+        return Ok(vec![line]);
+    }
+
+    let (first, last) = if args.list && args.operands.is_empty() {
+        (
+            history.len().saturating_sub(16),
+            history.len().saturating_sub(1),
+        )
+    } else {
+        let first = self::find_entry(args.operands.first().copied(), history)?;
+        let last = match args.operands.get(1) {
+            Some(&spec) => self::find_entry(Some(spec), history)?,
+            None if args.list => history.len() - 1,
+            None => first,
+        };
+        (first, last)
+    };
+
+    // a reversed range is listed in reverse order
+    let mut range = (first.min(last)..=first.max(last)).collect::<Vec<_>>();
+    if (first > last) != args.reverse {
+        range.reverse();
+    }
+
+    if args.list {
+        for i in range {
+            if args.no_numbers {
+                println!("\t{}", history[i]);
+            } else {
+                println!("{}\t{}", i + 1, history[i]);
+            }
+        }
+        return Ok(Vec::new());
+    }
+
+    let lines = range.iter().map(|&i| history[i]).collect::<Vec<_>>();
+    self::edit(&lines, args.editor)
+}
+
+fn parse_args(args: &[String]) -> Result<FcArgs<'_>, String> {
+    let mut out = FcArgs::default();
+    let mut args = args.iter().skip(1).map(String::as_str);
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            out.operands.extend(args.by_ref());
+            break;
+        }
+
+        // `-N` is an offset, not an option
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && flags.parse::<usize>().is_err() => flags,
+            _ => {
+                out.operands.push(arg);
+                continue;
+            }
+        };
+
+        for flag in flags.chars() {
+            match flag {
+                'l' => out.list = true,
+                'n' => out.no_numbers = true,
+                'r' => out.reverse = true,
+                's' => out.substitute = true,
+                'e' => out.editor = Some(args.next().ok_or("-e: option requires an argument")?),
+                _ => return Err(format!("-{flag}: invalid option")),
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Returns the index of the history entry. `None` means the last entry.
+fn find_entry(spec: Option<&str>, history: &[&str]) -> Result<usize, String> {
+    let index = match spec {
+        None => history.len().checked_sub(1),
+        Some(spec) => match spec.parse::<isize>() {
+            Ok(n) if n < 0 => history.len().checked_sub(n.unsigned_abs()),
+            Ok(n) => (n as usize).checked_sub(1).filter(|&i| i < history.len()),
+            Err(_) => history.iter().rposition(|entry| entry.starts_with(spec)),
+        },
+    };
+
+    index.ok_or_else(|| match spec {
+        Some(spec) => format!("{spec}: history specification out of range"),
+        None => "no command found".to_string(),
+    })
+}
+
+/// Opens the lines in an editor and returns the edited lines
+fn edit(lines: &[&str], editor: Option<&str>) -> Result<Vec<String>, String> {
+    let editor = match editor {
+        Some(editor) => editor.to_string(),
+        None => env::var("FCEDIT")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string()),
+    };
+
+    let path = env::temp_dir().join(format!("zerosh-fc-{}.sh", process::id()));
+    fs::write(&path, lines.join("\n") + "\n")
+        .map_err(|err| format!("{}: {err}", path.display()))?;
+
+    // the editor may have arguments, e.g., `code --wait`
+    let mut words = editor.split_whitespace();
+    let status = match words.next() {
+        Some(program) => process::Command::new(program)
+            .args(words)
+            .arg(&path)
+            .status()
+            .map_err(|err| format!("{editor}: {err}")),
+        None => Err("empty editor".to_string()),
+    };
+
+    let content = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    // nothing is executed if the editor fails, like bash
+    let status = status?;
+    if !status.success() {
+        return Err(format!("{editor}: exited with {status}"));
+    }
+
+    let content = content.map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
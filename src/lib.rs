@@ -4,54 +4,390 @@
 //!
 //! - `main` reads user input and sends it to the `worker` thread.
 //! - `signal_handler` receieves signals and sends them to the `worker` thread.
+//! - `timer` wakes up the `worker` thread now and then to check the jobs, in case a `SIGCHLD` was
+//!   missed.
 //! - `worker` is the core processor and the process manager.
-
-pub mod shell;
+//!
+//! # Job notices
+//!
+//! `main` blocks in `readline` while waiting for user input, so it can't receive messages from the
+//! `worker` thread. Instead, the `worker` prints job notices (e.g., a background job is done) with
+//! rustyline's external printer. It sends the text through a pipe that `readline` polls together
+//! with the terminal, so `readline` wakes up, prints the notice above the prompt and redraws the
+//! input line.
+//!
+//! That happens right away only with `set -b` (`notify`). By default, the `worker` keeps the
+//! notices and prints them before it returns the exit code of the next command, i.e., before the
+//! next prompt.
+//!
+//! # Embedding
+//!
+//! [`Shell::run_c`] runs a command line once. [`Shell::parse`] and [`Shell::execute`] split it into
+//! parsing and running, so that programs can also build the syntax tree ([`Ast`]) themselves.
 
 pub(crate) mod util;
 
+mod arith;
+#[cfg(feature = "capture")]
+mod capture;
+mod editor;
+mod expand;
+mod glob;
+mod history;
+mod parser;
+mod printf;
 mod worker;
 
-use nix::sys::signal;
+#[cfg(feature = "capture")]
+pub use capture::CommandOutput;
+pub use parser::{
+    tokenize, Ast, CaseItem, Command, Compound, Connector, List, ParseError, Pipeline, Redirect,
+    RedirectKind, TimeFormat, Token, TokenKind,
+};
+
+use nix::{libc, sys::signal};
 
-use std::{ops::ControlFlow, sync::mpsc, thread};
+use std::{
+    env,
+    ops::ControlFlow,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
 
 pub type DynError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+/// Prints text above the prompt from the `worker` thread
+trait Printer: rustyline::ExternalPrinter + std::fmt::Debug + Send {}
+
+impl<T: rustyline::ExternalPrinter + std::fmt::Debug + Send> Printer for T {}
+
 /// Message to the `worker` thread
 #[derive(Debug, Clone)]
 enum WorkerMsg {
     /// Message from the `signal_handler` thread, i.e., a signal.
     Signal { signal: i32 },
 
-    /// Message from the `main` thread, i.e., user input.
+    /// Message from the `main` thread, i.e., user input. An empty line only gets the next prompt
+    /// ready.
     Cmd { cmd: String },
+
+    /// Message from the `timer` thread, sent every [`TICK_INTERVAL`].
+    Tick,
+
+    /// Message from the `main` thread when the terminal is gone. The `worker` exits as if hung
+    /// up, even if `SIGHUP` is ignored or has not arrived yet.
+    Hangup,
 }
 
+/// Interval of the `timer` thread
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Message to the `main` thread
 #[derive(Debug, Clone)]
 enum ShellMsg {
-    /// Continue reading user input
-    Continue { code: i32 },
+    /// Continue reading user input. `jobs` is the number of background and stopped jobs, and
+    /// `duration` is the time the command took.
+    Continue {
+        code: i32,
+        jobs: usize,
+        duration: Duration,
+    },
 
     /// Quit the shell
     Quit { code: i32 },
+
+    /// Switch the editing mode (`set -o vi` or `set -o emacs`). Sent before the exit code.
+    SetEditMode { mode: rustyline::EditMode },
+
+    /// Names of the shell variables for completion, sent before the exit code when they change
+    SetVarNames { names: Vec<String> },
+
+    /// A foreground job was stopped (`Ctrl+z`) and the prompt comes back without it. Sent before
+    /// the exit code.
+    Stopped { job_id: usize },
 }
 
 #[derive(Debug)]
 pub struct Shell {
     log_file: String,
+    bindings: Vec<editor::KeyBinding>,
+    /// Shows the characters closing an unterminated quote or expansion as a hint
+    closing_hints: bool,
+    /// Suggests the rest of the latest history line starting with the input as a hint
+    history_hints: bool,
+    limits: parser::Limits,
+    /// Private mode: the history file is neither read nor written, and no line is added to the
+    /// history
+    private: bool,
+    /// Login shell: runs the profiles at startup and `~/.zerosh_logout` on exit
+    login: bool,
+    /// Run by an interactive shell at startup in place of `~/.zeroshrc`
+    rc_file: Option<PathBuf>,
+    /// No rc file is run
+    norc: bool,
+    /// Finds the git branch of the right prompt in a thread
+    async_prompt: bool,
 }
 
 impl Shell {
+    /// Creates a shell with the history file, unless `ZEROSH_HISTFILE` is set
     pub fn new(log_file: String) -> Self {
-        Self { log_file }
+        Self {
+            log_file: history::history_file(Some(log_file)),
+            bindings: Vec::new(),
+            closing_hints: true,
+            history_hints: self::history_hints_env(),
+            limits: parser::Limits::default(),
+            private: self::private_env(),
+            login: false,
+            rc_file: None,
+            norc: false,
+            async_prompt: self::async_prompt_env(),
+        }
+    }
+
+    pub fn builder() -> ShellBuilder {
+        ShellBuilder::default()
+    }
+
+    /// Returns the version of ZeroSh, which is also `ZEROSH_VERSION`
+    pub fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    /// Runs a command line once and returns the exit code, e.g., for `zerosh -c`. No prompt and no
+    /// history.
+    pub fn run_c(&self, cmd: &str) -> i32 {
+        crate::worker::run_command_line(cmd, self.limits, self.login)
+    }
+
+    /// Parses a command line with the limits of the shell. `None` if the line is empty. Aliases
+    /// are not expanded, as they are defined while running.
+    pub fn parse(&self, cmd: &str) -> Result<Option<Ast>, ParseError> {
+        parser::parse(cmd, self.limits)
+    }
+
+    /// Runs a syntax tree like [`Shell::run_c`] and returns the exit code. The tree may be the
+    /// result of [`Shell::parse`] or built by hand. Raw words are expanded as if they were typed.
+    pub fn execute(&self, ast: &Ast) -> i32 {
+        crate::worker::run_list(ast, self.limits, self.login)
+    }
+}
+
+/// Configures a [`Shell`]
+#[derive(Debug, Default)]
+pub struct ShellBuilder {
+    log_file: Option<String>,
+    /// Key sequence and text or an editing command, not parsed yet
+    bindings: Vec<(String, Binding)>,
+    closing_hints: Option<bool>,
+    history_hints: Option<bool>,
+    max_pipeline_len: Option<usize>,
+    max_words: Option<usize>,
+    max_source_depth: Option<usize>,
+    max_func_depth: Option<usize>,
+    max_line_len: Option<usize>,
+    private: Option<bool>,
+    login: bool,
+    rc_file: Option<PathBuf>,
+    norc: bool,
+    async_prompt: Option<bool>,
+}
+
+impl ShellBuilder {
+    /// Sets the history file. `ZEROSH_HISTFILE` takes precedence over it. If neither is set,
+    /// `$XDG_STATE_HOME/zerosh/history` or `~/.zerosh_history` is used.
+    pub fn log_file(mut self, log_file: impl Into<String>) -> Self {
+        self.log_file = Some(log_file.into());
+        self
+    }
+
+    /// Binds a key sequence such as `\C-g` to text inserted at the cursor. If the text ends with
+    /// a newline, the line is executed, e.g., `bind("\\C-g", "git status\n")`.
+    pub fn bind(mut self, keys: impl Into<String>, text: impl Into<String>) -> Self {
+        self.bindings
+            .push((keys.into(), Binding::Text(text.into())));
+        self
+    }
+
+    /// Binds a key sequence to an editing command by its readline name, e.g.,
+    /// `bind_command("\\C-w", "backward-kill-word")`. The commands are `beginning-of-line`,
+    /// `end-of-line`, `forward-word`, `backward-word`, `kill-line`, `unix-line-discard`,
+    /// `kill-whole-line`, `kill-word`, `backward-kill-word`, `unix-word-rubout` and
+    /// `clear-screen`.
+    pub fn bind_command(mut self, keys: impl Into<String>, command: impl Into<String>) -> Self {
+        self.bindings
+            .push((keys.into(), Binding::Command(command.into())));
+        self
+    }
+
+    /// Shows (or hides) a dim hint of the characters closing an unterminated quote, `$(` or `${`
+    /// at the end of the input line. Shown by default.
+    pub fn closing_hints(mut self, enabled: bool) -> Self {
+        self.closing_hints = Some(enabled);
+        self
+    }
+
+    /// Shows (or hides) a dim suggestion of the rest of the latest history line that starts with
+    /// the input, like fish. The right arrow or the End key accepts it at the end of the line.
+    /// Closing hints take precedence. Shown if `ZEROSH_HISTORY_HINTS` is set by default.
+    pub fn history_hints(mut self, enabled: bool) -> Self {
+        self.history_hints = Some(enabled);
+        self
+    }
+
+    /// Sets the maximum number of commands in a pipeline. A longer pipeline is a syntax error.
+    /// Defaults to 1024.
+    pub fn max_pipeline_len(mut self, len: usize) -> Self {
+        self.max_pipeline_len = Some(len);
+        self
+    }
+
+    /// Sets the maximum number of words in a command line. A longer line is a syntax error.
+    /// Defaults to 65536.
+    pub fn max_words(mut self, words: usize) -> Self {
+        self.max_words = Some(words);
+        self
+    }
+
+    /// Sets the maximum number of files run by `source` (or `.`) inside each other. Deeper ones
+    /// fail, e.g., a file that sources itself. Defaults to 100.
+    pub fn max_source_depth(mut self, depth: usize) -> Self {
+        self.max_source_depth = Some(depth);
+        self
+    }
+
+    /// Sets the maximum number of function calls inside each other. Deeper ones fail, e.g., a
+    /// function that calls itself forever. Defaults to 200.
+    ///
+    /// Each call takes stack space of the thread running the command. The `worker` thread of
+    /// [`run_shell`] has 16 MiB, while [`Shell::run_c`] and [`Shell::execute`] run on the calling
+    /// thread.
+    pub fn max_func_depth(mut self, depth: usize) -> Self {
+        self.max_func_depth = Some(depth);
+        self
+    }
+
+    /// Sets the maximum length of a command line in bytes. A longer line, e.g., a file pasted by
+    /// mistake, is rejected before history expansion and parsing. Defaults to 1 MiB.
+    pub fn max_line_len(mut self, len: usize) -> Self {
+        self.max_line_len = Some(len);
+        self
+    }
+
+    /// Turns on (or off) the private mode, where the history file is neither read nor written and
+    /// no line is added to the history, e.g., for typing secrets. On if `ZEROSH_NO_HISTORY` is set
+    /// by default. The prompt shows it with `\P`.
+    pub fn private(mut self, enabled: bool) -> Self {
+        self.private = Some(enabled);
+        self
+    }
+
+    /// Makes the shell a login shell, which imports the environment of `/etc/profile` (run by
+    /// `/bin/sh`) and runs `~/.zerosh_profile` at startup, and runs `~/.zerosh_logout` on exit.
+    /// Missing files are skipped. Off by default.
+    pub fn login(mut self, enabled: bool) -> Self {
+        self.login = enabled;
+        self
     }
+
+    /// Sets the file that an interactive shell runs at startup, after the profiles of a login
+    /// shell. Defaults to `~/.zeroshrc`, which is skipped if it's missing.
+    pub fn rc_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rc_file = Some(path.into());
+        self
+    }
+
+    /// Skips the rc file (or not), e.g., for reproducible runs. Off by default.
+    pub fn norc(mut self, enabled: bool) -> Self {
+        self.norc = enabled;
+        self
+    }
+
+    /// Finds the git branch (`\g`) of `ZEROSH_RPROMPT` in a background thread (or not), so that a
+    /// slow file system doesn't hold up the prompt. The right prompt shows a placeholder if it
+    /// takes longer than a moment, and is redrawn when it's found. Off by default, unless
+    /// `ZEROSH_ASYNC_PROMPT` is set.
+    pub fn async_prompt(mut self, enabled: bool) -> Self {
+        self.async_prompt = Some(enabled);
+        self
+    }
+
+    /// Fails if a key sequence is malformed or an editing command is unknown
+    pub fn build(self) -> Result<Shell, DynError> {
+        let bindings = self
+            .bindings
+            .iter()
+            .map(|(keys, binding)| match binding {
+                Binding::Text(text) => editor::KeyBinding::parse(keys, text),
+                Binding::Command(name) => editor::KeyBinding::parse_command(keys, name),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Shell {
+            log_file: history::history_file(self.log_file),
+            bindings,
+            closing_hints: self.closing_hints.unwrap_or(true),
+            history_hints: self.history_hints.unwrap_or_else(self::history_hints_env),
+            limits: {
+                let default = parser::Limits::default();
+                parser::Limits {
+                    pipeline_len: self.max_pipeline_len.unwrap_or(default.pipeline_len),
+                    words: self.max_words.unwrap_or(default.words),
+                    source_depth: self.max_source_depth.unwrap_or(default.source_depth),
+                    func_depth: self.max_func_depth.unwrap_or(default.func_depth),
+                    line_len: self.max_line_len.unwrap_or(default.line_len),
+                }
+            },
+            private: self.private.unwrap_or_else(self::private_env),
+            login: self.login,
+            rc_file: self.rc_file,
+            norc: self.norc,
+            async_prompt: self.async_prompt.unwrap_or_else(self::async_prompt_env),
+        })
+    }
+}
+
+/// What a key sequence of [`ShellBuilder`] is bound to
+#[derive(Debug)]
+enum Binding {
+    Text(String),
+    Command(String),
 }
 
+/// Returns true if `ZEROSH_NO_HISTORY` is set
+fn private_env() -> bool {
+    env::var_os("ZEROSH_NO_HISTORY").is_some_and(|value| !value.is_empty())
+}
+
+/// Returns true if `ZEROSH_HISTORY_HINTS` is set
+fn history_hints_env() -> bool {
+    env::var_os("ZEROSH_HISTORY_HINTS").is_some_and(|value| !value.is_empty())
+}
+
+/// Returns true if `ZEROSH_ASYNC_PROMPT` is set
+fn async_prompt_env() -> bool {
+    env::var_os("ZEROSH_ASYNC_PROMPT").is_some_and(|value| !value.is_empty())
+}
+
+/// Exit code of [`run_shell_with_code`] when the `worker` thread has terminated unexpectedly, i.e.,
+/// `EX_SOFTWARE` of `sysexits.h`
+pub const EXIT_WORKER_TERMINATED: i32 = 70;
+
 /// Creates the `worker` and the `signal_handler` threads from the `main` thread and starts handling
-/// user input.
+/// user input. Use [`run_shell_with_code`] for the exit code of the shell.
 pub fn run_shell(sh: &Shell) -> Result<(), DynError> {
+    self::run_shell_with_code(sh).map(|_| ())
+}
+
+/// Runs the shell like [`run_shell`] and returns the exit code of the shell, e.g., of `exit`
+pub fn run_shell_with_code(sh: &Shell) -> Result<i32, DynError> {
+    crate::worker::reserve_fds();
     unsafe {
         signal::signal(signal::Signal::SIGTTOU, signal::SigHandler::SigIgn).unwrap();
     }
@@ -59,127 +395,645 @@ pub fn run_shell(sh: &Shell) -> Result<(), DynError> {
     let (worker_tx, worker_rx) = mpsc::channel();
     let (shell_tx, mut shell_rx) = mpsc::sync_channel(0);
 
-    let mut state = State::create(&sh.log_file, worker_tx.clone())?;
+    let mut state = State::create(sh, worker_tx.clone());
+
+    // not available if the terminal is not a TTY
+    let printer = match &mut state.editor {
+        editor::LineReader::Editor(editor) => editor.create_external_printer().ok(),
+        editor::LineReader::Plain(_) => None,
+    };
+    let printer = printer.map(|printer| Box::new(printer) as Box<dyn Printer>);
 
-    self::spawn_signal_handler(worker_tx.clone())?;
-    crate::worker::spawn_worker(worker_rx, shell_tx.clone());
+    let comp_specs = state.editor.helper_mut().map(|h| h.comp_specs.clone());
+    let shared = editor::Shared {
+        comp_specs: comp_specs.unwrap_or_default(),
+        last_key: state.last_key.clone(),
+        histexpand: state.histexpand.clone(),
+    };
+    let signal_handler = self::spawn_signal_handler(worker_tx.clone())?;
+    let interactive = nix::unistd::isatty(nix::libc::STDIN_FILENO).unwrap_or(false);
+    // the `worker` owns the only sender, so that `recv` fails if it terminates
+    let exited = crate::worker::spawn_worker(
+        sh,
+        worker_rx,
+        shell_tx,
+        interactive,
+        printer,
+        signal_handler,
+        shared,
+    );
+    if let Some(code) = exited {
+        return Ok(code);
+    }
+    self::spawn_timer(worker_tx.clone());
 
     loop {
-        if self::process(&mut state, sh, &mut shell_rx)?.is_break() {
+        if self::process(&mut state, &mut shell_rx)?.is_break() {
             break;
         }
     }
 
-    Ok(())
+    if !sh.private {
+        if let Err(err) = state.editor.append_history(&sh.log_file) {
+            util::print_warning(format!("unable to write history file: {err}"));
+        }
+    }
+
+    Ok(state.exit_code)
+}
+
+/// What the `worker` keeps of the `signal_handler` thread
+pub(crate) struct SignalHandler {
+    /// Adds signals to forward, e.g., for `trap`
+    pub handle: signal_hook::iterator::Handle,
+    pub interrupted: crate::worker::Interrupted,
 }
 
 /// Spawns the `signal_handler` thread
-fn spawn_signal_handler(tx: mpsc::Sender<WorkerMsg>) -> Result<(), DynError> {
+fn spawn_signal_handler(tx: mpsc::Sender<WorkerMsg>) -> Result<SignalHandler, DynError> {
     let mut signals = signal_hook::iterator::Signals::new({
         use signal_hook::consts::*;
-        &[SIGINT, SIGTSTP, SIGCHLD]
+        [SIGINT, SIGQUIT, SIGTSTP, SIGCHLD, SIGHUP, SIGTERM, SIGWINCH]
     })?;
+    let handle = signals.handle();
+    let interrupted = crate::worker::Interrupted::default();
 
+    let flag = interrupted.clone();
     thread::spawn(move || {
         for signal in signals.forever() {
-            tx.send(WorkerMsg::Signal { signal }).unwrap();
+            // a builtin waiting in the `worker` doesn't read messages
+            if signal == signal_hook::consts::SIGINT {
+                flag.store(true, Ordering::Relaxed);
+            }
+            // the `main` thread reports it if the `worker` has terminated
+            if tx.send(WorkerMsg::Signal { signal }).is_err() {
+                break;
+            }
         }
     });
 
-    Ok(())
+    Ok(SignalHandler {
+        handle,
+        interrupted,
+    })
+}
+
+/// Spawns the `timer` thread, which stops when the `worker` has terminated
+fn spawn_timer(tx: mpsc::Sender<WorkerMsg>) {
+    thread::spawn(move || loop {
+        thread::sleep(TICK_INTERVAL);
+        if tx.send(WorkerMsg::Tick).is_err() {
+            break;
+        }
+    });
 }
 
 #[derive(Debug)]
 struct State {
-    editor: rustyline::Editor<()>,
+    editor: editor::LineReader,
+    log_file: String,
+    private: bool,
+    /// Line submitted by a key binding
+    pending_line: editor::PendingLine,
+    /// Time of the last key press, for `TMOUT` in the `worker`
+    last_key: editor::LastKey,
     worker_tx: mpsc::Sender<WorkerMsg>,
+    limits: parser::Limits,
     exit_code: i32,
     last_exit_code: i32,
+    /// Number of jobs in the `worker` after the last command
+    jobs: usize,
+    /// Time the last command took
+    last_duration: Duration,
+    /// Job stopped by the last command, which is not a failure nor done
+    stopped_job: Option<usize>,
+    /// Number of command lines sent to the `worker`, which counts them as `ZEROSH_CMD_COUNT`
+    cmd_count: usize,
+    /// The standard input is a terminal. Otherwise, the lines come from a pipe or a file, where
+    /// the end is EOF and read errors exit the shell with 1.
+    terminal: bool,
+    /// On for a terminal, off for lines from a pipe or a file
+    histexpand: editor::HistExpand,
+    async_prompt: bool,
 }
 
 impl State {
-    fn create(log_file: &str, worker_tx: mpsc::Sender<WorkerMsg>) -> rustyline::Result<Self> {
-        let mut editor = rustyline::Editor::<()>::new()?;
+    /// Creates the line editor, or falls back to reading plain lines if that fails, e.g., on an
+    /// unusable terminal
+    fn create(sh: &Shell, worker_tx: mpsc::Sender<WorkerMsg>) -> Self {
+        let pending_line = editor::PendingLine::default();
+        let last_key = editor::LastKey::default();
+        let terminal = nix::unistd::isatty(libc::STDIN_FILENO).unwrap_or(false);
 
-        if let Err(err) = editor.load_history(log_file) {
-            eprintln!("unable to read history file: {err}");
-        }
+        let editor = match rustyline::Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(editor::ShellHelper {
+                    closing_hints: sh.closing_hints,
+                    history_hints: sh.history_hints,
+                    comp_specs: editor::CompSpecs::default(),
+                    var_names: Vec::new(),
+                    rprompt: None,
+                    rprompt_room: Default::default(),
+                    history_hint: Default::default(),
+                    known_commands: Default::default(),
+                }));
+                editor::bind_keys(&mut editor, &sh.bindings, &pending_line, &last_key);
 
-        Ok(Self {
+                if !sh.private {
+                    if let Err(err) = editor.load_history(&sh.log_file) {
+                        util::print_warning(format!("unable to read history file: {err}"));
+                    }
+                }
+                editor::LineReader::Editor(Box::new(editor))
+            }
+            Err(err) => {
+                util::print_warning(format!(
+                    "line editor unavailable, reading plain lines: {err}"
+                ));
+                editor::LineReader::Plain(Vec::new())
+            }
+        };
+
+        Self {
             editor,
+            log_file: sh.log_file.clone(),
+            private: sh.private,
+            pending_line,
+            last_key,
             worker_tx,
+            limits: sh.limits,
             exit_code: 0,
             last_exit_code: 0,
-        })
+            jobs: 0,
+            last_duration: Duration::ZERO,
+            stopped_job: None,
+            cmd_count: 0,
+            terminal,
+            histexpand: Arc::new(AtomicBool::new(terminal)),
+            async_prompt: sh.async_prompt,
+        }
     }
 
+    /// Renders the `ZEROSH_PROMPT` template (or [`DEFAULT_PROMPT`]). The `worker` has run
+    /// `PROMPT_COMMAND` before it.
     fn prompt(&self) -> String {
-        let face = if self.last_exit_code == 0 {
-            '\u{1F642}'
-        } else {
-            '\u{1F480}'
+        let template = env::var("ZEROSH_PROMPT").unwrap_or_else(|_| DEFAULT_PROMPT.to_string());
+        self.render_prompt(&template)
+    }
+
+    /// Renders the `ZEROSH_RPROMPT` template, which is shown at the right end of the input line.
+    /// `None` if it's not set or empty.
+    ///
+    /// With the asynchronous prompt, the git branch is found by a thread. It's waited for
+    /// [`ASYNC_PROMPT_WAIT`], and then the line editor shows a placeholder until the thread redraws
+    /// the prompt.
+    fn rprompt(&mut self) -> Option<editor::Rprompt> {
+        let template = env::var("ZEROSH_RPROMPT").ok()?;
+        let printer = match &mut self.editor {
+            editor::LineReader::Editor(editor) if self.async_prompt => {
+                editor.create_external_printer().ok()
+            }
+            _ => None,
         };
+        if let Some(mut printer) = printer {
+            let rendered = self.render_prompt_with(&template, || editor::ASYNC_SEGMENT.to_string());
+            let rprompt = Arc::new(editor::AsyncRprompt::new(rendered));
+            if rprompt.needs_segment() {
+                let found = Arc::clone(&rprompt);
+                thread::spawn(move || found.set_segment(self::git_segment(), &mut printer));
+                rprompt.wait(ASYNC_PROMPT_WAIT);
+                return Some(editor::Rprompt::Async(rprompt));
+            }
+        }
 
-        format!("ZeroSh {face} %>")
+        let rprompt = self.render_prompt(&template);
+        (!rprompt.is_empty()).then_some(editor::Rprompt::Text(rprompt))
+    }
+
+    /// Renders a prompt template. Escapes:
+    ///
+    /// - `\F`: face showing if the last command succeeded, or sleeping if it was stopped
+    /// - `\w`: current directory, `$HOME` collapsed to `~` and shortened to the last
+    ///   `ZEROSH_PROMPT_DIR_DEPTH` components (if set)
+    /// - `\W`: base name of the current directory
+    /// - `\P`: lock (or `(private)`) and a space in the private mode, empty otherwise
+    /// - `\s`: nesting level of the shell (`SHLVL`) in parentheses and a space if it's in another
+    ///   shell, empty otherwise
+    /// - `\S`: host name after `@` and a space over SSH (`SSH_CONNECTION` is set), empty
+    ///   otherwise
+    /// - `\j`: number of background and stopped jobs in brackets and a space, empty if none
+    /// - `\g`: git branch of the current directory and a space, empty outside a repository
+    /// - `\T`: time the last command took and a space, empty if it was shorter than
+    ///   [`PROMPT_DURATION_MIN`] or stopped
+    /// - `\?`: exit code of the last command
+    /// - `\#`: number of the next command line, counted as `ZEROSH_CMD_COUNT`
+    /// - `\t`: current time as `HH:MM:SS`
+    /// - `\\`: backslash
+    ///
+    /// Some terminals miscount the width of the emoji faces, which breaks line wrapping. The prompt
+    /// is kept ASCII-only if [`util::ascii_only`].
+    fn render_prompt(&self, template: &str) -> String {
+        self.render_prompt_with(template, self::git_segment)
+    }
+
+    /// Renders a prompt template with `git` rendering `\g`, e.g., a placeholder
+    fn render_prompt_with(&self, template: &str, git: impl Fn() -> String) -> String {
+        let ascii = util::ascii_only();
+        let mut out = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('F') => out.push_str(self.face(ascii)),
+                Some('w') => out.push_str(&self::prompt_cwd(ascii)),
+                Some('W') => {
+                    let cwd = self::prompt_cwd(ascii);
+                    match cwd.rsplit_once('/') {
+                        Some((_, base)) if !base.is_empty() => out.push_str(base),
+                        _ => out.push_str(&cwd),
+                    }
+                }
+                Some('P') if self.private => {
+                    out.push_str(if ascii { "(private) " } else { "\u{1F512} " })
+                }
+                Some('P') => {}
+                Some('s') => {
+                    let level = env::var("SHLVL").ok().and_then(|l| l.parse::<u32>().ok());
+                    if let Some(level) = level.filter(|&level| level > 1) {
+                        out.push_str(&format!("({level}) "));
+                    }
+                }
+                Some('S') if env::var_os("SSH_CONNECTION").is_some() => {
+                    let host = nix::unistd::gethostname().unwrap_or_default();
+                    out.push_str(&format!("@{} ", host.to_string_lossy()));
+                }
+                Some('S') => {}
+                Some('j') if self.jobs > 0 => out.push_str(&format!("[{}] ", self.jobs)),
+                Some('j') => {}
+                Some('g') => out.push_str(&git()),
+                Some('T')
+                    if self.last_duration >= PROMPT_DURATION_MIN && self.stopped_job.is_none() =>
+                {
+                    out.push_str(&self::format_duration(self.last_duration));
+                    out.push(' ');
+                }
+                Some('T') => {}
+                Some('?') => out.push_str(&self.last_exit_code.to_string()),
+                Some('#') => out.push_str(&(self.cmd_count + 1).to_string()),
+                Some('t') => out.push_str(&self::clock()),
+                Some('\\') => out.push('\\'),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push('\\'),
+            }
+        }
+
+        out
+    }
+
+    fn face(&self, ascii: bool) -> &'static str {
+        if self.stopped_job.is_some() {
+            return if ascii { ":z" } else { "\u{1F634}" };
+        }
+        match (self.last_exit_code == 0, ascii) {
+            (true, false) => "\u{1F642}",
+            (false, false) => "\u{1F480}",
+            (true, true) => ":)",
+            (false, true) => "x(",
+        }
+    }
+}
+
+/// Prompt template used when `ZEROSH_PROMPT` is not set. See [`State::prompt`] for the escapes.
+const DEFAULT_PROMPT: &str = "ZeroSh \\F \\P\\S\\j\\w %>";
+
+/// How long the prompt waits for the git branch of the asynchronous prompt before it shows a
+/// placeholder
+const ASYNC_PROMPT_WAIT: Duration = Duration::from_millis(50);
+
+/// Git branch and a space for `\g` in the prompt, empty outside a repository
+fn git_segment() -> String {
+    let branch = util::logical_cwd()
+        .ok()
+        .and_then(|cwd| util::git_branch(&cwd));
+    branch
+        .map(|branch| format!("{branch} "))
+        .unwrap_or_default()
+}
+
+/// Shortest duration of a command shown by `\T` in the prompt
+const PROMPT_DURATION_MIN: Duration = Duration::from_secs(2);
+
+/// Formats a duration for the prompt, e.g., `2.5s`, `3m04s` or `1h02m`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// Local time for the prompt as `HH:MM:SS`
+fn clock() -> String {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return "??:??:??".to_string();
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+/// Current directory for the prompt
+fn prompt_cwd(ascii: bool) -> String {
+    match util::logical_cwd() {
+        Ok(cwd) => {
+            let home = env::var_os("HOME").map(std::path::PathBuf::from);
+            let path = util::display_path(&cwd, home.as_deref());
+
+            let depth = env::var("ZEROSH_PROMPT_DIR_DEPTH")
+                .ok()
+                .and_then(|depth| depth.parse().ok())
+                .unwrap_or(0);
+            let ellipsis = if ascii { "..." } else { "…" };
+            util::truncate_path(&path, depth, ellipsis)
+        }
+        Err(_) => "?".to_string(),
     }
 }
 
 /// Returns `Ok(true)` if the shell can continue processing
 fn process(
     state: &mut State,
-    sh: &Shell,
     shell_rx: &mut mpsc::Receiver<ShellMsg>,
 ) -> Result<ControlFlow<()>, DynError> {
     let prompt = state.prompt();
+    let rprompt = state.rprompt();
+    if let Some(helper) = state.editor.helper_mut() {
+        helper.rprompt = rprompt;
+        // commands may have been installed or `PATH` changed
+        helper.known_commands.get_mut().clear();
+    }
 
     // TODO: Allow multiline input (?)
     use rustyline::error::ReadlineError;
     use ControlFlow::*;
 
-    let line = match state.editor.readline(&prompt) {
-        Ok(line) => line,
+    let line = state.editor.readline(&prompt);
+    if let Some(helper) = state.editor.helper_mut() {
+        helper.finish_rprompt();
+    }
+    let line = match line {
+        Ok(line) => match state.pending_line.lock().unwrap().take() {
+            Some(pending) => pending,
+            None => line,
+        },
         Err(ReadlineError::Interrupted) => {
-            eprintln!("ZeroSh: you can exit with `Ctrl+d`");
+            util::print_warning("you can exit with `Ctrl+d`");
             return Ok(Continue(()));
         }
         Err(ReadlineError::Eof) => {
-            state.worker_tx.send(WorkerMsg::Cmd {
-                cmd: "exit".to_string(),
-            })?;
-
-            match shell_rx.recv()? {
-                ShellMsg::Quit { code } => {
-                    state.exit_code = code;
-                    return Ok(Break(()));
-                }
-                _ => panic!("failed to exit")
+            // `exit` without the history, which may not quit, e.g., if there are stopped jobs
+            state.cmd_count += 1;
+            return Ok(self::send_cmd(state, shell_rx, "exit".to_string()));
+        }
+        // a pipe or a file has no hangup, so it's an error like others
+        Err(err) if state.terminal && self::is_terminal_lost(&err) => {
+            // e.g., the SSH session dropped. The history is saved after each command.
+            state.exit_code = 128 + libc::SIGHUP;
+            if state.worker_tx.send(WorkerMsg::Hangup).is_ok() {
+                // the `worker` runs the `EXIT` trap and exits the process
+                while shell_rx.recv().is_ok() {}
             }
+            return Ok(Break(()));
         }
         Err(err) => {
-            eprintln!("ZeroSh: read error\n{err}");
+            util::print_error(format!("read error\n{err}"));
             state.exit_code = 1;
             return Ok(Break(()));
         }
     };
 
-    let line = line.trim();
+    let mut line = line.trim().to_string();
     if line.is_empty() {
+        return Ok(self::send_cmd(state, shell_rx, line));
+    }
+    // rejected by the `worker` as it parses the line, which sets `$?`, but kept out of the history
+    if parser::check_line_len(&line, state.limits).is_err() {
+        state.cmd_count += 1;
+        return Ok(self::send_cmd(state, shell_rx, line));
+    }
+
+    // History lives on the `main` thread, so history references are expanded here. The `worker`
+    // only sees expanded lines.
+    let expanded = if !state.histexpand.load(Ordering::Relaxed) {
+        Ok(None)
+    } else if line.starts_with('^') {
+        let prev = state.editor.history().last().copied();
+        parser::expand_quick_substitution(&line, prev)
+    } else if line.contains('!') {
+        parser::expand_history(&line, &state.editor.history())
+    } else {
+        Ok(None)
+    };
+
+    match expanded {
+        Ok(Some(expanded)) => {
+            // echo like bash
+            println!("{expanded}");
+            line = expanded;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            util::print_error(err);
+            state.last_exit_code = 1;
+            return Ok(Continue(()));
+        }
+    }
+
+    // `fc` needs the history as well. The edited lines are run instead of the `fc` line.
+    if let Some(args) = self::fc_args(&line, state.limits) {
+        let lines = history::fc(&args, &state.editor.history());
+
+        let lines = match lines {
+            Ok(lines) => lines,
+            Err(err) => {
+                util::print_error(format!("fc: {err}"));
+                state.last_exit_code = 1;
+                return Ok(Continue(()));
+            }
+        };
+
+        state.last_exit_code = 0;
+        for line in lines {
+            println!("{line}");
+            if self::run_line(state, shell_rx, line)?.is_break() {
+                return Ok(Break(()));
+            }
+        }
+
         return Ok(Continue(()));
     }
-    state.editor.add_history_entry(line);
 
-    state.worker_tx.send(WorkerMsg::Cmd {
-        cmd: line.to_string(),
-    })?;
+    self::run_line(state, shell_rx, line)
+}
 
-    match shell_rx.recv()? {
-        ShellMsg::Continue { code } => {
-            state.last_exit_code = code;
-            Ok(Continue(()))
+/// Returns true if the error means the terminal is gone, so reading it again would fail forever
+fn is_terminal_lost(err: &rustyline::error::ReadlineError) -> bool {
+    use rustyline::error::ReadlineError;
+
+    let errno = match err {
+        ReadlineError::Io(err) => err.raw_os_error(),
+        ReadlineError::Errno(errno) => Some(*errno as i32),
+        _ => None,
+    };
+    matches!(errno, Some(libc::EIO | libc::EBADF | libc::ENXIO))
+}
+
+/// Returns the arguments if the line is a single `fc` command
+fn fc_args(line: &str, limits: parser::Limits) -> Option<Vec<String>> {
+    let list = parser::parse(line, limits).ok()??;
+    let [(_, pipeline)] = &list.pipelines[..] else {
+        return None;
+    };
+    match &pipeline.commands[..] {
+        [cmd]
+            if !pipeline.background
+                && cmd.redirects.is_empty()
+                && cmd
+                    .words
+                    .first()
+                    .is_some_and(|word| expand::unquote(word) == "fc") =>
+        {
+            Some(cmd.words.iter().map(|w| expand::unquote(w)).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Adds the line to the history (unless in the private mode) and runs it on the `worker` thread
+fn run_line(
+    state: &mut State,
+    shell_rx: &mut mpsc::Receiver<ShellMsg>,
+    line: String,
+) -> Result<ControlFlow<()>, DynError> {
+    if !state.private {
+        state.editor.add_history_entry(line.as_str());
+        // the shell may be killed while reading the next line (`SIGHUP` or `SIGTERM`), so save
+        // the history now. A failure is reported on exit.
+        let _ = state.editor.append_history(&state.log_file);
+    }
+
+    state.cmd_count += 1;
+    state.stopped_job = None;
+    Ok(self::send_cmd(state, shell_rx, line))
+}
+
+/// Runs a command line on the `worker` thread and waits for it. An empty line only reports the
+/// jobs and runs `PROMPT_COMMAND` for the next prompt.
+fn send_cmd(
+    state: &mut State,
+    shell_rx: &mut mpsc::Receiver<ShellMsg>,
+    cmd: String,
+) -> ControlFlow<()> {
+    use ControlFlow::*;
+
+    if state.worker_tx.send(WorkerMsg::Cmd { cmd }).is_err() {
+        return self::worker_terminated(state);
+    }
+
+    loop {
+        let Ok(msg) = shell_rx.recv() else {
+            return self::worker_terminated(state);
+        };
+        match msg {
+            ShellMsg::Continue {
+                code,
+                jobs,
+                duration,
+            } => {
+                state.last_exit_code = code;
+                state.jobs = jobs;
+                state.last_duration = duration;
+                return Continue(());
+            }
+            ShellMsg::Quit { code } => {
+                state.exit_code = code;
+                return Break(());
+            }
+            ShellMsg::SetEditMode { mode } => state.editor.set_edit_mode(mode),
+            ShellMsg::SetVarNames { names } => {
+                if let Some(helper) = state.editor.helper_mut() {
+                    helper.var_names = names;
+                }
+            }
+            ShellMsg::Stopped { job_id } => state.stopped_job = Some(job_id),
         }
-        ShellMsg::Quit { code } => {
-            state.exit_code = code;
-            Ok(Break(()))
+    }
+}
+
+/// Reports that the `worker` thread has terminated, e.g., by a panic, and quits the shell. The
+/// history is saved on the way.
+fn worker_terminated(state: &mut State) -> ControlFlow<()> {
+    util::print_error("worker thread terminated");
+    state.exit_code = EXIT_WORKER_TERMINATED;
+    ControlFlow::Break(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates the state of the `main` thread with the receiver of the `worker`
+    fn state() -> (State, mpsc::Receiver<WorkerMsg>) {
+        let sh = Shell::builder().private(true).build().unwrap();
+        let (worker_tx, worker_rx) = mpsc::channel();
+        (State::create(&sh, worker_tx), worker_rx)
+    }
+
+    #[test]
+    fn faces_take_two_columns_or_are_ascii() {
+        let (mut state, _) = state();
+        for (code, stopped) in [(0, None), (1, None), (0, Some(1))] {
+            state.last_exit_code = code;
+            state.stopped_job = stopped;
+            assert_eq!(util::display_width(state.face(false)), 2);
+            assert!(state.face(true).is_ascii());
+            assert_eq!(util::display_width(state.face(true)), 2);
         }
     }
+
+    #[test]
+    fn main_loop_quits_if_the_worker_is_gone() {
+        let (mut state, worker_rx) = state();
+        drop(worker_rx);
+        let (_, mut shell_rx) = mpsc::channel();
+        let flow = run_line(&mut state, &mut shell_rx, "echo".to_string()).unwrap();
+        assert!(flow.is_break());
+        assert_eq!(state.exit_code, EXIT_WORKER_TERMINATED);
+    }
+
+    #[test]
+    fn main_loop_quits_if_the_worker_terminates_while_running_a_line() {
+        let (mut state, worker_rx) = state();
+        let (shell_tx, mut shell_rx) = mpsc::channel();
+        // the `worker` takes the line and terminates without an answer
+        let worker = thread::spawn(move || {
+            let msg = worker_rx.recv().unwrap();
+            drop(shell_tx);
+            msg
+        });
+
+        let flow = run_line(&mut state, &mut shell_rx, "echo".to_string()).unwrap();
+        assert!(flow.is_break());
+        assert_eq!(state.exit_code, EXIT_WORKER_TERMINATED);
+        assert!(matches!(worker.join().unwrap(), WorkerMsg::Cmd { cmd } if cmd == "echo"));
+    }
 }
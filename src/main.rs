@@ -1,5 +1,59 @@
 //! Zero Shell
+//!
+//! `zerosh` starts an interactive shell, and `zerosh -c COMMAND` runs the command line once.
+//! `zerosh -l` (or `--login`, or a name starting with `-` as login programs give) is a login shell.
+//! `--norc` skips `~/.zeroshrc` of the interactive shell, and `--rcfile PATH` runs the file
+//! instead. `--version` prints the version.
+
+use std::{env, process};
 
 fn main() {
-    println!("Hello, world!");
+    let mut args = env::args().collect::<Vec<_>>();
+
+    let login = args.first().is_some_and(|name| name.starts_with('-'));
+    let mut builder = zerosh::Shell::builder().login(login);
+    while let Some(arg) = args.get(1) {
+        match arg.as_str() {
+            "-l" | "--login" => builder = builder.login(true),
+            "--norc" => builder = builder.norc(true),
+            "--version" => {
+                println!("ZeroSh {}", zerosh::Shell::version());
+                return;
+            }
+            "--rcfile" => {
+                let Some(path) = args.get(2) else {
+                    eprintln!("ZeroSh: --rcfile: option requires an argument");
+                    process::exit(2);
+                };
+                builder = builder.rc_file(path);
+                args.remove(2);
+            }
+            _ => break,
+        }
+        args.remove(1);
+    }
+
+    let sh = match builder.build() {
+        Ok(sh) => sh,
+        Err(err) => {
+            eprintln!("ZeroSh: {err}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(flag @ ("-c" | "--command")) = args.get(1).map(String::as_str) {
+        let Some(cmd) = args.get(2) else {
+            eprintln!("ZeroSh: {flag}: option requires an argument");
+            process::exit(2);
+        };
+        process::exit(sh.run_c(cmd));
+    }
+
+    match zerosh::run_shell_with_code(&sh) {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("ZeroSh: {err}");
+            process::exit(1);
+        }
+    }
 }
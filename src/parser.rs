@@ -0,0 +1,1535 @@
+//! Command line parser
+//!
+//! Words are kept in their raw form (quotes included) and processed later by the
+//! [`expand`](crate::expand) stage. Only line continuations (backslash-newline) are removed by the
+//! tokenizer, e.g., in a pasted command line.
+//!
+//! Commands are parsed by recursive descent, as compound commands (`{ LIST; }`, `( LIST )`, `case`,
+//! `if`, `while` and `until`) have lists inside. `!`, `{`, `}`, `case`, `esac`, `if`, `then`,
+//! `elif`, `else`, `fi`, `while`, `until`, `do`, `done` and `function` are reserved words, which
+//! are recognized only where a command may start, so `echo }` prints `}`.
+//!
+//! `#` at the start of a word begins a comment until the end of the line, so `echo a#b` prints
+//! `a#b` but `echo a #b` prints `a`.
+//!
+//! A newline separates commands like `;`, and more newlines may follow wherever a command is
+//! expected, e.g., in a function defined over lines of a file.
+
+use std::{collections::BTreeMap, fmt};
+
+/// Error on parsing a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub msg: String,
+    /// Byte offset in the input where the error was found
+    pub pos: usize,
+}
+
+impl ParseError {
+    fn new(msg: impl Into<String>, pos: usize) -> Self {
+        Self {
+            msg: msg.into(),
+            pos,
+        }
+    }
+
+    /// Whether the input ends in an unterminated quote, expansion or compound command (or after
+    /// `|`, `&&` or `||`), which more input could complete
+    pub fn is_unterminated(&self) -> bool {
+        self.msg.starts_with("unterminated")
+            || self.msg.starts_with("syntax error: unexpected end of line")
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Class of a [`Token`]. More kinds may be added as the syntax grows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenKind {
+    /// Raw word with quotes
+    Word(String),
+    /// `|`
+    Pipe,
+    /// `&`
+    Background,
+    /// `;`
+    Semi,
+    /// Newline outside quotes, which separates commands like `;`
+    Newline,
+    /// `;;`, which ends a branch of `case`
+    DoubleSemi,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `<`, `>`, `>>` or `>|`, with the file descriptor number before it if any
+    Redirect(RedirectKind, Option<i32>),
+}
+
+/// Token of a command line with its position, e.g., for syntax highlighting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Byte range in the input
+    pub span: (usize, usize),
+}
+
+/// Splits a command line into tokens. Fails on unterminated quotes or expansions, where the
+/// error position is the start of the unterminated part.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c == '\n' {
+            chars.next();
+            // blank lines make one separator
+            if tokens.last().map(|token: &Token| &token.kind) != Some(&TokenKind::Newline) {
+                tokens.push(Token {
+                    kind: TokenKind::Newline,
+                    span: (start, start + 1),
+                });
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        // comment until the end of the line, at the start of a word
+        if c == '#' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            continue;
+        }
+        // line continuation between words
+        if input[start..].starts_with("\\\n") {
+            chars.next();
+            chars.next();
+            continue;
+        }
+
+        let op = match (c, input[start + 1..].chars().next()) {
+            ('|', Some('|')) => Some((TokenKind::Or, 2)),
+            ('&', Some('&')) => Some((TokenKind::And, 2)),
+            ('|', _) => Some((TokenKind::Pipe, 1)),
+            ('&', _) => Some((TokenKind::Background, 1)),
+            (';', Some(';')) => Some((TokenKind::DoubleSemi, 2)),
+            (';', _) => Some((TokenKind::Semi, 1)),
+            ('(', _) => Some((TokenKind::LParen, 1)),
+            (')', _) => Some((TokenKind::RParen, 1)),
+            ('>', Some('>')) => Some((TokenKind::Redirect(RedirectKind::Append, None), 2)),
+            ('>', Some('|')) => Some((TokenKind::Redirect(RedirectKind::Clobber, None), 2)),
+            ('>', Some('&')) => Some((TokenKind::Redirect(RedirectKind::DupOutput, None), 2)),
+            ('>', _) => Some((TokenKind::Redirect(RedirectKind::Output, None), 1)),
+            ('<', Some('&')) => Some((TokenKind::Redirect(RedirectKind::DupInput, None), 2)),
+            ('<', _) => Some((TokenKind::Redirect(RedirectKind::Input, None), 1)),
+            _ => None,
+        };
+
+        if let Some((mut kind, len)) = op {
+            for _ in 0..len {
+                chars.next();
+            }
+
+            // digits right before a redirection are the file descriptor, e.g., `2>`
+            let (mut start, end) = (start, start + len);
+            if let TokenKind::Redirect(_, fd) = &mut kind {
+                if let Some(Token {
+                    kind: TokenKind::Word(word),
+                    span,
+                }) = tokens.last()
+                {
+                    if span.1 == start && word.bytes().all(|b| b.is_ascii_digit()) {
+                        let Ok(n) = word.parse() else {
+                            return Err(ParseError::new(
+                                format!("{word}: file descriptor out of range"),
+                                span.0,
+                            ));
+                        };
+                        *fd = Some(n);
+                        start = span.0;
+                        tokens.pop();
+                    }
+                }
+            }
+
+            tokens.push(Token {
+                kind,
+                span: (start, end),
+            });
+            continue;
+        }
+
+        // word
+        let mut end = start;
+        // offsets of line continuations (backslash-newline) in the word
+        let mut joins = Vec::new();
+        while let Some(&(i, c)) = chars.peek() {
+            let array = c == '(' && input[start..i].strip_suffix('=').is_some_and(self::is_name);
+            if c.is_whitespace() || "|&;<>)".contains(c) || (c == '(' && !array) {
+                break;
+            }
+
+            chars.next();
+            end = i + c.len_utf8();
+
+            match c {
+                '\\' => {
+                    if let Some((j, c)) = chars.next() {
+                        end = j + c.len_utf8();
+                        if c == '\n' {
+                            joins.push(i);
+                        }
+                    }
+                }
+                '\'' => loop {
+                    match chars.next() {
+                        Some((i, '\'')) => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some(_) => {}
+                        None => return Err(ParseError::new("unterminated single quote", i)),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some((i, '"')) => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some((j, '\\')) => {
+                            if let Some((_, '\n')) = chars.next() {
+                                joins.push(j);
+                            }
+                        }
+                        Some((j, '$')) if input[j + 1..].starts_with(['(', '{']) => {
+                            self::skip_expansion(input, j, &mut chars, &mut joins)?;
+                        }
+                        Some(_) => {}
+                        None => return Err(ParseError::new("unterminated double quote", i)),
+                    }
+                },
+                '$' if input[i + 1..].starts_with(['(', '{']) => {
+                    end = self::skip_expansion(input, i, &mut chars, &mut joins)?;
+                }
+                // array assignment `NAME=(...)`, kept in one word
+                '(' => {
+                    let len = self::closing_len(&input[i + 1..], '(', ')')
+                        .ok_or_else(|| ParseError::new("unterminated array assignment", i))?;
+                    end = i + 1 + len + 1;
+                    while chars.next_if(|&(j, _)| j < end).is_some() {}
+                }
+                _ => {}
+            }
+        }
+
+        tokens.push(Token {
+            kind: TokenKind::Word(self::join_lines(&input[start..end], start, &joins)),
+            span: (start, end),
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Removes the line continuations from the text of a word starting at `offset`. Backslash-newline
+/// joins lines outside quotes and in double quotes, but it's literal in single quotes.
+fn join_lines(word: &str, offset: usize, joins: &[usize]) -> String {
+    let mut text = String::with_capacity(word.len());
+    let mut rest = 0;
+    for &join in joins {
+        text.push_str(&word[rest..join - offset]);
+        rest = join - offset + 2;
+    }
+    text.push_str(&word[rest..]);
+    text
+}
+
+/// Consumes `$(...)` or `${...}` starting at `start` and returns the end offset. The line
+/// continuations in `${...}` are added to `joins`.
+fn skip_expansion(
+    input: &str,
+    start: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    joins: &mut Vec<usize>,
+) -> Result<usize, ParseError> {
+    let body = start + 2;
+    let len = if input[start + 1..].starts_with('(') {
+        // the command is tokenized again when it's run
+        self::subst_len(&input[body..])
+            .ok_or_else(|| ParseError::new("unterminated command substitution", start))?
+    } else {
+        let len = self::param_len(&input[body..])
+            .ok_or_else(|| ParseError::new("unterminated parameter expansion", start))?;
+        self::param_joins(&input[body..body + len], body, joins);
+        len
+    };
+
+    let end = body + len + 1;
+    while chars.next_if(|&(i, _)| i < end).is_some() {}
+    Ok(end)
+}
+
+/// Adds the offsets of the line continuations in the body of `${...}` at `offset`, which are not in
+/// single quotes
+fn param_joins(body: &str, offset: usize, joins: &mut Vec<usize>) {
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, '\n')) = chars.next() {
+                    joins.push(offset + i);
+                }
+            }
+            '\'' => {
+                chars.find(|&(_, c)| c == '\'');
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the length of the command in a command substitution, i.e., the offset of the `)` that
+/// closes `$(`. `body` is the text after `$(`.
+pub(crate) fn subst_len(body: &str) -> Option<usize> {
+    self::closing_len(body, '(', ')')
+}
+
+/// Returns the length of a parameter expansion, i.e., the offset of the `}` that closes `${`.
+/// `body` is the text after `${`.
+pub(crate) fn param_len(body: &str) -> Option<usize> {
+    self::closing_len(body, '{', '}')
+}
+
+fn closing_len(body: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = body.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' => {
+                chars.find(|&(_, c)| c == '\'')?;
+            }
+            '"' => loop {
+                match chars.next()? {
+                    (_, '"') => break,
+                    (_, '\\') => {
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            },
+            c if c == open => depth += 1,
+            c if c == close && depth == 0 => return Some(i),
+            c if c == close => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Returns `true` if the word can be the name of a function: a word without quotes or expansions,
+/// which may have `-`, `.` and `:` unlike a variable name, e.g., `git-branch`
+fn is_function_name(word: &str) -> bool {
+    !word.is_empty()
+        && !word.bytes().all(|b| b.is_ascii_digit())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.:".contains(c))
+}
+
+/// Returns `true` if the name can be a variable name
+pub(crate) fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a raw word into the name and the raw value if it's an assignment `NAME=VALUE`
+pub(crate) fn assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    self::is_name(name).then_some((name, value))
+}
+
+/// Returns the raw words of an array assignment value `(WORD...)`, or `None` if the value is not
+/// an array
+pub(crate) fn array_words(value: &str) -> Option<Result<Vec<String>, ParseError>> {
+    let list = value.strip_prefix('(')?.strip_suffix(')')?;
+
+    let words = self::tokenize(list).and_then(|tokens| {
+        tokens
+            .into_iter()
+            .map(|token| match token.kind {
+                TokenKind::Word(word) => Ok(word),
+                _ => {
+                    let op = &list[token.span.0..token.span.1];
+                    Err(ParseError::new(
+                        format!("syntax error near `{op}` in an array assignment"),
+                        token.span.0 + 1,
+                    ))
+                }
+            })
+            .collect()
+    });
+    Some(words)
+}
+
+/// Commands connected with pipes. Built by hand, e.g., by programs embedding the shell, `text` is
+/// only the name of the job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Run in background (`&`)
+    pub background: bool,
+    /// `!` before the pipeline inverts the exit code
+    pub negated: bool,
+    /// `time` before the pipeline reports the time it took. The commands may be empty then.
+    pub time: Option<TimeFormat>,
+    /// Source text without the trailing `&`, used as the job name
+    pub text: String,
+}
+
+/// How `time` reports the time of a pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// The format of `TIMEFORMAT`, or the default of bash
+    Bash,
+    /// `time -p`: the format of POSIX
+    Posix,
+}
+
+/// Simple command, or a compound command with its redirections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// Raw words; the first one is the command name. Empty if the command has only redirections
+    /// or it's a compound command.
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+    pub compound: Option<Compound>,
+}
+
+/// Command made of other commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compound {
+    /// `{ LIST; }`: run in the shell itself
+    Group(List),
+    /// `( LIST )`: run in a forked child, so that changes to the shell don't leak
+    Subshell(List),
+    /// `case WORD in PATTERN) LIST;; ... esac`: runs the first branch matching the raw word
+    Case { word: String, items: Vec<CaseItem> },
+    /// `if LIST; then LIST; [elif LIST; then LIST;]... [else LIST;] fi`: runs the body of the first
+    /// condition that succeeds, or the `else` branch
+    If {
+        branches: Vec<IfBranch>,
+        otherwise: Option<List>,
+    },
+    /// `while LIST; do LIST; done`: runs the body as long as the condition succeeds, or as long as
+    /// it fails with `until`
+    While {
+        condition: List,
+        body: List,
+        until: bool,
+    },
+    /// `NAME() COMMAND` or `function NAME COMMAND`: defines a function whose body is the compound
+    /// command with its redirections. `text` is the source text of the definition.
+    Function {
+        name: String,
+        body: Box<Command>,
+        text: String,
+    },
+}
+
+/// Branch of `case`: `[(]PATTERN[|PATTERN...]) LIST;;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    /// Raw words of the glob patterns
+    pub patterns: Vec<String>,
+    /// Commands of the branch, which may be empty
+    pub body: List,
+}
+
+/// Branch of `if` or `elif`: `LIST; then LIST;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfBranch {
+    /// And-or list whose exit code decides the branch
+    pub condition: List,
+    pub body: List,
+}
+
+/// How a redirection opens the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `<`: read
+    Input,
+    /// `>`: truncate or create, unless `noclobber` is on and the file exists
+    Output,
+    /// `>|`: truncate or create even if `noclobber` is on
+    Clobber,
+    /// `>>`: append or create
+    Append,
+    /// `>&`: duplicate a file descriptor (the target), or close it with `-`
+    DupOutput,
+    /// `<&`: same as [`DupOutput`](Self::DupOutput) but defaults to the standard input
+    DupInput,
+}
+
+/// Redirection of a file descriptor to a file or another file descriptor, e.g., `2> FILE` or
+/// `2>&1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    /// Raw word of the file name, or of the file descriptor for `>&` and `<&`
+    pub target: String,
+}
+
+/// How a pipeline is connected to the previous one in a [`List`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `;` or `&`: always run
+    Seq,
+    /// `&&`: run if the previous one succeeded
+    And,
+    /// `||`: run if the previous one failed
+    Or,
+}
+
+/// Pipelines connected with `;`, `&`, `&&` and `||`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct List {
+    /// The first connector is [`Connector::Seq`]
+    pub pipelines: Vec<(Connector, Pipeline)>,
+}
+
+/// Syntax tree of a command line, which [`Shell::execute`](crate::Shell::execute) runs
+pub type Ast = List;
+
+/// Limits on the size of a command line and the nesting of `source`, against resource
+/// exhaustion from pathological input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limits {
+    /// Maximum number of commands in a pipeline
+    pub pipeline_len: usize,
+    /// Maximum number of words in a command line
+    pub words: usize,
+    /// Maximum number of files run by `source` inside each other, e.g., a file sourcing itself
+    pub source_depth: usize,
+    /// Maximum number of function calls inside each other, e.g., a function calling itself
+    pub func_depth: usize,
+    /// Maximum length of a command line in bytes, e.g., a pasted file
+    pub line_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            pipeline_len: 1024,
+            words: 65536,
+            source_depth: 100,
+            func_depth: 200,
+            line_len: 1 << 20,
+        }
+    }
+}
+
+/// Rejects a command line longer than the limit before anything else looks at it
+pub(crate) fn check_line_len(input: &str, limits: Limits) -> Result<(), ParseError> {
+    if input.len() > limits.line_len {
+        return Err(ParseError::new(
+            format!(
+                "command line too long (more than {} bytes)",
+                limits.line_len
+            ),
+            limits.line_len,
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a command line. Returns `None` if the line is empty.
+pub(crate) fn parse(input: &str, limits: Limits) -> Result<Option<List>, ParseError> {
+    self::check_line_len(input, limits)?;
+    let tokens = self::tokenize(input)?;
+    if tokens.iter().all(|token| token.kind == TokenKind::Newline) {
+        return Ok(None);
+    }
+
+    let mut parser = Parser {
+        input,
+        tokens,
+        pos: 0,
+        last: (0, 0),
+        limits,
+        n_words: 0,
+    };
+    let list = parser.list()?;
+
+    // e.g., `}` or `)` without the opening one
+    if let Some(token) = parser.next() {
+        return Err(parser.unexpected(&token));
+    }
+
+    Ok(Some(list))
+}
+
+/// Recursive descent parser over the tokens of a command line
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    /// Index of the next token
+    pos: usize,
+    /// Span of the last consumed token
+    last: (usize, usize),
+    limits: Limits,
+    /// Number of words so far, against [`Limits::words`]
+    n_words: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        self.last = token.span;
+        Some(token)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    /// Returns `true` if the next token is a word that is exactly the reserved word
+    fn peek_reserved(&mut self, reserved: &str) -> bool {
+        matches!(self.peek_kind(), Some(TokenKind::Word(word)) if word == reserved)
+    }
+
+    /// Returns the kind of the token after the next one
+    fn peek_second_kind(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos + 1).map(|token| &token.kind)
+    }
+
+    /// Skips newlines where a command may follow, e.g., after `&&` or `{`
+    fn skip_newlines(&mut self) {
+        while let Some(TokenKind::Newline) = self.peek_kind() {
+            self.next();
+        }
+    }
+
+    fn unexpected(&self, token: &Token) -> ParseError {
+        let op = &self.input[token.span.0..token.span.1];
+        ParseError::new(
+            format!("syntax error near unexpected token `{op}`"),
+            token.span.0,
+        )
+    }
+
+    /// Error on the end of the line after the last consumed token
+    fn unexpected_end(&self) -> ParseError {
+        let last = &self.input[self.last.0..self.last.1];
+        ParseError::new(
+            format!("syntax error: unexpected end of line after `{last}`"),
+            self.input.len(),
+        )
+    }
+
+    /// Error on the next token, or on the end of the line
+    fn unexpected_next(&mut self) -> ParseError {
+        match self.peek().cloned() {
+            Some(token) => self.unexpected(&token),
+            None => self.unexpected_end(),
+        }
+    }
+
+    /// Returns `true` if the next token ends the list of a compound command, e.g., `}`, `)`, `;;`,
+    /// `then` or `done`, or the end of the line. Newlines before it are skipped.
+    fn at_list_end(&mut self) -> bool {
+        self.skip_newlines();
+        match self.peek_kind() {
+            None | Some(TokenKind::RParen | TokenKind::DoubleSemi) => true,
+            Some(TokenKind::Word(word)) => matches!(
+                word.as_str(),
+                "}" | "esac" | "then" | "elif" | "else" | "fi" | "do" | "done"
+            ),
+            _ => false,
+        }
+    }
+
+    /// Parses pipelines connected with `;`, `&`, `&&` and `||`, until the end of the line or the
+    /// end of a compound command
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut pipelines = Vec::new();
+        let mut connector = Connector::Seq;
+
+        loop {
+            if self.at_list_end() {
+                // `;` and `&` may end a list, but `&&` and `||` need a pipeline after them
+                if pipelines.is_empty() || connector != Connector::Seq {
+                    return Err(self.unexpected_next());
+                }
+                break;
+            }
+
+            let mut pipeline = self.pipeline()?;
+            let next = match self.peek_kind() {
+                Some(TokenKind::Semi | TokenKind::Background | TokenKind::Newline) => {
+                    Connector::Seq
+                }
+                Some(TokenKind::And) => Connector::And,
+                Some(TokenKind::Or) => Connector::Or,
+                // the end of the list, or an error reported by the caller
+                _ => {
+                    pipelines.push((connector, pipeline));
+                    break;
+                }
+            };
+
+            let token = self.next().unwrap();
+            if token.kind == TokenKind::Background {
+                if connector != Connector::Seq {
+                    return Err(ParseError::new(
+                        "syntax error: `&` after `&&` or `||` is not supported",
+                        token.span.0,
+                    ));
+                }
+                pipeline.background = true;
+            }
+            if next != Connector::Seq {
+                self.skip_newlines();
+            }
+
+            pipelines.push((connector, pipeline));
+            connector = next;
+        }
+
+        Ok(List { pipelines })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let start = self.peek().map_or(self.last.1, |token| token.span.0);
+        let time = self.peek_reserved("time").then(|| {
+            self.next();
+            match self.peek_reserved("-p") {
+                true => {
+                    self.next();
+                    TimeFormat::Posix
+                }
+                false => TimeFormat::Bash,
+            }
+        });
+        let negated = self.peek_reserved("!");
+        if negated {
+            self.next();
+        }
+
+        // `time` alone shows the time of nothing, like bash
+        let ends = matches!(
+            self.peek_kind(),
+            None | Some(
+                TokenKind::Semi
+                    | TokenKind::Newline
+                    | TokenKind::Background
+                    | TokenKind::And
+                    | TokenKind::Or
+                    | TokenKind::RParen
+                    | TokenKind::DoubleSemi
+            )
+        );
+        if time.is_some() && !negated && ends {
+            return Ok(Pipeline {
+                commands: Vec::new(),
+                background: false,
+                negated,
+                time,
+                text: self.input[start..self.last.1].to_string(),
+            });
+        }
+
+        let mut commands = vec![self.command()?];
+
+        while let Some(TokenKind::Pipe) = self.peek_kind() {
+            let token = self.next().unwrap();
+            self.skip_newlines();
+            if commands.len() == self.limits.pipeline_len {
+                return Err(ParseError::new(
+                    format!(
+                        "too many commands in a pipeline (more than {})",
+                        self.limits.pipeline_len
+                    ),
+                    token.span.0,
+                ));
+            }
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline {
+            commands,
+            background: false,
+            negated,
+            time,
+            text: self.input[start..self.last.1].to_string(),
+        })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let compound = match self.peek_kind() {
+            Some(TokenKind::Word(word)) if word == "{" => {
+                self.next();
+                let list = self.list()?;
+                self.close("}")?;
+                Compound::Group(list)
+            }
+            Some(TokenKind::LParen) => {
+                self.next();
+                let list = self.list()?;
+                match self.peek_kind() {
+                    Some(TokenKind::RParen) => {
+                        self.next();
+                    }
+                    _ => return Err(self.unclosed(")")),
+                }
+                Compound::Subshell(list)
+            }
+            Some(TokenKind::Word(word)) if word == "case" => {
+                self.next();
+                self.case()?
+            }
+            Some(TokenKind::Word(word)) if word == "if" => {
+                self.next();
+                self.if_clause()?
+            }
+            Some(TokenKind::Word(word)) if word == "while" || word == "until" => {
+                let until = word == "until";
+                self.next();
+                self.while_clause(until)?
+            }
+            Some(TokenKind::Word(word)) if word == "function" => {
+                let start = self.next().unwrap().span.0;
+                let name = self.function_name()?;
+                if let Some(TokenKind::LParen) = self.peek_kind() {
+                    self.next();
+                    self.close_paren()?;
+                }
+                return self.function(name, start);
+            }
+            Some(TokenKind::Word(word))
+                if self::is_function_name(word)
+                    && self.peek_second_kind() == Some(&TokenKind::LParen) =>
+            {
+                let start = self.peek().unwrap().span.0;
+                let name = self.word()?;
+                self.next();
+                self.close_paren()?;
+                return self.function(name, start);
+            }
+            _ => return self.simple_command(),
+        };
+
+        // only redirections may follow a compound command
+        let mut redirects = Vec::new();
+        while let Some(TokenKind::Redirect(..)) = self.peek_kind() {
+            redirects.push(self.redirect()?);
+        }
+
+        Ok(Command {
+            words: Vec::new(),
+            redirects,
+            compound: Some(compound),
+        })
+    }
+
+    /// Consumes the name of a function after `function`
+    fn function_name(&mut self) -> Result<String, ParseError> {
+        match self.peek_kind() {
+            Some(TokenKind::Word(word)) if self::is_function_name(word) => self.word(),
+            _ => Err(self.unexpected_next()),
+        }
+    }
+
+    /// Consumes the `)` of `NAME()`
+    fn close_paren(&mut self) -> Result<(), ParseError> {
+        match self.peek_kind() {
+            Some(TokenKind::RParen) => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unclosed(")")),
+        }
+    }
+
+    /// Parses the body of a function definition starting at `start`, which must be a compound
+    /// command, e.g., `{ LIST; }`
+    fn function(&mut self, name: String, start: usize) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.unclosed("{"));
+        };
+        let body = self.command()?;
+        if body.compound.is_none() {
+            return Err(self.unexpected(&token));
+        }
+
+        Ok(Command {
+            words: Vec::new(),
+            redirects: Vec::new(),
+            compound: Some(Compound::Function {
+                name,
+                body: Box::new(body),
+                text: self.input[start..self.last.1].to_string(),
+            }),
+        })
+    }
+
+    /// Parses the rest of `case` after the reserved word
+    fn case(&mut self) -> Result<Compound, ParseError> {
+        let word = self.word()?;
+        self.skip_newlines();
+        self.close("in")?;
+
+        let mut items = Vec::new();
+        while {
+            self.skip_newlines();
+            !self.peek_reserved("esac")
+        } {
+            if self.peek().is_none() {
+                return Err(self.unclosed("esac"));
+            }
+
+            // the opening parenthesis is optional
+            if let Some(TokenKind::LParen) = self.peek_kind() {
+                self.next();
+            }
+            let mut patterns = vec![self.word()?];
+            while let Some(TokenKind::Pipe) = self.peek_kind() {
+                self.next();
+                patterns.push(self.word()?);
+            }
+            match self.peek_kind() {
+                Some(TokenKind::RParen) => {
+                    self.next();
+                }
+                _ => return Err(self.unclosed(")")),
+            }
+
+            let body = if self.at_list_end() {
+                List {
+                    pipelines: Vec::new(),
+                }
+            } else {
+                self.list()?
+            };
+            items.push(CaseItem { patterns, body });
+
+            // `;;` may be omitted in the last branch
+            self.skip_newlines();
+            if let Some(TokenKind::DoubleSemi) = self.peek_kind() {
+                self.next();
+            } else if !self.peek_reserved("esac") {
+                return Err(self.unclosed("esac"));
+            }
+        }
+        self.next();
+
+        Ok(Compound::Case { word, items })
+    }
+
+    /// Parses the rest of `if` after the reserved word
+    fn if_clause(&mut self) -> Result<Compound, ParseError> {
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.list()?;
+            self.close("then")?;
+            let body = self.list()?;
+            branches.push(IfBranch { condition, body });
+            if !self.peek_reserved("elif") {
+                break;
+            }
+            self.next();
+        }
+
+        let otherwise = match self.peek_reserved("else") {
+            true => {
+                self.next();
+                Some(self.list()?)
+            }
+            false => None,
+        };
+        self.close("fi")?;
+
+        Ok(Compound::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    /// Parses the rest of `while` or `until` after the reserved word
+    fn while_clause(&mut self, until: bool) -> Result<Compound, ParseError> {
+        let condition = self.list()?;
+        self.close("do")?;
+        let body = self.list()?;
+        self.close("done")?;
+
+        Ok(Compound::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    /// Consumes a word, counting it against [`Limits::words`]
+    fn word(&mut self) -> Result<String, ParseError> {
+        let Some(TokenKind::Word(_)) = self.peek_kind() else {
+            return Err(self.unexpected_next());
+        };
+
+        let token = self.next().unwrap();
+        if self.n_words == self.limits.words {
+            return Err(ParseError::new(
+                format!("too many words (more than {})", self.limits.words),
+                token.span.0,
+            ));
+        }
+        self.n_words += 1;
+
+        match token.kind {
+            TokenKind::Word(word) => Ok(word),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Consumes the reserved word closing a compound command
+    fn close(&mut self, reserved: &str) -> Result<(), ParseError> {
+        if !self.peek_reserved(reserved) {
+            return Err(self.unclosed(reserved));
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn unclosed(&mut self, closing: &str) -> ParseError {
+        match self.peek().cloned() {
+            Some(token) => self.unexpected(&token),
+            None => ParseError::new(
+                format!("syntax error: unexpected end of line, expecting `{closing}`"),
+                self.input.len(),
+            ),
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+
+        loop {
+            match self.peek_kind() {
+                Some(TokenKind::Word(_)) => words.push(self.word()?),
+                Some(TokenKind::Redirect(..)) => redirects.push(self.redirect()?),
+                _ => break,
+            }
+        }
+
+        if words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected_next());
+        }
+
+        Ok(Command {
+            words,
+            redirects,
+            compound: None,
+        })
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let token = self.next().unwrap();
+        let TokenKind::Redirect(kind, fd) = token.kind else {
+            unreachable!()
+        };
+
+        let target = match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(_),
+                ..
+            }) => match self.next().unwrap().kind {
+                TokenKind::Word(word) => word,
+                _ => unreachable!(),
+            },
+            _ => return Err(self.unexpected_next()),
+        };
+
+        let fd = fd.unwrap_or(match kind {
+            RedirectKind::Input | RedirectKind::DupInput => 0,
+            _ => 1,
+        });
+        Ok(Redirect { fd, kind, target })
+    }
+}
+
+/// Expands history references in a command line: `!!` (the previous command), `!N` (the N-th
+/// entry), `!-N` (the N-th previous command) and `!PREFIX` (the most recent command starting with
+/// the prefix). A `!` that doesn't form an event is left as it is.
+///
+/// Returns `None` if the line has no history reference.
+pub(crate) fn expand_history(input: &str, history: &[&str]) -> Result<Option<String>, ParseError> {
+    let mut out = String::with_capacity(input.len());
+    let mut expanded = false;
+    let mut in_double = false;
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                out.extend(chars.next().map(|(_, c)| c));
+                continue;
+            }
+            '\'' if !in_double => {
+                // no expansion inside single quotes
+                out.push(c);
+                for (_, c) in chars.by_ref() {
+                    out.push(c);
+                    if c == '\'' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '"' => {
+                in_double = !in_double;
+                out.push(c);
+                continue;
+            }
+            // `$!` is the last background process
+            '!' if !out.ends_with('$') => {}
+            _ => {
+                out.push(c);
+                continue;
+            }
+        }
+
+        // `!`
+        let event_end = match chars.peek() {
+            Some(&(_, '!')) => {
+                chars.next();
+                i + 2
+            }
+            Some(&(_, c)) if !c.is_whitespace() && !"=(\"".contains(c) => {
+                let mut end = i + 1;
+                while let Some(&(j, c)) = chars.peek() {
+                    if c.is_whitespace() || ";|&()<>\"'".contains(c) {
+                        break;
+                    }
+                    chars.next();
+                    end = j + c.len_utf8();
+                }
+                end
+            }
+            _ => {
+                out.push('!');
+                continue;
+            }
+        };
+
+        let event = &input[i..event_end];
+        let designator = &event[1..];
+
+        let entry = if designator == "!" {
+            history.last()
+        } else if let Ok(n) = designator.parse::<isize>() {
+            if n < 0 {
+                history
+                    .len()
+                    .checked_sub(n.unsigned_abs())
+                    .and_then(|i| history.get(i))
+            } else {
+                (n as usize).checked_sub(1).and_then(|i| history.get(i))
+            }
+        } else {
+            history
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(designator))
+        };
+
+        match entry {
+            Some(entry) => {
+                out.push_str(entry);
+                expanded = true;
+            }
+            None => return Err(ParseError::new(format!("{event}: event not found"), i)),
+        }
+    }
+
+    Ok(expanded.then_some(out))
+}
+
+/// Expands the quick substitution `^OLD^NEW^`, i.e., the previous command with the first
+/// occurrence of `OLD` replaced with `NEW`. The last `^` is optional.
+///
+/// Returns `None` if the line doesn't start with `^`.
+pub(crate) fn expand_quick_substitution(
+    input: &str,
+    prev: Option<&str>,
+) -> Result<Option<String>, ParseError> {
+    let Some(rest) = input.strip_prefix('^') else {
+        return Ok(None);
+    };
+
+    let mut parts = rest.splitn(3, '^');
+    let old = parts.next().unwrap_or_default();
+    let new = parts.next().unwrap_or_default();
+    let trailing = parts.next().unwrap_or_default();
+
+    let Some(prev) = prev else {
+        return Err(ParseError::new("^: no previous command", 0));
+    };
+
+    if old.is_empty() || !prev.contains(old) {
+        return Err(ParseError::new(
+            format!("^{old}^{new}: substitution failed"),
+            0,
+        ));
+    }
+
+    Ok(Some(prev.replacen(old, new, 1) + trailing))
+}
+
+/// Expands aliases in the command words of a command line, i.e., the first words of simple
+/// commands, after assignments and redirections. The value of an alias is expanded again, except
+/// for aliases being expanded. If the value ends in a space, the next word is also expanded like
+/// bash, e.g., `ll` in `sudo ll` with `alias sudo='sudo '`. Quoted words are never expanded.
+///
+/// Returns `None` if the line has no alias.
+pub(crate) fn expand_aliases(
+    input: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<Option<String>, ParseError> {
+    if aliases.is_empty() {
+        return Ok(None);
+    }
+    let (out, expanded, _) = self::expand_alias_words(input, aliases, &mut Vec::new())?;
+    Ok(expanded.then_some(out))
+}
+
+/// Expands the aliases in a text, skipping the ones in `active`. Returns the text, whether an
+/// alias was expanded and whether the next word is a command word.
+fn expand_alias_words<'a>(
+    input: &str,
+    aliases: &'a BTreeMap<String, String>,
+    active: &mut Vec<&'a str>,
+) -> Result<(String, bool, bool), ParseError> {
+    let mut out = String::with_capacity(input.len());
+    let mut expanded = false;
+    // the next word is a command word (or may be an alias by a trailing space)
+    let mut command_word = true;
+    let mut redirect_target = false;
+    let mut copied = 0;
+
+    for token in self::tokenize(input)? {
+        let word = match &token.kind {
+            TokenKind::Word(word) => word,
+            TokenKind::Redirect(..) => {
+                redirect_target = true;
+                continue;
+            }
+            _ => {
+                command_word = true;
+                continue;
+            }
+        };
+
+        if std::mem::take(&mut redirect_target) || !command_word {
+            continue;
+        }
+        // reserved words and assignments keep the next word a command word
+        if matches!(word.as_str(), "{" | "!" | "time") || self::assignment(word).is_some() {
+            continue;
+        }
+        command_word = false;
+
+        let Some((name, value)) = aliases.get_key_value(word.as_str()) else {
+            continue;
+        };
+        if active.contains(&name.as_str()) {
+            continue;
+        }
+
+        active.push(name);
+        let res = self::expand_alias_words(value, aliases, active);
+        active.pop();
+        let (value, _, next_command_word) = res?;
+
+        out.push_str(&input[copied..token.span.0]);
+        out.push_str(&value);
+        copied = token.span.1;
+        expanded = true;
+        command_word = next_command_word || value.ends_with([' ', '\t']);
+    }
+
+    out.push_str(&input[copied..]);
+    Ok((out, expanded, command_word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> List {
+        super::parse(input, Limits::default())
+            .unwrap_or_else(|err| panic!("{input:?}: {err}"))
+            .unwrap()
+    }
+
+    /// Returns the name and the definition text of a function definition
+    fn function(list: &List) -> (&str, &str) {
+        match &list.pipelines[0].1.commands[0].compound {
+            Some(Compound::Function { name, text, .. }) => (name, text),
+            compound => panic!("not a function definition: {compound:?}"),
+        }
+    }
+
+    #[test]
+    fn limits_on_the_size() {
+        let limits = Limits {
+            pipeline_len: 3,
+            words: 5,
+            ..Limits::default()
+        };
+        assert!(super::parse("a | b | c", limits).is_ok());
+        let err = super::parse("a | b | c | d", limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "too many commands in a pipeline (more than 3)"
+        );
+        // each pipeline has its own length
+        assert!(super::parse("a | b | c; d | e", limits).is_ok());
+
+        assert!(super::parse("a b c; d e", limits).is_ok());
+        let err = super::parse("a b c; d e f", limits).unwrap_err();
+        assert_eq!(err.to_string(), "too many words (more than 5)");
+        assert_eq!(err.pos, "a b c; d e ".len());
+
+        let limits = Limits {
+            line_len: 8,
+            ..Limits::default()
+        };
+        assert!(super::parse("echo abc", limits).is_ok());
+        let err = super::parse("echo abcd", limits).unwrap_err();
+        assert_eq!(err.to_string(), "command line too long (more than 8 bytes)");
+        // bytes, not characters
+        assert!(check_line_len("echo \u{e9}\u{e9}", limits).is_err());
+    }
+
+    /// Returns the words of a line
+    fn words(input: &str) -> Vec<String> {
+        let tokens = tokenize(input).unwrap_or_else(|err| panic!("{input:?}: {err}"));
+        tokens
+            .into_iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn line_continuations() {
+        // between words and in a word
+        assert_eq!(words("echo \\\n a"), ["echo", "a"]);
+        assert_eq!(words("ec\\\nho a\\\nb"), ["echo", "ab"]);
+        assert_eq!(words("a |\\\n b"), ["a", "b"]);
+        // in double quotes and expansions
+        assert_eq!(words("\"a\\\nb\""), ["\"ab\""]);
+        assert_eq!(
+            words("${x:-a\\\nb} \"${x:-a\\\nb}\""),
+            ["${x:-ab}", "\"${x:-ab}\""]
+        );
+        // the command of a substitution is tokenized when it's run
+        assert_eq!(words("$(echo a\\\nb)"), ["$(echo a\\\nb)"]);
+        // literal in single quotes
+        assert_eq!(words("'a\\\nb'"), ["'a\\\nb'"]);
+        assert_eq!(words("${x:-'a\\\nb'}"), ["${x:-'a\\\nb'}"]);
+        // an escaped backslash doesn't continue the line
+        assert_eq!(words("a\\\\\nb"), ["a\\\\", "b"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(words("echo a # b c"), ["echo", "a"]);
+        assert_eq!(words("# a\necho b #c\n"), ["echo", "b"]);
+        assert_eq!(words("a;#b\nc"), ["a", "c"]);
+        // only at the start of a word
+        assert_eq!(
+            words("echo a#b '#c' \\#d ${#x}"),
+            ["echo", "a#b", "'#c'", "\\#d", "${#x}"]
+        );
+        // a newline still ends the command
+        assert_eq!(parse("a # b\nc").pipelines.len(), 2);
+        assert!(super::parse("# a", Limits::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn history_references() {
+        let history = ["echo a", "ls -l"];
+        let expand = |input| super::expand_history(input, &history).unwrap();
+        assert_eq!(expand("!! | less").as_deref(), Some("ls -l | less"));
+        assert_eq!(expand("!e b").as_deref(), Some("echo a b"));
+        // `$!` is the last background process
+        assert_eq!(expand("wait $!"), None);
+        assert_eq!(expand("echo '!!' \\!!"), None);
+    }
+
+    /// Expands the aliases of `NAME=VALUE`s in a line, or returns it as is without any
+    fn aliased(input: &str, aliases: &[(&str, &str)]) -> String {
+        let aliases = aliases
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let expanded = expand_aliases(input, &aliases).unwrap();
+        expanded.unwrap_or_else(|| input.to_string())
+    }
+
+    #[test]
+    fn aliases_in_command_words() {
+        let aliases = [("ll", "ls -l"), ("x", "echo x")];
+        assert_eq!(
+            aliased("ll /; x ll | ll", &aliases),
+            "ls -l /; echo x ll | ls -l"
+        );
+        assert_eq!(aliased("A=1 > f ll", &aliases), "A=1 > f ls -l");
+        // quoted words
+        assert_eq!(aliased("'ll' \\x \"x\"", &aliases), "'ll' \\x \"x\"");
+    }
+
+    #[test]
+    fn aliases_ending_in_a_space() {
+        // the space of the value is kept before the one of the line
+        let aliases = [("sudo", "sudo "), ("ll", "ls -l"), ("nice", "nice")];
+        assert_eq!(aliased("sudo ll", &aliases), "sudo  ls -l");
+        assert_eq!(aliased("sudo sudo ll /", &aliases), "sudo  sudo  ls -l /");
+        // only right after the space
+        assert_eq!(aliased("sudo -u root ll", &aliases), "sudo  -u root ll");
+        assert_eq!(aliased("nice ll", &aliases), "nice ll");
+    }
+
+    #[test]
+    fn recursive_aliases() {
+        // nested expansions, but an alias being expanded is kept
+        let aliases = [("a", "b 1"), ("b", "c 2"), ("c", "a 3")];
+        assert_eq!(aliased("a", &aliases), "a 3 2 1");
+        assert_eq!(aliased("ls", &[("ls", "ls -F")]), "ls -F");
+        let aliases = [("a", "a "), ("b", "b ")];
+        assert_eq!(aliased("a a b", &aliases), "a  a  b ");
+    }
+
+    #[test]
+    fn function_definitions() {
+        assert_eq!(function(&parse("f() { echo; }")), ("f", "f() { echo; }"));
+        assert_eq!(
+            function(&parse("function f { :; }")),
+            ("f", "function f { :; }")
+        );
+        assert_eq!(
+            function(&parse("function f() ( : )")),
+            ("f", "function f() ( : )")
+        );
+        assert_eq!(function(&parse("git-st () {\n:\n}")).0, "git-st");
+        // the redirections belong to the body
+        let list = parse("f() { :; } > out; f");
+        assert_eq!(list.pipelines.len(), 2);
+        let Some(Compound::Function { body, .. }) = &list.pipelines[0].1.commands[0].compound
+        else {
+            unreachable!()
+        };
+        assert_eq!(body.redirects.len(), 1);
+    }
+
+    #[test]
+    fn function_bodies_must_be_compound_commands() {
+        assert!(super::parse("f() echo", Limits::default()).is_err());
+        assert!(super::parse("function 1 { :; }", Limits::default()).is_err());
+        let err = super::parse("f() {", Limits::default()).unwrap_err();
+        assert!(err.is_unterminated(), "{err}");
+    }
+
+    #[test]
+    fn newlines_separate_commands() {
+        let list = parse("echo a\n\necho b\n");
+        assert_eq!(list.pipelines.len(), 2);
+        assert_eq!(list.pipelines[1].0, Connector::Seq);
+
+        // a command may follow on the next line
+        let list = parse("true &&\necho a |\ncat");
+        assert_eq!(list.pipelines.len(), 2);
+        assert_eq!(list.pipelines[1].1.commands.len(), 2);
+
+        assert!(super::parse("\n\n", Limits::default()).unwrap().is_none());
+        let err = super::parse("true &&\n", Limits::default()).unwrap_err();
+        assert!(err.is_unterminated(), "{err}");
+    }
+
+    #[test]
+    fn newlines_in_case() {
+        let list = parse("case $x\nin\n  a) echo a;;\n  *) echo b\nesac");
+        let Some(Compound::Case { items, .. }) = &list.pipelines[0].1.commands[0].compound else {
+            panic!("not case");
+        };
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn if_and_while() {
+        let list = parse("if true && false; then :; elif ! true || true\nthen :\nelse echo fi; fi");
+        let Some(Compound::If {
+            branches,
+            otherwise,
+        }) = &list.pipelines[0].1.commands[0].compound
+        else {
+            panic!("not if");
+        };
+        // the conditions are and-or lists
+        assert_eq!(branches[0].condition.pipelines[1].0, Connector::And);
+        assert_eq!(branches[1].condition.pipelines[1].0, Connector::Or);
+        // `fi` is a reserved word only where a command may start
+        let otherwise = otherwise.as_ref().unwrap();
+        assert_eq!(otherwise.pipelines[0].1.commands[0].words, ["echo", "fi"]);
+
+        let list = parse("until false; do :; done > out");
+        let Some(Compound::While {
+            condition, until, ..
+        }) = &list.pipelines[0].1.commands[0].compound
+        else {
+            panic!("not until");
+        };
+        assert!(*until);
+        assert_eq!(condition.pipelines.len(), 1);
+        assert_eq!(list.pipelines[0].1.commands[0].redirects.len(), 1);
+
+        for input in ["if true; then :; fi fi", "while; do :; done", "if true; fi"] {
+            assert!(super::parse(input, Limits::default()).is_err(), "{input}");
+        }
+        for input in ["if true; then", "while true; do :;", "if :; then :; else"] {
+            let err = super::parse(input, Limits::default()).unwrap_err();
+            assert!(err.is_unterminated(), "{input}: {err}");
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Formatting for the `printf` builtin
+//!
+//! A conversion is `%[FLAGS][WIDTH][.PRECISION]CONV`, where the flags are `-` (left-justify), `0`
+//! (pad with zeros), `+` and ` ` (sign of positive numbers) and `#` (`0x` or `0` prefix). `CONV`
+//! is one of `s`, `b` (string with escapes), `c`, `d`, `i`, `u`, `o`, `x`, `X` and `%`. The format
+//! is reused while arguments remain, and missing arguments are empty strings or zeros.
+
+use std::ops::ControlFlow;
+
+/// Formats the arguments. Invalid numbers are formatted as zero and reported in `errors`.
+pub(crate) fn format(format: &str, args: &[String], errors: &mut Vec<String>) -> String {
+    let format = format.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut args = args.iter();
+
+    loop {
+        let remaining = args.len();
+        if self::format_once(&format, &mut args, &mut out, errors).is_break() {
+            break;
+        }
+        // the format has no conversion taking arguments, or they are all consumed
+        if args.len() == 0 || args.len() == remaining {
+            break;
+        }
+    }
+
+    out
+}
+
+/// Formats once through the format. Breaks on `\c` in a `%b` argument, which stops the output.
+fn format_once<'a>(
+    format: &[char],
+    args: &mut impl Iterator<Item = &'a String>,
+    out: &mut String,
+    errors: &mut Vec<String>,
+) -> ControlFlow<()> {
+    let mut i = 0;
+
+    while i < format.len() {
+        match format[i] {
+            '\\' => i += self::unescape_one(&format[i + 1..], out) + 1,
+            '%' => {
+                let (spec, len) = Spec::parse(&format[i + 1..]);
+                i += len + 1;
+                match spec.conv {
+                    Some('%') => out.push('%'),
+                    Some(conv) => {
+                        let arg = args.next().map_or("", String::as_str);
+                        if spec.format(conv, arg, out, errors).is_break() {
+                            return ControlFlow::Break(());
+                        }
+                    }
+                    // incomplete conversion at the end
+                    None => out.extend(&format[i - len - 1..i]),
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+/// Conversion specification
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: Option<char>,
+}
+
+impl Spec {
+    /// Parses the text after `%` and returns the length
+    fn parse(text: &[char]) -> (Self, usize) {
+        let mut spec = Self::default();
+        let mut i = 0;
+
+        while let Some(&c) = text.get(i) {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alt = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let digits = |i: &mut usize| {
+            let mut n = 0usize;
+            while let Some(d) = text.get(*i).and_then(|c| c.to_digit(10)) {
+                n = n.saturating_mul(10).saturating_add(d as usize);
+                *i += 1;
+            }
+            n
+        };
+
+        spec.width = digits(&mut i);
+        if text.get(i) == Some(&'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i));
+        }
+
+        spec.conv = text.get(i).copied().filter(|c| "sbcdiuoxX%".contains(*c));
+        if spec.conv.is_some() {
+            i += 1;
+        }
+
+        (spec, i)
+    }
+
+    fn format(
+        &self,
+        conv: char,
+        arg: &str,
+        out: &mut String,
+        errors: &mut Vec<String>,
+    ) -> ControlFlow<()> {
+        let mut flow = ControlFlow::Continue(());
+
+        let (sign, body) = match conv {
+            's' => (String::new(), self.truncate(arg)),
+            'b' => {
+                let mut text = String::new();
+                flow = self::unescape(arg, &mut text);
+                (String::new(), self.truncate(&text))
+            }
+            'c' => (String::new(), arg.chars().take(1).collect()),
+            _ => {
+                let num = self::parse_number(arg).unwrap_or_else(|| {
+                    errors.push(format!("{arg}: invalid number"));
+                    0
+                });
+                self.format_number(conv, num)
+            }
+        };
+
+        let len = sign.chars().count() + body.chars().count();
+        let pad = self.width.saturating_sub(len);
+        let numeric = !matches!(conv, 's' | 'b' | 'c');
+
+        if self.left {
+            out.push_str(&sign);
+            out.push_str(&body);
+            out.extend(std::iter::repeat_n(' ', pad));
+        } else if self.zero && numeric && self.precision.is_none() {
+            out.push_str(&sign);
+            out.extend(std::iter::repeat_n('0', pad));
+            out.push_str(&body);
+        } else {
+            out.extend(std::iter::repeat_n(' ', pad));
+            out.push_str(&sign);
+            out.push_str(&body);
+        }
+
+        flow
+    }
+
+    fn truncate(&self, text: &str) -> String {
+        match self.precision {
+            Some(precision) => text.chars().take(precision).collect(),
+            None => text.to_string(),
+        }
+    }
+
+    /// Returns the sign (or prefix) and the digits of a number
+    fn format_number(&self, conv: char, num: i64) -> (String, String) {
+        // unsigned conversions show negative numbers in two's complement like C
+        let digits = match conv {
+            'd' | 'i' => num.unsigned_abs().to_string(),
+            'u' => (num as u64).to_string(),
+            'o' => format!("{:o}", num as u64),
+            'x' => format!("{:x}", num as u64),
+            _ => format!("{:X}", num as u64),
+        };
+
+        let digits = match self.precision {
+            // `%.0d` prints nothing for zero
+            Some(0) if num == 0 => String::new(),
+            Some(precision) if digits.len() < precision => {
+                format!("{}{digits}", "0".repeat(precision - digits.len()))
+            }
+            _ => digits,
+        };
+
+        let sign = match conv {
+            'd' | 'i' if num < 0 => "-",
+            'd' | 'i' if self.plus => "+",
+            'd' | 'i' if self.space => " ",
+            'o' if self.alt && !digits.starts_with('0') => "0",
+            'x' if self.alt && num != 0 => "0x",
+            'X' if self.alt && num != 0 => "0X",
+            _ => "",
+        };
+
+        (sign.to_string(), digits)
+    }
+}
+
+/// Parses a numeric argument: decimal, `0x` hexadecimal, `0` octal or `'C` for the code of `C`
+fn parse_number(arg: &str) -> Option<i64> {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Some(0);
+    }
+    if let Some(rest) = arg.strip_prefix(['\'', '"']) {
+        return Some(rest.chars().next().map_or(0, |c| c as i64));
+    }
+
+    let (negative, digits) = match arg.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+
+    let num = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { num.wrapping_neg() } else { num })
+}
+
+/// Replaces the escapes in the text of `%b`, where octal escapes are `\0NNN`. Breaks on `\c`.
+fn unescape(text: &str, out: &mut String) -> ControlFlow<()> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '\\' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        match chars.get(i + 1) {
+            Some('c') => return ControlFlow::Break(()),
+            Some('0') if !chars.get(i + 2).is_some_and(|c| c.is_digit(8)) => {
+                out.push('\0');
+                i += 2;
+            }
+            // read the digits after `\0` as `\NNN`
+            Some('0') => i += 2 + self::unescape_one(&chars[i + 2..], out),
+            _ => i += 1 + self::unescape_one(&chars[i + 1..], out),
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+/// Pushes the character of an escape and returns the length consumed. `text` is after `\`.
+fn unescape_one(text: &[char], out: &mut String) -> usize {
+    let Some(&c) = text.first() else {
+        out.push('\\');
+        return 0;
+    };
+
+    let simple = match c {
+        'a' => Some('\x07'),
+        'b' => Some('\x08'),
+        'e' => Some('\x1b'),
+        'f' => Some('\x0c'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'v' => Some('\x0b'),
+        '\\' | '"' | '\'' => Some(c),
+        _ => None,
+    };
+    if let Some(c) = simple {
+        out.push(c);
+        return 1;
+    }
+
+    // `\NNN` (octal) or `\xHH` (hexadecimal)
+    let (radix, start, max) = match c {
+        '0'..='7' => (8, 0, 3),
+        'x' => (16, 1, 2),
+        _ => {
+            out.push('\\');
+            out.push(c);
+            return 1;
+        }
+    };
+
+    let digits = text[start..]
+        .iter()
+        .take(max)
+        .take_while(|c| c.is_digit(radix))
+        .collect::<String>();
+    if digits.is_empty() {
+        // `\x` without digits
+        out.push('\\');
+        out.push(c);
+        return 1;
+    }
+
+    let code = u32::from_str_radix(&digits, radix).unwrap_or(0);
+    out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+    start + digits.len()
+}
+
+#[cfg(test)]
+mod tests {
+    /// Formats the arguments and returns the output and the errors
+    fn format(format: &str, args: &[&str]) -> (String, Vec<String>) {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut errors = Vec::new();
+        let out = super::format(format, &args, &mut errors);
+        (out, errors)
+    }
+
+    /// Formats arguments that are all valid
+    fn ok(format: &str, args: &[&str]) -> String {
+        let (out, errors) = self::format(format, args);
+        assert!(errors.is_empty(), "{format:?} {args:?}: {errors:?}");
+        out
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(ok("%s-%s\\n", &["a", "b"]), "a-b\n");
+        assert_eq!(
+            ok("[%5s][%-5s][%.2s]", &["ab", "cd", "efg"]),
+            "[   ab][cd   ][ef]"
+        );
+        assert_eq!(ok("%c%c", &["xyz", ""]), "x");
+        assert_eq!(ok("100%%", &[]), "100%");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(ok("%d %i %u", &["42", "-7", "3"]), "42 -7 3");
+        assert_eq!(
+            ok("[%5d][%-5d][%05d]", &["42", "42", "-42"]),
+            "[   42][42   ][-0042]"
+        );
+        assert_eq!(ok("%+d % d %.3d %.0d", &["1", "2", "3", "0"]), "+1  2 003 ");
+        assert_eq!(ok("%o %x %X", &["8", "255", "255"]), "10 ff FF");
+        assert_eq!(
+            ok("%#o %#x %#X %#x", &["8", "255", "255", "0"]),
+            "010 0xff 0XFF 0"
+        );
+        // hexadecimal, octal and character codes as arguments
+        assert_eq!(
+            ok("%d %d %d %d", &["0x1f", "010", "'A", "\"a"]),
+            "31 8 65 97"
+        );
+        // two's complement in unsigned conversions
+        assert_eq!(
+            ok("%u %x", &["-1", "-1"]),
+            "18446744073709551615 ffffffffffffffff"
+        );
+    }
+
+    #[test]
+    fn invalid_numbers_are_zero() {
+        let (out, errors) = self::format("%d %d", &["1a", "x"]);
+        assert_eq!(out, "0 0");
+        assert_eq!(errors, ["1a: invalid number", "x: invalid number"]);
+    }
+
+    #[test]
+    fn format_is_reused_for_the_remaining_arguments() {
+        assert_eq!(ok("%s=%d\\n", &["a", "1", "b"]), "a=1\nb=0\n");
+        assert_eq!(ok("%s\\n", &[]), "\n");
+        // without conversions, once
+        assert_eq!(ok("x\\n", &["a", "b"]), "x\n");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(ok("a\\tb\\\\c\\x41\\101\\q", &[]), "a\tb\\cAA\\q");
+        assert_eq!(ok("%b|%s", &["a\\nb\\0101", "c\\n"]), "a\nbA|c\\n");
+        // `\c` in `%b` stops the output, even of the remaining arguments
+        assert_eq!(ok("%b-%s\\n", &["a\\cb", "c", "d"]), "a");
+        // an incomplete conversion is left as it is
+        assert_eq!(ok("50%", &[]), "50%");
+        assert_eq!(ok("%5", &[]), "%5");
+    }
+}
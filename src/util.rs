@@ -1,5 +1,15 @@
 //! Utilities
 
+use nix::sys::signal::Signal;
+
+use std::{
+    cell::RefCell,
+    env, fs,
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
 /// Calls a syscall function while handling the EINTR signal.
 pub fn run_syscall<T>(f: impl Fn() -> Result<T, nix::Error>) -> Result<T, nix::Error> {
     loop {
@@ -11,3 +21,437 @@ pub fn run_syscall<T>(f: impl Fn() -> Result<T, nix::Error>) -> Result<T, nix::E
         }
     }
 }
+
+/// Text color for messages of the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Self::Red => "31",
+            Self::Green => "32",
+            Self::Yellow => "33",
+            Self::Cyan => "36",
+        }
+    }
+}
+
+/// Returns true if messages to stderr should be colored, i.e., stderr is a terminal and neither
+/// `NO_COLOR` nor `ZEROSH_NO_COLOR` is set
+pub fn color_enabled() -> bool {
+    let is_set = |name| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    !is_set("NO_COLOR")
+        && !is_set("ZEROSH_NO_COLOR")
+        && nix::unistd::isatty(nix::libc::STDERR_FILENO).unwrap_or(false)
+}
+
+/// Returns true if the shell should print only ASCII characters in the prompt, i.e.,
+/// `ZEROSH_ASCII` is set or the locale (`LC_ALL`, `LC_CTYPE` or `LANG`) is not UTF-8
+pub fn ascii_only() -> bool {
+    self::ascii_only_with(|name| std::env::var(name).ok())
+}
+
+/// [`ascii_only`] with the environment variables of `var`
+fn ascii_only_with(var: impl Fn(&str) -> Option<String>) -> bool {
+    let var = |name| var(name).filter(|value| !value.is_empty());
+    if var("ZEROSH_ASCII").is_some() {
+        return true;
+    }
+
+    // the first one set takes effect, and the `C` locale is used if none is set
+    let locale = var("LC_ALL")
+        .or_else(|| var("LC_CTYPE"))
+        .or_else(|| var("LANG"));
+    !locale.is_some_and(|locale| {
+        let locale = locale.to_ascii_lowercase();
+        locale.contains("utf-8") || locale.contains("utf8")
+    })
+}
+
+/// Wraps the text with ANSI color escapes if [`color_enabled`]
+pub fn paint(color: Color, text: &str) -> String {
+    if self::color_enabled() {
+        format!("\x1b[{}m{text}\x1b[0m", color.code())
+    } else {
+        text.to_string()
+    }
+}
+
+thread_local! {
+    /// File and line of the command being run from a file, shown in the messages of the shell
+    static MESSAGE_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the location (`FILE:LINE`) shown in the messages of the shell, e.g., while running a file
+/// with `source`. Returns the previous one.
+pub fn set_message_location(location: Option<String>) -> Option<String> {
+    MESSAGE_LOCATION.with(|current| current.replace(location))
+}
+
+/// Prefixes a message of the shell with `ZeroSh:` and the location, if any
+fn message_text(msg: impl std::fmt::Display) -> String {
+    MESSAGE_LOCATION.with(|location| match &*location.borrow() {
+        Some(location) => format!("ZeroSh: {location}: {msg}"),
+        None => format!("ZeroSh: {msg}"),
+    })
+}
+
+/// Formats an error message of the shell
+pub fn error_text(msg: impl std::fmt::Display) -> String {
+    self::paint(Color::Red, &self::message_text(msg))
+}
+
+/// Prints an error message of the shell to stderr. Unlike `eprintln!`, it doesn't panic if the
+/// terminal is gone.
+pub fn print_error(msg: impl std::fmt::Display) {
+    let _ = writeln!(io::stderr(), "{}", self::error_text(msg));
+}
+
+/// Returns the text of an I/O error without the ` (os error N)` suffix, e.g., `No such file or
+/// directory`, as shells print it
+pub fn strerror(err: &io::Error) -> String {
+    match err.raw_os_error() {
+        Some(code) => nix::errno::Errno::from_i32(code).desc().to_string(),
+        None => err.to_string(),
+    }
+}
+
+/// Prints a line of the shell, e.g., a job notification, to stderr as it is. Unlike `eprintln!`, it
+/// ignores write errors, and it writes to the process's stderr even where `eprintln!` is captured,
+/// e.g., in a shell forked by a test.
+pub fn print_line(text: impl std::fmt::Display) {
+    let _ = writeln!(io::stderr(), "{text}");
+}
+
+/// Prints a warning message of the shell to stderr, ignoring write errors
+pub fn print_warning(msg: impl std::fmt::Display) {
+    let text = self::paint(Color::Yellow, &self::message_text(msg));
+    let _ = writeln!(io::stderr(), "{text}");
+}
+
+/// Formats a directory for display, collapsing the home directory to `~`
+pub fn display_path(path: &Path, home: Option<&Path>) -> String {
+    let relative = home
+        .filter(|home| home.parent().is_some())
+        .and_then(|home| path.strip_prefix(home).ok());
+
+    match relative {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Returns the current directory as `PWD` if it names the directory, keeping the symbolic links
+/// followed by `cd` (`cd -L`). Otherwise the physical one.
+pub fn logical_cwd() -> io::Result<PathBuf> {
+    let physical = env::current_dir()?;
+    let Some(pwd) = env::var_os("PWD").map(PathBuf::from) else {
+        return Ok(physical);
+    };
+
+    let same = pwd.is_absolute()
+        && !pwd.components().any(|c| c == Component::ParentDir)
+        && match (fs::metadata(&pwd), fs::metadata(&physical)) {
+            (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+            _ => false,
+        };
+    Ok(if same { pwd } else { physical })
+}
+
+/// Returns the git branch checked out in a directory or the repository above it, or the short
+/// commit ID if the `HEAD` is detached. `None` outside a repository. `.git` may be a file
+/// pointing to the repository, e.g., of a worktree.
+///
+/// Only files are read, but they may be slow, e.g., on a network file system.
+pub fn git_branch(dir: &Path) -> Option<String> {
+    let git = dir
+        .ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|git| git.exists())?;
+    let git = match fs::read_to_string(&git) {
+        Ok(file) => {
+            let target = PathBuf::from(file.strip_prefix("gitdir:")?.trim());
+            git.parent()?.join(target)
+        }
+        Err(_) => git,
+    };
+
+    let head = fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(name) => Some(name.strip_prefix("refs/heads/").unwrap_or(name).to_string()),
+        None => head.get(..7).map(str::to_string),
+    }
+}
+
+/// Shortens a path from [`display_path`] to its last `depth` components with a leading ellipsis,
+/// e.g., `…/b/c` for `/a/b/c` with depth 2. Zero depth means no truncation.
+pub fn truncate_path(path: &str, depth: usize, ellipsis: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) => rest,
+        None => path,
+    };
+
+    let components = rest
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    if depth == 0 || components.len() <= depth {
+        return path.to_string();
+    }
+
+    format!(
+        "{ellipsis}/{}",
+        components[components.len() - depth..].join("/")
+    )
+}
+
+/// Returns the size of the terminal as columns and lines, queried on the standard error, output
+/// and input in this order. `None` if none of them is a terminal.
+pub fn terminal_size() -> Option<(u16, u16)> {
+    use nix::libc;
+
+    [libc::STDERR_FILENO, libc::STDOUT_FILENO, libc::STDIN_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size = libc::winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            let res = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+            (res == 0 && size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col, size.ws_row))
+        })
+}
+
+/// Returns the number of columns a text takes on the terminal. Escape sequences take none.
+pub fn display_width(text: &str) -> usize {
+    use unicode_width::UnicodeWidthChar;
+
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // e.g., `\x1b[1;32m`
+            chars.find(char::is_ascii_alphabetic);
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
+/// Returns the path of an executable file in the directories of `PATH`
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|path| self::is_executable(path))
+}
+
+/// Returns true if the path is a file that can be executed by someone
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Returns the edit distance of two strings, counting a swap of adjacent characters as one edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    // distances of prefixes: `rows[i][j]` is for `a[..i]` and `b[..j]`
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+
+    rows[a.len()][b.len()]
+}
+
+/// Parses a signal name or number, e.g., `TERM`, `SIGTERM`, `term` or `15`
+pub fn signal_from_str(name: &str) -> Option<Signal> {
+    if let Ok(num) = name.parse::<i32>() {
+        return Signal::try_from(num).ok();
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    Signal::iterator().find(|signal| self::signal_name(*signal) == name)
+}
+
+/// Returns the name of a signal without the `SIG` prefix, e.g., `TERM`
+pub fn signal_name(signal: Signal) -> &'static str {
+    &signal.as_str()[3..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("", "ls"), 2);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("sl", "ls"), 1);
+        assert_eq!(edit_distance("carg", "cargo"), 1);
+        assert_eq!(edit_distance("cargoo", "cargo"), 1);
+        assert_eq!(edit_distance("grpe", "grep"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        // characters, not bytes
+        assert_eq!(edit_distance("\u{e9}cho", "echo"), 1);
+    }
+
+    #[test]
+    fn signal_names_and_numbers() {
+        assert_eq!(signal_name(Signal::SIGKILL), "KILL");
+        assert_eq!(signal_from_str("9"), Some(Signal::SIGKILL));
+        // with or without `SIG`, in any case
+        assert_eq!(signal_from_str("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(signal_from_str("sigint"), Some(Signal::SIGINT));
+        assert_eq!(signal_from_str("SigHup"), Some(Signal::SIGHUP));
+        assert_eq!(signal_from_str("0"), None);
+        assert_eq!(signal_from_str("99"), None);
+        assert_eq!(signal_from_str("SIG"), None);
+        assert_eq!(signal_from_str("FOO"), None);
+        for signal in Signal::iterator() {
+            assert_eq!(signal_from_str(signal_name(signal)), Some(signal));
+        }
+    }
+
+    #[test]
+    fn display_width_of_prompts() {
+        assert_eq!(display_width("\u{1F642}"), 2);
+        assert_eq!(display_width("\u{1F480}"), 2);
+        assert_eq!(display_width("\u{1F512} "), 3);
+        assert_eq!(display_width("\u{2026}/src"), 5);
+        assert_eq!(display_width("ZeroSh \u{1F642} ~/src %>"), 18);
+        // colors take no columns
+        assert_eq!(display_width("\x1b[1;32mZeroSh\x1b[0m \u{1F480}"), 9);
+        assert_eq!(display_width(""), 0);
+    }
+
+    #[test]
+    fn ascii_only_follows_the_locale() {
+        let ascii_only = |vars: &[(&str, &str)]| {
+            ascii_only_with(|name| {
+                let var = vars.iter().find(|(n, _)| *n == name);
+                var.map(|(_, value)| value.to_string())
+            })
+        };
+        assert!(!ascii_only(&[("LANG", "en_US.UTF-8")]));
+        assert!(!ascii_only(&[("LANG", "ja_JP.utf8")]));
+        assert!(ascii_only(&[("LANG", "C")]));
+        assert!(ascii_only(&[]));
+        // `LC_ALL`, then `LC_CTYPE`, then `LANG`, skipping empty ones
+        assert!(ascii_only(&[("LC_ALL", "C"), ("LANG", "en_US.UTF-8")]));
+        assert!(!ascii_only(&[("LC_CTYPE", "C.UTF-8"), ("LANG", "C")]));
+        assert!(!ascii_only(&[("LC_ALL", ""), ("LANG", "C.UTF-8")]));
+        // forced
+        assert!(ascii_only(&[
+            ("ZEROSH_ASCII", "1"),
+            ("LANG", "en_US.UTF-8")
+        ]));
+        assert!(!ascii_only(&[
+            ("ZEROSH_ASCII", ""),
+            ("LANG", "en_US.UTF-8")
+        ]));
+    }
+
+    #[test]
+    fn display_path_collapses_home() {
+        let home = Some(Path::new("/home/me"));
+        assert_eq!(display_path(Path::new("/home/me"), home), "~");
+        assert_eq!(display_path(Path::new("/home/me/a/b"), home), "~/a/b");
+        assert_eq!(
+            display_path(Path::new("/home/mexico"), home),
+            "/home/mexico"
+        );
+        assert_eq!(display_path(Path::new("/tmp"), home), "/tmp");
+        assert_eq!(display_path(Path::new("/tmp"), None), "/tmp");
+    }
+
+    #[test]
+    fn display_path_keeps_root_home() {
+        // everything would be under `~`
+        assert_eq!(
+            display_path(Path::new("/usr"), Some(Path::new("/"))),
+            "/usr"
+        );
+    }
+
+    #[test]
+    fn truncate_path_keeps_the_last_components() {
+        assert_eq!(truncate_path("/a/b/c", 2, "…"), "…/b/c");
+        assert_eq!(truncate_path("/a/b/c", 1, "..."), ".../c");
+        assert_eq!(truncate_path("/a/b/c", 3, "…"), "/a/b/c");
+        assert_eq!(truncate_path("/a/b/c", 5, "…"), "/a/b/c");
+        assert_eq!(truncate_path("/a/b/c", 0, "…"), "/a/b/c");
+    }
+
+    #[test]
+    fn truncate_path_edge_cases() {
+        assert_eq!(truncate_path("/", 1, "…"), "/");
+        assert_eq!(truncate_path("~", 1, "…"), "~");
+        assert_eq!(truncate_path("~/a", 1, "…"), "~/a");
+        assert_eq!(truncate_path("/a/b/", 1, "…"), "…/b");
+    }
+
+    #[test]
+    fn truncate_path_composes_with_display_path() {
+        let path = display_path(Path::new("/home/me/x/y/z"), Some(Path::new("/home/me")));
+        assert_eq!(truncate_path(&path, 2, "…"), "…/y/z");
+        assert_eq!(truncate_path(&path, 3, "…"), "~/x/y/z");
+    }
+
+    #[test]
+    fn git_branches() {
+        let dir = env::temp_dir().join(format!("zerosh-git-branch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("repo/src")).unwrap();
+        fs::create_dir_all(dir.join("worktree")).unwrap();
+
+        fs::write(dir.join("repo/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(git_branch(&dir.join("repo")).as_deref(), Some("main"));
+        assert_eq!(git_branch(&dir.join("repo/src")).as_deref(), Some("main"));
+        fs::write(dir.join("repo/.git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(git_branch(&dir.join("repo")).as_deref(), Some("0123456"));
+
+        // `.git` of a worktree is a file
+        fs::create_dir_all(dir.join("repo/.git/worktrees/w")).unwrap();
+        fs::write(
+            dir.join("repo/.git/worktrees/w/HEAD"),
+            "ref: refs/heads/w\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("worktree/.git"),
+            "gitdir: ../repo/.git/worktrees/w\n",
+        )
+        .unwrap();
+        assert_eq!(git_branch(&dir.join("worktree")).as_deref(), Some("w"));
+
+        assert_eq!(git_branch(&dir), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,45 +1,2261 @@
 //! Worker thread.
 
-use nix::unistd;
+mod builtins;
+mod functions;
+mod hash;
+mod jobs;
+mod profile;
+mod redirect;
+mod vars;
+
+use nix::{
+    fcntl::OFlag,
+    libc,
+    poll::{self, PollFd, PollFlags},
+    sys::{
+        resource::{self, UsageWho},
+        signal::{self, SigHandler, Signal},
+        termios,
+        time::{TimeVal, TimeValLike},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::{self, ForkResult},
+};
 
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    sync::mpsc,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env,
+    ffi::CString,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{FromRawFd, RawFd},
+    },
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
     thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    editor, expand, glob,
+    parser::{self, CaseItem, Command, Compound, Connector, IfBranch, List, Pipeline, TimeFormat},
+    util::{self, Color},
+    DynError, Printer, Shell, ShellMsg, WorkerMsg,
 };
 
-use crate::{ShellMsg, WorkerMsg};
+use self::{
+    jobs::{Job, JobState, ProcessInfo},
+    redirect::Redirection,
+};
+
+/// Maximum number of command lines in [`Worker::parse_cache`]. The cache is cleared when it's full.
+const PARSE_CACHE_SIZE: usize = 256;
+
+/// Maximum number of directories in [`Worker::dir_history`]
+const DIR_HISTORY_SIZE: usize = 20;
+
+/// Time to wait for more `SIGCHLD`s before reaping, so that jobs finishing together are reaped
+/// in one pass and notified at once
+const CHLD_DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// `TIMEFORMAT` of `time` when it's not set, as in bash
+const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+
+/// Format of `time -p`
+const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+/// Stack size of the `worker` thread, where each nested function call takes a few KiB (more in
+/// debug builds)
+const WORKER_STACK_SIZE: usize = 16 << 20;
+
+/// Interval of checking for exits (or `Ctrl+c`) while a builtin waits for children (or input)
+/// interruptibly
+const INTERRUPT_POLL: Duration = Duration::from_millis(20);
+
+/// Set by the `signal_handler` thread when `SIGINT` arrives, so that builtins blocking in the
+/// `worker` can stop waiting
+pub(crate) type Interrupted = Arc<AtomicBool>;
+
+/// Function run in place of a command that is not found, with the command and its arguments as
+/// the positional parameters, like in Bash
+const NOT_FOUND_HANDLER: &str = "command_not_found_handle";
 
 #[derive(Debug)]
 pub struct Worker {
     /// Exit code
     exit_code: i32,
 
-    /// Foreground process ID
+    /// Set by the `exit` builtin. The shell quits after the current command.
+    quit: bool,
+
+    /// Set by the `return` builtin. The function or the file of `source` ends after the current
+    /// command.
+    returning: bool,
+
+    /// If the standard input is a terminal
+    interactive: bool,
+
+    /// Login shell, which runs `~/.zerosh_logout` when it exits
+    login: bool,
+
+    /// Terminal modes before the line editor changes them, restored when the shell is killed
+    termios: Option<termios::Termios>,
+
+    /// Set if `exit` was refused because of stopped jobs. `exit` right after that quits.
+    exit_warned: bool,
+
+    /// Foreground process group ID
     fg: Option<unistd::Pid>,
-    // jobs: BTreeMap<usize, (unistd::Pid, String)>,
-    // gpid_to_pid: HashMap<unistd::Pid, (usize, HashSet<unistd::Pid>)>,
-    // pid_to_info: HashMap<unistd::Pid, ProcessInfo>,
+
+    /// Process group ID of the shell itself
+    shell_gpid: unistd::Pid,
+
+    /// Job ID to the job
+    jobs: BTreeMap<usize, Job>,
+    /// Number of jobs started so far
+    jobs_started: usize,
+    /// Process group ID to the job ID and the processes that have not exited yet
+    gpid_to_pid: HashMap<unistd::Pid, (usize, HashSet<unistd::Pid>)>,
+    pid_to_info: HashMap<unistd::Pid, ProcessInfo>,
+    /// Processes of the jobs reported as done and their exit codes, the oldest first
+    finished: VecDeque<(unistd::Pid, i32)>,
+    /// Processes removed from the job table before they exited, e.g., by `disown`, reaped without
+    /// notices
+    orphans: HashSet<unistd::Pid>,
+
+    /// Variable scopes, the global one first
+    scopes: Vec<vars::Scope>,
+
+    /// Function names to the functions
+    functions: BTreeMap<String, Arc<functions::Function>>,
+    /// Arguments of the current function call, `$1` and so on
+    positional: Vec<String>,
+    /// Number of function calls inside each other
+    func_depth: usize,
+
+    /// `RANDOM`, `SECONDS`, `LINENO`, `$` and `PPID`
+    specials: vars::Specials,
+
+    /// Puts jobs in their own process groups and gives them the terminal. Off in subshells for
+    /// command substitutions, which stay in the shell's process group.
+    job_control: bool,
+
+    /// Exit code of the last command substitution in the current command
+    subst_code: Option<i32>,
+
+    /// Exit codes of the commands in the last foreground job, for `PIPESTATUS`
+    pipe_status: Option<Vec<i32>>,
+
+    /// Process ID of the last command of the last background job, for `$!`
+    last_bg_pid: Option<unistd::Pid>,
+    /// Foreground job stopped during the current command line, reported to the `main` thread
+    stopped_fg: Option<usize>,
+
+    /// Editing mode of the `main` thread's line editor, changed by `set -o`
+    edit_mode: rustyline::EditMode,
+
+    /// Assigned variables are exported, e.g., by `NAME=VALUE` alone (`set -a`)
+    allexport: bool,
+
+    /// `>` refuses to overwrite existing files (`set -o noclobber`)
+    noclobber: bool,
+
+    /// Pathname expansion is disabled (`set -f`)
+    noglob: bool,
+
+    /// Output redirections create the missing parent directories (`set -o mkdir_on_redirect`)
+    mkdir_on_redirect: bool,
+
+    /// A directory name as a command changes the directory (`set -o autocd`)
+    autocd: bool,
+
+    /// `cd FILE` changes to the parent directory of the file (`set -o cdfile`)
+    cdfile: bool,
+
+    /// The jobs are sent `SIGHUP` when the interactive shell exits or is replaced by `exec`
+    /// (`set -o huponexit`). Otherwise only the stopped ones are, and the others are left running.
+    huponexit: bool,
+
+    /// The exit code of a pipeline is the last nonzero one of the commands (`set -o pipefail`)
+    pipefail: bool,
+
+    /// The shell exits when a pipeline fails (`set -e`), unless it's a condition
+    errexit: bool,
+    /// Number of conditions being run inside each other, e.g., pipelines before `&&` or `||` and
+    /// negated ones, where `set -e` doesn't apply
+    conditions: usize,
+
+    /// History references are expanded by the `main` thread (`set -o histexpand`)
+    histexpand: editor::HistExpand,
+
+    /// Suggests similar names for unknown commands (`set -o suggest`). On in interactive mode.
+    suggest: bool,
+
+    /// Command line to the parsed list, so that repeated lines are not parsed again. Parsing
+    /// depends only on the line itself and the aliases, so `alias` and `unalias` clear it.
+    parse_cache: HashMap<String, Arc<List>>,
+    /// Alias names to their values, expanded before parsing
+    aliases: BTreeMap<String, String>,
+    /// Limits on the size of command lines and the nesting of `source`, set by the
+    /// [`Shell`](crate::Shell)
+    limits: parser::Limits,
+    /// Number of files being run by `source` inside each other
+    source_depth: usize,
+
+    /// Prints job notices above the prompt. `None` if the terminal doesn't support it.
+    printer: Option<Box<dyn Printer>>,
+
+    /// Job notices are printed as soon as jobs change (`set -b`), instead of before the next prompt
+    notify: bool,
+
+    /// Job notices waiting for the next prompt
+    notices: Vec<String>,
+
+    /// File descriptors above 2 opened by `exec` redirections. The others up to 9 are reserved
+    /// and not available to `>&` until then.
+    user_fds: HashSet<RawFd>,
+
+    /// Previous directories of `cd`, the most recent first, for `cd -N` and `dirs`
+    dir_history: VecDeque<PathBuf>,
+
+    /// Commands of `trap` run when the signals arrive. An empty command ignores the signal.
+    traps: HashMap<Signal, String>,
+    /// Command of `trap ... EXIT` run when the shell exits
+    exit_trap: Option<String>,
+    /// Adds signals to the ones the `signal_handler` thread forwards (or to `polled_signals`)
+    signal_handle: Option<signal_hook::iterator::Handle>,
+    /// Trapped signals without the `signal_handler` thread (`zerosh -c` or a subshell), checked
+    /// after each pipeline
+    polled_signals: Option<signal_hook::iterator::Signals>,
+    /// `Ctrl+c` was pressed. `None` without the `signal_handler` thread, where `SIGINT` is not
+    /// caught.
+    interrupted: Option<Interrupted>,
+
+    /// Completions of commands set by `complete`, shared with the line editor
+    comp_specs: editor::CompSpecs,
+    /// Time of the last key press in the line editor, which resets `TMOUT`
+    last_key: editor::LastKey,
+    /// Names of the shell variables last sent to the line editor, for completion
+    var_names: Vec<String>,
+    /// Locations of the external commands run so far, shown by `hash`
+    commands: hash::CommandTable,
 }
 
 impl Worker {
-    fn new() -> Self {
+    fn new(interactive: bool, printer: Option<Box<dyn Printer>>) -> Self {
         Self {
             exit_code: 0,
+            quit: false,
+            returning: false,
+            interactive,
+            login: false,
+            // called before the `main` thread starts reading input
+            termios: termios::tcgetattr(libc::STDIN_FILENO).ok(),
+            exit_warned: false,
             // the shell is the foreground process
             fg: None,
+            shell_gpid: unistd::getpgrp(),
+            jobs: BTreeMap::new(),
+            jobs_started: 0,
+            gpid_to_pid: HashMap::new(),
+            pid_to_info: HashMap::new(),
+            finished: VecDeque::new(),
+            orphans: HashSet::new(),
+            scopes: vec![vars::Scope::new()],
+            functions: BTreeMap::new(),
+            positional: Vec::new(),
+            func_depth: 0,
+            specials: vars::Specials::new(),
+            job_control: true,
+            subst_code: None,
+            pipe_status: None,
+            last_bg_pid: None,
+            stopped_fg: None,
+            edit_mode: rustyline::EditMode::Emacs,
+            allexport: false,
+            noclobber: false,
+            noglob: false,
+            mkdir_on_redirect: false,
+            autocd: false,
+            cdfile: false,
+            huponexit: false,
+            histexpand: editor::HistExpand::default(),
+            pipefail: false,
+            errexit: false,
+            conditions: 0,
+            suggest: interactive,
+            parse_cache: HashMap::new(),
+            aliases: BTreeMap::new(),
+            limits: parser::Limits::default(),
+            source_depth: 0,
+            printer,
+            notify: false,
+            notices: Vec::new(),
+            user_fds: HashSet::new(),
+            dir_history: VecDeque::new(),
+            traps: HashMap::new(),
+            exit_trap: None,
+            signal_handle: None,
+            polled_signals: None,
+            interrupted: None,
+            comp_specs: editor::CompSpecs::default(),
+            last_key: editor::LastKey::default(),
+            var_names: Vec::new(),
+            commands: hash::CommandTable::default(),
+        }
+    }
+
+    /// Runs a line of user input and returns the message to the `main` thread
+    fn process_cmd(&mut self, cmd: &str) -> ShellMsg {
+        let exit_warned = self.exit_warned;
+        let start = Instant::now();
+
+        match self.parse(cmd) {
+            Ok(Some(list)) => {
+                self.execute(&list);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                util::print_error(err);
+                self.exit_code = 2;
+            }
+        }
+
+        // the warning is valid only for the next command
+        if self.exit_warned == exit_warned && !cmd.is_empty() {
+            self.exit_warned = false;
+        }
+
+        if self.quit {
+            self.quit_msg()
+        } else {
+            ShellMsg::Continue {
+                code: self.exit_code,
+                jobs: self.jobs.len(),
+                duration: start.elapsed(),
+            }
+        }
+    }
+
+    /// Runs `~/.zerosh_logout` of a login shell and the `EXIT` trap, and returns the message to
+    /// quit
+    fn quit_msg(&mut self) -> ShellMsg {
+        self.hang_up_on_exit();
+        if self.login {
+            self.run_logout();
+        }
+        self.run_exit_trap();
+        ShellMsg::Quit {
+            code: self.exit_code,
+        }
+    }
+
+    /// Runs `PROMPT_COMMAND` before a prompt. `$?` is kept for the next command and the prompt.
+    fn run_prompt_command(&mut self) {
+        let Some(cmd) = self.var("PROMPT_COMMAND").filter(|cmd| !cmd.is_empty()) else {
+            return;
+        };
+
+        let exit_code = self.exit_code;
+        match self.parse(&cmd) {
+            Ok(Some(list)) => {
+                self.execute(&list);
+            }
+            Ok(None) => {}
+            Err(err) => util::print_error(format!("PROMPT_COMMAND: {err}")),
+        }
+        // `exit` in it sets the exit code
+        if !self.quit {
+            self.exit_code = exit_code;
+        }
+    }
+
+    /// Runs a parsed command line and returns the exit code. This is the executor itself,
+    /// independent of the messages from the `main` thread; `exit` only sets [`Worker::quit`].
+    pub(crate) fn execute(&mut self, list: &List) -> i32 {
+        self.run_list(list);
+        self.exit_code
+    }
+
+    /// Parses a command line, reusing the result for a line seen before
+    fn parse(&mut self, cmd: &str) -> Result<Option<Arc<List>>, parser::ParseError> {
+        if let Some(list) = self.parse_cache.get(cmd) {
+            return Ok(Some(Arc::clone(list)));
+        }
+
+        let expanded = parser::expand_aliases(cmd, &self.aliases)?;
+        let Some(list) = parser::parse(expanded.as_deref().unwrap_or(cmd), self.limits)? else {
+            return Ok(None);
+        };
+
+        if self.parse_cache.len() >= PARSE_CACHE_SIZE {
+            self.parse_cache.clear();
+        }
+        let list = Arc::new(list);
+        self.parse_cache.insert(cmd.to_string(), Arc::clone(&list));
+        Ok(Some(list))
+    }
+
+    /// Runs the pipelines in a list, skipping ones by `&&` and `||`. With `set -e`, a failed
+    /// pipeline that is not a condition quits.
+    fn run_list(&mut self, list: &List) {
+        for (i, (connector, pipeline)) in list.pipelines.iter().enumerate() {
+            let run = match connector {
+                Connector::Seq => true,
+                Connector::And => self.exit_code == 0,
+                Connector::Or => self.exit_code != 0,
+            };
+
+            if run {
+                let next = list.pipelines.get(i + 1).map(|&(connector, _)| connector);
+                let condition =
+                    pipeline.negated || matches!(next, Some(Connector::And | Connector::Or));
+                self.conditions += condition as usize;
+                let code = match pipeline.time {
+                    // a background pipeline is not timed
+                    Some(format) if !pipeline.background => {
+                        self.run_timed_pipeline(pipeline, format)
+                    }
+                    _ if pipeline.commands.is_empty() => 0,
+                    _ => self.run_pipeline(pipeline),
+                };
+                // commands run by the shell itself have only `$?`
+                let status = self.pipe_status.take().unwrap_or(vec![code]);
+                let status = status.iter().map(i32::to_string).collect();
+                self.set_array("PIPESTATUS", status);
+                self.exit_code = match pipeline.negated {
+                    true => (code == 0) as i32,
+                    false => code,
+                };
+                self.conditions -= condition as usize;
+                self.poll_signals();
+
+                if self.errexit && self.conditions == 0 && !pipeline.background {
+                    self.quit |= !condition && self.exit_code != 0;
+                }
+            }
+
+            if self.quit || self.returning {
+                break;
+            }
+        }
+    }
+
+    /// Runs a pipeline of `time` and prints the time it took in the format of `TIMEFORMAT` to the
+    /// standard error. The CPU times are the ones of the shell and its waited children.
+    fn run_timed_pipeline(&mut self, pipeline: &Pipeline, format: TimeFormat) -> i32 {
+        let start = Instant::now();
+        let cpu = self::cpu_times();
+        let code = match pipeline.commands.is_empty() {
+            true => 0,
+            false => self.run_pipeline(pipeline),
+        };
+        let real = start.elapsed();
+        let (user, sys) = self::cpu_times();
+        let (user, sys) = (user.saturating_sub(cpu.0), sys.saturating_sub(cpu.1));
+
+        let template = match format {
+            TimeFormat::Bash => self
+                .var("TIMEFORMAT")
+                .unwrap_or_else(|| DEFAULT_TIMEFORMAT.to_string()),
+            TimeFormat::Posix => POSIX_TIMEFORMAT.to_string(),
+        };
+        // an empty `TIMEFORMAT` shows nothing
+        if !template.is_empty() {
+            util::print_line(self::format_times(&template, real, user, sys));
+        }
+        code
+    }
+
+    /// Runs a pipeline in the foreground and returns the exit code
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        self.subst_code = None;
+
+        // `NAME=VALUE...` sets shell variables. Files of the redirections are only created.
+        if let [cmd] = &pipeline.commands[..] {
+            let assignments = cmd
+                .words
+                .iter()
+                .all(|word| parser::assignment(word).is_some());
+            if assignments && cmd.compound.is_none() {
+                if let Err(err) = self.open_redirects(&cmd.redirects) {
+                    util::print_error(err);
+                    return 1;
+                }
+                return self.assign(&cmd.words);
+            }
+        }
+
+        let mut commands = Vec::new();
+        // `NAME=VALUE` before each command, set only for the command
+        let mut temp_vars = Vec::new();
+        let mut redirections = Vec::new();
+        for cmd in &pipeline.commands {
+            let n_assigns = cmd
+                .words
+                .iter()
+                .take_while(|word| parser::assignment(word).is_some())
+                .count();
+            let (assigns, words) = cmd.words.split_at(n_assigns);
+
+            let mut vars = Vec::new();
+            for word in assigns {
+                let (name, value) = parser::assignment(word).unwrap();
+                match expand::expand_value(value, self) {
+                    Ok(value) => vars.push((name.to_string(), value)),
+                    Err(err) => {
+                        util::print_error(format!("{name}: {err}"));
+                        return 1;
+                    }
+                }
+            }
+            temp_vars.push(vars);
+
+            let declaration = words
+                .first()
+                .is_some_and(|word| builtins::is_declaration(&expand::unquote(word)));
+
+            let mut args = Vec::new();
+            for word in words {
+                let res = match parser::assignment(word) {
+                    // `declare NAME=(WORD...)` passes the elements quoted for `declare` to split
+                    Some((name, value)) if declaration && parser::array_words(value).is_some() => {
+                        self.expand_array(value).map(|elements| {
+                            let elements = elements.iter().map(|e| builtins::quote(e));
+                            vec![format!(
+                                "{name}=({})",
+                                elements.collect::<Vec<_>>().join(" ")
+                            )]
+                        })
+                    }
+                    // `declare NAME=$(cmd)` is not split, like a plain assignment
+                    Some((name, value)) if declaration => expand::expand_value(value, self)
+                        .map(|value| vec![format!("{name}={value}")]),
+                    _ => expand::expand_word(word, self),
+                };
+
+                match res {
+                    Ok(fields) => args.extend(fields),
+                    Err(err) => {
+                        util::print_error(err);
+                        return 1;
+                    }
+                }
+            }
+            commands.push(args);
+
+            match self.open_redirects(&cmd.redirects) {
+                Ok(files) => redirections.push(files),
+                Err(err) => {
+                    util::print_error(err);
+                    return 1;
+                }
+            }
+        }
+
+        // e.g., `$(true)`, where the assignments are kept like bash
+        if let ([args], None) = (&commands[..], &pipeline.commands[0].compound) {
+            if args.is_empty() {
+                for (name, value) in temp_vars.remove(0) {
+                    if let Err(err) = self.set_var(&name, value) {
+                        util::print_error(format!("{name}: {err}"));
+                        return 1;
+                    }
+                }
+                return self.subst_code.unwrap_or(0);
+            }
+        }
+        let empty = commands
+            .iter()
+            .zip(&pipeline.commands)
+            .any(|(args, cmd)| args.is_empty() && cmd.compound.is_none());
+        if empty {
+            util::print_error("empty command in a pipeline");
+            return 1;
+        }
+        // they can't be passed to `execve`, e.g., pasted binary data
+        let values = temp_vars.iter().flatten().map(|(_, value)| value);
+        if let Some(arg) = commands
+            .iter()
+            .flatten()
+            .chain(values)
+            .find(|arg| arg.contains('\0'))
+        {
+            let arg = arg.replace('\0', "\\0");
+            util::print_error(format!("{arg}: argument contains a NUL byte"));
+            return 1;
+        }
+
+        // `{ LIST; }`, `case`, `if`, loops and function definitions run in the shell itself,
+        // unless in background
+        if let [Command {
+            compound:
+                Some(
+                    compound @ (Compound::Group(_)
+                    | Compound::Case { .. }
+                    | Compound::If { .. }
+                    | Compound::While { .. }
+                    | Compound::Function { .. }),
+                ),
+            ..
+        }] = &pipeline.commands[..]
+        {
+            if !pipeline.background {
+                let redirections = &redirections[0];
+                if let Err(err) = self.check_shell_fds(redirections) {
+                    util::print_error(err);
+                    return 1;
+                }
+                return redirect::with_redirections(redirections, || self.run_compound(compound));
+            }
+        }
+
+        // commands run by the shell itself
+        if let ([args], None) = (&commands[..], &pipeline.commands[0].compound) {
+            let redirections = &redirections[0];
+            let autocd = self.autocd && args.len() == 1 && self::is_autocd(&args[0]);
+            let builtin = builtins::find(&args[0])
+                .filter(|builtin| !builtin.forks && (builtin.shell_state || !pipeline.background));
+            if let Some(builtin) = builtin.filter(|_| pipeline.background) {
+                util::print_warning(format!(
+                    "{}: changes the shell's state, so it runs in the foreground",
+                    builtin.name
+                ));
+            }
+            let function = self
+                .functions
+                .get(&args[0])
+                .filter(|_| !pipeline.background)
+                .cloned();
+            if autocd || builtin.is_some() || function.is_some() {
+                if let Err(err) = self.check_shell_fds(redirections) {
+                    util::print_error(err);
+                    return 1;
+                }
+            }
+
+            // functions come before builtins and commands
+            if let Some(function) = function {
+                return self.with_temp_vars(&temp_vars[0], |worker| {
+                    redirect::with_redirections(redirections, || {
+                        worker.call_function(&function, args)
+                    })
+                });
+            }
+
+            // `set -o autocd`: a directory name that is not a command changes the directory
+            if autocd {
+                let cd = ["cd".to_string(), args[0].clone()];
+                return self.with_temp_vars(&temp_vars[0], |worker| {
+                    redirect::with_redirections(redirections, || worker.run_cd(&cd))
+                });
+            }
+
+            // `exec` without a command keeps the redirections in the shell
+            if args.len() == 1 && args[0] == "exec" {
+                self.keep_redirections(redirections);
+                return 0;
+            }
+
+            if let Some(builtin) = builtin {
+                return self.with_temp_vars(&temp_vars[0], |worker| {
+                    redirect::with_redirections(redirections, || builtin.call(worker, args))
+                });
+            }
+        }
+
+        self.spawn_pipeline(&commands, &temp_vars, &redirections, pipeline)
+    }
+
+    /// Runs a function with variables set and exported temporarily, e.g., a builtin of
+    /// `NAME=VALUE CMD`
+    fn with_temp_vars(
+        &mut self,
+        vars: &[(String, String)],
+        f: impl FnOnce(&mut Self) -> i32,
+    ) -> i32 {
+        let saved = match self.set_temp_vars(vars) {
+            Ok(saved) => saved,
+            Err(err) => {
+                util::print_error(err);
+                return 1;
+            }
+        };
+        let code = f(self);
+        self.restore_vars(saved);
+        code
+    }
+
+    /// Runs a compound command in the current process and returns the exit code
+    fn run_compound(&mut self, compound: &Compound) -> i32 {
+        match compound {
+            Compound::Group(list) | Compound::Subshell(list) => {
+                self.run_list(list);
+                self.exit_code
+            }
+            Compound::Case { word, items } => self.run_case(word, items),
+            Compound::If {
+                branches,
+                otherwise,
+            } => self.run_if(branches, otherwise.as_ref()),
+            Compound::While {
+                condition,
+                body,
+                until,
+            } => self.run_while(condition, body, *until),
+            Compound::Function { name, body, text } => {
+                self.define_function(name, body, text);
+                0
+            }
+        }
+    }
+
+    /// Runs the first branch of `case` with a pattern matching the word. The exit code is 0 if
+    /// no branch matches or the branch is empty.
+    fn run_case(&mut self, word: &str, items: &[CaseItem]) -> i32 {
+        let word = match expand::expand_value(word, self) {
+            Ok(word) => word,
+            Err(err) => {
+                util::print_error(err);
+                return 1;
+            }
+        };
+
+        for item in items {
+            for pattern in &item.patterns {
+                let pattern = match expand::expand_pattern(pattern, self) {
+                    Ok(pattern) => pattern,
+                    Err(err) => {
+                        util::print_error(err);
+                        return 1;
+                    }
+                };
+                if !glob::matches(&pattern, &word) {
+                    continue;
+                }
+
+                if item.body.pipelines.is_empty() {
+                    return 0;
+                }
+                self.run_list(&item.body);
+                return self.exit_code;
+            }
+        }
+
+        0
+    }
+
+    /// Runs the body of the first branch of `if` whose condition succeeds, or the `else` branch.
+    /// The exit code is 0 if no branch runs.
+    fn run_if(&mut self, branches: &[IfBranch], otherwise: Option<&List>) -> i32 {
+        for branch in branches {
+            let success = self.run_condition(&branch.condition);
+            if self.quit || self.returning {
+                return self.exit_code;
+            }
+            if success {
+                self.run_list(&branch.body);
+                return self.exit_code;
+            }
+        }
+
+        match otherwise {
+            Some(list) => {
+                self.run_list(list);
+                self.exit_code
+            }
+            None => 0,
+        }
+    }
+
+    /// Runs the body of `while` as long as the condition succeeds, or fails with `until`. The loop
+    /// also ends on `exit`, `return` or `Ctrl+c`. The exit code is the one of the last body, or 0
+    /// if it never runs.
+    fn run_while(&mut self, condition: &List, body: &List, until: bool) -> i32 {
+        let mut code = 0;
+        loop {
+            let success = self.run_condition(condition);
+            if self.quit || self.returning {
+                return self.exit_code;
+            }
+            if success == until || self.take_interrupt() {
+                return code;
+            }
+
+            self.run_list(body);
+            code = self.exit_code;
+            if self.quit || self.returning || self.take_interrupt() {
+                return code;
+            }
+        }
+    }
+
+    /// Runs the list of a condition, where `set -e` doesn't quit, and returns whether it
+    /// succeeded
+    fn run_condition(&mut self, list: &List) -> bool {
+        self.conditions += 1;
+        self.run_list(list);
+        self.conditions -= 1;
+        self.exit_code == 0
+    }
+
+    /// Runs assignment words and returns the exit code of the last command substitution
+    fn assign(&mut self, words: &[String]) -> i32 {
+        for word in words {
+            let (name, value) = parser::assignment(word).unwrap();
+            let res = match parser::array_words(value) {
+                Some(_) => self
+                    .expand_array(value)
+                    .map(|elements| self.set_array(name, elements)),
+                None => {
+                    expand::expand_value(value, self).and_then(|value| self.set_var(name, value))
+                }
+            };
+            if let Err(err) = res {
+                util::print_error(format!("{name}: {err}"));
+                return 1;
+            }
+        }
+
+        self.subst_code.unwrap_or(0)
+    }
+
+    /// Expands an array assignment value `(WORD...)`, where the words are split into elements
+    /// like arguments
+    fn expand_array(&mut self, value: &str) -> Result<Vec<String>, String> {
+        let words = parser::array_words(value)
+            .unwrap_or_else(|| Ok(Vec::new()))
+            .map_err(|err| err.to_string())?;
+        let mut elements = Vec::new();
+        for word in &words {
+            elements.extend(expand::expand_word(word, self)?);
+        }
+        Ok(elements)
+    }
+
+    /// Forks the commands connected with pipes as a new job. Waits for it unless it's run in
+    /// background. `temp_vars` has the variables exported to each command, and `redirections`
+    /// has the opened files.
+    fn spawn_pipeline(
+        &mut self,
+        commands: &[Vec<String>],
+        temp_vars: &[Vec<(String, String)>],
+        redirections: &[Vec<Redirection>],
+        pipeline: &Pipeline,
+    ) -> i32 {
+        let mut gpid = None;
+        let mut pids = Vec::new();
+        // read end of the previous pipe
+        let mut input = None;
+
+        for (i, args) in commands.iter().enumerate() {
+            let pipe = if i + 1 < commands.len() {
+                // close-on-exec so that the pipes never leak into unrelated children
+                match unistd::pipe2(OFlag::O_CLOEXEC) {
+                    Ok(pipe) => Some(pipe),
+                    Err(err) => {
+                        util::print_error(format!("unable to create a pipe: {err}"));
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+
+            let compound = pipeline.commands[i].compound.as_ref();
+            // the child inherits the environment, and the shell restores it right away
+            let res = self
+                .set_temp_vars(&temp_vars[i])
+                .map_err(DynError::from)
+                .and_then(|saved| {
+                    let res =
+                        self.fork_command(args, compound, &redirections[i], gpid, input, pipe);
+                    self.restore_vars(saved);
+                    res
+                });
+
+            // the parent doesn't use the pipes itself
+            if let Some(fd) = input.take() {
+                let _ = unistd::close(fd);
+            }
+            if let Some((read, write)) = pipe {
+                let _ = unistd::close(write);
+                input = Some(read);
+            }
+
+            match res {
+                Ok(pid) => {
+                    gpid.get_or_insert(pid);
+                    pids.push(pid);
+                }
+                Err(err) => {
+                    util::print_error(err);
+                    break;
+                }
+            }
+        }
+
+        if let Some(fd) = input {
+            let _ = unistd::close(fd);
+        }
+
+        let gpid = match gpid {
+            Some(gpid) => gpid,
+            None => return 1,
+        };
+
+        let job_id = self.insert_job(gpid, &pids, &pipeline.text);
+
+        if pipeline.background {
+            self.last_bg_pid = pids.last().copied();
+            if self.interactive {
+                util::print_line(format_args!("[{job_id}] {gpid}"));
+            }
+            return 0;
+        }
+
+        let code = self.put_job_fg(job_id);
+
+        // the last command failed to start
+        if pids.len() < commands.len() {
+            if let Some(status) = &mut self.pipe_status {
+                status.resize(commands.len(), 1);
+            }
+            return 1;
+        }
+
+        code
+    }
+
+    /// Gives the terminal to a job and waits until it exits or stops. Returns the exit code.
+    fn put_job_fg(&mut self, job_id: usize) -> i32 {
+        let gpid = self.jobs[&job_id].gpid;
+
+        if !self.job_control {
+            return self.wait_fg(job_id);
+        }
+
+        self.fg = Some(gpid);
+        self.set_terminal_fg(gpid);
+        let code = self.wait_fg(job_id);
+        self.set_terminal_fg(self.shell_gpid);
+        self.fg = None;
+
+        code
+    }
+
+    /// Runs a command line in a subshell and returns its standard output. Bytes that are not
+    /// valid UTF-8 are replaced with U+FFFD, as variables and arguments are strings.
+    fn substitute(&mut self, cmd: &str) -> Result<(String, i32), DynError> {
+        let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+        let child = match util::run_syscall(|| unsafe { unistd::fork() }) {
+            Ok(ForkResult::Child) => {
+                let _ = unistd::close(read);
+                let _ = util::run_syscall(|| unistd::dup2(write, libc::STDOUT_FILENO));
+                let _ = unistd::close(write);
+
+                // the `signal_handler` thread doesn't exist in the subshell
+                unsafe {
+                    for signal in [
+                        Signal::SIGINT,
+                        Signal::SIGQUIT,
+                        Signal::SIGHUP,
+                        Signal::SIGTERM,
+                    ] {
+                        let _ = signal::signal(signal, SigHandler::SigDfl);
+                    }
+                    let _ = signal::signal(Signal::SIGTSTP, SigHandler::SigIgn);
+                }
+
+                self.enter_subshell();
+                // never unwind back into the loop of the parent's worker
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| self.process_cmd(cmd)));
+                let _ = io::stdout().flush();
+                unsafe { libc::_exit(self.exit_code) }
+            }
+            Ok(ForkResult::Parent { child }) => child,
+            Err(err) => {
+                let _ = unistd::close(read);
+                let _ = unistd::close(write);
+                return Err(err.into());
+            }
+        };
+
+        let _ = unistd::close(write);
+        let mut output = Vec::new();
+        let res = unsafe { std::fs::File::from_raw_fd(read) }.read_to_end(&mut output);
+
+        let code = match util::run_syscall(|| wait::waitpid(child, None))? {
+            WaitStatus::Exited(_, code) => code,
+            WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+            _ => 0,
+        };
+
+        res?;
+        Ok((String::from_utf8_lossy(&output).into_owned(), code))
+    }
+
+    /// Forks a child process that runs a command in the process group (or a new one if `None`).
+    ///
+    /// `input` and `pipe` are pipe ends the child connects to its standard input and output.
+    /// Redirections are applied after them. A compound command is run by the child itself, and
+    /// `args` are empty then.
+    fn fork_command(
+        &mut self,
+        args: &[String],
+        compound: Option<&Compound>,
+        redirections: &[Redirection],
+        gpid: Option<unistd::Pid>,
+        input: Option<RawFd>,
+        pipe: Option<(RawFd, RawFd)>,
+    ) -> Result<unistd::Pid, DynError> {
+        let function = args
+            .first()
+            .and_then(|name| self.functions.get(name))
+            .cloned();
+        let builtin = args
+            .first()
+            .and_then(|name| builtins::find(name))
+            .filter(|_| function.is_none());
+
+        let c_args = args
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // allocating in the forked child is not safe, so prepare the message beforehand
+        let name = args.first().map_or("", String::as_str);
+        // a name with a slash is run as a path without searching `PATH`
+        let is_path = name.contains('/');
+        let (not_found, not_found_code) = if is_path {
+            self::exec_path_error(name)
+        } else {
+            let command = builtin.is_none() && function.is_none() && compound.is_none();
+            let suggestion = match self.suggest && command {
+                true => self::suggest_command(name),
+                false => None,
+            };
+            let msg = match suggestion {
+                Some(suggestion) => {
+                    format!("command not found: {name} (did you mean: {suggestion}?)")
+                }
+                None => format!("command not found: {name}"),
+            };
+            (msg, 127)
+        };
+        let not_found = format!("{}\n", util::error_text(not_found));
+        let handler = match builtin.is_none() && function.is_none() && compound.is_none() {
+            true if !is_path => self.functions.get(NOT_FOUND_HANDLER).cloned(),
+            _ => None,
+        };
+
+        // the location remembered by the shell, so that `PATH` isn't searched every time
+        let program = match builtin.is_none() && function.is_none() && compound.is_none() {
+            true => self.commands.locate(name, true),
+            false => None,
+        };
+        let program = program.and_then(|path| CString::new(path.as_os_str().as_bytes()).ok());
+
+        let gpid = gpid.unwrap_or_else(|| unistd::Pid::from_raw(0));
+
+        match util::run_syscall(|| unsafe { unistd::fork() })? {
+            ForkResult::Child => {
+                if self.job_control {
+                    let _ = unistd::setpgid(unistd::Pid::from_raw(0), gpid);
+                }
+
+                if let Some(fd) = input {
+                    let _ = util::run_syscall(|| unistd::dup2(fd, libc::STDIN_FILENO));
+                    let _ = unistd::close(fd);
+                }
+                if let Some((read, write)) = pipe {
+                    let _ = util::run_syscall(|| unistd::dup2(write, libc::STDOUT_FILENO));
+                    let _ = unistd::close(read);
+                    let _ = unistd::close(write);
+                }
+                redirect::connect(redirections);
+
+                self::reset_signals();
+
+                if let Some(compound) = compound {
+                    self.enter_subshell();
+                    // never unwind back into the loop of the parent's worker
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| self.run_compound(compound)));
+                    self.run_exit_trap();
+                    let _ = io::stdout().flush();
+                    unsafe { libc::_exit(self.exit_code) }
+                }
+
+                if let Some(function) = &function {
+                    self.enter_subshell();
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                        self.exit_code = self.call_function(function, args);
+                    }));
+                    self.run_exit_trap();
+                    let _ = io::stdout().flush();
+                    unsafe { libc::_exit(self.exit_code) }
+                }
+
+                if let Some(builtin) = builtin {
+                    let code = builtin.call(self, args);
+                    let _ = io::stdout().flush();
+                    unsafe { libc::_exit(code) }
+                }
+
+                if let Some(program) = &program {
+                    let _ = unistd::execv(program, &c_args);
+                }
+                let _ = unistd::execvp(&c_args[0], &c_args);
+                if let Some(handler) = &handler {
+                    self.run_not_found_handler(handler, args);
+                }
+                let _ = unistd::write(libc::STDERR_FILENO, not_found.as_bytes());
+                unsafe { libc::_exit(not_found_code) }
+            }
+            ForkResult::Parent { child } => {
+                // set the process group from the parent side, too, to avoid race conditions
+                let gpid = if gpid.as_raw() == 0 { child } else { gpid };
+                if self.job_control {
+                    let _ = unistd::setpgid(child, gpid);
+                }
+                Ok(child)
+            }
+        }
+    }
+
+    /// Runs [`NOT_FOUND_HANDLER`] in the forked child of a command that is not found and exits
+    /// with its exit code
+    fn run_not_found_handler(&mut self, handler: &functions::Function, args: &[String]) -> ! {
+        self.enter_subshell();
+        // so that a command not found by the handler doesn't run it again
+        self.functions.remove(NOT_FOUND_HANDLER);
+        let args = std::iter::once(NOT_FOUND_HANDLER.to_string())
+            .chain(args.iter().cloned())
+            .collect::<Vec<_>>();
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.exit_code = self.call_function(handler, &args);
+        }));
+        self.run_exit_trap();
+        let _ = io::stdout().flush();
+        unsafe { libc::_exit(self.exit_code) }
+    }
+
+    /// Blocks until the foreground job exits or stops, returning the exit code of the last process
+    ///
+    /// Children are reaped only on the worker thread, here and by [`Worker::reap_children`]
+    /// between commands, and both record the statuses in the job table. So a process reaped by one
+    /// is not waited for by the other, and `ECHILD` means the job has nothing left to wait for.
+    /// Without job control, the foreground job shares the process group of the shell, so any
+    /// process of the job table is waited for by [`Worker::wait_known`].
+    fn wait_fg(&mut self, job_id: usize) -> i32 {
+        let gpid = self.jobs[&job_id].gpid;
+        let group = unistd::Pid::from_raw(-gpid.as_raw());
+
+        loop {
+            match self.job_state(job_id) {
+                Some(JobState::Running) => {}
+                Some(JobState::Stopped) => {
+                    let msg = self.format_job(job_id, JobState::Stopped);
+                    util::print_line(format_args!("\n{}", util::paint(Color::Yellow, &msg)));
+                    self.stopped_fg = Some(job_id);
+                    // as if it was killed by `SIGTSTP`
+                    break 128 + libc::SIGTSTP;
+                }
+                Some(JobState::Done) => {
+                    let Some(job) = self.remove_job(job_id) else {
+                        break 0;
+                    };
+                    let code = self.job_exit_code(&job);
+                    // `Ctrl+c` and closed pipes are usual
+                    if let Some((signal, core_dumped)) = job.signal {
+                        if !matches!(signal, Signal::SIGINT | Signal::SIGPIPE) {
+                            util::print_line(jobs::signal_message(signal, core_dumped));
+                        }
+                    }
+                    self.pipe_status = Some(job.exit_codes);
+                    break code;
+                }
+                None => break 0,
+            }
+
+            let res = if self.job_control {
+                util::run_syscall(|| wait::waitpid(group, Some(WaitPidFlag::WUNTRACED)))
+            } else {
+                self.wait_known(WaitPidFlag::WUNTRACED)
+            };
+            match res {
+                Ok(status) => {
+                    self.update_process(status);
+                }
+                Err(nix::Error::ECHILD) => {
+                    // the rest were reaped without the table knowing, so the job is done with
+                    // the statuses recorded so far
+                    self.forget_processes(job_id);
+                }
+                Err(err) => {
+                    util::print_error(format!("unable to wait for {gpid}: {err}"));
+                    break 1;
+                }
+            }
+        }
+    }
+
+    fn handle_signal(&mut self, signal: i32) {
+        let trap = Signal::try_from(signal)
+            .ok()
+            .and_then(|signal| self.traps.get(&signal))
+            .cloned();
+        if let Some(trap) = trap {
+            match signal {
+                libc::SIGCHLD => self.reap_children(),
+                libc::SIGWINCH => self.update_window_size(),
+                _ => {}
+            }
+            self.run_trap(&trap);
+            return;
+        }
+
+        if signal == libc::SIGWINCH {
+            self.update_window_size();
+        }
+        match signal {
+            libc::SIGCHLD => self.reap_children(),
+            libc::SIGWINCH => {}
+            libc::SIGHUP | libc::SIGTERM => self.hang_up(signal),
+            // handled by the line editor or ignored by the interactive shell
+            libc::SIGINT | libc::SIGQUIT | libc::SIGTSTP if self.polled_signals.is_none() => {}
+            // the trap was removed, but the signal is still caught
+            _ => {
+                let _ = signal_hook::low_level::emulate_default_handler(signal);
+            }
+        }
+    }
+
+    /// Handles the trapped signals that arrived while running a pipeline, without the
+    /// `signal_handler` thread
+    fn poll_signals(&mut self) {
+        let Some(signals) = &mut self.polled_signals else {
+            return;
+        };
+        let pending = signals.pending().collect::<Vec<_>>();
+        for signal in pending {
+            self.handle_signal(signal);
+        }
+    }
+
+    /// Runs the command of a trap keeping `$?` and `PIPESTATUS`. Quits the shell if the command
+    /// runs `exit`.
+    fn run_trap(&mut self, cmd: &str) {
+        if cmd.is_empty() {
+            return;
+        }
+
+        let exit_code = self.exit_code;
+        let pipe_status = self.array("PIPESTATUS");
+        self.process_cmd(cmd);
+        if self.quit {
+            // the `main` thread is waiting for user input and can't be told to quit
+            self.restore_terminal();
+            let _ = io::stdout().flush();
+            std::process::exit(self.exit_code);
+        }
+        self.exit_code = exit_code;
+        if let Some(status) = pipe_status {
+            self.set_array("PIPESTATUS", status);
+        }
+    }
+
+    /// Runs the `EXIT` trap once
+    fn run_exit_trap(&mut self) {
+        if let Some(cmd) = self.exit_trap.take() {
+            let exit_code = self.exit_code;
+            self.quit = false;
+            self.process_cmd(&cmd);
+            // `exit` in the trap sets the exit code
+            if !self.quit {
+                self.exit_code = exit_code;
+            }
+            self.quit = true;
+        }
+    }
+
+    /// Restores the signals of traps in a subshell. Ignored signals stay ignored.
+    /// Prepares a forked child to run commands like a shell
+    fn enter_subshell(&mut self) {
+        self.job_control = false;
+        // or the subshell would repeat the numbers of the parent
+        self.specials.reseed();
+        self.reset_traps();
+    }
+
+    fn reset_traps(&mut self) {
+        for (signal, cmd) in self.traps.drain() {
+            let handler = match cmd.is_empty() {
+                true => SigHandler::SigIgn,
+                false => SigHandler::SigDfl,
+            };
+            let _ = unsafe { signal::signal(signal, handler) };
+        }
+        self.exit_trap = None;
+        self.signal_handle = None;
+        self.polled_signals = None;
+        self.interrupted = None;
+    }
+
+    /// Sends `SIGHUP` to the jobs and exits, e.g., when the terminal is closed. The `main` thread
+    /// is blocked in `readline`, but it saves the history after each command, so nothing is lost.
+    fn hang_up(&mut self, signal: i32) -> ! {
+        self.hang_up_jobs(true);
+        self.run_exit_trap();
+        self.restore_terminal();
+        std::process::exit(128 + signal);
+    }
+
+    /// Hangs up the jobs as the shell exits, knowing which are stopped even without `SIGCHLD`
+    /// handled, e.g., in `zerosh -c`. The changes are not notified any more.
+    fn hang_up_on_exit(&mut self) {
+        self.update_jobs();
+        self.hang_up_jobs(self.huponexit);
+    }
+
+    /// Sends `SIGHUP` to the jobs as the shell exits, or only to the stopped ones unless `all`, as
+    /// they would never run again. Jobs of `disown -h` are skipped.
+    fn hang_up_jobs(&self, all: bool) {
+        for (&job_id, job) in &self.jobs {
+            if job.nohup || !(all || self.job_state(job_id) == Some(JobState::Stopped)) {
+                continue;
+            }
+            let _ = self.signal_job(job_id, Some(Signal::SIGHUP));
+            // stopped jobs handle the signal once continued
+            let _ = self.signal_job(job_id, Some(Signal::SIGCONT));
+        }
+    }
+
+    /// Returns when `TMOUT` ends the interactive shell, waiting for a command since `idle_since`.
+    /// Key presses put it off. `None` if `TMOUT` is not a positive number of seconds.
+    fn idle_deadline(&self, idle_since: Instant) -> Option<Instant> {
+        if !self.interactive {
+            return None;
+        }
+        let secs = self
+            .var("TMOUT")?
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|&secs| secs > 0)?;
+        let last_key = *self.last_key.lock().unwrap();
+        let since = last_key.map_or(idle_since, |last_key| last_key.max(idle_since));
+        Some(since + Duration::from_secs(secs))
+    }
+
+    /// Exits the shell after `TMOUT` without input, like bash. The `main` thread is blocked in
+    /// `readline`, as with [`Worker::hang_up`].
+    fn auto_logout(&mut self) -> ! {
+        self.restore_terminal();
+        // below the input line
+        util::print_line("\ntimed out waiting for input: auto-logout");
+
+        if self.login {
+            self.run_logout();
+        }
+        self.run_exit_trap();
+        let _ = io::stdout().flush();
+        std::process::exit(self.exit_code);
+    }
+
+    /// Restores the terminal settings, as the line editor may have put it in raw mode
+    fn restore_terminal(&self) {
+        if let Some(termios) = &self.termios {
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, termios::SetArg::TCSANOW, termios);
+        }
+    }
+
+    /// Reaps background processes and notifies job state changes.
+    ///
+    /// Signals are merged, so one `SIGCHLD` may stand for many children. All the pending statuses
+    /// are drained first (each is an O(1) lookup in `pid_to_info`), and then each changed job is
+    /// notified once.
+    fn reap_children(&mut self) {
+        for (job_id, is_stop) in self.update_jobs() {
+            self.notify_job_change(job_id, is_stop);
+        }
+        self.show_notices();
+    }
+
+    /// Reaps the children on a tick of the `timer` thread while idle, in case a `SIGCHLD` was
+    /// missed, so that the job table and the notices catch up within a tick
+    fn poll_jobs(&mut self) {
+        if !self.jobs.is_empty() {
+            self.reap_children();
+        }
+    }
+
+    /// Drains the pending statuses of the children into the job table without notices. Returns
+    /// the changed jobs with whether a process of each has stopped.
+    fn update_jobs(&mut self) -> BTreeMap<usize, bool> {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        let mut changed = BTreeMap::new();
+
+        loop {
+            let status = match self.wait_known(flags) {
+                Ok(WaitStatus::StillAlive) | Err(nix::Error::ECHILD) => break,
+                Ok(status) => status,
+                Err(err) => {
+                    util::print_error(format!("unable to wait for children: {err}"));
+                    break;
+                }
+            };
+
+            let is_stop = matches!(status, WaitStatus::Stopped(..));
+            if let Some(job_id) = self.update_process(status) {
+                *changed.entry(job_id).or_insert(false) |= is_stop;
+            }
+        }
+        changed
+    }
+
+    /// Blocks until a child process exits, stops or continues and updates the job table. Returns
+    /// the wait status and the ID of the job the process belongs to.
+    fn wait_child(&mut self) -> nix::Result<(WaitStatus, Option<usize>)> {
+        let flags = WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        let status = self.wait_known(flags)?;
+        Ok((status, self.update_process(status)))
+    }
+
+    /// `waitpid(-1, flags)` for the processes of the job table, and the orphans reaped on the way.
+    /// Other children, e.g., a command of `complete -C` run by the `main` thread, are only peeked
+    /// at with `WNOWAIT` and left to whoever started them, while the known ones are polled.
+    /// `ECHILD` if no known process is left.
+    fn wait_known(&mut self, flags: WaitPidFlag) -> nix::Result<WaitStatus> {
+        let nohang = flags | WaitPidFlag::WNOHANG;
+        loop {
+            let peek = flags | WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT;
+            let pid = match util::run_syscall(|| wait::waitid(wait::Id::All, peek))?.pid() {
+                Some(pid) => pid,
+                None => return Ok(WaitStatus::StillAlive),
+            };
+            if self.pid_to_info.contains_key(&pid) {
+                return util::run_syscall(|| wait::waitpid(pid, Some(nohang)));
+            }
+            if self.orphans.contains(&pid) {
+                if let Ok(WaitStatus::Exited(..) | WaitStatus::Signaled(..)) =
+                    util::run_syscall(|| wait::waitpid(pid, Some(nohang)))
+                {
+                    self.orphans.remove(&pid);
+                }
+                continue;
+            }
+
+            let mut alive = false;
+            let pids = self.pid_to_info.keys().copied().collect::<Vec<_>>();
+            for pid in pids {
+                match util::run_syscall(|| wait::waitpid(pid, Some(nohang))) {
+                    Ok(WaitStatus::StillAlive) => alive = true,
+                    // reaped without the table knowing
+                    Err(nix::Error::ECHILD) => {}
+                    res => return res,
+                }
+            }
+            if !alive {
+                return Err(nix::Error::ECHILD);
+            }
+            if flags.contains(WaitPidFlag::WNOHANG) {
+                return Ok(WaitStatus::StillAlive);
+            }
+            thread::sleep(INTERRUPT_POLL);
+        }
+    }
+
+    /// Like [`Worker::wait_child`], but fails with `EINTR` when `Ctrl+c` is pressed, for builtins
+    /// that may wait forever. The `signal_handler` thread only sets a flag, so the exits are
+    /// polled meanwhile.
+    fn wait_child_interruptible(&mut self) -> nix::Result<(WaitStatus, Option<usize>)> {
+        let Some(interrupted) = self.interrupted.clone() else {
+            return self.wait_child();
+        };
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        loop {
+            if interrupted.swap(false, Ordering::Relaxed) {
+                return Err(nix::Error::EINTR);
+            }
+            match self.wait_known(flags)? {
+                WaitStatus::StillAlive => thread::sleep(INTERRUPT_POLL),
+                status => return Ok((status, self.update_process(status))),
+            }
+        }
+    }
+
+    /// Reads a byte of the standard input, or fails with `EINTR` when `Ctrl+c` is pressed like
+    /// [`Worker::wait_child_interruptible`], for `read`. The input is polled meanwhile. `None` at
+    /// the end of the input.
+    fn read_byte_interruptible(&self) -> nix::Result<Option<u8>> {
+        loop {
+            if let Some(interrupted) = &self.interrupted {
+                if interrupted.swap(false, Ordering::Relaxed) {
+                    return Err(nix::Error::EINTR);
+                }
+                let mut fds = [PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN)];
+                match poll::poll(&mut fds, INTERRUPT_POLL.as_millis() as i32) {
+                    Ok(0) | Err(nix::Error::EINTR) => continue,
+                    Ok(_) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            let mut byte = [0];
+            match unistd::read(libc::STDIN_FILENO, &mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(nix::Error::EINTR) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns `true` once if `Ctrl+c` was pressed while the shell itself was running, or if it
+    /// killed the last foreground command, e.g., to end a loop. Always `false` without the
+    /// `signal_handler` thread, where `SIGINT` kills the shell.
+    fn take_interrupt(&self) -> bool {
+        let Some(interrupted) = &self.interrupted else {
+            return false;
+        };
+        interrupted.swap(false, Ordering::Relaxed) || self.exit_code == 128 + libc::SIGINT
+    }
+
+    /// Forgets `Ctrl+c` pressed before, e.g., while the line editor was reading
+    fn clear_interrupt(&self) {
+        if let Some(interrupted) = &self.interrupted {
+            interrupted.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Checks that a job still has processes right before `fg` or `bg` continues it, as it may
+    /// have exited before its `SIGCHLD` was handled. The exits are collected without blocking,
+    /// and processes that `kill(pid, 0)` doesn't find are taken as exited with 127, their status
+    /// unknown. If the job is done, it's reported and removed, and its exit code is returned.
+    fn check_job_done(&mut self, job_id: usize) -> Option<i32> {
+        let gpid = self.jobs.get(&job_id)?.gpid;
+        let pids = self.gpid_to_pid.get(&gpid)?.1.clone();
+
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        for pid in pids {
+            let status = match util::run_syscall(|| wait::waitpid(pid, Some(flags))) {
+                Ok(WaitStatus::StillAlive) if signal::kill(pid, None).is_ok() => continue,
+                Ok(WaitStatus::StillAlive) | Err(_) => {
+                    // reaped by someone else, so the exit status is lost
+                    util::print_warning(format!("{pid}: status unknown"));
+                    WaitStatus::Exited(pid, 127)
+                }
+                Ok(status) => status,
+            };
+            self.update_process(status);
+        }
+
+        if self.job_state(job_id) != Some(JobState::Done) {
+            return None;
+        }
+        let msg = self.format_job(job_id, JobState::Done);
+        let job = self.remove_done_job(job_id)?;
+        util::print_line(util::paint(Color::Yellow, &msg));
+        Some(self.job_exit_code(&job))
+    }
+
+    /// Notifies that a background job is done (removing it from the job table) or stopped. The
+    /// notice is shown by [`Worker::show_notices`].
+    fn notify_job_change(&mut self, job_id: usize, is_stop: bool) {
+        match self.job_state(job_id) {
+            Some(JobState::Done) => {
+                let msg = self.format_job(job_id, JobState::Done);
+                self.remove_done_job(job_id);
+                self.notify(msg);
+            }
+            // notify once the last running process of the job stops
+            Some(JobState::Stopped) if is_stop => {
+                let msg = self.format_job(job_id, JobState::Stopped);
+                self.notify(msg);
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds a job notice
+    fn notify(&mut self, msg: String) {
+        self.notices.push(util::paint(Color::Yellow, &msg));
+    }
+
+    /// Prints the job notices. With `set -b`, they show up above the prompt at once even while
+    /// the `main` thread is reading user input. Otherwise they wait for the next prompt.
+    fn show_notices(&mut self) {
+        if self.notices.is_empty() {
+            return;
+        }
+        match self.printer.as_mut() {
+            Some(printer) if self.notify => {
+                let text = self
+                    .notices
+                    .drain(..)
+                    .map(|msg| msg + "\n")
+                    .collect::<String>();
+                if printer.print(text.clone()).is_err() {
+                    let _ = write!(io::stderr(), "{text}");
+                }
+            }
+            Some(_) => {}
+            None => self.flush_notices(),
+        }
+    }
+
+    /// Prints the job notices waiting for the next prompt. Called before returning to the `main`
+    /// thread, which is blocked until then.
+    fn flush_notices(&mut self) {
+        for msg in self.notices.drain(..) {
+            util::print_line(msg);
+        }
+    }
+
+    /// Gives the terminal to the process group (if the standard input is a terminal)
+    fn set_terminal_fg(&self, gpid: unistd::Pid) {
+        if unistd::isatty(libc::STDIN_FILENO).unwrap_or(false) {
+            // SIGTTOU is ignored by the shell, so this works from a background process group, too
+            let _ = unistd::tcsetpgrp(libc::STDIN_FILENO, gpid);
+        }
+    }
+}
+
+impl expand::Context for Worker {
+    fn var(&self, name: &str) -> Option<String> {
+        Worker::var(self, name)
+    }
+
+    fn array(&self, name: &str) -> Option<Vec<String>> {
+        Worker::array(self, name)
+    }
+
+    fn set_var(&mut self, name: &str, value: String) -> Result<(), String> {
+        Worker::set_var(self, name, value)
+    }
+
+    fn run_subst(&mut self, cmd: &str) -> String {
+        match self.substitute(cmd) {
+            Ok((output, code)) => {
+                self.subst_code = Some(code);
+                output
+            }
+            Err(err) => {
+                util::print_error(format!("command substitution: {err}"));
+                self.subst_code = Some(1);
+                String::new()
+            }
+        }
+    }
+
+    fn set_subst_code(&mut self, code: i32) {
+        self.subst_code = Some(code);
+    }
+
+    fn noglob(&self) -> bool {
+        self.noglob
+    }
+}
+
+/// Restores the signal dispositions and the signal mask in a forked child. Ignored signals stay
+/// ignored after `exec`, so the ones ignored by the shell (`SIGTTOU`, and `SIGPIPE` by Rust) would
+/// break programs. Builtins are killed by `SIGPIPE` quietly, too, instead of panicking in
+/// `println!` on a closed pipe.
+///
+/// Returns the previous state for [`restore_signals`], e.g., if `exec` fails in the shell.
+fn reset_signals() -> SavedSignals {
+    let default = signal::SigAction::new(
+        SigHandler::SigDfl,
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+
+    let mut actions = Vec::new();
+    for signal in [
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGTSTP,
+        Signal::SIGTTIN,
+        Signal::SIGTTOU,
+        Signal::SIGCHLD,
+        Signal::SIGPIPE,
+    ] {
+        if let Ok(action) = unsafe { signal::sigaction(signal, &default) } {
+            actions.push((signal, action));
+        }
+    }
+
+    let mut mask = signal::SigSet::empty();
+    let _ = signal::sigprocmask(
+        signal::SigmaskHow::SIG_SETMASK,
+        Some(&signal::SigSet::empty()),
+        Some(&mut mask),
+    );
+
+    SavedSignals { actions, mask }
+}
+
+/// Signal dispositions and the signal mask replaced by [`reset_signals`]
+struct SavedSignals {
+    actions: Vec<(Signal, signal::SigAction)>,
+    mask: signal::SigSet,
+}
+
+fn restore_signals(saved: SavedSignals) {
+    for (signal, action) in saved.actions {
+        let _ = unsafe { signal::sigaction(signal, &action) };
+    }
+    let _ = signal::sigprocmask(signal::SigmaskHow::SIG_SETMASK, Some(&saved.mask), None);
+}
+
+/// Returns true if the command name is a directory, not a builtin or a command in `PATH`
+fn is_autocd(name: &str) -> bool {
+    Path::new(name).is_dir()
+        && builtins::find(name).is_none()
+        && (name.contains('/') || util::find_in_path(name).is_none())
+}
+
+/// Returns the user and system CPU times of the shell and its waited children so far
+fn cpu_times() -> (Duration, Duration) {
+    let mut user = Duration::ZERO;
+    let mut sys = Duration::ZERO;
+    for who in [UsageWho::RUSAGE_SELF, UsageWho::RUSAGE_CHILDREN] {
+        if let Ok(usage) = resource::getrusage(who) {
+            let time = |time: TimeVal| Duration::from_micros(time.num_microseconds() as u64);
+            user += time(usage.user_time());
+            sys += time(usage.system_time());
+        }
+    }
+    (user, sys)
+}
+
+/// Formats the times of `time` with a `TIMEFORMAT` template. `%[P][l]R`, `%[P][l]U` and
+/// `%[P][l]S` are the real, user and system times in seconds with `P` (0 to 3, 3 by default)
+/// digits after the decimal point, or as `MmS.FFs` with `l`. `%P` is the CPU percentage, and
+/// `%%` is `%`.
+fn format_times(template: &str, real: Duration, user: Duration, sys: Duration) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let digit = chars.next_if(char::is_ascii_digit);
+        let precision = digit
+            .and_then(|c| c.to_digit(10))
+            .map_or(3, |digit| digit.min(3)) as usize;
+        let long = chars.next_if_eq(&'l').is_some();
+        // an unknown or incomplete directive is kept as it is
+        let literal = |out: &mut String| {
+            out.push('%');
+            out.extend(digit);
+            if long {
+                out.push('l');
+            }
+        };
+        let time = match chars.next() {
+            Some('R') => real,
+            Some('U') => user,
+            Some('S') => sys,
+            Some('P') => {
+                let cpu = (user + sys).as_secs_f64();
+                let percent = match real.is_zero() {
+                    true => 0.0,
+                    false => cpu / real.as_secs_f64() * 100.0,
+                };
+                out.push_str(&format!("{percent:.2}"));
+                continue;
+            }
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some(c) => {
+                literal(&mut out);
+                out.push(c);
+                continue;
+            }
+            None => {
+                literal(&mut out);
+                break;
+            }
+        };
+
+        let secs = time.as_secs_f64();
+        if long {
+            let mins = time.as_secs() / 60;
+            let secs = secs - (mins * 60) as f64;
+            out.push_str(&format!("{mins}m{secs:.precision$}s"));
+        } else {
+            out.push_str(&format!("{secs:.precision$}"));
+        }
+    }
+
+    out
+}
+
+/// Returns the message and the exit code for a path of a command that fails to run: 127 if it
+/// doesn't exist and 126 if it's not executable, like bash
+fn exec_path_error(path: &str) -> (String, i32) {
+    match fs::metadata(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            (format!("{path}: No such file or directory"), 127)
+        }
+        Err(err) => (format!("{path}: {err}"), 126),
+        Ok(meta) if meta.is_dir() => (format!("{path}: Is a directory"), 126),
+        Ok(_) if !util::is_executable(Path::new(path)) => {
+            (format!("{path}: Permission denied"), 126)
+        }
+        // e.g., the interpreter of the script is missing
+        Ok(_) => (format!("{path}: cannot execute"), 126),
+    }
+}
+
+/// Returns the builtin or the command in `PATH` with the name closest to an unknown command. Only
+/// names of similar lengths are compared.
+fn suggest_command(name: &str) -> Option<String> {
+    if name.contains('/') || util::find_in_path(name).is_some() {
+        return None;
+    }
+
+    let len = name.chars().count();
+    let max_distance = (len / 3).max(1);
+    let similar = |candidate: &str| candidate.chars().count().abs_diff(len) <= max_distance;
+
+    let mut candidates = builtins::BUILTINS
+        .iter()
+        .map(|builtin| builtin.name.to_string())
+        .filter(|candidate| similar(candidate))
+        .collect::<Vec<_>>();
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        candidates.extend(
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|candidate| similar(candidate))
+                .filter(|candidate| util::is_executable(&dir.join(candidate))),
+        );
+    }
+
+    candidates
+        .into_iter()
+        .map(|candidate| (util::edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Returns true if the name is a builtin command, e.g., for highlighting the input line
+pub(crate) fn is_builtin(name: &str) -> bool {
+    builtins::find(name).is_some()
+}
+
+/// Returns the candidates for completing an argument of a builtin, e.g., its option flags. `args`
+/// are the words before it. `None` if it's not an argument of a builtin with such candidates.
+pub(crate) fn complete_builtin(args: &[String], word: &str) -> Option<Vec<String>> {
+    builtins::complete(args, word)
+}
+
+/// Reserves the file descriptors up to 9 for redirections. Called before the libraries open
+/// theirs.
+pub(crate) fn reserve_fds() {
+    redirect::reserve_fds();
+}
+
+/// Runs a command line without the `main` and the `signal_handler` threads, e.g., for
+/// `zerosh -c`. Jobs stay in the shell's process group. A login shell runs the profiles first.
+/// Returns the exit code.
+pub(crate) fn run_command_line(cmd: &str, limits: parser::Limits, login: bool) -> i32 {
+    self::run_once(limits, login, |worker| {
+        worker.process_cmd(cmd);
+    })
+}
+
+/// Runs a parsed command line like [`run_command_line`], e.g., built by a program embedding the
+/// shell. Returns the exit code.
+pub(crate) fn run_list(list: &List, limits: parser::Limits, login: bool) -> i32 {
+    self::run_once(limits, login, |worker| {
+        worker.execute(list);
+        if worker.quit && worker.login {
+            worker.run_logout();
         }
+    })
+}
+
+/// Runs something with a new worker without the other threads, and then the exit trap
+fn run_once(limits: parser::Limits, login: bool, run: impl FnOnce(&mut Worker)) -> i32 {
+    self::reserve_fds();
+    let mut worker = Worker::new(false, None);
+    worker.job_control = false;
+    worker.limits = limits;
+    worker.login = login;
+    vars::increment_shlvl();
+    worker.update_window_size();
+    if login {
+        worker.run_profiles();
+    }
+    worker.specials.lineno += 1;
+    worker.specials.cmd_count += 1;
+    if worker.quit {
+        worker.run_logout();
+    } else {
+        run(&mut worker);
+    }
+    // `exit` has done it
+    if !worker.quit {
+        worker.hang_up_on_exit();
     }
+    worker.run_exit_trap();
+    let _ = io::stdout().flush();
+    worker.exit_code
 }
 
-pub(crate) fn spawn_worker(worker_rx: mpsc::Receiver<WorkerMsg>, shell_tx: mpsc::SyncSender<ShellMsg>) {
-    let mut worker = Worker::new();
+/// Creates the `worker` thread. A login shell runs the profiles before it, and an interactive
+/// shell runs the rc file. The exit code is returned if they exit the shell.
+pub(crate) fn spawn_worker(
+    sh: &Shell,
+    worker_rx: mpsc::Receiver<WorkerMsg>,
+    shell_tx: mpsc::SyncSender<ShellMsg>,
+    interactive: bool,
+    printer: Option<Box<dyn Printer>>,
+    signal_handler: crate::SignalHandler,
+    shared: editor::Shared,
+) -> Option<i32> {
+    let mut worker = Worker::new(interactive, printer);
+    worker.limits = sh.limits;
+    worker.login = sh.login;
+    worker.signal_handle = Some(signal_handler.handle);
+    worker.interrupted = Some(signal_handler.interrupted);
+    worker.comp_specs = shared.comp_specs;
+    worker.last_key = shared.last_key;
+    worker.histexpand = shared.histexpand;
+    vars::increment_shlvl();
+    worker.update_window_size();
+
+    // before the first prompt
+    if worker.login {
+        worker.run_profiles();
+    }
+    if interactive && !sh.norc && !worker.quit {
+        worker.run_rc_file(sh.rc_file.as_deref());
+    }
+    if !worker.quit {
+        worker.run_prompt_command();
+    }
+    worker.flush_notices();
+    if worker.quit {
+        worker.quit_msg();
+        let _ = io::stdout().flush();
+        return Some(worker.exit_code);
+    }
+
+    let builder = thread::Builder::new().stack_size(WORKER_STACK_SIZE);
+    let spawned = builder.spawn(move || {
+        // waiting for a command since then, for `TMOUT`
+        let mut idle_since = Instant::now();
+
+        // `SIGCHLD`s are merged until then
+        let mut reap_at = None;
+
+        loop {
+            let now = Instant::now();
+            let deadline = worker.idle_deadline(idle_since);
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                worker.auto_logout();
+            }
+
+            let res = match deadline.into_iter().chain(reap_at).min() {
+                Some(wake) => worker_rx.recv_timeout(wake - now),
+                None => worker_rx
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            let msg = match res {
+                Ok(msg) => msg,
+                // checks the deadline again, which key presses may have put off
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if reap_at.is_some_and(|reap_at| Instant::now() >= reap_at) {
+                        reap_at = None;
+                        worker.reap_children();
+                    }
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
 
-    thread::spawn(move || {
-        for msg in worker_rx.iter() {
             match msg {
-                _ => todo!(),
+                WorkerMsg::Cmd { cmd } => {
+                    // the job table is up to date for the command
+                    if reap_at.take().is_some() {
+                        worker.reap_children();
+                    }
+                    worker.specials.lineno += 1;
+                    // an empty line only gets the next prompt ready
+                    if !cmd.is_empty() {
+                        worker.specials.cmd_count += 1;
+                    }
+                    // resized while the line editor had `SIGWINCH`
+                    worker.update_window_size();
+                    let edit_mode = worker.edit_mode;
+                    let mut msg = worker.process_cmd(&cmd);
+                    if !worker.quit {
+                        worker.run_prompt_command();
+                        if worker.quit {
+                            msg = worker.quit_msg();
+                        }
+                    }
+                    worker.flush_notices();
+
+                    // the editor lives on the `main` thread
+                    if worker.edit_mode != edit_mode {
+                        let mode = worker.edit_mode;
+                        shell_tx.send(ShellMsg::SetEditMode { mode }).unwrap();
+                    }
+                    let names = worker.var_names();
+                    if names != worker.var_names && !worker.quit {
+                        worker.var_names = names.clone();
+                        shell_tx.send(ShellMsg::SetVarNames { names }).unwrap();
+                    }
+                    if let Some(job_id) = worker.stopped_fg.take() {
+                        // the job may have been resumed by a later command
+                        if worker.jobs.contains_key(&job_id) && !worker.quit {
+                            shell_tx.send(ShellMsg::Stopped { job_id }).unwrap();
+                        }
+                    }
+
+                    shell_tx.send(msg).unwrap();
+                    idle_since = Instant::now();
+                }
+                WorkerMsg::Signal {
+                    signal: libc::SIGCHLD,
+                } if !worker.traps.contains_key(&Signal::SIGCHLD) => {
+                    reap_at.get_or_insert(Instant::now() + CHLD_DEBOUNCE);
+                }
+                WorkerMsg::Signal { signal } => worker.handle_signal(signal),
+                WorkerMsg::Hangup => worker.hang_up(libc::SIGHUP),
+                // in case a `SIGCHLD` was missed, unless one is about to be handled
+                WorkerMsg::Tick if reap_at.is_none() => worker.poll_jobs(),
+                WorkerMsg::Tick => {}
             }
         }
     });
+    // as `thread::spawn` does
+    spawned.expect("failed to spawn the worker thread");
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    /// Keeps the job notices waiting for the next prompt
+    #[derive(Debug)]
+    struct HeldNotices;
+
+    impl rustyline::ExternalPrinter for HeldNotices {
+        fn print(&mut self, _msg: String) -> rustyline::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn polled_jobs_catch_up_without_sigchld() {
+        // no `SIGCHLD` is handled in the tests, as if all of them were missed
+        let mut worker = Worker::new(false, Some(Box::new(HeldNotices)));
+        let pid = match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => unsafe {
+                libc::setpgid(0, 0);
+                loop {
+                    libc::pause();
+                }
+            },
+            ForkResult::Parent { child } => child,
+        };
+        let _ = unistd::setpgid(pid, pid);
+        let job_id = worker.insert_job(pid, &[pid], "pause");
+
+        let poll_until = |worker: &mut Worker, done: &dyn Fn(&Worker) -> bool| {
+            for _ in 0..100 {
+                worker.poll_jobs();
+                if done(worker) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("the job table didn't catch up: {:?}", worker.jobs);
+        };
+
+        signal::kill(pid, Signal::SIGSTOP).unwrap();
+        poll_until(&mut worker, &|worker| {
+            worker.job_state(job_id) == Some(JobState::Stopped)
+        });
+        assert_eq!(worker.notices.len(), 1);
+        assert!(
+            worker.notices[0].contains("Stopped"),
+            "{:?}",
+            worker.notices
+        );
+
+        signal::kill(pid, Signal::SIGKILL).unwrap();
+        poll_until(&mut worker, &|worker| worker.jobs.is_empty());
+        assert!(worker.notices[1].contains("Killed"), "{:?}", worker.notices);
+    }
+
+    #[test]
+    fn aliases_invalidate_cached_lines() {
+        let mut worker = Worker::new(false, None);
+        worker.process_cmd("alias f='x=1'");
+        worker.process_cmd("f");
+        assert!(worker.parse_cache.contains_key("f"));
+        assert_eq!(worker.var("x").as_deref(), Some("1"));
+
+        worker.process_cmd("alias f='x=2'");
+        assert!(!worker.parse_cache.contains_key("f"));
+        worker.process_cmd("f");
+        assert_eq!(worker.var("x").as_deref(), Some("2"));
+
+        worker.process_cmd("unalias f");
+        assert!(worker.parse_cache.is_empty());
+        worker.process_cmd("x=3; f; y=$?");
+        assert_eq!(worker.var("y").as_deref(), Some("127"));
+    }
+
+    /// Runs the closure and returns how long it took
+    fn measure(f: impl FnOnce()) -> Duration {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    }
+
+    /// `cargo test --lib -- --ignored --nocapture bench` shows the timings. The benches change
+    /// the environment, so they are ignored by default.
+    #[test]
+    #[ignore = "bench"]
+    fn bench_parse_cache() {
+        const ITERATIONS: usize = 100_000;
+        // builtins only, so that forks do not hide the parsing
+        let line = ": a b c; x=${x:-1} && : \"$x\" 'd' || :; y=\"$x$x\"; : ${#y}";
+        let mut worker = Worker::new(false, None);
+
+        let cached = measure(|| {
+            for _ in 0..ITERATIONS {
+                worker.process_cmd(line);
+            }
+        });
+        let uncached = measure(|| {
+            for _ in 0..ITERATIONS {
+                worker.parse_cache.clear();
+                worker.process_cmd(line);
+            }
+        });
+        eprintln!("{ITERATIONS} lines: {cached:?} with the parse cache, {uncached:?} without");
+        assert_eq!(worker.exit_code, 0);
+        assert!(cached < uncached);
+    }
+
+    #[test]
+    #[ignore = "bench"]
+    fn bench_command_hash() {
+        const ITERATIONS: usize = 10_000;
+        let old_path = env::var_os("PATH").unwrap_or_default();
+        let mut dirs: Vec<PathBuf> = (0..100)
+            .map(|i| format!("/nonexistent/{i}").into())
+            .collect();
+        dirs.extend(env::split_paths(&old_path));
+        env::set_var("PATH", env::join_paths(dirs).unwrap());
+
+        let mut worker = Worker::new(false, None);
+        let hashed = measure(|| {
+            for _ in 0..ITERATIONS {
+                assert!(worker.commands.locate("sh", false).is_some());
+            }
+        });
+        let scanned = measure(|| {
+            for _ in 0..ITERATIONS {
+                assert!(util::find_in_path("sh").is_some());
+            }
+        });
+        env::set_var("PATH", old_path);
+        eprintln!("{ITERATIONS} lookups: {hashed:?} hashed, {scanned:?} scanning PATH");
+        assert!(hashed < scanned);
+    }
+
+    #[test]
+    fn times_in_timeformat() {
+        let real = Duration::from_millis(83_456);
+        let user = Duration::from_millis(1_500);
+        let sys = Duration::from_millis(250);
+        let format = |template| format_times(template, real, user, sys);
+
+        assert_eq!(
+            format(DEFAULT_TIMEFORMAT),
+            "\nreal\t1m23.456s\nuser\t0m1.500s\nsys\t0m0.250s"
+        );
+        assert_eq!(format(POSIX_TIMEFORMAT), "real 83.46\nuser 1.50\nsys 0.25");
+        // the precision is 3 at most
+        assert_eq!(
+            format("%R %0R %1U %9S %lR %0lU"),
+            "83.456 83 1.5 0.250 1m23.456s 0m2s"
+        );
+        assert_eq!(format("%P%%"), "2.10%");
+        assert_eq!(format_times("%P", Duration::ZERO, user, sys), "0.00");
+        // unknown or incomplete ones as they are
+        assert_eq!(format("%x %3"), "%x %3");
+        assert_eq!(format("%3x %l"), "%3x %l");
+        assert_eq!(format("100%"), "100%");
+    }
+
+    #[test]
+    fn exit_codes_of_paths_failing_to_run() {
+        let dir = env::temp_dir().join(format!("zerosh-exec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).display().to_string();
+
+        let missing = path("missing");
+        assert_eq!(
+            exec_path_error(&missing),
+            (format!("{missing}: No such file or directory"), 127)
+        );
+        let dir_path = dir.display().to_string();
+        assert_eq!(
+            exec_path_error(&dir_path),
+            (format!("{dir_path}: Is a directory"), 126)
+        );
+
+        let file = path("file");
+        fs::write(&file, "echo a\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(
+            exec_path_error(&file),
+            (format!("{file}: Permission denied"), 126)
+        );
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            exec_path_error(&file),
+            (format!("{file}: cannot execute"), 126)
+        );
+        // a file as a directory
+        let (msg, code) = exec_path_error(&format!("{file}/a"));
+        assert_eq!(code, 126, "{msg}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
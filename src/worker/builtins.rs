@@ -0,0 +1,2584 @@
+//! Builtin commands run by the worker thread
+//!
+//! Builtins run in the shell process unless in a pipeline. With `&`, the ones that only print
+//! something run in a background job, while the ones that change the state of the shell (`cd`,
+//! `export` and so on) still run in the foreground with a warning, as they would do nothing in a
+//! child process.
+
+use nix::{
+    fcntl::{self, OFlag},
+    libc,
+    sys::{
+        signal::{self, SigHandler, Signal},
+        stat::Mode,
+        termios,
+        wait::WaitStatus,
+    },
+    unistd::{self, AccessFlags},
+};
+
+use rustyline::EditMode;
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    ffi::{CString, OsStr},
+    fmt,
+    io::{self, Read, Write},
+    os::unix::ffi::OsStringExt,
+    panic::{self, AssertUnwindSafe},
+    path::{Component, Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use crate::{editor, expand, parser, printf, util};
+
+use super::{
+    functions::Function,
+    jobs::JobState,
+    vars::{Value, Var},
+    Worker,
+};
+
+/// `println!` for the builtins, which doesn't panic when the standard output fails, e.g., a full
+/// disk. The builtin fails with the error instead.
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        self::write_line(format_args!($($arg)*))
+    };
+}
+
+thread_local! {
+    /// First error writing the output of the running builtin. The rest of the output is dropped.
+    static WRITE_ERROR: RefCell<Option<io::Error>> = const { RefCell::new(None) };
+}
+
+/// Builtin command
+pub(super) struct Builtin {
+    pub name: &'static str,
+    /// Synopsis shown by `help NAME`
+    pub usage: &'static str,
+    /// One-line description shown by `help`
+    pub summary: &'static str,
+    /// Changes the state of the shell, e.g., variables or the current directory, so it runs in the
+    /// shell even in background (`&`). Other builtins run in a background job then.
+    pub shell_state: bool,
+    /// Runs in a forked child process even without pipes, e.g., to exec another program
+    pub forks: bool,
+    /// Runs the command with `args[0]` being the command name and returns the exit code
+    pub run: fn(&mut Worker, &[String]) -> i32,
+}
+
+/// Options of `set -o`, sorted by name
+const OPTIONS: &[&str] = &[
+    "allexport",
+    "autocd",
+    "cdfile",
+    "emacs",
+    "errexit",
+    "histexpand",
+    "huponexit",
+    "mkdir_on_redirect",
+    "noclobber",
+    "noglob",
+    "notify",
+    "pipefail",
+    "suggest",
+    "vi",
+];
+
+/// Single-letter flags of `set` and the options they stand for
+const FLAGS: &[(char, &str)] = &[
+    ('C', "noclobber"),
+    ('H', "histexpand"),
+    ('a', "allexport"),
+    ('b', "notify"),
+    ('e', "errexit"),
+    ('f', "noglob"),
+];
+
+/// Operand of `wait`
+enum WaitTarget {
+    /// Job ID
+    Job(usize),
+    /// Job ID and the position of the process in the pipeline
+    Process(usize, usize),
+    /// Exit code of a process of a job that is already reported as done
+    Finished(i32),
+}
+
+impl WaitTarget {
+    fn job_id(&self) -> Option<usize> {
+        match *self {
+            Self::Job(job_id) | Self::Process(job_id, _) => Some(job_id),
+            Self::Finished(_) => None,
+        }
+    }
+}
+
+/// Builtin commands sorted by name
+pub(super) static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: ".",
+        usage: ". FILE",
+        summary: "Run a file in the current shell, like `source`",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_source,
+    },
+    Builtin {
+        name: ":",
+        usage: ": [ARG...]",
+        summary: "Do nothing and succeed",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_colon,
+    },
+    Builtin {
+        name: "alias",
+        usage: "alias [NAME[=VALUE]...]",
+        summary: "Define aliases or show them",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_alias,
+    },
+    Builtin {
+        name: "bg",
+        usage: "bg [JOB]",
+        summary: "Continue a stopped job in background",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_bg,
+    },
+    Builtin {
+        name: "cd",
+        usage: "cd [-L | -P] [--] [DIR | - | -N]",
+        summary: "Change the current directory",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_cd,
+    },
+    Builtin {
+        name: "clear",
+        usage: "clear",
+        summary: "Clear the terminal screen",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_clear,
+    },
+    Builtin {
+        name: "complete",
+        usage: "complete [-dfpr] [-W WORDS] [-C COMMAND] [NAME...]",
+        summary: "Set how the arguments of commands are completed",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_complete,
+    },
+    Builtin {
+        name: "declare",
+        usage: "declare [-afFipx] [+ix] [NAME[=VALUE]...]",
+        summary: "Set variables with attributes, or list them",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_declare,
+    },
+    Builtin {
+        name: "dirs",
+        usage: "dirs [-c]",
+        summary: "List the previous directories of cd",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_dirs,
+    },
+    Builtin {
+        name: "disown",
+        usage: "disown [-h] [-a | JOB...]",
+        summary: "Remove jobs from the job table",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_disown,
+    },
+    Builtin {
+        name: "env",
+        usage: "env [-i] [-u NAME]... [NAME=VALUE]... [COMMAND [ARG...]]",
+        summary: "Run a command in a modified environment",
+        shell_state: false,
+        forks: true,
+        run: Worker::run_env,
+    },
+    Builtin {
+        name: "exec",
+        usage: "exec [COMMAND [ARG...]]",
+        summary: "Replace the shell with a command",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_exec,
+    },
+    Builtin {
+        name: "exit",
+        usage: "exit [CODE]",
+        summary: "Exit the shell with the code or the last exit code",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_exit,
+    },
+    Builtin {
+        name: "export",
+        usage: "export [-p] [NAME[=VALUE]...]",
+        summary: "Export variables to commands, or list them",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_export,
+    },
+    Builtin {
+        name: "fc",
+        usage: "fc [-lnrs] [-e EDITOR] [FIRST [LAST]]",
+        summary: "List or edit and re-run the history",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_fc,
+    },
+    Builtin {
+        name: "fg",
+        usage: "fg [JOB]",
+        summary: "Continue a job in foreground",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_fg,
+    },
+    Builtin {
+        name: "hash",
+        usage: "hash [-r] [-p PATH NAME] [NAME...]",
+        summary: "Show or set the remembered locations of commands",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_hash,
+    },
+    Builtin {
+        name: "help",
+        usage: "help [NAME]",
+        summary: "Show the builtin commands or the usage of one",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_help,
+    },
+    Builtin {
+        name: "jobs",
+        usage: "jobs [-l | -p] [JOB...]",
+        summary: "List the jobs",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_jobs,
+    },
+    Builtin {
+        name: "kill",
+        usage: "kill [-s SIGNAL | -SIGNAL] PID | %JOB... | -l [SIGNAL...]",
+        summary: "Send a signal to processes or jobs",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_kill,
+    },
+    Builtin {
+        name: "local",
+        usage: "local [-aix] NAME[=VALUE]...",
+        summary: "Set variables local to the current function",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_local,
+    },
+    Builtin {
+        name: "mapfile",
+        usage: "mapfile [-t] [ARRAY]",
+        summary: "Read the lines of the standard input into an array",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_mapfile,
+    },
+    Builtin {
+        name: "nohup",
+        usage: "nohup COMMAND [ARG...]",
+        summary: "Run a command immune to hangups",
+        shell_state: false,
+        forks: true,
+        run: Worker::run_nohup,
+    },
+    Builtin {
+        name: "printf",
+        usage: "printf FORMAT [ARG...]",
+        summary: "Format and print the arguments",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_printf,
+    },
+    Builtin {
+        name: "pwd",
+        usage: "pwd [-LP]",
+        summary: "Print the current directory",
+        shell_state: false,
+        forks: false,
+        run: Worker::run_pwd,
+    },
+    Builtin {
+        name: "read",
+        usage: "read [-r] [-p PROMPT] [NAME...]",
+        summary: "Read a line of the standard input into variables",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_read,
+    },
+    Builtin {
+        name: "readarray",
+        usage: "readarray [-t] [ARRAY]",
+        summary: "Same as mapfile",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_mapfile,
+    },
+    Builtin {
+        name: "return",
+        usage: "return [CODE]",
+        summary: "Return from a function or a sourced file",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_return,
+    },
+    Builtin {
+        name: "set",
+        usage: "set [-abCefH] [+abCefH] [-o | +o] [NAME] | set -- [ARG...]",
+        summary: "Set the shell options or the positional parameters",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_set,
+    },
+    Builtin {
+        name: "shift",
+        usage: "shift [N]",
+        summary: "Shift the positional parameters",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_shift,
+    },
+    Builtin {
+        name: "source",
+        usage: "source FILE",
+        summary: "Run a file in the current shell",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_source,
+    },
+    Builtin {
+        name: "suspend",
+        usage: "suspend [-f]",
+        summary: "Stop the shell until it is continued",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_suspend,
+    },
+    Builtin {
+        name: "trap",
+        usage: "trap [[--] [COMMAND | '' | -] SIGNAL...] | -p [SIGNAL...] | -l",
+        summary: "Run a command on signals or on exit",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_trap_builtin,
+    },
+    Builtin {
+        name: "unalias",
+        usage: "unalias [-a] NAME...",
+        summary: "Remove aliases, or all of them with -a",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_unalias,
+    },
+    Builtin {
+        name: "unset",
+        usage: "unset [-f | -v] NAME...",
+        summary: "Remove variables, or functions with -f",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_unset,
+    },
+    Builtin {
+        name: "wait",
+        usage: "wait [-n] [JOB | PID...]",
+        summary: "Wait for background jobs",
+        shell_state: true,
+        forks: false,
+        run: Worker::run_wait,
+    },
+];
+
+impl Builtin {
+    /// Runs the builtin. A panic, i.e., a bug in the builtin, is reported as an error with exit
+    /// code 1 so that the worker keeps running.
+    /// Output is flushed before it returns, and a write error fails the builtin.
+    pub(super) fn call(&self, worker: &mut Worker, args: &[String]) -> i32 {
+        WRITE_ERROR.with(|error| error.take());
+        let code = match panic::catch_unwind(AssertUnwindSafe(|| (self.run)(worker, args))) {
+            Ok(code) => code,
+            Err(_) => {
+                util::print_error(format!(
+                    "{}: internal error, the command was aborted",
+                    self.name
+                ));
+                1
+            }
+        };
+
+        let flushed = io::stdout().flush();
+        match WRITE_ERROR.with(|error| error.take()).or(flushed.err()) {
+            Some(err) => {
+                util::print_error(format!("{}: write error: {err}", self.name));
+                1
+            }
+            None => code,
+        }
+    }
+}
+
+/// Builtins only for the unit tests, found after [`BUILTINS`] but not listed by `help`
+#[cfg(test)]
+static TEST_BUILTINS: &[Builtin] = &[Builtin {
+    name: "zerosh-test-panic",
+    usage: "zerosh-test-panic",
+    summary: "Panic, as a builtin with a bug would",
+    shell_state: false,
+    forks: false,
+    run: |_, _| panic!("zerosh-test-panic"),
+}];
+
+/// Finds a builtin command by name
+pub(super) fn find(name: &str) -> Option<&'static Builtin> {
+    let builtins = BUILTINS.iter();
+    #[cfg(test)]
+    let builtins = builtins.chain(TEST_BUILTINS);
+    builtins.into_iter().find(|builtin| builtin.name == name)
+}
+
+/// Returns the candidates for completing an argument of a builtin: option flags taken from the
+/// usage, signal names, option names of `set -o` and so on. `args` are the words before the
+/// argument, the command name first. `None` if the argument is completed as a file name.
+pub(super) fn complete(args: &[String], word: &str) -> Option<Vec<String>> {
+    let builtin = self::find(args.first()?)?;
+    let signals = || Signal::iterator().map(|signal| util::signal_name(signal).to_string());
+
+    let candidates = match (builtin.name, args.last().map(String::as_str)) {
+        ("kill", Some("-s")) => signals().collect(),
+        ("kill", _) if word.starts_with('-') => {
+            let mut candidates = self::usage_options(builtin.usage);
+            candidates.extend(signals().map(|name| format!("-{name}")));
+            candidates
+        }
+        // the first argument is the command
+        ("trap", _) if args.len() >= 2 => {
+            let mut candidates = vec!["EXIT".to_string()];
+            candidates.extend(signals());
+            candidates
+        }
+        ("set", Some("-o" | "+o")) => OPTIONS.iter().map(|name| name.to_string()).collect(),
+        ("help", _) if args.len() == 1 => BUILTINS.iter().map(|b| b.name.to_string()).collect(),
+        _ if word.starts_with(['-', '+']) => self::usage_options(builtin.usage),
+        // only the environment is visible to the `main` thread
+        ("declare" | "local", _) if !word.contains('=') => env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .collect(),
+        _ => return None,
+    };
+
+    let mut candidates = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates.dedup();
+    Some(candidates)
+}
+
+/// Returns the option flags in a usage, e.g., `-l`, `-n` and `-e` of `fc [-ln] [-e EDITOR]`.
+/// Placeholders in capitals like `-N` are not flags.
+fn usage_options(usage: &str) -> Vec<String> {
+    let mut options = Vec::new();
+    for word in usage.split([' ', '[', ']', '|']) {
+        let Some(sign) = word.chars().next().filter(|c| matches!(c, '-' | '+')) else {
+            continue;
+        };
+        let flags = &word[1..];
+        let placeholder = flags.chars().all(|c| c.is_ascii_uppercase());
+        if flags.is_empty() || placeholder || !flags.chars().all(|c| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        options.extend(flags.chars().map(|c| format!("{sign}{c}")));
+    }
+    options
+}
+
+/// Returns `true` if the builtin takes `NAME=VALUE` arguments, which are expanded like assignments
+pub(super) fn is_declaration(name: &str) -> bool {
+    matches!(name, "declare" | "export" | "local")
+}
+
+impl Worker {
+    /// `help [NAME]`: shows the builtin commands or the usage of one
+    fn run_help(&mut self, args: &[String]) -> i32 {
+        let Some(name) = args.get(1) else {
+            // longer usages put the summary on the next line, as in bash
+            const WIDTH: usize = 24;
+            for builtin in BUILTINS {
+                if builtin.usage.len() > WIDTH {
+                    outln!("{}", builtin.usage);
+                    outln!("{:WIDTH$}  {}", "", builtin.summary);
+                } else {
+                    outln!("{:<WIDTH$}  {}", builtin.usage, builtin.summary);
+                }
+            }
+            return 0;
+        };
+
+        match self::find(name) {
+            Some(builtin) => {
+                outln!("{}: {}", builtin.name, builtin.usage);
+                outln!("    {}", builtin.summary);
+                0
+            }
+            None => {
+                util::print_error(format!("help: no help topics match `{name}`"));
+                1
+            }
+        }
+    }
+
+    /// `:`: does nothing. The arguments have been expanded for side effects, e.g., `${X:=default}`.
+    fn run_colon(&mut self, _args: &[String]) -> i32 {
+        0
+    }
+
+    /// `cd [-L | -P] [--] [DIR | - | -N]`: changes the current directory. A relative `DIR` that is
+    /// not in the current directory is searched in the `:`-separated directories of `CDPATH`. With
+    /// `set -o cdfile`, a file stands for its parent directory. `-N` is the N-th previous directory
+    /// listed by `dirs`, and `...` is `../..`. `PWD` keeps the symbolic links on the way unless
+    /// `-P` is given, where `..` goes back through them.
+    pub(super) fn run_cd(&mut self, args: &[String]) -> i32 {
+        // `-L` keeps symbolic links in `PWD` (the default), and `-P` resolves them
+        let mut physical = false;
+        let mut rest = &args[1..];
+        while let Some(flags) = rest
+            .first()
+            .and_then(|arg| arg.strip_prefix('-'))
+            .filter(|flags| !flags.is_empty() && flags.chars().all(|c| matches!(c, 'L' | 'P')))
+        {
+            physical = flags.ends_with('P');
+            rest = &rest[1..];
+        }
+
+        // `--` ends the options, e.g., `cd -- -dir`
+        let end_of_options = rest.first().is_some_and(|arg| arg == "--");
+        let operand = if end_of_options {
+            rest.get(1)
+        } else {
+            rest.first()
+        };
+
+        let (dir, print) = match operand.map(String::as_str) {
+            None => match self.var("HOME") {
+                // like bash, an empty `HOME` stays in the current directory
+                Some(home) if home.is_empty() => return 0,
+                Some(home) => (home, false),
+                None => {
+                    util::print_error("cd: HOME not set");
+                    return 1;
+                }
+            },
+            Some("-") => match self.var("OLDPWD") {
+                Some(oldpwd) if !oldpwd.is_empty() => (oldpwd, true),
+                _ => {
+                    util::print_error("cd: OLDPWD not set");
+                    return 1;
+                }
+            },
+            // `cd -N`: the N-th previous directory in `dirs`
+            Some(arg)
+                if !end_of_options
+                    && arg.len() > 1
+                    && arg
+                        .strip_prefix('-')
+                        .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit())) =>
+            {
+                let n = arg[1..].parse::<usize>().unwrap_or(0);
+                match n.checked_sub(1).and_then(|i| self.dir_history.get(i)) {
+                    Some(dir) => (dir.display().to_string(), true),
+                    None => {
+                        util::print_error(format!(
+                            "cd: {arg}: no such entry in the directory history"
+                        ));
+                        return 1;
+                    }
+                }
+            }
+            // `cd ...` goes up two levels, `cd ....` three, and so on
+            Some(dots)
+                if dots.len() > 2
+                    && dots.bytes().all(|b| b == b'.')
+                    && !Path::new(dots).is_dir() =>
+            {
+                (vec![".."; dots.len() - 1].join("/"), false)
+            }
+            Some(dir) => match self.find_cdpath(dir) {
+                // the destination is not obvious from the argument, so show it
+                Some(dir) => (dir, true),
+                None => (dir.to_string(), false),
+            },
+        };
+
+        let dir = match Path::new(&dir).parent() {
+            Some(parent) if self.cdfile && Path::new(&dir).is_file() => {
+                match parent.as_os_str().is_empty() {
+                    true => ".".to_string(),
+                    false => parent.display().to_string(),
+                }
+            }
+            _ => dir,
+        };
+
+        let old = util::logical_cwd().ok();
+
+        // `..` removes the last component of the logical path, unless the result doesn't exist
+        let logical = (!physical)
+            .then(|| old.as_ref().map(|old| self::normalize(&old.join(&dir))))
+            .flatten()
+            .filter(|logical| env::set_current_dir(logical).is_ok());
+        if logical.is_none() {
+            if let Err(err) = env::set_current_dir(&dir) {
+                util::print_error(format!("cd: {dir}: {}", util::strerror(&err)));
+                return 1;
+            }
+        }
+
+        if let Some(old) = old {
+            env::set_var("OLDPWD", &old);
+            self.push_dir_history(old);
+        }
+        if let Some(cwd) = logical.or_else(|| env::current_dir().ok()) {
+            if print {
+                outln!("{}", cwd.display());
+            }
+            env::set_var("PWD", cwd);
+        }
+
+        0
+    }
+
+    /// `pwd [-LP]`: prints the current directory, keeping the symbolic links followed by `cd`
+    /// unless `-P` is given
+    fn run_pwd(&mut self, args: &[String]) -> i32 {
+        let mut physical = false;
+        for arg in &args[1..] {
+            match arg.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => {
+                    util::print_error(format!("pwd: {arg}: invalid option"));
+                    return 2;
+                }
+            }
+        }
+
+        let cwd = if physical {
+            env::current_dir()
+        } else {
+            util::logical_cwd()
+        };
+        match cwd {
+            Ok(cwd) => {
+                outln!("{}", cwd.display());
+                0
+            }
+            Err(err) => {
+                util::print_error(format!("pwd: {err}"));
+                1
+            }
+        }
+    }
+
+    /// Adds the previous directory to the front of the history, removing its older entry
+    fn push_dir_history(&mut self, dir: PathBuf) {
+        self.dir_history.retain(|prev| *prev != dir);
+        self.dir_history.push_front(dir);
+        self.dir_history.truncate(super::DIR_HISTORY_SIZE);
+    }
+
+    /// `dirs [-c]`: lists the current directory as 0 and the previous directories of `cd` as the
+    /// numbers for `cd -N`
+    fn run_dirs(&mut self, args: &[String]) -> i32 {
+        match args.get(1).map(String::as_str) {
+            None => {}
+            Some("-c") => {
+                self.dir_history.clear();
+                return 0;
+            }
+            Some(arg) => {
+                util::print_error(format!("dirs: {arg}: invalid option"));
+                return 2;
+            }
+        }
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let cwd = util::logical_cwd().ok();
+        for (i, dir) in cwd.iter().chain(&self.dir_history).enumerate() {
+            outln!("{i:2}  {}", util::display_path(dir, home.as_deref()));
+        }
+        0
+    }
+
+    /// Returns the directory in `CDPATH` that has `dir`, unless `dir` is in the current directory
+    fn find_cdpath(&self, dir: &str) -> Option<String> {
+        let explicit = dir.starts_with('/')
+            || dir == "."
+            || dir == ".."
+            || dir.starts_with("./")
+            || dir.starts_with("../");
+        if explicit || Path::new(dir).is_dir() {
+            return None;
+        }
+
+        let cdpath = self.var("CDPATH")?;
+        cdpath
+            .split(':')
+            // an empty entry or `.` is the current directory, which is already searched
+            .filter(|base| !base.is_empty() && *base != ".")
+            .map(|base| Path::new(base).join(dir))
+            .find(|path| path.is_dir())
+            .map(|path| path.display().to_string())
+    }
+
+    /// `clear`: clears the screen without forking `/usr/bin/clear`
+    fn run_clear(&mut self, _args: &[String]) -> i32 {
+        if !unistd::isatty(libc::STDOUT_FILENO).unwrap_or(false) {
+            return 0;
+        }
+
+        let mut stdout = io::stdout().lock();
+        match stdout
+            .write_all(b"\x1b[2J\x1b[H")
+            .and_then(|_| stdout.flush())
+        {
+            Ok(()) => 0,
+            Err(err) => {
+                util::print_error(format!("clear: {err}"));
+                1
+            }
+        }
+    }
+
+    /// `complete [-dfpr] [-W WORDS] [-C COMMAND] [NAME...]`: sets how the arguments of the
+    /// commands are completed. Without a spec, prints the ones of the commands (or all) as
+    /// `complete` commands. `-r` removes them.
+    fn run_complete(&mut self, args: &[String]) -> i32 {
+        let mut spec = editor::CompSpec::default();
+        let (mut print, mut remove) = (false, false);
+        let mut operands = &args[1..];
+
+        while let Some(arg) = operands
+            .first()
+            .filter(|arg| arg.len() > 1 && arg.starts_with('-'))
+        {
+            operands = &operands[1..];
+            if arg == "--" {
+                break;
+            }
+            for flag in arg[1..].chars() {
+                match flag {
+                    'd' => spec.dirs = true,
+                    'f' => spec.files = true,
+                    'p' => print = true,
+                    'r' => remove = true,
+                    'W' => {
+                        let Some((words, rest)) = operands.split_first() else {
+                            util::print_error("complete: -W: option requires an argument");
+                            return 2;
+                        };
+                        spec.words
+                            .extend(words.split_whitespace().map(str::to_string));
+                        operands = rest;
+                    }
+                    'C' => {
+                        let Some((command, rest)) = operands.split_first() else {
+                            util::print_error("complete: -C: option requires an argument");
+                            return 2;
+                        };
+                        spec.command = Some(command.clone());
+                        operands = rest;
+                    }
+                    _ => {
+                        util::print_error(format!("complete: -{flag}: invalid option"));
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        let mut specs = self.comp_specs.lock().unwrap();
+
+        if remove {
+            if operands.is_empty() {
+                specs.clear();
+            }
+            for name in operands {
+                specs.remove(name);
+            }
+            return 0;
+        }
+
+        if print || spec == editor::CompSpec::default() {
+            let mut code = 0;
+            let names = match operands {
+                [] => specs.keys().cloned().collect(),
+                names => names.to_vec(),
+            };
+            for name in names {
+                let Some(spec) = specs.get(&name) else {
+                    util::print_error(format!("complete: {name}: no completion specification"));
+                    code = 1;
+                    continue;
+                };
+                let mut line = String::from("complete");
+                if spec.dirs {
+                    line.push_str(" -d");
+                }
+                if spec.files {
+                    line.push_str(" -f");
+                }
+                if !spec.words.is_empty() {
+                    line.push_str(&format!(" -W {}", self::quote(&spec.words.join(" "))));
+                }
+                if let Some(command) = &spec.command {
+                    line.push_str(&format!(" -C {}", self::quote(command)));
+                }
+                outln!("{line} {name}");
+            }
+            return code;
+        }
+
+        for name in operands {
+            specs.insert(name.clone(), spec.clone());
+        }
+        0
+    }
+
+    /// `fc`: placeholder for `fc` in a pipeline, in background or with redirections. A plain `fc`
+    /// command line is run by the `main` thread, which has the history.
+    fn run_fc(&mut self, _args: &[String]) -> i32 {
+        util::print_error(
+            "fc: the history is not available in a pipeline, in background or with redirections",
+        );
+        1
+    }
+
+    /// `printf FORMAT [ARG...]`: formats the arguments, reusing the format while arguments remain
+    fn run_printf(&mut self, args: &[String]) -> i32 {
+        let Some(format) = args.get(1) else {
+            util::print_error("printf: usage: printf FORMAT [ARG...]");
+            return 2;
+        };
+
+        let mut errors = Vec::new();
+        let out = printf::format(format, &args[2..], &mut errors);
+        for err in &errors {
+            util::print_error(format!("printf: {err}"));
+        }
+
+        // a write error is reported once the builtin returns
+        self::write_out(|stdout| stdout.write_all(out.as_bytes()));
+        i32::from(!errors.is_empty())
+    }
+
+    /// `mapfile [-t] [ARRAY]`: reads the standard input into an array, one line per element.
+    /// Lines are stored without the trailing newline; `-t` is accepted for bash compatibility.
+    fn run_mapfile(&mut self, args: &[String]) -> i32 {
+        let cmd = &args[0];
+        let mut operands = &args[1..];
+        while let Some(flag) = operands.first().filter(|arg| arg.starts_with('-')) {
+            if flag != "-t" {
+                util::print_error(format!("{cmd}: {flag}: invalid option"));
+                return 2;
+            }
+            operands = &operands[1..];
+        }
+
+        let name = match operands {
+            [] => "MAPFILE",
+            [name] if parser::is_name(name) => name.as_str(),
+            [name] => {
+                util::print_error(format!("{cmd}: `{name}': not a valid identifier"));
+                return 1;
+            }
+            _ => {
+                util::print_error(format!("{cmd}: too many arguments"));
+                return 2;
+            }
+        };
+
+        let mut input = Vec::new();
+        if let Err(err) = io::stdin().lock().read_to_end(&mut input) {
+            util::print_error(format!("{cmd}: {err}"));
+            return 1;
+        }
+
+        let input = String::from_utf8_lossy(&input);
+        let lines = input.lines().map(str::to_string).collect();
+        self.set_array(name, lines);
+        0
+    }
+
+    /// `read [-r] [-p PROMPT] [NAME...]`: reads a line of the standard input and assigns the fields
+    /// split by `IFS` to the variables, the rest of the line to the last one, or the whole line to
+    /// `REPLY` without names. A backslash escapes the next character and joins the next line unless
+    /// `-r`. The prompt is printed to the standard error on a terminal. Fails with 1 at the end of
+    /// the input, and with 130 on `Ctrl+c`.
+    fn run_read(&mut self, args: &[String]) -> i32 {
+        let mut raw = false;
+        let mut prompt = None;
+        let mut operands = &args[1..];
+        while let Some(flag) = operands.first().filter(|arg| arg.starts_with('-')) {
+            operands = &operands[1..];
+            match flag.as_str() {
+                "-r" => raw = true,
+                "-p" => match operands.split_first() {
+                    Some((text, rest)) => {
+                        prompt = Some(text);
+                        operands = rest;
+                    }
+                    None => {
+                        util::print_error("read: -p: option requires an argument");
+                        return 2;
+                    }
+                },
+                "--" => break,
+                _ => {
+                    util::print_error(format!("read: {flag}: invalid option"));
+                    return 2;
+                }
+            }
+        }
+        if let Some(name) = operands.iter().find(|name| !parser::is_name(name)) {
+            util::print_error(format!("read: `{name}': not a valid identifier"));
+            return 1;
+        }
+
+        if let Some(prompt) = prompt.filter(|_| unistd::isatty(libc::STDIN_FILENO).unwrap_or(false))
+        {
+            eprint!("{prompt}");
+        }
+
+        // bytes, and whether a backslash escaped each
+        let mut bytes = Vec::new();
+        let mut escaped = Vec::new();
+        let mut backslash = false;
+        let eof = loop {
+            let byte = match self.read_byte_interruptible() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => break true,
+                // `Ctrl+c`, as if the builtin was killed by `SIGINT`
+                Err(nix::Error::EINTR) => return 128 + libc::SIGINT,
+                Err(err) => {
+                    util::print_error(format!("read: {err}"));
+                    return 1;
+                }
+            };
+            match byte {
+                b'\n' if backslash => backslash = false,
+                b'\n' => break false,
+                b'\\' if !raw && !backslash => backslash = true,
+                byte => {
+                    bytes.push(byte);
+                    escaped.push(backslash);
+                    backslash = false;
+                }
+            }
+        };
+
+        let text = String::from_utf8_lossy(&bytes);
+        // the offsets are of the bytes unless invalid UTF-8 was replaced
+        let valid = matches!(text, Cow::Borrowed(_));
+        let line = text
+            .char_indices()
+            .map(|(i, c)| (c, valid && escaped[i]))
+            .collect::<Vec<_>>();
+
+        let values = match operands {
+            [] => vec![("REPLY", line.iter().map(|&(c, _)| c).collect())],
+            names => {
+                let ifs = self.var("IFS").unwrap_or_else(|| " \t\n".to_string());
+                let fields = self::split_read_line(&line, &ifs, names.len());
+                names.iter().map(String::as_str).zip(fields).collect()
+            }
+        };
+        for (name, value) in values {
+            if let Err(err) = self.set_var(name, value) {
+                util::print_error(format!("read: {err}"));
+                return 1;
+            }
+        }
+        i32::from(eof)
+    }
+
+    /// `set [-o | +o] [NAME]`: shows the options, or turns on (`-o`) or off (`+o`) an option.
+    /// Turning off one of the editing modes switches to the other. Options in [`FLAGS`] can be
+    /// set with the letters, too, e.g., `set -b`.
+    fn run_set(&mut self, args: &[String]) -> i32 {
+        let flag_option = |c| {
+            FLAGS
+                .iter()
+                .find(|&&(flag, _)| flag == c)
+                .map(|&(_, name)| name)
+        };
+        let (on, name) = match (args.get(1).map(String::as_str), args.get(2)) {
+            (None, _) => return 0,
+            (Some("--"), _) => {
+                self.positional = args[2..].to_vec();
+                return 0;
+            }
+            (Some(flag @ ("-o" | "+o")), Some(name)) => (flag == "-o", name.as_str()),
+            (Some(flag @ ("-o" | "+o")), None) => {
+                // a space after the longest name, and at least 15 columns like bash
+                let width = OPTIONS
+                    .iter()
+                    .map(|name| name.len() + 1)
+                    .max()
+                    .unwrap_or(0)
+                    .max(15);
+                for name in OPTIONS {
+                    let enabled = self.option(name);
+                    if flag == "-o" {
+                        outln!("{name:<width$}{}", if enabled { "on" } else { "off" });
+                    } else {
+                        outln!("set {}o {name}", if enabled { '-' } else { '+' });
+                    }
+                }
+                return 0;
+            }
+            (Some(arg), _)
+                if arg.len() > 1
+                    && arg.starts_with(['-', '+'])
+                    && arg[1..].chars().all(|c| flag_option(c).is_some()) =>
+            {
+                for c in arg[1..].chars() {
+                    self.set_option(flag_option(c).unwrap(), arg.starts_with('-'));
+                }
+                return 0;
+            }
+            (Some(arg), _) => {
+                util::print_error(format!("set: {arg}: invalid option"));
+                return 2;
+            }
+        };
+
+        if !OPTIONS.contains(&name) {
+            util::print_error(format!("set: {name}: invalid option name"));
+            return 1;
+        }
+        self.set_option(name, on);
+
+        0
+    }
+
+    /// Returns if an option in [`OPTIONS`] is on
+    fn option(&self, name: &str) -> bool {
+        match name {
+            "emacs" => self.edit_mode == EditMode::Emacs,
+            "vi" => self.edit_mode == EditMode::Vi,
+            "allexport" => self.allexport,
+            "noclobber" => self.noclobber,
+            "noglob" => self.noglob,
+            "mkdir_on_redirect" => self.mkdir_on_redirect,
+            "autocd" => self.autocd,
+            "cdfile" => self.cdfile,
+            "huponexit" => self.huponexit,
+            "histexpand" => self.histexpand.load(Ordering::Relaxed),
+            "notify" => self.notify,
+            "pipefail" => self.pipefail,
+            "errexit" => self.errexit,
+            "suggest" => self.suggest,
+            _ => unreachable!("unknown option: {name}"),
+        }
+    }
+
+    /// Returns the letters of the options that are on, which is `$-`: `h` (commands are hashed,
+    /// always), `i` (interactive), `m` (job control) and the ones of [`FLAGS`]
+    pub(super) fn option_flags(&self) -> String {
+        let mut flags = String::from("h");
+        if self.interactive {
+            flags.push('i');
+        }
+        if self.job_control {
+            flags.push('m');
+        }
+        for &(flag, name) in FLAGS {
+            if self.option(name) {
+                flags.push(flag);
+            }
+        }
+        flags
+    }
+
+    /// Turns on or off an option in [`OPTIONS`]
+    fn set_option(&mut self, name: &str, on: bool) {
+        match (name, on) {
+            ("vi", true) | ("emacs", false) => self.edit_mode = EditMode::Vi,
+            ("emacs", true) | ("vi", false) => self.edit_mode = EditMode::Emacs,
+            ("allexport", _) => self.allexport = on,
+            ("noclobber", _) => self.noclobber = on,
+            ("noglob", _) => self.noglob = on,
+            ("mkdir_on_redirect", _) => self.mkdir_on_redirect = on,
+            ("autocd", _) => self.autocd = on,
+            ("cdfile", _) => self.cdfile = on,
+            ("huponexit", _) => self.huponexit = on,
+            ("histexpand", _) => self.histexpand.store(on, Ordering::Relaxed),
+            ("notify", _) => self.notify = on,
+            ("pipefail", _) => self.pipefail = on,
+            ("errexit", _) => self.errexit = on,
+            ("suggest", _) => self.suggest = on,
+            _ => unreachable!("unknown option: {name}"),
+        }
+    }
+
+    /// `alias [NAME[=VALUE]...]`: defines aliases, or shows them as commands that define them
+    fn run_alias(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 {
+            for (name, value) in &self.aliases {
+                outln!("alias {name}={}", self::quote(value));
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for arg in &args[1..] {
+            match arg.split_once('=') {
+                Some((name, value)) if self::is_alias_name(name) => {
+                    self.aliases.insert(name.to_string(), value.to_string());
+                    // lines parsed with the old aliases
+                    self.parse_cache.clear();
+                }
+                Some((name, _)) => {
+                    util::print_error(format!("alias: `{name}': invalid alias name"));
+                    code = 1;
+                }
+                None => match self.aliases.get(arg) {
+                    Some(value) => outln!("alias {arg}={}", self::quote(value)),
+                    None => {
+                        util::print_error(format!("alias: {arg}: not found"));
+                        code = 1;
+                    }
+                },
+            }
+        }
+        code
+    }
+
+    /// `unalias [-a] NAME...`: removes aliases
+    fn run_unalias(&mut self, args: &[String]) -> i32 {
+        self.parse_cache.clear();
+        if args.get(1).map(String::as_str) == Some("-a") {
+            self.aliases.clear();
+            return 0;
+        }
+        if args.len() == 1 {
+            util::print_error("unalias: usage: unalias [-a] NAME...");
+            return 2;
+        }
+
+        let mut code = 0;
+        for name in &args[1..] {
+            if self.aliases.remove(name).is_none() {
+                util::print_error(format!("unalias: {name}: not found"));
+                code = 1;
+            }
+        }
+        code
+    }
+
+    /// `unset [-f | -v] NAME...`: removes variables with `-v`, or functions with `-f`. Without
+    /// either, a name that is not a variable removes the function, like bash. Names that are not
+    /// set are skipped.
+    fn run_unset(&mut self, args: &[String]) -> i32 {
+        // `Some(true)` for functions only, `Some(false)` for variables only
+        let mut functions = None;
+        let mut names = &args[1..];
+        while let Some(arg) = names
+            .first()
+            .filter(|arg| arg.len() > 1 && arg.starts_with('-'))
+        {
+            let only = match arg.as_str() {
+                "-f" => true,
+                "-v" => false,
+                "--" => {
+                    names = &names[1..];
+                    break;
+                }
+                _ => {
+                    util::print_error(format!("unset: {arg}: invalid option"));
+                    return 2;
+                }
+            };
+            if functions.is_some_and(|functions| functions != only) {
+                util::print_error("unset: cannot unset a function and a variable at once");
+                return 2;
+            }
+            functions = Some(only);
+            names = &names[1..];
+        }
+
+        let mut code = 0;
+        for name in names {
+            if functions == Some(true) {
+                self.functions.remove(name);
+            } else if !parser::is_name(name) {
+                util::print_error(format!("unset: {name}: not a valid identifier"));
+                code = 1;
+            } else if !self.unset_var(name) && functions.is_none() {
+                self.functions.remove(name);
+            }
+        }
+        code
+    }
+
+    /// `declare [-afFipx] [+ix] [NAME[=VALUE]...]`: sets variables in the current scope or lists
+    /// them
+    fn run_declare(&mut self, args: &[String]) -> i32 {
+        self.declare(args)
+    }
+
+    /// `export [-p] [NAME[=VALUE]...]`: `declare -x`, or prints the environment without names
+    fn run_export(&mut self, args: &[String]) -> i32 {
+        let operands = match args.get(1).map(String::as_str) {
+            None | Some("-p") => &[][..],
+            Some("--") => &args[2..],
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => {
+                util::print_error(format!("export: {arg}: invalid option"));
+                return 2;
+            }
+            _ => &args[1..],
+        };
+
+        if operands.is_empty() {
+            let vars = env::vars_os().collect::<BTreeMap<_, _>>();
+            for (name, value) in vars {
+                // the ones which can't be set by `export` again
+                let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+                    continue;
+                };
+                if parser::is_name(name) {
+                    outln!("export {name}={}", self::quote(value));
+                }
+            }
+            return 0;
+        }
+
+        let mut args = vec![args[0].clone(), "-x".to_string(), "--".to_string()];
+        args.extend_from_slice(operands);
+        self.declare(&args)
+    }
+
+    /// `local [-aix] NAME[=VALUE]...`: `declare` in a function
+    fn run_local(&mut self, args: &[String]) -> i32 {
+        // only the global scope exists outside functions
+        if self.scopes.len() < 2 {
+            util::print_error("local: can only be used in a function");
+            return 1;
+        }
+
+        self.declare(args)
+    }
+
+    /// Sets variables in the innermost scope. `-i` makes them integer variables, `-x` exports them
+    /// and `-a` makes them arrays, also set by `NAME=(WORD...)`. `+i` and `+x` turn off the
+    /// attributes. `-p` prints them as commands instead, and `-f` and `-F` print the definitions or
+    /// the names of functions.
+    fn declare(&mut self, args: &[String]) -> i32 {
+        let cmd = &args[0];
+        let mut attrs = Vec::new();
+        let mut array = false;
+        let mut print = false;
+        // `Some(true)` prints the definitions of functions, and `Some(false)` only their names
+        let mut functions = None;
+        let mut operands = &args[1..];
+
+        while let Some(arg) = operands
+            .first()
+            .filter(|arg| arg.len() > 1 && arg.starts_with(['-', '+']))
+        {
+            operands = &operands[1..];
+            if arg == "--" {
+                break;
+            }
+            let on = arg.starts_with('-');
+            for flag in arg[1..].chars() {
+                match flag {
+                    'i' | 'x' => attrs.push((flag, on)),
+                    'a' if on => array = true,
+                    'p' if on => print = true,
+                    'f' if on => functions = Some(functions.unwrap_or(true)),
+                    'F' if on => functions = Some(false),
+                    _ => {
+                        util::print_error(format!("{cmd}: {}{flag}: invalid option", &arg[..1]));
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        if let Some(definitions) = functions {
+            return self.print_functions(cmd, operands, definitions);
+        }
+        if print {
+            return self.print_declarations(cmd, operands);
+        }
+
+        if operands.is_empty() {
+            // inner scopes shadow outer ones
+            let vars = self.scopes.iter().flatten().collect::<BTreeMap<_, _>>();
+            for (name, var) in vars {
+                match &var.value {
+                    Value::Scalar(value) => outln!("{name}={value}"),
+                    Value::Array(elements) => {
+                        let elements = elements.iter().map(|e| format!("{e:?}"));
+                        outln!("{name}=({})", elements.collect::<Vec<_>>().join(" "));
+                    }
+                }
+            }
+            return 0;
+        }
+
+        let depth = self.scopes.len() - 1;
+        let mut code = 0;
+
+        for operand in operands {
+            let (name, value) = match operand.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (operand.as_str(), None),
+            };
+
+            if !parser::is_name(name) {
+                util::print_error(format!("{cmd}: `{operand}': not a valid identifier"));
+                code = 1;
+                continue;
+            }
+
+            // a local variable shadows the one from the environment, which comes back on return
+            let declared = self.scopes.iter().any(|scope| scope.contains_key(name));
+            if depth > 0 && !declared {
+                if let Ok(value) = env::var(name) {
+                    let var = Var {
+                        value: Value::Scalar(value),
+                        exported: true,
+                        ..Var::default()
+                    };
+                    self.scopes[0].insert(name.to_string(), var);
+                }
+            }
+
+            let var = self.scopes[depth]
+                .entry(name.to_string())
+                .or_insert_with(|| {
+                    // a global declaration takes over the environment variable
+                    let value = env::var(name).ok().filter(|_| depth == 0);
+                    Var {
+                        exported: value.is_some(),
+                        value: Value::Scalar(value.unwrap_or_default()),
+                        ..Var::default()
+                    }
+                });
+
+            for &(flag, on) in &attrs {
+                match flag {
+                    'i' => var.integer = on,
+                    _ => var.exported = on,
+                }
+            }
+            // `-a` keeps a value as the first element
+            if let (true, Value::Scalar(scalar)) = (array, &var.value) {
+                let elements = Some(scalar.clone()).filter(|s| !s.is_empty());
+                var.value = Value::Array(elements.into_iter().collect());
+            }
+            let is_array = matches!(var.value, Value::Array(_));
+
+            if attrs.contains(&('x', false)) {
+                env::remove_var(name);
+            }
+
+            // `NAME=(WORD...)` has the elements quoted by the expansion
+            if let Some(words) = value.and_then(parser::array_words) {
+                match words {
+                    Ok(words) => {
+                        let elements = words.iter().map(|word| expand::unquote(word)).collect();
+                        self.set_array(name, elements);
+                    }
+                    Err(err) => {
+                        util::print_error(format!("{cmd}: {name}: {err}"));
+                        code = 1;
+                    }
+                }
+                continue;
+            }
+
+            let res = match value {
+                Some(value) => self.set_var_in(depth, name, value.to_string()),
+                // the environment has only the first element
+                None if is_array => {
+                    let var = &self.scopes[depth][name];
+                    if var.exported {
+                        env::set_var(name, var.value.scalar());
+                    }
+                    Ok(())
+                }
+                None => {
+                    let value = self.scopes[depth][name].value.scalar().to_string();
+                    self.set_var_in(depth, name, value)
+                }
+            };
+            if let Err(err) = res {
+                util::print_error(format!("{cmd}: {name}: {err}"));
+                code = 1;
+            }
+        }
+
+        code
+    }
+
+    /// Prints the definitions of functions, or `declare -f NAME` lines without `definitions`, all
+    /// of them without names
+    fn print_functions(&self, cmd: &str, names: &[String], definitions: bool) -> i32 {
+        let print = |name: &str, function: &Function| match definitions {
+            true => outln!("{}", function.text),
+            false => outln!("declare -f {name}"),
+        };
+
+        if names.is_empty() {
+            for (name, function) in &self.functions {
+                print(name, function);
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for name in names {
+            match self.functions.get(name) {
+                Some(function) => print(name, function),
+                None => {
+                    util::print_error(format!("{cmd}: {name}: function not found"));
+                    code = 1;
+                }
+            }
+        }
+        code
+    }
+
+    /// Prints variables as `declare` commands which set them again, all of them without names,
+    /// e.g., `declare -a arr=('a' 'b c')`
+    fn print_declarations(&self, cmd: &str, names: &[String]) -> i32 {
+        // inner scopes shadow outer ones
+        let vars = self.scopes.iter().flatten().collect::<BTreeMap<_, _>>();
+        let mut code = 0;
+
+        let print = |name: &str, var: &Var| {
+            let is_array = matches!(var.value, Value::Array(_));
+            let flags = [(is_array, 'a'), (var.integer, 'i'), (var.exported, 'x')]
+                .into_iter()
+                .filter_map(|(on, flag)| on.then_some(flag))
+                .collect::<String>();
+            let flags = if flags.is_empty() {
+                "--".to_string()
+            } else {
+                format!("-{flags}")
+            };
+            match &var.value {
+                Value::Scalar(value) => outln!("declare {flags} {name}={}", self::quote(value)),
+                Value::Array(elements) => {
+                    let elements = elements.iter().map(|e| self::quote(e));
+                    outln!(
+                        "declare {flags} {name}=({})",
+                        elements.collect::<Vec<_>>().join(" ")
+                    );
+                }
+            }
+        };
+
+        if names.is_empty() {
+            for (name, var) in vars {
+                print(name, var);
+            }
+            return 0;
+        }
+
+        for name in names {
+            match vars.get(name) {
+                Some(var) => print(name, var),
+                // not declared in the shell, but in the environment
+                None => match env::var(name) {
+                    Ok(value) if parser::is_name(name) => {
+                        outln!("declare -x {name}={}", self::quote(&value))
+                    }
+                    _ => {
+                        util::print_error(format!("{cmd}: {name}: not found"));
+                        code = 1;
+                    }
+                },
+            }
+        }
+
+        code
+    }
+
+    /// `disown [-h] [-a | JOB...]`: removes the jobs (all with `-a`, the current one by default)
+    /// from the table, so that the shell neither reports nor hangs them up. With `-h`, they stay
+    /// in the table but are not sent `SIGHUP` when the shell exits.
+    fn run_disown(&mut self, args: &[String]) -> i32 {
+        let mut nohup = false;
+        let mut all = false;
+        let mut specs = &args[1..];
+        while let Some(arg) = specs
+            .first()
+            .filter(|arg| arg.len() > 1 && arg.starts_with('-'))
+        {
+            match arg.as_str() {
+                "-h" => nohup = true,
+                "-a" => all = true,
+                "-ah" | "-ha" => (nohup, all) = (true, true),
+                "--" => {
+                    specs = &specs[1..];
+                    break;
+                }
+                _ => {
+                    util::print_error(format!("disown: {arg}: invalid option"));
+                    return 2;
+                }
+            }
+            specs = &specs[1..];
+        }
+
+        let mut code = 0;
+        let job_ids = if all {
+            self.jobs.keys().copied().collect::<Vec<_>>()
+        } else if specs.is_empty() {
+            match self.find_job(None) {
+                Some(job_id) => vec![job_id],
+                None => {
+                    util::print_error("disown: no current job");
+                    return 1;
+                }
+            }
+        } else {
+            let mut job_ids = Vec::new();
+            for spec in specs {
+                match self.find_job(Some(spec)) {
+                    Some(job_id) => job_ids.push(job_id),
+                    None => {
+                        util::print_error(format!("disown: {spec}: no such job"));
+                        code = 1;
+                    }
+                }
+            }
+            job_ids
+        };
+
+        for job_id in job_ids {
+            if nohup {
+                if let Some(job) = self.jobs.get_mut(&job_id) {
+                    job.nohup = true;
+                }
+            } else {
+                self.remove_job(job_id);
+            }
+        }
+
+        code
+    }
+
+    /// `exec [COMMAND [ARG...]]`: replaces the shell process with the command. Without a command,
+    /// the redirections are applied to the shell by the caller.
+    ///
+    /// The jobs are left running (or hung up, see [`Worker::hang_up_jobs`]) and removed from the
+    /// table once the command is found, so that they're kept if it's not. The terminal modes are
+    /// restored, and the history has been saved by the `main` thread before the line was run.
+    fn run_exec(&mut self, args: &[String]) -> i32 {
+        if args.len() < 2 {
+            return 0;
+        }
+
+        let c_args = match args[1..]
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(c_args) => c_args,
+            Err(err) => {
+                util::print_error(format!("exec: {err}"));
+                return 126;
+            }
+        };
+
+        let runnable = if args[1].contains('/') {
+            unistd::access(args[1].as_str(), AccessFlags::X_OK)
+        } else {
+            self.commands
+                .locate(&args[1], true)
+                .map(|_| ())
+                .ok_or(nix::Error::ENOENT)
+        };
+        let err = match runnable {
+            Ok(()) => {
+                self.hang_up_jobs(self.huponexit);
+                let job_ids = self.jobs.keys().copied().collect::<Vec<_>>();
+                for job_id in job_ids {
+                    self.remove_job(job_id);
+                }
+                self.restore_terminal();
+
+                let _ = io::stdout().flush();
+                let saved = super::reset_signals();
+                let err = unistd::execvp(&c_args[0], &c_args).unwrap_err();
+                super::restore_signals(saved);
+                err
+            }
+            Err(err) => err,
+        };
+
+        util::print_error(format!("exec: {}: {err}", args[1]));
+        if err == nix::Error::ENOENT {
+            127
+        } else {
+            126
+        }
+    }
+
+    /// `exit [CODE]`: quits the shell with the given code or the last exit code
+    fn run_exit(&mut self, args: &[String]) -> i32 {
+        let code = match args.get(1) {
+            Some(arg) => match arg.parse::<i32>() {
+                Ok(code) => code,
+                Err(_) => {
+                    util::print_error(format!("exit: {arg}: numeric argument required"));
+                    return 2;
+                }
+            },
+            None => self.exit_code,
+        };
+
+        // scripts exit right away
+        let stopped = self
+            .jobs
+            .keys()
+            .any(|&job_id| self.job_state(job_id) == Some(JobState::Stopped));
+        if self.interactive && stopped && !self.exit_warned {
+            util::print_warning("there are stopped jobs (`exit` again to quit)");
+            self.exit_warned = true;
+            return 1;
+        }
+
+        self.quit = true;
+        code
+    }
+
+    /// `return [CODE]`: ends the function or the file of `source` after this command. The exit code
+    /// is the code or the last exit code.
+    fn run_return(&mut self, args: &[String]) -> i32 {
+        if self.func_depth == 0 && self.source_depth == 0 {
+            util::print_error("return: can only be used in a function or a file of source");
+            return 1;
+        }
+
+        let code = match args.get(1) {
+            Some(arg) => match arg.parse::<i32>() {
+                Ok(code) => code & 0xff,
+                Err(_) => {
+                    util::print_error(format!("return: {arg}: numeric argument required"));
+                    2
+                }
+            },
+            None => self.exit_code,
+        };
+
+        self.returning = true;
+        code
+    }
+
+    /// `shift [N]`: removes the first N positional parameters, or none if there are fewer
+    fn run_shift(&mut self, args: &[String]) -> i32 {
+        let n = match args.get(1).map(|arg| (arg, arg.parse::<usize>())) {
+            Some((_, Ok(n))) => n,
+            Some((arg, Err(_))) => {
+                util::print_error(format!("shift: {arg}: numeric argument required"));
+                return 2;
+            }
+            None => 1,
+        };
+
+        if n > self.positional.len() {
+            util::print_error(format!("shift: {n}: shift count out of range"));
+            return 1;
+        }
+        self.positional.drain(..n);
+        0
+    }
+
+    /// `hash [-r] [-p PATH NAME] [NAME...]`: remembers the locations of the commands, or shows
+    /// the remembered ones with the number of times they were run
+    fn run_hash(&mut self, args: &[String]) -> i32 {
+        let mut operands = &args[1..];
+        match operands.first().map(String::as_str) {
+            Some("-r") => {
+                self.commands.clear();
+                operands = &operands[1..];
+            }
+            Some("-p") => {
+                let [_, path, name, ..] = operands else {
+                    util::print_error("hash: usage: hash -p PATH NAME");
+                    return 2;
+                };
+                self.commands.insert(name, PathBuf::from(path));
+                return 0;
+            }
+            Some("--") => operands = &operands[1..],
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => {
+                util::print_error(format!("hash: {arg}: invalid option"));
+                return 2;
+            }
+            _ => {}
+        }
+
+        if operands.is_empty() && args.len() == 1 {
+            let mut entries = self.commands.entries().peekable();
+            if entries.peek().is_none() {
+                outln!("hash: hash table empty");
+                return 0;
+            }
+            outln!("hits    command");
+            for (_, entry) in entries {
+                outln!("{:>4}    {}", entry.hits, entry.path.display());
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for name in operands {
+            // not searched in `PATH`
+            if name.contains('/') || self::find(name).is_some() {
+                continue;
+            }
+            if self.commands.locate(name, false).is_none() {
+                util::print_error(format!("hash: {name}: not found"));
+                code = 1;
+            }
+        }
+        code
+    }
+
+    /// `jobs [-l | -p] [JOB...]`: lists the jobs, or the given ones. `-l` adds the process IDs of
+    /// the processes of a pipeline that haven't exited, and `-p` shows only the process group IDs.
+    fn run_jobs(&mut self, args: &[String]) -> i32 {
+        let (flag, specs) = match args.get(1).map(String::as_str) {
+            Some(flag @ ("-l" | "-p")) => (Some(flag), &args[2..]),
+            Some("--") => (None, &args[2..]),
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => {
+                util::print_error(format!("jobs: {arg}: invalid option"));
+                return 2;
+            }
+            _ => (None, &args[1..]),
+        };
+
+        let mut code = 0;
+        let job_ids = if specs.is_empty() {
+            self.jobs.keys().copied().collect::<Vec<_>>()
+        } else {
+            let mut job_ids = Vec::new();
+            for spec in specs {
+                match self.find_job(Some(spec)) {
+                    Some(job_id) => job_ids.push(job_id),
+                    None => {
+                        util::print_error(format!("jobs: {spec}: no such job"));
+                        code = 1;
+                    }
+                }
+            }
+            job_ids
+        };
+
+        for job_id in job_ids {
+            let Some(state) = self.job_state(job_id) else {
+                continue;
+            };
+            let msg = self.format_job(job_id, state);
+            match flag {
+                Some("-p") => outln!("{}", self.jobs[&job_id].gpid),
+                Some("-l") => {
+                    let pids = self.job_pids(job_id);
+                    let pids = pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>();
+                    let pids = pids.join(" ");
+                    // after `[N]`
+                    let (id, rest) = msg.split_once("  ").unwrap_or((&msg, ""));
+                    outln!("{id}  {pids}  {rest}");
+                }
+                _ => outln!("{msg}"),
+            }
+        }
+
+        code
+    }
+
+    /// `fg [JOB]`: continues a job in foreground
+    fn run_fg(&mut self, args: &[String]) -> i32 {
+        let Some(job_id) = self.find_job(args.get(1).map(String::as_str)) else {
+            util::print_error("fg: no such job");
+            return 1;
+        };
+        // the job may have just exited
+        if let Some(code) = self.check_job_done(job_id) {
+            return code;
+        }
+
+        util::print_line(&self.jobs[&job_id].line);
+
+        if let Err(err) = self.signal_job(job_id, Some(Signal::SIGCONT)) {
+            if let Some(code) = self.check_job_done(job_id) {
+                return code;
+            }
+            util::print_error(format!("fg: {err}"));
+            return 1;
+        }
+
+        self.set_job_running(job_id);
+        self.put_job_fg(job_id)
+    }
+
+    /// `bg [JOB]`: continues a stopped job in background
+    fn run_bg(&mut self, args: &[String]) -> i32 {
+        let Some(job_id) = self.find_job(args.get(1).map(String::as_str)) else {
+            util::print_error("bg: no such job");
+            return 1;
+        };
+        if let Some(code) = self.check_job_done(job_id) {
+            return code;
+        }
+
+        if self.job_state(job_id) == Some(JobState::Running) {
+            util::print_warning(format!("bg: job {job_id} already in background"));
+            return 0;
+        }
+
+        if let Err(err) = self.signal_job(job_id, Some(Signal::SIGCONT)) {
+            if let Some(code) = self.check_job_done(job_id) {
+                return code;
+            }
+            util::print_error(format!("bg: {err}"));
+            return 1;
+        }
+        util::print_line(format_args!("[{job_id}] {} &", self.jobs[&job_id].line));
+
+        self.set_job_running(job_id);
+        0
+    }
+
+    /// `kill [-s SIGNAL | -SIGNAL] PID | %JOB...`: sends a signal to processes or to the process
+    /// groups of jobs. A stopped job is continued so that it can handle the signal. `kill -l` lists
+    /// the signals.
+    fn run_kill(&mut self, args: &[String]) -> i32 {
+        if args.get(1).is_some_and(|arg| arg == "-l") {
+            return self::list_signals(&args[2..]);
+        }
+
+        let (signal, targets) = match args.get(1).map(String::as_str) {
+            Some("-s") => (
+                args.get(2).map(String::as_str),
+                args.get(3..).unwrap_or_default(),
+            ),
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => (Some(&arg[1..]), &args[2..]),
+            _ => (Some("TERM"), args.get(1..).unwrap_or_default()),
+        };
+
+        // signal 0 only checks if the processes exist
+        let signal = match signal {
+            Some("0") => None,
+            Some(name) => match util::signal_from_str(name) {
+                Some(signal) => Some(signal),
+                None => {
+                    util::print_error(format!("kill: {name}: invalid signal"));
+                    return 1;
+                }
+            },
+            None => {
+                util::print_error("kill: -s: option requires an argument");
+                return 2;
+            }
+        };
+        if targets.is_empty() {
+            util::print_error("kill: usage: kill [-s SIGNAL | -SIGNAL] PID | %JOB...");
+            return 2;
+        }
+
+        let mut code = 0;
+        for target in targets {
+            let res = if target.starts_with('%') {
+                let Some(job_id) = self.find_job(Some(target)) else {
+                    util::print_error(format!("kill: {target}: no such job"));
+                    code = 1;
+                    continue;
+                };
+                let res = self.signal_job(job_id, signal);
+                if res.is_ok() && self.job_state(job_id) == Some(JobState::Stopped) {
+                    let _ = self.signal_job(job_id, Some(Signal::SIGCONT));
+                }
+                res
+            } else {
+                match target.parse::<i32>() {
+                    Ok(pid) => signal::kill(unistd::Pid::from_raw(pid), signal),
+                    Err(_) => {
+                        util::print_error(format!("kill: {target}: not a PID or a job spec"));
+                        code = 1;
+                        continue;
+                    }
+                }
+            };
+
+            if let Err(err) = res {
+                util::print_error(format!("kill: {target}: {}", err.desc()));
+                code = 1;
+            }
+        }
+        code
+    }
+
+    /// Sends a signal to the process group of a job, or to each process without job control
+    pub(super) fn signal_job(&self, job_id: usize, signal: Option<Signal>) -> nix::Result<()> {
+        let gpid = self.jobs[&job_id].gpid;
+        if self.job_control {
+            return signal::killpg(gpid, signal);
+        }
+
+        let pids = self.gpid_to_pid.get(&gpid).map(|(_, pids)| pids);
+        for &pid in pids.into_iter().flatten() {
+            signal::kill(pid, signal)?;
+        }
+        Ok(())
+    }
+
+    /// `source FILE` (or `. FILE`): runs the command lines of a file in the shell. The exit code is
+    /// the one of the last command.
+    fn run_source(&mut self, args: &[String]) -> i32 {
+        let cmd = &args[0];
+        let [_, path] = args else {
+            util::print_error(format!("{cmd}: usage: {cmd} FILE"));
+            return 2;
+        };
+
+        if self.source_depth >= self.limits.source_depth {
+            util::print_error(format!("{cmd}: maximum nesting level exceeded"));
+            return 1;
+        }
+
+        self.exit_code = 0;
+        self.source_depth += 1;
+        let res = self.run_file(Path::new(path));
+        self.source_depth -= 1;
+        // `return` ends only the file
+        self.returning = false;
+        match res {
+            Ok(()) => self.exit_code,
+            Err(err) => {
+                util::print_error(format!("{cmd}: {path}: {err}"));
+                1
+            }
+        }
+    }
+
+    /// `suspend [-f]`: stops the shell's process group, giving the terminal to the parent shell.
+    /// A login shell or a session leader has no parent shell to return to, so it's refused without
+    /// `-f`.
+    fn run_suspend(&mut self, args: &[String]) -> i32 {
+        let force = match args.get(1).map(String::as_str) {
+            None => false,
+            Some("-f") => true,
+            Some(arg) => {
+                util::print_error(format!("suspend: {arg}: invalid option"));
+                return 2;
+            }
+        };
+
+        let login = env::args().next().is_some_and(|arg0| arg0.starts_with('-'));
+        let leader = unistd::getsid(None).ok() == Some(unistd::getpid());
+        if (login || leader) && !force {
+            util::print_error("suspend: cannot suspend a login shell");
+            return 1;
+        }
+
+        // the parent shell gives the terminal back when it continues this shell
+        if let Ok(parent) = unistd::getpgid(Some(unistd::getppid())) {
+            if parent != self.shell_gpid {
+                self.set_terminal_fg(parent);
+            }
+        }
+        if let Some(termios) = &self.termios {
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, termios::SetArg::TCSANOW, termios);
+        }
+
+        if let Err(err) = signal::killpg(self.shell_gpid, Signal::SIGSTOP) {
+            util::print_error(format!("suspend: {err}"));
+            return 1;
+        }
+
+        0
+    }
+
+    /// `trap [[COMMAND | '' | -] SIGNAL...]`: sets the command run when the signals arrive, or
+    /// when the shell exits for `EXIT` (or `0`). An empty command ignores the signals and `-`
+    /// resets them. Without arguments or with `-p`, prints the traps as commands that set them
+    /// again, and `-l` lists the signals.
+    fn run_trap_builtin(&mut self, args: &[String]) -> i32 {
+        let mut args = &args[1..];
+        match args.first().map(String::as_str) {
+            Some("-p") => return self.print_traps(&args[1..]),
+            Some("-l") => return self::list_signals(&[]),
+            Some("--") => args = &args[1..],
+            _ => {}
+        }
+
+        let (cmd, names) = match args {
+            [] => return self.print_traps(&[]),
+            // `trap SIGNAL` resets the signal
+            [name] => ("-", std::slice::from_ref(name)),
+            [cmd, names @ ..] => (cmd.as_str(), names),
+        };
+
+        let mut code = 0;
+        for name in names {
+            if matches!(name.to_ascii_uppercase().as_str(), "EXIT" | "0") {
+                self.exit_trap = (cmd != "-").then(|| cmd.to_string());
+                continue;
+            }
+
+            let Some(signal) = util::signal_from_str(name) else {
+                util::print_error(format!("trap: {name}: invalid signal"));
+                code = 1;
+                continue;
+            };
+
+            if cmd == "-" {
+                self.traps.remove(&signal);
+                continue;
+            }
+            if let Err(err) = self.catch_signal(signal) {
+                util::print_error(format!("trap: {name}: {err}"));
+                code = 1;
+                continue;
+            }
+            self.traps.insert(signal, cmd.to_string());
+        }
+        code
+    }
+
+    /// Makes the signal forwarded to the worker, or polled without the `signal_handler` thread
+    fn catch_signal(&mut self, signal: Signal) -> io::Result<()> {
+        if signal_hook::consts::FORBIDDEN.contains(&(signal as i32)) {
+            return Err(io::Error::other("cannot be trapped"));
+        }
+
+        if self.signal_handle.is_none() {
+            let signals = signal_hook::iterator::Signals::new::<[i32; 0], i32>([])?;
+            self.signal_handle = Some(signals.handle());
+            self.polled_signals = Some(signals);
+        }
+
+        match &self.signal_handle {
+            Some(handle) => handle.add_signal(signal as i32),
+            None => Ok(()),
+        }
+    }
+
+    /// Prints the traps of the signals (all of them if none is given) as `trap` commands
+    fn print_traps(&self, names: &[String]) -> i32 {
+        let print = |cmd: &str, name: &str| outln!("trap -- {} {name}", self::quote(cmd));
+
+        if names.is_empty() {
+            if let Some(cmd) = &self.exit_trap {
+                print(cmd, "EXIT");
+            }
+            let mut traps = self.traps.iter().collect::<Vec<_>>();
+            traps.sort_by_key(|(signal, _)| **signal as i32);
+            for (signal, cmd) in traps {
+                print(cmd, signal.as_str());
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for name in names {
+            if matches!(name.to_ascii_uppercase().as_str(), "EXIT" | "0") {
+                if let Some(cmd) = &self.exit_trap {
+                    print(cmd, "EXIT");
+                }
+                continue;
+            }
+            match util::signal_from_str(name) {
+                Some(signal) => {
+                    if let Some(cmd) = self.traps.get(&signal) {
+                        print(cmd, signal.as_str());
+                    }
+                }
+                None => {
+                    util::print_error(format!("trap: {name}: invalid signal"));
+                    code = 1;
+                }
+            }
+        }
+        code
+    }
+
+    /// `wait [-n] [JOB | PID...]`: waits for the jobs (all jobs if none is given) to finish and
+    /// returns the exit code of the last one. A PID, e.g., `$!`, waits for the job of the process
+    /// and gives the exit code of the process. With `-n`, waits for the next one to finish,
+    /// returning its exit code.
+    fn run_wait(&mut self, args: &[String]) -> i32 {
+        let mut next = false;
+        let mut targets = Vec::new();
+
+        for arg in &args[1..] {
+            if arg == "-n" {
+                next = true;
+                continue;
+            }
+
+            if let Some(pid) = arg.parse::<i32>().ok().filter(|&pid| pid > 0) {
+                let pid = unistd::Pid::from_raw(pid);
+                // the process may have exited before the job is done
+                let job = self.jobs.iter().find_map(|(&job_id, job)| {
+                    let index = job.pids.iter().position(|&p| p == pid)?;
+                    Some((job_id, index))
+                });
+                if let Some((job_id, index)) = job {
+                    targets.push(WaitTarget::Process(job_id, index));
+                } else if let Some(code) = self.take_finished(pid) {
+                    // the job has been reported as done
+                    targets.push(WaitTarget::Finished(code));
+                } else {
+                    util::print_error(format!("wait: pid {pid} is not a child of this shell"));
+                    return 127;
+                }
+                continue;
+            }
+
+            match arg.starts_with('%').then(|| self.find_job(Some(arg))) {
+                Some(Some(job_id)) => targets.push(WaitTarget::Job(job_id)),
+                Some(None) => {
+                    util::print_error(format!("wait: {arg}: no such job"));
+                    return 127;
+                }
+                None => {
+                    util::print_error(format!("wait: {arg}: not a job spec"));
+                    return 2;
+                }
+            }
+        }
+
+        let mut pending = if targets.is_empty() {
+            self.jobs.keys().copied().collect::<BTreeSet<_>>()
+        } else {
+            targets.iter().filter_map(WaitTarget::job_id).collect()
+        };
+        // job ID to the finished job
+        let mut done = HashMap::new();
+        self.clear_interrupt();
+
+        loop {
+            // done before they were waited for, e.g., reaped while waiting for another job
+            let finished = pending
+                .iter()
+                .copied()
+                .filter(|&job_id| self.job_state(job_id) == Some(JobState::Done))
+                .collect::<Vec<_>>();
+            for job_id in finished {
+                let Some(job) = self.remove_job(job_id) else {
+                    continue;
+                };
+                if next {
+                    return self.job_exit_code(&job);
+                }
+                done.insert(job_id, job);
+            }
+
+            // stopped jobs would never finish
+            pending.retain(|&job_id| self.job_state(job_id) == Some(JobState::Running));
+            if pending.is_empty() {
+                break;
+            }
+
+            let (status, job_id) = match self.wait_child_interruptible() {
+                Ok((status, Some(job_id))) => (status, job_id),
+                Ok((_, None)) => continue,
+                // `Ctrl+c`, as if the builtin was killed by `SIGINT`
+                Err(nix::Error::EINTR) => return 128 + libc::SIGINT,
+                Err(err) => {
+                    util::print_error(format!("wait: {err}"));
+                    return 1;
+                }
+            };
+
+            let is_stop = matches!(status, WaitStatus::Stopped(..));
+            if !pending.contains(&job_id) || self.job_state(job_id) != Some(JobState::Done) {
+                self.notify_job_change(job_id, is_stop);
+                self.show_notices();
+                continue;
+            }
+
+            // the waited job is done; no notice is needed
+            let Some(job) = self.remove_job(job_id) else {
+                continue;
+            };
+            if next {
+                return self.job_exit_code(&job);
+            }
+            done.insert(job_id, job);
+        }
+
+        if next {
+            // no job to wait for
+            return 127;
+        }
+
+        match targets.last() {
+            Some(&WaitTarget::Job(job_id)) => {
+                done.get(&job_id).map_or(0, |job| self.job_exit_code(job))
+            }
+            Some(&WaitTarget::Process(job_id, index)) => done
+                .get(&job_id)
+                .and_then(|job| job.exit_codes.get(index).copied())
+                .unwrap_or(0),
+            Some(&WaitTarget::Finished(code)) => code,
+            None => 0,
+        }
+    }
+
+    /// `nohup COMMAND [ARG...]`: execs the command with `SIGHUP` ignored, so that it survives the
+    /// shell. Output to a terminal goes to `nohup.out` instead. Runs in a forked child.
+    fn run_nohup(&mut self, args: &[String]) -> i32 {
+        if args.len() < 2 {
+            util::print_error("nohup: missing operand");
+            return 125;
+        }
+
+        let c_args = match args[1..]
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(c_args) => c_args,
+            Err(err) => {
+                util::print_error(format!("nohup: {err}"));
+                return 125;
+            }
+        };
+
+        if let Err(err) = unsafe { signal::signal(Signal::SIGHUP, SigHandler::SigIgn) } {
+            util::print_error(format!("nohup: {}", err.desc()));
+            return 125;
+        }
+
+        let is_tty = |fd| unistd::isatty(fd).unwrap_or(false);
+        let mut notes = Vec::new();
+
+        if is_tty(libc::STDIN_FILENO) {
+            // make reads fail like GNU nohup
+            if let Ok(fd) = fcntl::open("/dev/null", OFlag::O_WRONLY, Mode::empty()) {
+                let _ = unistd::dup2(fd, libc::STDIN_FILENO);
+                let _ = unistd::close(fd);
+            }
+            notes.push("ignoring input".to_string());
+        }
+
+        let stdout_is_tty = is_tty(libc::STDOUT_FILENO);
+        if stdout_is_tty {
+            let flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND;
+            let mode = Mode::S_IRUSR | Mode::S_IWUSR;
+
+            let mut path = "nohup.out".to_string();
+            let mut res = fcntl::open(path.as_str(), flags, mode);
+            if res.is_err() {
+                if let Ok(home) = env::var("HOME") {
+                    path = format!("{home}/nohup.out");
+                    res = fcntl::open(path.as_str(), flags, mode);
+                }
+            }
+
+            match res {
+                Ok(fd) => {
+                    let _ = unistd::dup2(fd, libc::STDOUT_FILENO);
+                    let _ = unistd::close(fd);
+                    notes.push(format!("appending output to '{path}'"));
+                }
+                Err(err) => {
+                    util::print_error(format!("nohup: failed to open '{path}': {}", err.desc()));
+                    return 125;
+                }
+            }
+        }
+
+        if is_tty(libc::STDERR_FILENO) {
+            if !stdout_is_tty {
+                notes.push("redirecting stderr to stdout".to_string());
+            }
+            if !notes.is_empty() {
+                util::print_line(format_args!("nohup: {}", notes.join(" and ")));
+            }
+            let _ = unistd::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO);
+        } else if !notes.is_empty() {
+            util::print_line(format_args!("nohup: {}", notes.join(" and ")));
+        }
+
+        let err = unistd::execvp(&c_args[0], &c_args).unwrap_err();
+        util::print_error(format!(
+            "nohup: failed to run command '{}': {}",
+            args[1],
+            err.desc()
+        ));
+        if err == nix::Error::ENOENT {
+            127
+        } else {
+            126
+        }
+    }
+
+    /// `env [-i] [-u NAME]... [NAME=VALUE]... [COMMAND [ARG...]]`: runs a command with the
+    /// environment of the shell (the exported variables), starting empty with `-i`, without the
+    /// `-u` ones and with the assignments. Without a command, prints the environment sorted by
+    /// name. Runs in a forked child.
+    fn run_env(&mut self, args: &[String]) -> i32 {
+        let mut vars = env::vars_os().collect::<BTreeMap<_, _>>();
+        let mut operands = &args[1..];
+
+        while let Some(arg) = operands.first().filter(|arg| arg.starts_with('-')) {
+            operands = &operands[1..];
+            match arg.as_str() {
+                "-i" | "-" => vars.clear(),
+                "-u" => {
+                    let Some(name) = operands.first() else {
+                        util::print_error("env: -u: option requires an argument");
+                        return 125;
+                    };
+                    vars.remove(OsStr::new(name));
+                    operands = &operands[1..];
+                }
+                "--" => break,
+                _ => {
+                    util::print_error(format!("env: {arg}: invalid option"));
+                    return 125;
+                }
+            }
+        }
+
+        while let Some((name, value)) = operands.first().and_then(|arg| arg.split_once('=')) {
+            vars.insert(name.into(), value.into());
+            operands = &operands[1..];
+        }
+
+        if operands.is_empty() {
+            let mut stdout = io::stdout().lock();
+            for (name, value) in vars {
+                let mut line = name.into_vec();
+                line.push(b'=');
+                line.extend(value.into_vec());
+                line.push(b'\n');
+                if stdout.write_all(&line).is_err() {
+                    return 1;
+                }
+            }
+            return 0;
+        }
+
+        let c_args = operands
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>();
+        let c_env = vars
+            .into_iter()
+            .map(|(name, value)| {
+                let mut entry = name.into_vec();
+                entry.push(b'=');
+                entry.extend(value.into_vec());
+                CString::new(entry)
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let (c_args, c_env) = match (c_args, c_env) {
+            (Ok(c_args), Ok(c_env)) => (c_args, c_env),
+            (Err(err), _) | (_, Err(err)) => {
+                util::print_error(format!("env: {err}"));
+                return 125;
+            }
+        };
+
+        let err = unistd::execvpe(&c_args[0], &c_args, &c_env).unwrap_err();
+        util::print_error(format!("env: {}: {err}", operands[0]));
+        if err == nix::Error::ENOENT {
+            127
+        } else {
+            126
+        }
+    }
+}
+
+/// `kill -l [SIGNAL...]`: lists the signals in columns like bash, or converts between the names
+/// and the numbers of signals. An exit code of a killed process is taken as its signal.
+fn list_signals(names: &[String]) -> i32 {
+    if names.is_empty() {
+        let signals = Signal::iterator().collect::<Vec<_>>();
+        for row in signals.chunks(5) {
+            let row = row
+                .iter()
+                .map(|&signal| format!("{:2}) {}", signal as i32, signal.as_str()))
+                .collect::<Vec<_>>();
+            outln!("{}", row.join("\t"));
+        }
+        return 0;
+    }
+
+    let mut code = 0;
+    for name in names {
+        let found = match name.parse::<i32>() {
+            Ok(num) => {
+                let num = if num > 128 { num - 128 } else { num };
+                let signal = Signal::try_from(num).ok();
+                signal.map(|signal| util::signal_name(signal).to_string())
+            }
+            Err(_) => util::signal_from_str(name).map(|signal| (signal as i32).to_string()),
+        };
+        match found {
+            Some(found) => outln!("{found}"),
+            None => {
+                util::print_error(format!("kill: {name}: invalid signal"));
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Returns true if the name can be an alias, i.e., a word without quotes, expansions or slashes
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| c.is_whitespace() || "/\\'\"`$=;|&()<>".contains(c))
+}
+
+/// Removes `.` and `..` from an absolute path without resolving symbolic links, for `cd -L`
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// Writes a line of [`outln!`] unless an earlier line failed
+fn write_line(args: fmt::Arguments) {
+    self::write_out(|stdout| stdout.write_fmt(args).and_then(|_| stdout.write_all(b"\n")));
+}
+
+/// Writes to the standard output for a builtin, unless an earlier write has failed. The error is
+/// kept in [`WRITE_ERROR`].
+fn write_out(write: impl FnOnce(&mut io::StdoutLock) -> io::Result<()>) {
+    WRITE_ERROR.with(|error| {
+        if error.borrow().is_some() {
+            return;
+        }
+        if let Err(err) = write(&mut io::stdout().lock()) {
+            *error.borrow_mut() = Some(err);
+        }
+    });
+}
+
+/// Splits a line of `read` into `n` fields at the `IFS` characters not escaped, the last field
+/// taking the rest of the line. IFS whitespace around the fields is removed as in word splitting.
+fn split_read_line(line: &[(char, bool)], ifs: &str, n: usize) -> Vec<String> {
+    let is_ifs = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_space = |x: &(char, bool)| is_ifs(x) && x.0.is_ascii_whitespace();
+    let text = |chars: &[(char, bool)]| chars.iter().map(|&(c, _)| c).collect::<String>();
+
+    let start = line.iter().position(|x| !is_space(x)).unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|x| !is_space(x))
+        .map_or(start, |i| i + 1);
+    let mut rest = &line[start..end];
+
+    let mut fields = Vec::with_capacity(n);
+    while fields.len() + 1 < n {
+        let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+        fields.push(text(&rest[..end]));
+        rest = &rest[end..];
+
+        // a delimiter is IFS whitespace around at most one other IFS character
+        let skip_spaces = |rest: &mut &[(char, bool)]| {
+            while rest.first().is_some_and(is_space) {
+                *rest = &rest[1..];
+            }
+        };
+        skip_spaces(&mut rest);
+        if rest.first().is_some_and(|x| is_ifs(x) && !is_space(x)) {
+            rest = &rest[1..];
+            skip_spaces(&mut rest);
+        }
+    }
+    fields.push(text(rest));
+    fields
+}
+
+/// Quotes a text with single quotes so that the shell reads it back as is
+pub(super) fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Shell;
+
+    #[test]
+    fn read_lines_split_into_fields() {
+        let split = |line: &str, ifs, n| {
+            let chars = line.chars().map(|c| (c, false)).collect::<Vec<_>>();
+            super::split_read_line(&chars, ifs, n)
+        };
+        assert_eq!(split("  a  b c  ", " \t\n", 2), ["a", "b c"]);
+        assert_eq!(split("a b", " ", 3), ["a", "b", ""]);
+        assert_eq!(split("a : b::c", ": ", 4), ["a", "b", "", "c"]);
+        assert_eq!(split("", " ", 1), [""]);
+
+        // escaped characters don't split
+        let chars = [
+            ('a', false),
+            (' ', true),
+            ('b', false),
+            (' ', false),
+            ('c', false),
+        ];
+        assert_eq!(super::split_read_line(&chars, " ", 2), ["a b", "c"]);
+    }
+
+    #[test]
+    fn panicking_builtins_fail_without_stopping_the_shell() {
+        let sh = Shell::builder().private(true).build().unwrap();
+        let cmd = "zerosh-test-panic; echo $?; zerosh-test-panic | cat; echo ${PIPESTATUS[@]}";
+        let out = sh.run_capture(cmd).unwrap();
+        assert_eq!(out.stdout, "1\n1 0\n", "{out:?}");
+        assert_eq!(
+            out.stderr
+                .matches("ZeroSh: zerosh-test-panic: internal error, the command was aborted")
+                .count(),
+            2,
+            "{out:?}"
+        );
+        assert_eq!(out.code, 0);
+    }
+}
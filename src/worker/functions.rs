@@ -0,0 +1,66 @@
+//! Shell functions
+//!
+//! `NAME() COMPOUND` or `function NAME COMPOUND` defines a function, which runs the compound
+//! command with its redirections in the shell itself, with the arguments as the positional
+//! parameters (`$1`, `$2`... `$#` and `$@`). A call pushes a variable scope for `local`, and
+//! `return` ends the function. Functions are found before builtins and external commands. In a
+//! pipeline or in background, a function runs in a forked child like a compound command.
+
+use std::{mem, sync::Arc};
+
+use crate::{
+    parser::{Command, Pipeline},
+    util,
+};
+
+use super::{vars, Worker};
+
+#[derive(Debug)]
+pub(super) struct Function {
+    /// The compound command as a pipeline of one command, run as is on each call
+    pub body: Pipeline,
+    /// Source text of the definition, printed by `declare -f`
+    pub text: String,
+}
+
+impl Worker {
+    /// Defines or replaces a function
+    pub(super) fn define_function(&mut self, name: &str, body: &Command, text: &str) {
+        let function = Function {
+            body: Pipeline {
+                commands: vec![body.clone()],
+                background: false,
+                negated: false,
+                time: None,
+                text: name.to_string(),
+            },
+            text: text.to_string(),
+        };
+        self.functions.insert(name.to_string(), Arc::new(function));
+    }
+
+    /// Calls a function with `args[0]` being its name and returns the exit code
+    pub(super) fn call_function(&mut self, function: &Function, args: &[String]) -> i32 {
+        if self.func_depth >= self.limits.func_depth {
+            util::print_error(format!(
+                "{}: maximum function nesting level exceeded",
+                args[0]
+            ));
+            return 1;
+        }
+
+        let positional = mem::replace(&mut self.positional, args[1..].to_vec());
+        self.scopes.push(vars::Scope::new());
+        self.func_depth += 1;
+
+        let code = self.run_pipeline(&function.body);
+
+        self.func_depth -= 1;
+        self.pop_scope();
+        self.positional = positional;
+        self.returning = false;
+        // `PIPESTATUS` is the exit code of the call, not of the last pipeline in it
+        self.pipe_status = None;
+        code
+    }
+}
@@ -0,0 +1,83 @@
+//! Locations of external commands, remembered so that `PATH` is searched once per command
+//!
+//! The table is cleared when `PATH` changes, as any entry may be shadowed by then. `hash` shows
+//! the entries, and `hash -p` sets ones by hand.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::util;
+
+/// Command names to their locations
+#[derive(Debug, Default)]
+pub(super) struct CommandTable {
+    /// `PATH` the entries were found in
+    path: Option<OsString>,
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug)]
+pub(super) struct Entry {
+    pub path: PathBuf,
+    /// Number of times the command was run from the entry
+    pub hits: usize,
+}
+
+impl CommandTable {
+    /// Returns the location of a command, searching `PATH` the first time. `None` for names with
+    /// a slash and commands not found. A hit is counted if `run`.
+    pub fn locate(&mut self, name: &str, run: bool) -> Option<&Path> {
+        if name.contains('/') {
+            return None;
+        }
+        self.check_path();
+
+        // the file may have been removed since
+        let found = self
+            .entries
+            .get(name)
+            .is_some_and(|entry| util::is_executable(&entry.path));
+        if !found {
+            let path = util::find_in_path(name)?;
+            self.entries
+                .insert(name.to_string(), Entry { path, hits: 0 });
+        }
+
+        let entry = self.entries.get_mut(name)?;
+        if run {
+            entry.hits += 1;
+        }
+        Some(&entry.path)
+    }
+
+    /// Sets the location of a command (`hash -p`)
+    pub fn insert(&mut self, name: &str, path: PathBuf) {
+        self.check_path();
+        self.entries
+            .insert(name.to_string(), Entry { path, hits: 0 });
+    }
+
+    /// Forgets all the locations (`hash -r`)
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the entries sorted by command name
+    pub fn entries(&mut self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.check_path();
+        self.entries.iter()
+    }
+
+    /// Clears the table if `PATH` changed since the entries were found
+    fn check_path(&mut self) {
+        let path = env::var_os("PATH");
+        if path != self.path {
+            self.entries.clear();
+            self.path = path;
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! Job table, i.e., bookkeeping of the process groups started by the shell
+//!
+//! Only the `worker` thread changes the tables. `SIGCHLD` arrives as a message from the
+//! `signal_handler` thread, and children are reaped between commands or while the worker waits for
+//! them, never in the middle of an update. So no signal mask or lock is needed. A child that exits
+//! before its job is inserted stays a zombie until then, and its status is recorded afterwards.
+//! Only the processes in the tables (and the ones removed from them before they exited) are
+//! reaped, so other threads may wait for children of their own.
+//!
+//! All the changes go through the methods here, which check in debug builds that the tables agree
+//! with each other afterwards.
+
+use nix::{
+    libc,
+    sys::{signal::Signal, wait::WaitStatus},
+    unistd::Pid,
+};
+
+use std::{collections::HashSet, ffi::CStr, mem};
+
+use super::Worker;
+
+/// Number of finished processes whose exit codes are kept for `wait PID`
+const FINISHED_MAX: usize = 256;
+
+/// Pipeline run by the shell
+#[derive(Debug)]
+pub(super) struct Job {
+    /// Process group ID
+    pub gpid: Pid,
+    /// Command line
+    pub line: String,
+    /// Processes of the pipeline in order
+    pub pids: Vec<Pid>,
+    /// Exit code of the last command in the pipeline, i.e., the exit code of the job
+    pub exit_code: i32,
+    /// Exit codes of the commands in the pipeline, filled as they exit
+    pub exit_codes: Vec<i32>,
+    /// Signal that killed the last command, and if it dumped core
+    pub signal: Option<(Signal, bool)>,
+    /// Not sent `SIGHUP` when the shell exits (`disown -h`)
+    pub nohup: bool,
+    /// Number of jobs started before it, which orders the jobs as IDs are reused
+    pub seq: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProcessState {
+    Running,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ProcessInfo {
+    pub state: ProcessState,
+    pub gpid: Pid,
+    /// Position of the process in the pipeline
+    pub index: usize,
+    /// If the process is the last command of the pipeline
+    pub is_last: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+impl JobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "Running",
+            Self::Stopped => "Stopped",
+            Self::Done => "Done",
+        }
+    }
+}
+
+impl Worker {
+    /// Registers a process group and returns the new job ID, which is the lowest one not in use
+    /// like bash
+    pub(super) fn insert_job(&mut self, gpid: Pid, pids: &[Pid], line: &str) -> usize {
+        let job_id = (1..).find(|id| !self.jobs.contains_key(id)).unwrap();
+        let seq = self.jobs_started;
+        self.jobs_started += 1;
+
+        self.jobs.insert(
+            job_id,
+            Job {
+                gpid,
+                line: line.to_string(),
+                pids: pids.to_vec(),
+                exit_code: 0,
+                exit_codes: vec![0; pids.len()],
+                signal: None,
+                nohup: false,
+                seq,
+            },
+        );
+
+        for (i, &pid) in pids.iter().enumerate() {
+            let info = ProcessInfo {
+                state: ProcessState::Running,
+                gpid,
+                index: i,
+                is_last: i + 1 == pids.len(),
+            };
+            self.pid_to_info.insert(pid, info);
+        }
+
+        let pids = pids.iter().copied().collect::<HashSet<_>>();
+        self.gpid_to_pid.insert(gpid, (job_id, pids));
+
+        self.check_tables();
+        job_id
+    }
+
+    /// Returns the exit code of a job: the last command's, or with `set -o pipefail`, the last
+    /// nonzero one
+    pub(super) fn job_exit_code(&self, job: &Job) -> i32 {
+        if !self.pipefail {
+            return job.exit_code;
+        }
+        job.exit_codes
+            .iter()
+            .rev()
+            .copied()
+            .find(|&code| code != 0)
+            .unwrap_or(0)
+    }
+
+    /// Removes a job from the table, returning it
+    pub(super) fn remove_job(&mut self, job_id: usize) -> Option<Job> {
+        let job = self.jobs.remove(&job_id)?;
+
+        if let Some((_, pids)) = self.gpid_to_pid.remove(&job.gpid) {
+            for pid in pids {
+                self.pid_to_info.remove(&pid);
+                self.orphans.insert(pid);
+            }
+        }
+
+        self.check_tables();
+        Some(job)
+    }
+
+    /// Removes a job that is reported as done, keeping the exit codes of its processes for
+    /// `wait PID`. The oldest ones are dropped after [`FINISHED_MAX`].
+    pub(super) fn remove_done_job(&mut self, job_id: usize) -> Option<Job> {
+        let job = self.remove_job(job_id)?;
+        for (&pid, &code) in job.pids.iter().zip(&job.exit_codes) {
+            if self.finished.len() == FINISHED_MAX {
+                self.finished.pop_front();
+            }
+            self.finished.push_back((pid, code));
+        }
+        Some(job)
+    }
+
+    /// Takes the exit code of a finished process of [`Worker::remove_done_job`]
+    pub(super) fn take_finished(&mut self, pid: Pid) -> Option<i32> {
+        let i = self.finished.iter().rposition(|&(p, _)| p == pid)?;
+        self.finished.remove(i).map(|(_, code)| code)
+    }
+
+    pub(super) fn job_state(&self, job_id: usize) -> Option<JobState> {
+        let job = self.jobs.get(&job_id)?;
+        let (_, pids) = self.gpid_to_pid.get(&job.gpid)?;
+
+        if pids.is_empty() {
+            return Some(JobState::Done);
+        }
+
+        let running = pids
+            .iter()
+            .any(|pid| self.pid_to_info[pid].state == ProcessState::Running);
+
+        Some(if running {
+            JobState::Running
+        } else {
+            JobState::Stopped
+        })
+    }
+
+    /// Returns the processes of a job that haven't exited, in the order of the pipeline
+    pub(super) fn job_pids(&self, job_id: usize) -> Vec<Pid> {
+        let Some(job) = self.jobs.get(&job_id) else {
+            return Vec::new();
+        };
+        job.pids
+            .iter()
+            .copied()
+            .filter(|pid| self.pid_to_info.contains_key(pid))
+            .collect()
+    }
+
+    /// Marks all the processes in a job as running, e.g., after sending `SIGCONT`
+    pub(super) fn set_job_running(&mut self, job_id: usize) {
+        let Some(job) = self.jobs.get(&job_id) else {
+            return;
+        };
+
+        if let Some((_, pids)) = self.gpid_to_pid.get(&job.gpid) {
+            for pid in pids {
+                if let Some(info) = self.pid_to_info.get_mut(pid) {
+                    info.state = ProcessState::Running;
+                }
+            }
+        }
+    }
+
+    /// Takes the processes of a job as exited, keeping the exit codes recorded so far. For
+    /// processes that were reaped without updating the table.
+    pub(super) fn forget_processes(&mut self, job_id: usize) {
+        let Some(job) = self.jobs.get(&job_id) else {
+            return;
+        };
+
+        if let Some((_, pids)) = self.gpid_to_pid.get_mut(&job.gpid) {
+            for pid in mem::take(pids) {
+                self.pid_to_info.remove(&pid);
+            }
+        }
+        self.check_tables();
+    }
+
+    /// Updates the job table with a status returned by `waitpid`. Returns the ID of the job that
+    /// the process belongs to.
+    pub(super) fn update_process(&mut self, status: WaitStatus) -> Option<usize> {
+        let (pid, exit_code, signal) = match status {
+            WaitStatus::Exited(pid, code) => (pid, code, None),
+            WaitStatus::Signaled(pid, signal, core_dumped) => {
+                (pid, 128 + signal as i32, Some((signal, core_dumped)))
+            }
+            WaitStatus::Stopped(pid, _) => {
+                return self.set_process_state(pid, ProcessState::Stopped);
+            }
+            WaitStatus::Continued(pid) => {
+                return self.set_process_state(pid, ProcessState::Running);
+            }
+            _ => return None,
+        };
+
+        let info = self.pid_to_info.remove(&pid)?;
+        let (job_id, pids) = self.gpid_to_pid.get_mut(&info.gpid)?;
+        pids.remove(&pid);
+        let job_id = *job_id;
+
+        if let Some(job) = self.jobs.get_mut(&job_id) {
+            job.exit_codes[info.index] = exit_code;
+            if info.is_last {
+                job.exit_code = exit_code;
+                job.signal = signal;
+            }
+        }
+
+        self.check_tables();
+        Some(job_id)
+    }
+
+    /// Asserts in debug builds that each job has its process group, and that the processes left
+    /// in the group are the ones with info, at their places in the pipeline
+    fn check_tables(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        assert_eq!(self.jobs.len(), self.gpid_to_pid.len(), "{:?}", self.jobs);
+        let mut n_pids = 0;
+        for (&job_id, job) in &self.jobs {
+            let (id, pids) = &self.gpid_to_pid[&job.gpid];
+            assert_eq!(*id, job_id, "{job:?}");
+            for pid in pids {
+                let info = &self.pid_to_info[pid];
+                assert_eq!(info.gpid, job.gpid, "{job:?}");
+                assert_eq!(job.pids[info.index], *pid, "{job:?}");
+            }
+            n_pids += pids.len();
+        }
+        assert_eq!(self.pid_to_info.len(), n_pids, "{:?}", self.pid_to_info);
+    }
+
+    fn set_process_state(&mut self, pid: Pid, state: ProcessState) -> Option<usize> {
+        let info = self.pid_to_info.get_mut(&pid)?;
+        info.state = state;
+        self.gpid_to_pid.get(&info.gpid).map(|(job_id, _)| *job_id)
+    }
+
+    /// Finds a job by a job spec (`%N`, `N`, `%%` or `%+`) or returns the most recent job if `None`
+    pub(super) fn find_job(&self, spec: Option<&str>) -> Option<usize> {
+        let spec = match spec {
+            None | Some("%%" | "%+") => {
+                let recent = self.jobs.iter().max_by_key(|(_, job)| job.seq);
+                return recent.map(|(&job_id, _)| job_id);
+            }
+            Some(spec) => spec,
+        };
+
+        let job_id = spec.strip_prefix('%').unwrap_or(spec).parse().ok()?;
+        self.jobs.contains_key(&job_id).then_some(job_id)
+    }
+
+    /// Formats a job for `jobs` and notifications. A job killed by a signal is shown with the
+    /// signal instead of `Done`.
+    pub(super) fn format_job(&self, job_id: usize, state: JobState) -> String {
+        let job = self.jobs.get(&job_id);
+        let line = job.map_or("", |job| job.line.as_str());
+        let status = match job.and_then(|job| job.signal) {
+            Some((signal, core_dumped)) if state == JobState::Done => {
+                self::signal_message(signal, core_dumped)
+            }
+            _ => state.as_str().to_string(),
+        };
+        format!("[{job_id}]  {status:<24}{line}")
+    }
+}
+
+/// Describes how a process was killed like bash, e.g., `Quit (core dumped)`
+pub(super) fn signal_message(signal: Signal, core_dumped: bool) -> String {
+    // `strsignal` may use a static buffer, but only the `worker` thread calls it
+    let desc = unsafe { CStr::from_ptr(libc::strsignal(signal as i32)) };
+    let desc = desc.to_string_lossy();
+    if core_dumped {
+        format!("{desc} (core dumped)")
+    } else {
+        desc.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_messages() {
+        assert_eq!(signal_message(Signal::SIGQUIT, true), "Quit (core dumped)");
+        assert_eq!(signal_message(Signal::SIGTERM, false), "Terminated");
+        assert_eq!(signal_message(Signal::SIGKILL, false), "Killed");
+    }
+
+    #[test]
+    fn signals_of_jobs_are_decoded() {
+        let mut worker = Worker::new(false, None);
+        let (first, last) = (Pid::from_raw(1000), Pid::from_raw(1001));
+        let job_id = worker.insert_job(first, &[first, last], "a | b");
+
+        // only the last command's signal is the job's
+        let status = WaitStatus::Signaled(first, Signal::SIGPIPE, false);
+        assert_eq!(worker.update_process(status), Some(job_id));
+        assert_eq!(worker.jobs[&job_id].signal, None);
+        let status = WaitStatus::Signaled(last, Signal::SIGQUIT, true);
+        assert_eq!(worker.update_process(status), Some(job_id));
+
+        let job = &worker.jobs[&job_id];
+        assert_eq!(job.exit_codes, [141, 131]);
+        assert_eq!(job.exit_code, 131);
+        assert_eq!(job.signal, Some((Signal::SIGQUIT, true)));
+        assert_eq!(
+            worker.format_job(job_id, JobState::Done),
+            "[1]  Quit (core dumped)      a | b"
+        );
+    }
+
+    #[test]
+    fn lowest_free_job_ids_are_reused() {
+        let mut worker = Worker::new(false, None);
+        let pids = [1000, 1001, 1002].map(Pid::from_raw);
+        for pid in pids {
+            worker.insert_job(pid, &[pid], "a");
+        }
+        assert_eq!(worker.jobs.keys().copied().collect::<Vec<_>>(), [1, 2, 3]);
+
+        worker.remove_job(1);
+        worker.remove_job(2);
+        let pid = Pid::from_raw(1003);
+        assert_eq!(worker.insert_job(pid, &[pid], "b"), 1);
+        // the current job is the most recent one, not the one with the highest ID
+        assert_eq!(worker.find_job(None), Some(1));
+        let pid = Pid::from_raw(1004);
+        assert_eq!(worker.insert_job(pid, &[pid], "c"), 2);
+    }
+
+    #[test]
+    fn tables_agree_after_interleaved_changes() {
+        // the statuses of many pipelines arrive out of order, some of them stops, while jobs are
+        // started and removed in between. Each change checks the tables.
+        let mut worker = Worker::new(false, None);
+        let mut next = 1000;
+        let mut pipelines = Vec::new();
+        for round in 0..50 {
+            let pids = (0..3).map(|i| Pid::from_raw(next + i)).collect::<Vec<_>>();
+            next += 3;
+            let job_id = worker.insert_job(pids[0], &pids, "a | b | c");
+            pipelines.push((job_id, pids));
+
+            for (job_id, pids) in &pipelines {
+                let pid = pids[round % 3];
+                worker.update_process(WaitStatus::Stopped(pid, Signal::SIGTSTP));
+                worker.update_process(WaitStatus::Exited(pid, 3));
+                worker.update_process(WaitStatus::Continued(pid));
+                if round % 7 == 0 {
+                    worker.forget_processes(*job_id);
+                }
+            }
+            pipelines.retain(|(job_id, _)| {
+                let done = worker.job_state(*job_id) == Some(JobState::Done);
+                if done {
+                    worker.remove_done_job(*job_id);
+                }
+                !done
+            });
+        }
+        assert_eq!(worker.jobs.len(), pipelines.len());
+    }
+
+    /// Forks a child in its own process group that exits with the code, and reaps it without
+    /// telling the job table
+    fn reaped_child(code: i32) -> (Pid, WaitStatus) {
+        use nix::unistd::{self, ForkResult};
+
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => unsafe {
+                libc::setpgid(0, 0);
+                libc::_exit(code)
+            },
+            ForkResult::Parent { child } => {
+                let _ = unistd::setpgid(child, child);
+                let status = nix::sys::wait::waitpid(child, None).unwrap();
+                (child, status)
+            }
+        }
+    }
+
+    #[test]
+    fn foreground_jobs_reaped_elsewhere() {
+        let mut worker = Worker::new(false, None);
+
+        // the status was recorded, e.g., on `SIGCHLD`
+        let (pid, status) = reaped_child(3);
+        let job_id = worker.insert_job(pid, &[pid], "a");
+        worker.update_process(status);
+        assert_eq!(worker.wait_fg(job_id), 3);
+        assert!(worker.jobs.is_empty());
+
+        // `ECHILD` without the status of the last one, keeping the recorded ones
+        let (first, status) = reaped_child(4);
+        let (last, _) = reaped_child(5);
+        let job_id = worker.insert_job(first, &[first, last], "a | b");
+        worker.update_process(status);
+        worker.pipefail = true;
+        assert_eq!(worker.wait_fg(job_id), 4);
+        assert_eq!(worker.pipe_status, Some(vec![4, 0]));
+        assert!(worker.jobs.is_empty() && worker.pid_to_info.is_empty());
+    }
+}
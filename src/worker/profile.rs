@@ -0,0 +1,184 @@
+//! Startup and logout files
+//!
+//! `/etc/profile` is a script for `sh`, which ZeroSh can't run (`if` and `for`), so it's run by
+//! `/bin/sh` and the environment it sets up is imported. `~/.zerosh_profile` and
+//! `~/.zerosh_logout` of a login shell and `~/.zeroshrc` of an interactive shell are run by the
+//! shell itself like files of `source`, one command line per line. Lines starting with `#` are
+//! comments, and a quote left open continues on the next line. Missing startup files are skipped.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::util;
+
+use super::Worker;
+
+/// System-wide profile for `sh`
+const SYSTEM_PROFILE: &str = "/etc/profile";
+
+/// Profile of ZeroSh in the home directory
+const USER_PROFILE: &str = ".zerosh_profile";
+
+/// Run by a login shell when it exits, in the home directory
+const USER_LOGOUT: &str = ".zerosh_logout";
+
+/// Run by an interactive shell at startup, in the home directory
+const USER_RC: &str = ".zeroshrc";
+
+impl Worker {
+    /// Imports the environment of `/etc/profile` and runs `~/.zerosh_profile`
+    pub(super) fn run_profiles(&mut self) {
+        if Path::new(SYSTEM_PROFILE).exists() {
+            self::import_profile_env(SYSTEM_PROFILE);
+        }
+        if let Some(path) = self::home_file(USER_PROFILE) {
+            self.run_startup_file(&path);
+        }
+    }
+
+    /// Runs an rc file given with `--rcfile`, or `~/.zeroshrc`
+    pub(super) fn run_rc_file(&mut self, path: Option<&Path>) {
+        match path {
+            Some(path) => {
+                if let Err(err) = self.run_file(path) {
+                    util::print_warning(format!("{}: {err}", path.display()));
+                }
+            }
+            None => {
+                if let Some(path) = self::home_file(USER_RC) {
+                    self.run_startup_file(&path);
+                }
+            }
+        }
+    }
+
+    /// Runs `~/.zerosh_logout`, keeping the exit code of the shell
+    pub(super) fn run_logout(&mut self) {
+        let exit_code = self.exit_code;
+        if let Some(path) = self::home_file(USER_LOGOUT) {
+            self.run_startup_file(&path);
+        }
+        self.exit_code = exit_code;
+    }
+
+    /// Runs a startup or logout file if it exists
+    fn run_startup_file(&mut self, path: &Path) {
+        match self.run_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                util::print_warning(format!("{}: {err}", path.display()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs the command lines of a file until one exits the shell, or until one fails to parse
+    /// with `set -e`. Messages of the shell show the file name and the line number, which is also
+    /// `LINENO`.
+    pub(super) fn run_file(&mut self, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let name = util::display_path(path, home.as_deref());
+        let saved_location = util::set_message_location(None);
+        let saved_lineno = self.specials.lineno;
+
+        // lines of a command line with an unterminated quote so far, and where it starts
+        let mut pending = String::new();
+        let mut start = 0;
+
+        for (i, line) in text.lines().enumerate() {
+            if pending.is_empty() {
+                let line = line.trim_start();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                pending.push_str(line);
+                start = i + 1;
+            } else {
+                pending.push('\n');
+                pending.push_str(line);
+            }
+
+            util::set_message_location(Some(format!("{name}:{start}")));
+            self.specials.lineno = start;
+            match self.parse(&pending) {
+                Ok(Some(list)) => {
+                    self.execute(&list);
+                }
+                Ok(None) => {}
+                Err(err) if err.is_unterminated() => continue,
+                Err(err) => {
+                    util::print_error(err);
+                    self.exit_code = 2;
+                    self.quit |= self.errexit;
+                }
+            }
+            pending.clear();
+
+            if self.quit || self.returning {
+                break;
+            }
+        }
+
+        if !self.quit && !self.returning {
+            if let Err(err) = self.parse(&pending) {
+                util::print_error(err);
+                self.exit_code = 2;
+            }
+        }
+
+        util::set_message_location(saved_location);
+        self.specials.lineno = saved_lineno;
+        Ok(())
+    }
+}
+
+/// Returns the path of a file in the home directory. `None` if `HOME` is not set.
+fn home_file(name: &str) -> Option<PathBuf> {
+    let home = env::var_os("HOME").filter(|home| !home.is_empty())?;
+    Some(Path::new(&home).join(name))
+}
+
+/// Runs a profile with `sh` and sets the variables it sets or changes in the environment. Its
+/// output goes to the standard error.
+fn import_profile_env(profile: &str) {
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(r#". "$1" >&2; exec env -0"#)
+        .arg("sh")
+        .arg(profile)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            util::print_warning(format!("{profile}: sh exited with {}", output.status));
+            return;
+        }
+        Err(err) => {
+            util::print_warning(format!("{profile}: unable to run sh: {err}"));
+            return;
+        }
+    };
+
+    for entry in output.stdout.split(|&b| b == 0) {
+        let Ok(entry) = std::str::from_utf8(entry) else {
+            continue;
+        };
+        let Some((name, value)) = entry.split_once('=') else {
+            continue;
+        };
+        // `sh` sets a few variables of its own
+        if matches!(name, "PWD" | "OLDPWD" | "SHLVL" | "_") {
+            continue;
+        }
+        if env::var(name).ok().as_deref() != Some(value) {
+            env::set_var(name, value);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Redirections
+//!
+//! Files are opened by the shell before forking, so that errors are reported without allocating in
+//! the child. They are opened close-on-exec and connected to the file descriptors with `dup2` in
+//! the child, or temporarily in the shell itself for builtins. Duplications (`2>&1`) and closes
+//! (`3>&-`) are applied in the same order as the files. With `set -o mkdir_on_redirect`, output
+//! redirections create the missing parent directories of the file.
+
+use nix::{
+    fcntl::{self, FcntlArg, FdFlag, OFlag},
+    libc,
+    sys::stat::Mode,
+    unistd,
+};
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::Path,
+};
+
+use crate::{
+    expand,
+    parser::{Redirect, RedirectKind},
+    util,
+};
+
+use super::Worker;
+
+/// Redirection ready to be applied
+#[derive(Debug)]
+pub(super) struct Redirection {
+    /// File descriptor the target is connected to
+    fd: RawFd,
+    target: Target,
+}
+
+#[derive(Debug)]
+enum Target {
+    /// File opened for the redirection. It's closed when dropped.
+    File(File),
+    /// Copy of another file descriptor (`>&N`)
+    Dup(RawFd),
+    /// Closes the file descriptor (`>&-`)
+    Close,
+}
+
+/// Largest file descriptor available to users, like `sh`
+const USER_FD_MAX: RawFd = 9;
+
+/// Lowest file descriptor for the files of redirections, so that they are not replaced by
+/// redirections to user file descriptors, e.g., a file opened on 3 by `3>&1 >FILE`
+const FILE_FD_MIN: RawFd = USER_FD_MAX + 1;
+
+/// Opens `/dev/null` on the free file descriptors up to [`USER_FD_MAX`], so that the ones opened
+/// by libraries (rustyline and signal-hook) don't take them. Called before creating them, as
+/// `exec 3>FILE` would replace them otherwise.
+pub(super) fn reserve_fds() {
+    let flags = OFlag::O_RDONLY | OFlag::O_CLOEXEC;
+    while let Ok(fd) = fcntl::open("/dev/null", flags, Mode::empty()) {
+        if fd > USER_FD_MAX {
+            let _ = unistd::close(fd);
+            break;
+        }
+    }
+}
+
+impl Worker {
+    /// Expands the file names of redirections and opens the files
+    pub(super) fn open_redirects(
+        &mut self,
+        redirects: &[Redirect],
+    ) -> Result<Vec<Redirection>, String> {
+        let mut redirections = Vec::with_capacity(redirects.len());
+
+        for redirect in redirects {
+            let path = match &expand::expand_word(&redirect.target, self)?[..] {
+                [path] => path.clone(),
+                _ => return Err(format!("{}: ambiguous redirect", redirect.target)),
+            };
+
+            let target = match redirect.kind {
+                RedirectKind::DupOutput | RedirectKind::DupInput => {
+                    self.dup_target(&path, &redirections)?
+                }
+                kind => self
+                    .open(kind, &path)
+                    .and_then(self::move_high)
+                    .map(Target::File)
+                    .map_err(|err| format!("{path}: {err}"))?,
+            };
+            redirections.push(Redirection {
+                fd: redirect.fd,
+                target,
+            });
+        }
+
+        Ok(redirections)
+    }
+
+    /// Parses the target of `>&` or `<&`: a file descriptor open for users or by a previous
+    /// redirection (and not closed by one), or `-`
+    fn dup_target(&self, word: &str, previous: &[Redirection]) -> Result<Target, String> {
+        if word == "-" {
+            return Ok(Target::Close);
+        }
+
+        let fd = word
+            .parse::<RawFd>()
+            .ok()
+            .filter(|_| word.bytes().all(|b| b.is_ascii_digit()))
+            .ok_or_else(|| format!("{word}: invalid file descriptor"))?;
+        let is_open = match previous
+            .iter()
+            .rev()
+            .find(|redirection| redirection.fd == fd)
+        {
+            Some(redirection) => !matches!(redirection.target, Target::Close),
+            None => {
+                self.user_fds.contains(&fd)
+                    || (fd <= 2 && fcntl::fcntl(fd, FcntlArg::F_GETFD).is_ok())
+            }
+        };
+        if !is_open {
+            return Err(format!("{word}: bad file descriptor"));
+        }
+
+        Ok(Target::Dup(fd))
+    }
+
+    /// Fails if redirections applied in the shell itself would replace a file descriptor used by
+    /// the shell
+    pub(super) fn check_shell_fds(&self, redirections: &[Redirection]) -> Result<(), String> {
+        for redirection in redirections {
+            let fd = redirection.fd;
+            if fd > USER_FD_MAX && fcntl::fcntl(fd, FcntlArg::F_GETFD).is_ok() {
+                return Err(format!("{fd}: file descriptor used by the shell"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies redirections to the shell permanently, for `exec` without a command
+    pub(super) fn keep_redirections(&mut self, redirections: &[Redirection]) {
+        self::connect(redirections);
+        for redirection in redirections.iter().filter(|r| r.fd > 2) {
+            match redirection.target {
+                Target::Close => self.user_fds.remove(&redirection.fd),
+                _ => self.user_fds.insert(redirection.fd),
+            };
+        }
+        // take back the closed ones
+        self::reserve_fds();
+    }
+
+    fn open(&self, kind: RedirectKind, path: &str) -> io::Result<File> {
+        if self.mkdir_on_redirect && kind != RedirectKind::Input {
+            if let Some(parent) = Path::new(path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        match kind {
+            RedirectKind::Input => options.read(true),
+            RedirectKind::Output if self.noclobber => return self::open_new(path),
+            RedirectKind::Output | RedirectKind::Clobber => {
+                options.write(true).create(true).truncate(true)
+            }
+            RedirectKind::Append => options.append(true).create(true),
+            RedirectKind::DupOutput | RedirectKind::DupInput => unreachable!(),
+        };
+        options.open(path)
+    }
+}
+
+/// Creates a file for `>` with `noclobber`. Existing files other than regular files, e.g.,
+/// `/dev/null`, can still be written.
+fn open_new(path: &str) -> io::Result<File> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            if fs::metadata(path).is_ok_and(|meta| meta.is_file()) {
+                Err(io::Error::other("cannot overwrite existing file"))
+            } else {
+                OpenOptions::new().write(true).open(path)
+            }
+        }
+        res => res,
+    }
+}
+
+/// Moves a file to [`FILE_FD_MIN`] or above, keeping it close-on-exec
+fn move_high(file: File) -> io::Result<File> {
+    let fd = fcntl::fcntl(file.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(FILE_FD_MIN))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+impl Redirection {
+    fn apply(&self) {
+        let source = match &self.target {
+            Target::File(file) => file.as_raw_fd(),
+            Target::Dup(fd) => *fd,
+            Target::Close => {
+                let _ = unistd::close(self.fd);
+                return;
+            }
+        };
+
+        if source == self.fd {
+            // `dup2` does nothing, so clear the close-on-exec flag by hand
+            let _ = fcntl::fcntl(source, FcntlArg::F_SETFD(FdFlag::empty()));
+        } else {
+            let _ = util::run_syscall(|| unistd::dup2(source, self.fd));
+        }
+    }
+}
+
+/// Connects the targets to their file descriptors in a forked child
+pub(super) fn connect(redirections: &[Redirection]) {
+    for redirection in redirections {
+        redirection.apply();
+    }
+}
+
+/// Drops the output left in the buffer of the standard output after a write error, which would go
+/// to the original file once it's restored. The redirected standard output is pointed to
+/// `/dev/null` until then.
+fn discard_stdout() {
+    let Ok(null) = fcntl::open(
+        "/dev/null",
+        OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    ) else {
+        return;
+    };
+    let _ = util::run_syscall(|| unistd::dup2(null, libc::STDOUT_FILENO));
+    let _ = unistd::close(null);
+    let _ = io::stdout().flush();
+}
+
+/// Runs a function in the shell process with the redirections, restoring the file descriptors
+/// afterwards
+pub(super) fn with_redirections<T>(redirections: &[Redirection], f: impl FnOnce() -> T) -> T {
+    // output buffered so far goes to the original file
+    let _ = io::stdout().flush();
+
+    // file descriptor to its copy, or `None` if it was not open
+    let mut saved = Vec::with_capacity(redirections.len());
+    for redirection in redirections {
+        let copy = fcntl::fcntl(redirection.fd, FcntlArg::F_DUPFD_CLOEXEC(FILE_FD_MIN)).ok();
+        saved.push((redirection.fd, copy));
+        redirection.apply();
+    }
+
+    let res = f();
+    let redirected = saved.iter().any(|&(fd, _)| fd == libc::STDOUT_FILENO);
+    if io::stdout().flush().is_err() && redirected {
+        self::discard_stdout();
+    }
+
+    for (fd, copy) in saved.into_iter().rev() {
+        match copy {
+            Some(copy) => {
+                let _ = util::run_syscall(|| unistd::dup2(copy, fd));
+                let _ = unistd::close(copy);
+            }
+            None => {
+                let _ = unistd::close(fd);
+            }
+        }
+    }
+
+    res
+}
@@ -0,0 +1,429 @@
+//! Shell variables
+//!
+//! Variables live in a stack of scopes. The bottom one is the global scope, and function calls
+//! push local scopes on top of it. Exported variables are also set in the environment of the
+//! shell process, so that child processes inherit them. Variables from the environment are
+//! exported variables, too.
+//!
+//! A few special variables are computed on each expansion instead: `?` (the last exit code),
+//! `RANDOM` (0 to 32767), `SECONDS` (since the shell started) and `LINENO` (the number of the
+//! command line). `$` and `PPID` are the process IDs of the shell and its parent, which subshells
+//! keep like bash, while `ZEROSH_PID` is the ID of the current process, e.g., a subshell. `!` is
+//! the process ID of the last command of the last background job, unset before one starts.
+//! `ZEROSH_CMD_COUNT` is the number of command lines read so far, including the current one.
+//! `ZEROSH_VERSION` is the version of the shell.
+//!
+//! The positional parameters `1`, `2`... are the arguments of the current function call, with `#`
+//! being their number and `@` and `*` all of them. `0` is the name of the shell.
+//!
+//! `SHLVL` is incremented in the environment once the shell starts, so that it's the nesting level
+//! of shells.
+//!
+//! `COLUMNS` and `LINES` are plain variables set to the size of the terminal (80x24 without one)
+//! at startup, on `SIGWINCH` and before each command line, as the line editor takes the signal
+//! while reading one.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    ffi::OsString,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{arith, util, Shell};
+
+use super::Worker;
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Var {
+    pub value: Value,
+    /// Set in the environment
+    pub exported: bool,
+    /// Assigned values are evaluated as arithmetic expressions (`declare -i`)
+    pub integer: bool,
+}
+
+/// Value of a [`Var`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Value {
+    Scalar(String),
+    /// Indexed array, e.g., from `mapfile`
+    Array(Vec<String>),
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Self::Scalar(String::new())
+    }
+}
+
+impl Value {
+    /// Returns the value as a string. It's the first element for an array, like bash.
+    pub fn scalar(&self) -> &str {
+        match self {
+            Self::Scalar(value) => value,
+            Self::Array(elements) => elements.first().map_or("", String::as_str),
+        }
+    }
+
+    /// Returns the elements. A scalar is an array of one element.
+    pub fn elements(&self) -> Vec<String> {
+        match self {
+            Self::Scalar(value) => vec![value.clone()],
+            Self::Array(elements) => elements.clone(),
+        }
+    }
+}
+
+/// Variable name to the variable
+pub(super) type Scope = HashMap<String, Var>;
+
+/// Variable before a temporary assignment, restored by [`Worker::restore_vars`]
+#[derive(Debug)]
+pub(super) struct SavedVar {
+    name: String,
+    /// In the innermost scope
+    var: Option<Var>,
+    env: Option<OsString>,
+}
+
+/// State of `RANDOM`, `SECONDS`, `LINENO`, `ZEROSH_CMD_COUNT`, `$` and `PPID`
+#[derive(Debug)]
+pub(super) struct Specials {
+    /// xorshift state, never zero. A `Cell` so that expanding `RANDOM` needs no `&mut`.
+    random: std::cell::Cell<u32>,
+    /// `SECONDS` is the value assigned last plus the time since then
+    seconds_base: (Instant, i64),
+    pub lineno: usize,
+    /// Number of command lines from the `main` thread (or `-c`), unlike `LINENO` of a file
+    pub cmd_count: usize,
+    /// Process IDs of the shell and its parent when it started, kept by subshells
+    pids: (u32, u32),
+}
+
+impl Specials {
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        Self {
+            random: std::cell::Cell::new(Self::seed(nanos ^ std::process::id())),
+            seconds_base: (Instant::now(), 0),
+            lineno: 0,
+            cmd_count: 0,
+            pids: (std::process::id(), std::os::unix::process::parent_id()),
+        }
+    }
+
+    fn seed(seed: u32) -> u32 {
+        seed.max(1)
+    }
+
+    /// Mixes the process ID into the random state, e.g., in a forked subshell
+    pub fn reseed(&self) {
+        let seed = self.random.get() ^ std::process::id().rotate_left(16);
+        self.random.set(Self::seed(seed));
+    }
+
+    fn random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        (x >> 16) & 0x7fff
+    }
+
+    fn seconds(&self) -> i64 {
+        let (start, base) = self.seconds_base;
+        base + start.elapsed().as_secs() as i64
+    }
+}
+
+impl Worker {
+    /// Returns the value of a variable, looking up the special variables, the innermost scope
+    /// and then the environment
+    pub(super) fn var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => return Some(self.exit_code.to_string()),
+            "RANDOM" => return Some(self.specials.random().to_string()),
+            "SECONDS" => return Some(self.specials.seconds().to_string()),
+            "LINENO" => return Some(self.specials.lineno.to_string()),
+            "$" => return Some(self.specials.pids.0.to_string()),
+            "!" => return self.last_bg_pid.map(|pid| pid.to_string()),
+            "-" => return Some(self.option_flags()),
+            "PPID" => return Some(self.specials.pids.1.to_string()),
+            "ZEROSH_PID" => return Some(std::process::id().to_string()),
+            "ZEROSH_CMD_COUNT" => return Some(self.specials.cmd_count.to_string()),
+            "ZEROSH_VERSION" => return Some(Shell::version().to_string()),
+            "#" => return Some(self.positional.len().to_string()),
+            "@" | "*" => return Some(self.positional.join(" ")),
+            "0" => return Some("zerosh".to_string()),
+            _ if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) => {
+                let n = name.parse::<usize>().ok()?;
+                return self.positional.get(n.checked_sub(1)?).cloned();
+            }
+            _ => {}
+        }
+
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(var) => Some(var.value.scalar().to_string()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// Sets `COLUMNS` and `LINES` to the size of the terminal, or 80x24 if it's unknown
+    pub(super) fn update_window_size(&mut self) {
+        self.set_window_size(util::terminal_size());
+    }
+
+    /// Sets `COLUMNS` and `LINES` to a queried terminal size, or 80x24 if it's `None`
+    fn set_window_size(&mut self, size: Option<(u16, u16)>) {
+        let (columns, lines) = size.unwrap_or((80, 24));
+        for (name, value) in [("COLUMNS", columns), ("LINES", lines)] {
+            let value = value.to_string();
+            if self.var(name).as_ref() != Some(&value) {
+                let _ = self.set_var_in(0, name, value);
+            }
+        }
+    }
+
+    /// Returns the names of the shell variables in all scopes, sorted, without the special ones
+    pub(super) fn var_names(&self) -> Vec<String> {
+        let names = self.scopes.iter().flat_map(|scope| scope.keys());
+        names
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the elements of an array variable. Other variables are arrays of one element.
+    /// `@` and `*` are the positional parameters.
+    pub(super) fn array(&self, name: &str) -> Option<Vec<String>> {
+        if name == "@" || name == "*" {
+            return Some(self.positional.clone());
+        }
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(var) => Some(var.value.elements()),
+            None => self.var(name).map(|value| vec![value]),
+        }
+    }
+
+    /// Makes the variable an array in the innermost scope that has it (or the global scope)
+    pub(super) fn set_array(&mut self, name: &str, elements: Vec<String>) {
+        let depth = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+            .unwrap_or(0);
+
+        let var = self.scopes[depth].entry(name.to_string()).or_default();
+        var.value = Value::Array(elements);
+        // the environment has only strings
+        if var.exported {
+            env::set_var(name, var.value.scalar());
+        }
+    }
+
+    /// Assigns a value to the variable in the innermost scope that has it (or the global scope).
+    /// Assigning to `RANDOM` seeds it, and assigning to `SECONDS` resets the count.
+    pub(super) fn set_var(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "RANDOM" | "SECONDS" => {
+                let value = arith::eval(&value, &|name| self.var(name))?;
+                if name == "RANDOM" {
+                    self.specials.random.set(Specials::seed(value as u32));
+                } else {
+                    self.specials.seconds_base = (Instant::now(), value);
+                }
+                return Ok(());
+            }
+            "LINENO" => return Ok(()),
+            _ => {}
+        }
+
+        let depth = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+            .unwrap_or(0);
+        self.set_var_in(depth, name, value)
+    }
+
+    /// Sets exported variables in the innermost scope for a command, e.g., `NAME=VALUE CMD`.
+    /// Returns the previous states, or restores them on an error.
+    pub(super) fn set_temp_vars(
+        &mut self,
+        vars: &[(String, String)],
+    ) -> Result<Vec<SavedVar>, String> {
+        let depth = self.scopes.len() - 1;
+        let mut saved = Vec::with_capacity(vars.len());
+
+        for (name, value) in vars {
+            saved.push(SavedVar {
+                name: name.clone(),
+                var: self.scopes[depth].get(name).cloned(),
+                env: env::var_os(name),
+            });
+
+            // keeps the integer attribute of the visible variable
+            let integer = self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+                .is_some_and(|var| var.integer);
+            let value = if integer {
+                match arith::eval(value, &|name| self.var(name)) {
+                    Ok(value) => value.to_string(),
+                    Err(err) => {
+                        self.restore_vars(saved);
+                        return Err(format!("{name}: {err}"));
+                    }
+                }
+            } else {
+                value.clone()
+            };
+
+            env::set_var(name, &value);
+            let var = Var {
+                value: Value::Scalar(value),
+                exported: true,
+                integer,
+            };
+            self.scopes[depth].insert(name.clone(), var);
+        }
+
+        Ok(saved)
+    }
+
+    /// Pops the scope of a function call. The environment gets back the values of the variables
+    /// that were exported in the scope.
+    pub(super) fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+
+        let exported = scope
+            .into_iter()
+            .filter_map(|(name, var)| var.exported.then_some(name));
+        for name in exported {
+            match self.scopes.iter().rev().find_map(|scope| scope.get(&name)) {
+                Some(outer) if outer.exported => env::set_var(&name, outer.value.scalar()),
+                _ => env::remove_var(&name),
+            }
+        }
+    }
+
+    /// Removes the variable from the innermost scope that has it (or the environment), so that an
+    /// outer one is visible again. Returns false if it's not set.
+    pub(super) fn unset_var(&mut self, name: &str) -> bool {
+        let Some(depth) = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+        else {
+            let set = env::var_os(name).is_some();
+            env::remove_var(name);
+            return set;
+        };
+
+        let var = self.scopes[depth].remove(name);
+        if var.is_some_and(|var| var.exported) {
+            match self.scopes[..depth]
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+            {
+                Some(outer) if outer.exported => env::set_var(name, outer.value.scalar()),
+                _ => env::remove_var(name),
+            }
+        }
+        true
+    }
+
+    /// Restores the variables and the environment from [`Worker::set_temp_vars`]
+    pub(super) fn restore_vars(&mut self, saved: Vec<SavedVar>) {
+        let depth = self.scopes.len() - 1;
+
+        for saved in saved.into_iter().rev() {
+            match saved.var {
+                Some(var) => self.scopes[depth].insert(saved.name.clone(), var),
+                None => self.scopes[depth].remove(&saved.name),
+            };
+            match saved.env {
+                Some(value) => env::set_var(&saved.name, value),
+                None => env::remove_var(&saved.name),
+            }
+        }
+    }
+
+    /// Assigns a value to the variable in a scope, creating it if missing
+    pub(super) fn set_var_in(
+        &mut self,
+        depth: usize,
+        name: &str,
+        value: String,
+    ) -> Result<(), String> {
+        let integer = self.scopes[depth].get(name).is_some_and(|var| var.integer);
+        let value = if integer {
+            arith::eval(&value, &|name| self.var(name))?.to_string()
+        } else {
+            value
+        };
+
+        let allexport = self.allexport;
+        let var = self.scopes[depth]
+            .entry(name.to_string())
+            .or_insert_with(|| Var {
+                // global assignments to variables from the environment keep them exported
+                exported: depth == 0 && env::var_os(name).is_some(),
+                ..Var::default()
+            });
+        var.exported |= allexport;
+
+        if var.exported {
+            env::set_var(name, &value);
+        }
+        match &mut var.value {
+            // the first element like bash
+            Value::Array(elements) if elements.is_empty() => elements.push(value),
+            Value::Array(elements) => elements[0] = value,
+            Value::Scalar(scalar) => *scalar = value,
+        }
+
+        Ok(())
+    }
+}
+
+/// Increments `SHLVL` in the environment as the shell starts. A missing or invalid value counts as
+/// 0. Once per process, as an embedding program may run many command lines.
+pub(super) fn increment_shlvl() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        let level = env::var("SHLVL")
+            .ok()
+            .and_then(|level| level.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+        env::set_var("SHLVL", (level + 1).to_string());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_size_variables() {
+        let mut worker = Worker::new(false, None);
+        worker.set_window_size(Some((132, 43)));
+        assert_eq!(worker.var("COLUMNS").as_deref(), Some("132"));
+        assert_eq!(worker.var("LINES").as_deref(), Some("43"));
+
+        // without a terminal
+        worker.set_window_size(None);
+        assert_eq!(worker.var("COLUMNS").as_deref(), Some("80"));
+        assert_eq!(worker.var("LINES").as_deref(), Some("24"));
+    }
+}
@@ -0,0 +1,249 @@
+mod common;
+
+use std::fs;
+
+use zerosh::Shell;
+
+use common::{run, run_with_input, TempDir, TestShell};
+
+#[test]
+fn cd_and_pwd() {
+    run("cd / && pwd").assert_stdout("/\n").assert_success();
+    run("cd /nonexistent")
+        .assert_stderr("ZeroSh: cd: /nonexistent: No such file or directory\n")
+        .assert_code(1);
+    run("cd /dev/null")
+        .assert_stderr("ZeroSh: cd: /dev/null: Not a directory\n")
+        .assert_code(1);
+}
+
+#[test]
+fn cd_without_home_or_oldpwd() {
+    run("unset HOME; cd; echo $?; pwd")
+        .assert_stdout(&format!(
+            "1\n{}\n",
+            std::env::current_dir().unwrap().display()
+        ))
+        .assert_stderr("ZeroSh: cd: HOME not set\n");
+    // an empty `HOME` stays, and a shell variable is enough
+    run("cd /; HOME=; cd; pwd; unset HOME; HOME=/tmp; cd; pwd").assert_stdout("/\n/tmp\n");
+    run("cd /; unset OLDPWD; cd -; echo $?; cd /tmp; cd -")
+        .assert_stdout("1\n/\n")
+        .assert_stderr("ZeroSh: cd: OLDPWD not set\n");
+}
+
+#[test]
+fn cd_in_subshell_keeps_the_directory() {
+    run("cd /; (cd /tmp; pwd); pwd").assert_stdout("/tmp\n/\n");
+}
+
+#[test]
+fn builtins_in_pipelines_keep_the_shell_state() {
+    // a command of a pipeline runs in a child, but a redirection alone doesn't fork
+    run("cd /; cd /tmp | cat; pwd; echo a | cd /tmp; pwd").assert_stdout("/\n/\n");
+    run("cd /; cd /tmp > /dev/null; pwd").assert_stdout("/tmp\n");
+    run("export A=a | cat; alias b=c | cat; echo \"[$A]\"; alias")
+        .assert_stdout("[]\n")
+        .assert_no_stderr();
+}
+
+#[test]
+fn alias_and_unalias() {
+    run("alias ll='ls -l'; alias ll").assert_stdout("alias ll='ls -l'\n");
+    run("alias ll='ls -l'; unalias ll; alias ll")
+        .assert_stderr_contains("alias: ll: not found")
+        .assert_code(1);
+}
+
+#[test]
+fn export_sets_the_environment() {
+    run("export ZEROSH_TEST=1; env | grep ^ZEROSH_TEST=").assert_stdout("ZEROSH_TEST=1\n");
+    run("ZEROSH_TEST=1; env | grep ^ZEROSH_TEST=").assert_stdout("");
+}
+
+#[test]
+fn printf_formats_arguments() {
+    run(r#"printf '%s-%d\n' a 3"#).assert_stdout("a-3\n");
+}
+
+#[test]
+fn exit_code() {
+    run("exit 7").assert_code(7);
+    run("false").assert_code(1);
+}
+
+#[test]
+fn unknown_command() {
+    run("zerosh-no-such-command")
+        .assert_stderr_contains("zerosh-no-such-command")
+        .assert_code(127);
+}
+
+#[test]
+fn paths_failing_to_run() {
+    let dir = TempDir::new("exec-paths");
+    fs::write(dir.path().join("file"), "echo a\n").unwrap();
+    dir.run("./missing; echo $?; ./file; echo $?; /; echo $?")
+        .assert_stdout("127\n126\n126\n")
+        .assert_stderr(
+            "ZeroSh: ./missing: No such file or directory\n\
+            ZeroSh: ./file: Permission denied\n\
+            ZeroSh: /: Is a directory\n",
+        );
+}
+
+#[test]
+fn execute_runs_a_syntax_tree() {
+    let sh = TestShell::new();
+    sh.execute("echo a && echo b")
+        .assert_stdout("a\nb\n")
+        .assert_success();
+    sh.execute("cd /; pwd").assert_stdout("/\n");
+}
+
+#[test]
+fn clear_writes_nothing_without_a_terminal() {
+    run("clear")
+        .assert_stdout("")
+        .assert_no_stderr()
+        .assert_success();
+    run("clear | wc -c").assert_stdout("0\n").assert_success();
+    run("clear > /dev/null; echo $?").assert_stdout("0\n");
+}
+
+#[test]
+fn builtins_stop_on_a_closed_pipe() {
+    // `printf` in a pipeline is a forked child killed by `SIGPIPE`, like external commands
+    run(r#"printf '%s\n' $(seq 200000) | head -1; echo ${PIPESTATUS[@]}"#)
+        .assert_stdout("1\n141 0\n")
+        .assert_no_stderr();
+    run("help | true; echo ${PIPESTATUS[@]}").assert_stdout("0 0\n");
+}
+
+#[test]
+fn help_wraps_long_usages() {
+    run("help | head -3").assert_stdout(concat!(
+        ". FILE                    Run a file in the current shell, like `source`\n",
+        ": [ARG...]                Do nothing and succeed\n",
+        "alias [NAME[=VALUE]...]   Define aliases or show them\n",
+    ));
+    run("help | grep -A1 '^cd '").assert_stdout(concat!(
+        "cd [-L | -P] [--] [DIR | - | -N]\n",
+        "                          Change the current directory\n",
+    ));
+    run("help | awk 'length($0) > 80' | wc -l").assert_stdout("0\n");
+    run("help cd")
+        .assert_stdout("cd: cd [-L | -P] [--] [DIR | - | -N]\n    Change the current directory\n");
+}
+
+#[test]
+fn builtins_report_write_errors_once() {
+    run("printf a > /dev/full; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr("ZeroSh: printf: write error: No space left on device (os error 28)\n");
+    run("pwd > /dev/full; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr_contains("pwd: write error");
+}
+
+#[test]
+fn cd_keeps_symbolic_links_unless_physical() {
+    let dir = TempDir::new("cd-links");
+    fs::create_dir_all(dir.path().join("real/sub")).unwrap();
+    std::os::unix::fs::symlink("real/sub", dir.path().join("link")).unwrap();
+    let base = dir.path().display();
+
+    // logical by default or with `-L`
+    dir.run("cd link; pwd; echo $PWD; pwd -P; cd ..; pwd")
+        .assert_stdout(&format!(
+            "{base}/link\n{base}/link\n{base}/real/sub\n{base}\n"
+        ));
+    dir.run("cd -L link && pwd")
+        .assert_stdout(&format!("{base}/link\n"));
+    // physical with `-P`, and the last flag wins
+    dir.run("cd -P link; pwd; echo $PWD; cd link; cd -P ..; pwd")
+        .assert_stdout(&format!("{base}/real/sub\n{base}/real/sub\n{base}/real\n"));
+    dir.run("cd -LP link && pwd")
+        .assert_stdout(&format!("{base}/real/sub\n"));
+    dir.run("cd -PL link && pwd")
+        .assert_stdout(&format!("{base}/link\n"));
+}
+
+#[test]
+fn background_builtins() {
+    // state-changing builtins run in the foreground with a warning
+    run("cd / & echo \"[$!]\"; pwd")
+        .assert_stdout("[]\n/\n")
+        .assert_stderr("ZeroSh: cd: changes the shell's state, so it runs in the foreground\n");
+    run("export ZEROSH_TEST=1 & env | grep ^ZEROSH_TEST=")
+        .assert_stdout("ZEROSH_TEST=1\n")
+        .assert_stderr_contains("export: changes the shell's state");
+    // output-only ones are forked into a job
+    run("cd /; pwd > /dev/stderr & wait $!; echo $?; jobs")
+        .assert_stdout("0\n")
+        .assert_stderr("/\n");
+    run("printf x & test -n \"$!\" && wait $!")
+        .assert_stdout("x")
+        .assert_success();
+}
+
+#[test]
+fn aliases_ending_in_a_space_expand_the_next_word() {
+    // aliases apply from the next line
+    let dir = TempDir::new("aliases");
+    let script = "alias say='echo ' hi=hello\nsay hi\nalias say=echo\nsay hi\n";
+    fs::write(dir.path().join("script"), script).unwrap();
+    dir.run("source script").assert_stdout("hello\nhi\n");
+}
+
+#[test]
+fn source_nesting_is_limited() {
+    let dir = TempDir::new("source-depth");
+    fs::write(dir.path().join("self"), "echo a\nsource self\n").unwrap();
+    fs::write(dir.path().join("a"), "source b\necho a\n").unwrap();
+    fs::write(dir.path().join("b"), "source a\necho b\n").unwrap();
+
+    dir.run("source self; echo $?")
+        .assert_stdout(&format!("{}1\n", "a\n".repeat(100)))
+        .assert_stderr("ZeroSh: self:2: source: maximum nesting level exceeded\n");
+    // the files return normally after the error
+    dir.run("source a; echo $?")
+        .assert_stdout(&format!("{}0\n", "b\na\n".repeat(50)))
+        .assert_stderr("ZeroSh: b:1: source: maximum nesting level exceeded\n");
+
+    let sh = TestShell::with_builder(Shell::builder().max_source_depth(3));
+    sh.run(&format!("cd {}; source self", dir.path().display()))
+        .assert_stdout("a\na\na\n")
+        .assert_code(1);
+}
+
+#[test]
+fn read_assigns_the_fields_of_a_line() {
+    let cmd = "read a b; echo \"[$a][$b]\"; read; echo \"[$REPLY]\"; read c; echo $? \"[$c]\"";
+    // `REPLY` gets the whole line with the spaces
+    run_with_input(cmd, "x  y   z \n next \n").assert_stdout("[x][y   z]\n[ next ]\n1 []\n");
+    run_with_input("IFS=: read p q r s; echo \"[$p][$q][$r][$s]\"", "a:b::c\n")
+        .assert_stdout("[a][b][][c]\n");
+    // a line without a newline at the end is assigned, but the status is 1
+    run_with_input("read v; echo $? $v", "last").assert_stdout("1 last\n");
+    run_with_input("while read n; do echo \"<$n>\"; done", "1\n2\n3\n")
+        .assert_stdout("<1>\n<2>\n<3>\n");
+}
+
+#[test]
+fn read_handles_backslashes_unless_raw() {
+    run_with_input("read x y; echo \"[$x][$y]\"", "a\\ b\\\nc d\\e\n")
+        .assert_stdout("[a bc][de]\n");
+    run_with_input("read -r x y; echo \"[$x][$y]\"", "a\\ b\n").assert_stdout("[a\\][b]\n");
+}
+
+#[test]
+fn read_rejects_bad_arguments() {
+    run("read 1x")
+        .assert_stderr("ZeroSh: read: `1x': not a valid identifier\n")
+        .assert_code(1);
+    run("read -z")
+        .assert_stderr_contains("ZeroSh: read: -z: invalid option\n")
+        .assert_code(2);
+    run("read -p").assert_code(2);
+}
@@ -0,0 +1,207 @@
+//! Test harness running command lines in forked shells, with their output captured
+//!
+//! Each command line runs in a fresh shell process forked by [`Shell::run_capture`], so that
+//! variables, the current directory and jobs don't leak between tests. There is no terminal and
+//! no `signal_handler` thread, as with `zerosh -c`.
+
+#![allow(dead_code)]
+
+pub mod pty;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use zerosh::{Ast, CommandOutput, Shell, ShellBuilder};
+
+/// Serializes the forks. A thread of another test holding a lock (e.g., of the environment) while
+/// a shell is forked would leave the lock held forever in the child.
+static FORK_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct TestShell {
+    sh: Shell,
+}
+
+impl TestShell {
+    pub fn new() -> Self {
+        Self::with_builder(Shell::builder())
+    }
+
+    /// Builds the shell with the settings of a builder, without the history
+    pub fn with_builder(builder: ShellBuilder) -> Self {
+        let sh = builder
+            .private(true)
+            .build()
+            .expect("failed to build the shell");
+        Self { sh }
+    }
+
+    pub fn shell(&self) -> &Shell {
+        &self.sh
+    }
+
+    /// Runs a command line with an empty standard input
+    pub fn run(&self, cmd: &str) -> Output {
+        let _lock = FORK_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        Output::new(cmd, self.sh.run_capture(cmd))
+    }
+
+    /// Runs a command line reading `input` from the standard input
+    pub fn run_with_input(&self, cmd: &str, input: &str) -> Output {
+        let _lock = FORK_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        Output::new(cmd, self.sh.run_capture_with_input(cmd, input))
+    }
+
+    /// Parses a command line and runs the syntax tree with [`Shell::execute`]
+    pub fn execute(&self, cmd: &str) -> Output {
+        let ast = self.parse(cmd);
+        self.execute_ast(&ast)
+    }
+
+    /// Runs a syntax tree with [`Shell::execute`]
+    pub fn execute_ast(&self, ast: &Ast) -> Output {
+        let _lock = FORK_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        Output::new(&format!("{ast:?}"), self.sh.execute_capture(ast))
+    }
+
+    pub fn parse(&self, cmd: &str) -> Ast {
+        match self.sh.parse(cmd) {
+            Ok(Some(ast)) => ast,
+            Ok(None) => panic!("empty command line: {cmd:?}"),
+            Err(err) => panic!("failed to parse {cmd:?}: {err}"),
+        }
+    }
+}
+
+/// Empty directory for the files of a test, removed on drop
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the directory, named after the test and the process
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("zerosh-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Runs a command line in the directory
+    pub fn run(&self, cmd: &str) -> Output {
+        run(&format!("cd {} && {{ {cmd}; }}", self.0.display()))
+    }
+
+    pub fn read(&self, file: &str) -> String {
+        fs::read_to_string(self.0.join(file)).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs a command line in a new [`TestShell`]
+pub fn run(cmd: &str) -> Output {
+    TestShell::new().run(cmd)
+}
+
+/// Runs a command line in a new [`TestShell`] reading `input`
+pub fn run_with_input(cmd: &str, input: &str) -> Output {
+    TestShell::new().run_with_input(cmd, input)
+}
+
+/// Output of a command line with assertions, which show the whole output on failure
+#[derive(Debug)]
+pub struct Output {
+    cmd: String,
+    pub out: CommandOutput,
+}
+
+impl Output {
+    fn new(cmd: &str, out: std::io::Result<CommandOutput>) -> Self {
+        let out = out.unwrap_or_else(|err| panic!("failed to run {cmd:?}: {err}"));
+        Self {
+            cmd: cmd.to_string(),
+            out,
+        }
+    }
+
+    pub fn stdout(&self) -> &str {
+        &self.out.stdout
+    }
+
+    pub fn stderr(&self) -> &str {
+        &self.out.stderr
+    }
+
+    pub fn code(&self) -> i32 {
+        self.out.code
+    }
+
+    #[track_caller]
+    pub fn assert_stdout(&self, expected: &str) -> &Self {
+        assert_eq!(
+            self.out.stdout, expected,
+            "stdout of {:?}: {self:#?}",
+            self.cmd
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_stdout_contains(&self, expected: &str) -> &Self {
+        assert!(
+            self.out.stdout.contains(expected),
+            "stdout of {:?} without {expected:?}: {self:#?}",
+            self.cmd
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_stderr(&self, expected: &str) -> &Self {
+        assert_eq!(
+            self.out.stderr, expected,
+            "stderr of {:?}: {self:#?}",
+            self.cmd
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_stderr_contains(&self, expected: &str) -> &Self {
+        assert!(
+            self.out.stderr.contains(expected),
+            "stderr of {:?} without {expected:?}: {self:#?}",
+            self.cmd
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_no_stderr(&self) -> &Self {
+        self.assert_stderr("")
+    }
+
+    #[track_caller]
+    pub fn assert_code(&self, expected: i32) -> &Self {
+        assert_eq!(
+            self.out.code, expected,
+            "exit code of {:?}: {self:#?}",
+            self.cmd
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_success(&self) -> &Self {
+        self.assert_code(0)
+    }
+}
@@ -0,0 +1,343 @@
+//! Interactive shells on pseudo terminals, for what needs a terminal: the line editor, job
+//! control and the messages printed above the prompt
+//!
+//! The shell is the `zerosh` binary started with `--norc` (unless [`PtyShell::start_with_rc`]),
+//! no history, no colors and the prompt [`PROMPT`] in an empty home directory. The output is read
+//! with the escape sequences removed.
+
+use std::{
+    env,
+    ffi::CString,
+    fs,
+    os::unix::io::RawFd,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    libc,
+    poll::{self, PollFd, PollFlags},
+    pty,
+    sys::{signal, wait},
+    unistd::{self, ForkResult},
+};
+
+/// Prompt of the shells
+pub const PROMPT: &str = "%> ";
+
+/// How long [`PtyShell::expect`] waits by default
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct PtyShell {
+    master: Option<RawFd>,
+    pid: unistd::Pid,
+    home: PathBuf,
+    /// Output so far without escape sequences and carriage returns
+    output: String,
+    /// Where the next [`PtyShell::expect`] starts searching
+    pos: usize,
+    /// Bytes of an escape sequence or a UTF-8 character split between reads
+    partial: Vec<u8>,
+    status: Option<wait::WaitStatus>,
+}
+
+impl PtyShell {
+    /// Starts an interactive shell and waits for the first prompt
+    pub fn spawn() -> Self {
+        Self::spawn_with(&[], &[])
+    }
+
+    /// Starts an interactive shell with more arguments and environment variables, and waits for
+    /// the first prompt
+    pub fn spawn_with(args: &[&str], vars: &[(&str, &str)]) -> Self {
+        let mut sh = Self::start(args, vars);
+        sh.expect(PROMPT);
+        sh
+    }
+
+    /// Starts a shell without waiting for anything
+    pub fn start(args: &[&str], vars: &[(&str, &str)]) -> Self {
+        let mut all = vec!["--norc"];
+        all.extend(args);
+        Self::start_with_rc(&all, vars)
+    }
+
+    /// Starts a shell without `--norc`, e.g., with `HOME` in `vars` having a `.zeroshrc`
+    pub fn start_with_rc(args: &[&str], vars: &[(&str, &str)]) -> Self {
+        static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let home = env::temp_dir().join(format!("zerosh-pty-{}-{n}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+
+        let program = CString::new(env!("CARGO_BIN_EXE_zerosh")).unwrap();
+        let mut argv = vec![program.clone()];
+        argv.extend(args.iter().map(|arg| CString::new(*arg).unwrap()));
+
+        let mut envp = env::vars()
+            .filter(|(name, _)| !name.starts_with("ZEROSH_"))
+            .collect::<Vec<_>>();
+        let defaults = [
+            ("HOME", home.to_str().unwrap()),
+            ("TERM", "xterm"),
+            ("NO_COLOR", "1"),
+            ("ZEROSH_NO_HISTORY", "1"),
+            ("ZEROSH_PROMPT", PROMPT),
+        ];
+        for (name, value) in defaults.iter().chain(vars) {
+            envp.retain(|(n, _)| n != name);
+            envp.push((name.to_string(), value.to_string()));
+        }
+        let envp = envp
+            .into_iter()
+            .map(|(name, value)| CString::new(format!("{name}={value}")).unwrap())
+            .collect::<Vec<_>>();
+
+        let size = pty::Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let res = {
+            let _lock = super::FORK_LOCK
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            unsafe { pty::forkpty(Some(&size), None) }.expect("failed to open a pty")
+        };
+        match res.fork_result {
+            ForkResult::Child => {
+                let _ = unistd::execve(&program, &argv, &envp);
+                unsafe { libc::_exit(127) }
+            }
+            ForkResult::Parent { child } => Self {
+                master: Some(res.master),
+                pid: child,
+                home,
+                output: String::new(),
+                pos: 0,
+                partial: Vec::new(),
+                status: None,
+            },
+        }
+    }
+
+    pub fn pid(&self) -> unistd::Pid {
+        self.pid
+    }
+
+    /// Home directory of the shell, removed with it
+    pub fn home(&self) -> &PathBuf {
+        &self.home
+    }
+
+    /// Writes bytes to the terminal as they are, e.g., `"\x03"` for `Ctrl+c`
+    pub fn send(&mut self, bytes: &[u8]) {
+        let master = self.master.expect("the terminal is closed");
+        let _ = unistd::write(master, bytes);
+    }
+
+    /// Resizes the terminal, which sends `SIGWINCH` to the foreground process group
+    pub fn resize(&mut self, columns: u16, lines: u16) {
+        let master = self.master.expect("the terminal is closed");
+        let size = libc::winsize {
+            ws_row: lines,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &size) };
+    }
+
+    /// Types a line and `Enter`
+    pub fn type_line(&mut self, line: &str) {
+        self.type_text(line);
+        self.type_text("\r");
+    }
+
+    /// Types text. Each key is sent separately, as the line editor of rustyline 10 leaves input
+    /// arriving at once in its buffer until the next key, while it also waits for notices.
+    pub fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            let mut buf = [0; 4];
+            self.send(c.encode_utf8(&mut buf).as_bytes());
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Runs a command line and returns the output before the next prompt
+    pub fn run(&mut self, line: &str) -> String {
+        self.type_line(line);
+        self.expect("\n");
+        self.expect(PROMPT)
+    }
+
+    /// Waits until the output after the last match has the text, and returns the output before
+    /// it. Panics with the whole output on timeout.
+    #[track_caller]
+    pub fn expect(&mut self, text: &str) -> String {
+        self.expect_within(text, TIMEOUT)
+            .unwrap_or_else(|| panic!("{text:?} not found in the output:\n{}", self.output))
+    }
+
+    /// Like [`PtyShell::expect`], but `None` on timeout
+    pub fn expect_within(&mut self, text: &str, timeout: Duration) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(i) = self.output[self.pos..].find(text) {
+                let before = self.output[self.pos..self.pos + i].to_string();
+                self.pos += i + text.len();
+                return Some(before);
+            }
+            let now = Instant::now();
+            if now >= deadline || !self.read(deadline - now) {
+                return None;
+            }
+        }
+    }
+
+    /// Reads the output for a while, e.g., to check that nothing more is printed, and returns the
+    /// new output after the last match
+    pub fn read_for(&mut self, duration: Duration) -> &str {
+        let deadline = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= deadline || !self.read(deadline - now) {
+                break;
+            }
+        }
+        &self.output[self.pos..]
+    }
+
+    /// Whole output so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Reads what is available within the timeout. `false` if the terminal is closed or the
+    /// shell is gone.
+    fn read(&mut self, timeout: Duration) -> bool {
+        let Some(master) = self.master else {
+            return false;
+        };
+        let mut fds = [PollFd::new(master, PollFlags::POLLIN)];
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match poll::poll(&mut fds, millis) {
+            Ok(0) => return true,
+            Err(Errno::EINTR) => return true,
+            Err(_) => return false,
+            Ok(_) => {}
+        }
+
+        let mut buf = [0; 4096];
+        match unistd::read(master, &mut buf) {
+            Ok(0) | Err(_) => false,
+            Ok(n) => {
+                self.partial.extend_from_slice(&buf[..n]);
+                self.decode();
+                true
+            }
+        }
+    }
+
+    /// Moves the complete part of [`PtyShell::partial`] to the output without escape sequences
+    fn decode(&mut self) {
+        let bytes = std::mem::take(&mut self.partial);
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                0x1b => match self::escape_len(&bytes[i..]) {
+                    Some(len) => i += len,
+                    None => break,
+                },
+                b'\r' | 0x07 => i += 1,
+                _ => {
+                    let len = self::utf8_len(bytes[i]);
+                    if i + len > bytes.len() {
+                        break;
+                    }
+                    self.output
+                        .push_str(&String::from_utf8_lossy(&bytes[i..i + len]));
+                    i += len;
+                }
+            }
+        }
+        self.partial = bytes[i..].to_vec();
+    }
+
+    /// Closes the terminal as if the connection was lost. The shell gets `SIGHUP`.
+    pub fn hang_up(&mut self) {
+        if let Some(master) = self.master.take() {
+            let _ = unistd::close(master);
+        }
+    }
+
+    /// Waits for the shell to exit within the timeout
+    pub fn wait(&mut self, timeout: Duration) -> Option<wait::WaitStatus> {
+        let deadline = Instant::now() + timeout;
+        while self.status.is_none() {
+            // read the output so that the shell doesn't block on a full terminal
+            if !self.read(Duration::from_millis(20)) {
+                thread::sleep(Duration::from_millis(20));
+            }
+            match wait::waitpid(self.pid, Some(wait::WaitPidFlag::WNOHANG)) {
+                Ok(wait::WaitStatus::StillAlive) => {}
+                Ok(status) => self.status = Some(status),
+                Err(_) => break,
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        self.status
+    }
+
+    /// Waits for the shell to exit and returns the exit code, or panics with the output
+    #[track_caller]
+    pub fn exit_code(&mut self) -> i32 {
+        match self.wait(TIMEOUT) {
+            Some(wait::WaitStatus::Exited(_, code)) => code,
+            status => panic!("shell not exited: {status:?}\n{}", self.output),
+        }
+    }
+}
+
+impl Drop for PtyShell {
+    fn drop(&mut self) {
+        if self.status.is_none() {
+            let _ = signal::kill(self.pid, signal::Signal::SIGKILL);
+            let _ = wait::waitpid(self.pid, None);
+        }
+        self.hang_up();
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+/// Returns the length of the escape sequence at the start, or `None` if it's incomplete
+fn escape_len(bytes: &[u8]) -> Option<usize> {
+    match bytes.get(1)? {
+        // CSI: parameters and a final byte
+        b'[' => {
+            let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))?;
+            Some(end + 3)
+        }
+        // OSC: until BEL or ST
+        b']' => {
+            let rest = &bytes[2..];
+            let bel = rest.iter().position(|&b| b == 0x07).map(|i| i + 3);
+            let st = rest.windows(2).position(|w| w == b"\x1b\\").map(|i| i + 4);
+            bel.into_iter().chain(st).min()
+        }
+        _ => Some(2),
+    }
+}
+
+fn utf8_len(first: u8) -> usize {
+    match first {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    }
+}
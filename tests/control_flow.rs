@@ -0,0 +1,146 @@
+mod common;
+
+use common::{run, TempDir};
+
+#[test]
+fn and_or_lists() {
+    run("true && echo y || echo n").assert_stdout("y\n");
+    run("false && echo y || echo n").assert_stdout("n\n");
+    run("false; echo $?").assert_stdout("1\n");
+}
+
+#[test]
+fn negation() {
+    run("! true").assert_code(1);
+    run("! false").assert_success();
+}
+
+#[test]
+fn comments() {
+    run("echo a # b; echo c").assert_stdout("a\n");
+    run("echo a#b '#c' \\#d").assert_stdout("a#b #c #d\n");
+
+    let dir = TempDir::new("comments");
+    let script = "# a\necho b # c\n  # d\ncase e in # f\n  e) echo g;; # h\nesac\n";
+    std::fs::write(dir.path().join("script"), script).unwrap();
+    dir.run("source script")
+        .assert_stdout("b\ng\n")
+        .assert_no_stderr();
+}
+
+#[test]
+fn brace_groups_and_subshells() {
+    run("{ false || echo a; }").assert_stdout("a\n");
+    run("(exit 3); echo $?").assert_stdout("3\n");
+}
+
+#[test]
+fn case_statement() {
+    run("case ab in a*) echo A;; *) echo B;; esac").assert_stdout("A\n");
+    run("case zz in a*) echo A;; *) echo B;; esac").assert_stdout("B\n");
+}
+
+#[test]
+fn if_statement() {
+    run("if true; then echo a; else echo b; fi").assert_stdout("a\n");
+    run("if false; then echo a; elif false; then echo b; else echo c; fi").assert_stdout("c\n");
+    // the conditions are and-or lists
+    run("if true && false; then echo a; elif false || true; then echo b; fi").assert_stdout("b\n");
+    run("if ! false && true; then echo a; fi").assert_stdout("a\n");
+    // the exit code of the body, or 0 if no branch runs
+    run("if true; then (exit 3); fi").assert_code(3);
+    run("if false; then :; fi").assert_success();
+    run("if false; then :; else (exit 4); fi; echo $?").assert_stdout("4\n");
+}
+
+#[test]
+fn while_and_until_loops() {
+    run("set -- a b c; while [ $# -gt 1 ] && [ $1 != c ]; do echo $1; shift; done")
+        .assert_stdout("a\nb\n");
+    run("set -- a b; until [ $# -eq 0 ] || false; do echo $1; shift; done").assert_stdout("a\nb\n");
+    // the exit code of the last body, or 0 if it never runs
+    run("set -- a; while [ $# -gt 0 ]; do shift; (exit 3); done").assert_code(3);
+    run("while false; do :; done").assert_success();
+    // in a pipeline, the loop runs in a forked child
+    run("set -- a b; while [ $# -gt 0 ]; do echo $1; shift; done | cat").assert_stdout("a\nb\n");
+}
+
+#[test]
+fn exit_and_return_end_loops() {
+    run("while true; do exit 3; done; echo a")
+        .assert_stdout("")
+        .assert_code(3);
+    run("f() { until false; do return 4; done; echo a; }; f; echo $?").assert_stdout("4\n");
+    run("f() { if return 5; then echo a; fi; echo b; }; f; echo $?").assert_stdout("5\n");
+}
+
+#[test]
+fn compound_statements_over_lines() {
+    let dir = TempDir::new("statements");
+    let script = "set -- a b\nwhile\n  [ $# -gt 0 ]\ndo\n  if [ $1 = a ] &&\n    true\n  then\n    echo A\n  else\n    echo $1\n  fi\n  shift\ndone\n";
+    std::fs::write(dir.path().join("script"), script).unwrap();
+    dir.run("source script")
+        .assert_stdout("A\nb\n")
+        .assert_no_stderr();
+}
+
+#[test]
+fn exit_stops_the_line() {
+    run("echo a; exit 2; echo b")
+        .assert_stdout("a\n")
+        .assert_code(2);
+}
+
+#[test]
+fn errexit_quits_on_failures() {
+    run("set -e; true; echo a; false; echo b")
+        .assert_stdout("a\n")
+        .assert_code(1);
+    run("set -o errexit; (exit 3); echo b").assert_code(3);
+    run("set -e; f() { false; echo f; }; f; echo b").assert_code(1);
+    run("set -e; set +e; false; echo b").assert_stdout("b\n");
+}
+
+#[test]
+fn errexit_ignores_conditions() {
+    run("set -e; false && echo a; ! true; false || echo b; true | false || echo c; echo d")
+        .assert_stdout("b\nc\nd\n")
+        .assert_success();
+    // a failure in a group before `||` doesn't quit either
+    run("set -e; { false; echo a; } || echo b; false & wait; echo c").assert_stdout("a\nc\n");
+    run("set -e; false || false; echo a")
+        .assert_code(1)
+        .assert_stdout("");
+}
+
+#[test]
+fn errexit_ignores_if_and_loop_conditions() {
+    run("set -e; if false && true; then :; elif false; then :; fi; echo a").assert_stdout("a\n");
+    run("set -e; while true && false; do :; done; until true || false; do :; done; echo a")
+        .assert_stdout("a\n")
+        .assert_success();
+    // but not the bodies
+    run("set -e; if true; then false; echo a; fi; echo b")
+        .assert_stdout("")
+        .assert_code(1);
+    run("set -e; set -- a; while [ $# -gt 0 ]; do shift; false; done; echo a").assert_code(1);
+}
+
+#[test]
+fn errexit_stops_a_sourced_file() {
+    let dir = TempDir::new("errexit");
+    std::fs::write(dir.path().join("fails"), "echo a\nfalse\necho b\n").unwrap();
+    std::fs::write(dir.path().join("syntax"), "echo a\n;;\necho b\n").unwrap();
+
+    dir.run("source fails; echo c").assert_stdout("a\nb\nc\n");
+    dir.run("set -e; source fails; echo c")
+        .assert_stdout("a\n")
+        .assert_code(1);
+    // lines after a syntax error run unless `set -e`
+    dir.run("source syntax; echo c")
+        .assert_stdout("a\nb\nc\n")
+        .assert_stderr_contains("syntax:2: ");
+    dir.run("set -e; source syntax; echo c")
+        .assert_stdout("a\n")
+        .assert_code(2);
+}
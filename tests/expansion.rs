@@ -0,0 +1,116 @@
+mod common;
+
+use std::fs;
+
+use common::{run, TempDir};
+
+#[test]
+fn assignment_keeps_the_output_of_a_substitution() {
+    run(r#"x=$(printf 'a  b\nc\n\n'); printf '[%s]' "$x""#).assert_stdout("[a  b\nc]");
+    // not split, and no arguments for an unquoted empty one
+    run(r#"x=$(printf 'a   b'); printf '[%s]' "$x" $x"#).assert_stdout("[a   b][a][b]");
+    run(r#"x=$(true); printf '[%s]' "$x""#).assert_stdout("[]");
+}
+
+#[test]
+fn assignment_sets_the_exit_code_of_the_substitution() {
+    run("x=$(exit 3); echo $?").assert_stdout("3\n");
+    run("x=$(false) y=$(true); echo $?").assert_stdout("0\n");
+    run("x=$(true); echo $?").assert_stdout("0\n");
+}
+
+#[test]
+fn declaration_arguments_are_not_split() {
+    run(r#"declare x=$(printf 'a   b'); export y=$(printf 'c   d'); printf '[%s]' "$x" "$y""#)
+        .assert_stdout("[a   b][c   d]");
+    run(r#"f() { local v=$(printf '1\n2'); printf '[%s]' "$v"; }; f"#).assert_stdout("[1\n2]");
+}
+
+#[test]
+fn line_continuations_are_removed() {
+    run("printf '%s\\n' ec\\\nho \"a\\\nb\" ${HOME:+c\\\nd} $(printf e\\\nf)")
+        .assert_stdout("echo\nab\ncd\nef\n");
+    run("printf '%s\\n' 'a\\\nb'").assert_stdout("a\\\nb\n");
+    run("printf '%s\\n' a \\\n b").assert_stdout("a\nb\n");
+}
+
+#[test]
+fn arguments_with_nul_bytes_fail() {
+    run("echo 'a\0b'; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr("ZeroSh: a\\0b: argument contains a NUL byte\n");
+    run("echo a | cat 'b\0'; echo ${PIPESTATUS[@]}")
+        .assert_stdout("1\n")
+        .assert_stderr_contains("b\\0: argument contains a NUL byte");
+}
+
+#[test]
+fn nul_bytes_are_dropped_from_substitutions() {
+    run("x=$(printf 'a\\0b'); printf '%s\\n' \"$x\" $(printf 'c\\0d')")
+        .assert_stdout("ab\ncd\n")
+        .assert_stderr(&"ZeroSh: command substitution: ignored NUL byte in the output\n".repeat(2));
+}
+
+#[test]
+fn invalid_utf8_in_the_output_is_replaced() {
+    // the `printf` builtin writes `\377` as a character, so an external command writes the byte
+    run("x=$(sh -c \"printf 'a\\377b'\"); echo \"$x\" ${#x}")
+        .assert_stdout("a\u{fffd}b 3\n")
+        .assert_no_stderr();
+    run("sh -c \"printf '\\377\\n'\"").assert_stdout("\u{fffd}\n");
+
+    let dir = TempDir::new("invalid-utf8");
+    fs::write(dir.path().join("binary"), b"\xff\xfe\x00c").unwrap();
+    dir.run("echo \"$(< binary)\"")
+        .assert_stdout("\u{fffd}\u{fffd}c\n")
+        .assert_stderr_contains("ignored NUL byte");
+}
+
+/// Directory with `a.txt`, `b.txt`, `.hidden`, `c*`, `d1/x.rs` and `d2/y.rs`
+fn glob_dir() -> TempDir {
+    let dir = TempDir::new("glob");
+    for sub in ["d1", "d2"] {
+        fs::create_dir(dir.path().join(sub)).unwrap();
+    }
+    for file in ["a.txt", "b.txt", ".hidden", "c*", "d1/x.rs", "d2/y.rs"] {
+        fs::write(dir.path().join(file), "").unwrap();
+    }
+    dir
+}
+
+#[test]
+fn pathname_expansion() {
+    let dir = glob_dir();
+    dir.run("echo *").assert_stdout("a.txt b.txt c* d1 d2\n");
+    dir.run("echo ?.txt [!a].* .*")
+        .assert_stdout("a.txt b.txt b.txt .hidden\n");
+    dir.run("echo */*.rs */")
+        .assert_stdout("d1/x.rs d2/y.rs d1/ d2/\n");
+    dir.run("echo $PWD/a*")
+        .assert_stdout(&format!("{}/a.txt\n", dir.path().display()));
+    // kept as they are without a match
+    dir.run("echo *.md d3/*").assert_stdout("*.md d3/*\n");
+}
+
+#[test]
+fn quoted_patterns_are_not_expanded() {
+    let dir = glob_dir();
+    dir.run("echo '*' \"*.txt\" \\* c\\*")
+        .assert_stdout("* *.txt * c*\n");
+    // the values of unquoted expansions are patterns
+    dir.run("x='*.txt'; echo $x \"$x\"")
+        .assert_stdout("a.txt b.txt *.txt\n");
+    dir.run("x='*'; echo c$x \"c$x\"").assert_stdout("c* c*\n");
+    // but not of assignments
+    dir.run("x=*; echo \"$x\"").assert_stdout("*\n");
+    dir.run("a=(*.txt); echo ${#a[@]}").assert_stdout("2\n");
+}
+
+#[test]
+fn noglob_disables_pathname_expansion() {
+    let dir = glob_dir();
+    dir.run("set -f; echo *.txt; set +f; echo *.txt")
+        .assert_stdout("*.txt\na.txt b.txt\n");
+    dir.run("set -o noglob; echo *; set -o | grep noglob")
+        .assert_stdout("*\nnoglob            on\n");
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::{run, TestShell};
+use zerosh::Shell;
+
+#[test]
+fn definition_and_call() {
+    run("f() { echo in f; }; f; f").assert_stdout("in f\nin f\n");
+    run("function g { echo g; }; g").assert_stdout("g\n");
+    run("function h() ( echo h ); h").assert_stdout("h\n");
+    run("f() { echo one; }; f() { echo two; }; f").assert_stdout("two\n");
+}
+
+#[test]
+fn positional_parameters() {
+    run(r#"f() { echo "$# [$1] [$2] [$3]"; }; f a "b c""#).assert_stdout("2 [a] [b c] []\n");
+    run(r#"f() { printf '[%s]' "$@"; echo; }; f x "y z" ''"#).assert_stdout("[x][y z][]\n");
+    // no arguments make no fields
+    run(r#"f() { printf '<%s>' "$@"; echo $#; }; f"#).assert_stdout("<>0\n");
+    run(r#"f() { echo ${#} ${10}; }; f 1 2 3 4 5 6 7 8 9 ten"#).assert_stdout("10 ten\n");
+    // the caller's parameters come back after the call
+    run(r#"set -- a b; f() { echo $1; }; f z; echo $1 $#"#).assert_stdout("z\na 2\n");
+}
+
+#[test]
+fn shift_and_set() {
+    run("f() { shift; echo $*; shift 2; echo $*; }; f 1 2 3 4").assert_stdout("2 3 4\n4\n");
+    run("set -- a b c; shift 4; echo $? $#")
+        .assert_stdout("1 3\n")
+        .assert_stderr_contains("shift count out of range");
+}
+
+#[test]
+fn local_variables() {
+    run("v=out; f() { local v=in; echo $v; }; f; echo $v").assert_stdout("in\nout\n");
+    run("f() { local v=in; g; }; g() { echo $v; }; f; echo [$v]").assert_stdout("in\n[]\n");
+    run("f() { v=global; }; f; echo $v").assert_stdout("global\n");
+    run("local v=1")
+        .assert_code(1)
+        .assert_stderr_contains("can only be used in a function");
+}
+
+#[test]
+fn local_exported_variables_are_restored() {
+    run("export X=g; f() { local -x X=l; printenv X; }; f; printenv X").assert_stdout("l\ng\n");
+    run("f() { local -x Y=l; printenv Y; }; f; printenv Y || echo unset")
+        .assert_stdout("l\nunset\n");
+}
+
+#[test]
+fn return_ends_the_function() {
+    run("f() { echo a; return 3; echo b; }; f; echo $?").assert_stdout("a\n3\n");
+    run("f() { false; return; }; f; echo $?").assert_stdout("1\n");
+    run("f() { { return 4; }; echo no; }; f; echo $?").assert_stdout("4\n");
+    run("f() { case x in x) return 5;; esac; echo no; }; f; echo $?").assert_stdout("5\n");
+    run("return 1; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr_contains("can only be used in a function");
+}
+
+#[test]
+fn functions_in_pipelines_and_background() {
+    run("f() { echo $1; }; f piped | tr a-z A-Z").assert_stdout("PIPED\n");
+    run("f() { cat; }; echo in | f").assert_stdout("in\n");
+    // a function in background runs in a child, so its assignments don't leak
+    run("f() { v=set; }; f & wait; echo [$v]").assert_stdout("[]\n");
+}
+
+#[test]
+fn redirections_of_the_definition_and_the_call() {
+    run("f() { echo err >&2; } 2>&1; f").assert_stdout("err\n");
+    run("f() { echo $1; }; f a > /dev/null; f b").assert_stdout("b\n");
+}
+
+#[test]
+fn functions_come_before_builtins_and_commands() {
+    run("pwd() { echo mine; }; pwd").assert_stdout("mine\n");
+    run("ls() { echo not ls; }; ls /").assert_stdout("not ls\n");
+}
+
+#[test]
+fn recursion_is_limited() {
+    // the default limit needs more than the stack of a test thread in debug builds
+    let sh = TestShell::with_builder(Shell::builder().max_func_depth(20));
+    sh.run("declare -i n=0; f() { n=n+1; f; }; f; echo $? $n")
+        .assert_stdout("1 20\n")
+        .assert_stderr_contains("f: maximum function nesting level exceeded");
+    sh.run("declare -i n=0; f() { n=n+1; [ $n -lt 20 ] && f; }; f; echo $n")
+        .assert_stdout("20\n");
+}
+
+#[test]
+fn definitions_over_lines() {
+    run("f() {\n  echo one\n  echo two\n}\nf").assert_stdout("one\ntwo\n");
+    run("g()\n{\n  case $1 in\n    a) echo A;;\n    *) echo other\n  esac\n}\ng a; g b")
+        .assert_stdout("A\nother\n");
+}
+
+#[test]
+fn declare_shows_functions() {
+    run("f() { echo f; }; g() (exit 1); declare -F").assert_stdout("declare -f f\ndeclare -f g\n");
+    run("f() { echo f; }; declare -f f").assert_stdout("f() { echo f; }\n");
+    run("declare -f nope")
+        .assert_code(1)
+        .assert_stderr_contains("nope: function not found");
+}
+
+#[test]
+fn return_ends_a_sourced_file() {
+    let dir = std::env::temp_dir().join(format!("zerosh-return-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("file.sh");
+    std::fs::write(&file, "echo a\nreturn 4\necho b\n").unwrap();
+
+    let out = run(&format!(
+        "source {0}; echo $?; f() {{ source {0}; echo after $?; }}; f",
+        file.display()
+    ));
+    out.assert_stdout("a\n4\na\nafter 4\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_not_found_handle_runs_for_missing_commands() {
+    let handler = "command_not_found_handle() { echo \"missing $1 ($#): $3\"; return 3; }";
+    run(&format!("{handler}; zerosh-none a 'b c'; echo $?"))
+        .assert_stdout("missing zerosh-none (3): b c\n3\n")
+        .assert_no_stderr();
+    // in a pipeline, but not for paths
+    run(&format!(
+        "{handler}; echo a | zerosh-none | cat; /zerosh-none; echo $?"
+    ))
+    .assert_stdout("missing zerosh-none (1): \n127\n")
+    .assert_stderr_contains("/zerosh-none: No such file or directory");
+    // a command missing in the handler is reported as usual
+    run("command_not_found_handle() { zerosh-other; }; zerosh-none; echo $?")
+        .assert_stdout("127\n")
+        .assert_stderr("ZeroSh: command not found: zerosh-other\n");
+}
+
+#[test]
+fn unset_removes_functions() {
+    run("f() { echo f; }; unset -f f; f").assert_code(127);
+    // without `-f`, only if there is no variable of the name
+    run("f() { echo f; }; f=1; unset f; f; unset f; f")
+        .assert_stdout("f\n")
+        .assert_stderr("ZeroSh: command not found: f\n");
+    run("f() { echo f; }; unset -v f; f").assert_stdout("f\n");
+    run("unset -f -v f")
+        .assert_stderr("ZeroSh: unset: cannot unset a function and a variable at once\n")
+        .assert_code(2);
+}
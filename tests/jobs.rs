@@ -0,0 +1,378 @@
+mod common;
+
+use std::time::Duration;
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::{self, Pid},
+};
+
+use common::{
+    pty::{PtyShell, PROMPT},
+    run, TempDir,
+};
+
+#[test]
+fn many_quick_background_jobs_are_reaped() {
+    let jobs = "true & ".repeat(300);
+    run(&format!("{jobs} wait; echo $?; jobs | wc -l"))
+        .assert_stdout("0\n0\n")
+        .assert_no_stderr();
+}
+
+#[test]
+fn wait_reports_the_exit_code_of_a_job() {
+    let jobs = "true & ".repeat(100);
+    run(&format!(
+        "{jobs} (exit 3) & pid=$!; {jobs} wait $pid; echo $?"
+    ))
+    .assert_stdout("3\n");
+}
+
+#[test]
+fn foreground_waits_with_children_exiting_meanwhile() {
+    // background jobs exit while the shell waits for the foreground one, with and without a
+    // trap reaping them on `SIGCHLD`
+    let jobs = "(exit 1) & ".repeat(50);
+    let line = format!("{jobs} sleep 0.2 | (exit 3); echo $? ${{PIPESTATUS[@]}}; wait; jobs");
+    run(&line).assert_stdout("3 0 3\n").assert_no_stderr();
+    // the trap keeps `$?` and `PIPESTATUS`, and the jobs reaped by it are reported
+    let out = run(&format!("trap : CHLD; {line}"));
+    out.assert_stdout("3 0 3\n");
+    assert_no_job_errors(out.stderr());
+}
+
+/// Asserts that the shell printed no errors, only job messages
+#[track_caller]
+fn assert_no_job_errors(stderr: &str) {
+    assert!(!stderr.contains("ZeroSh:"), "{stderr}");
+}
+
+#[test]
+fn fg_and_bg_of_jobs_exiting_meanwhile() {
+    // the jobs exit before, while or after `fg` and `bg` continue them. `bg` continues the job
+    // once it's stopped, or it would be taken as running.
+    let fg = "(exit 3) & fg > /dev/null; echo -n $?; ".repeat(50);
+    let bg = "(exit 3) & kill -STOP $! 2> /dev/null; sleep 0.01; bg > /dev/null; ".repeat(50);
+    let out = run(&format!("{fg}echo; {bg}wait; jobs"));
+    out.assert_stdout(&format!("{}\n", "3".repeat(50)));
+    assert_no_job_errors(out.stderr());
+}
+
+#[test]
+fn fg_and_bg_of_jobs_exiting_meanwhile_in_interactive_mode() {
+    let mut sh = PtyShell::spawn();
+    let home = sh.home().clone();
+    let codes = home.join("codes");
+    let fg = format!(
+        "(exit 3) & fg > /dev/null; echo -n $? >> {}\n",
+        codes.display()
+    );
+    let bg = "(exit 3) & kill -STOP $! 2> /dev/null; sleep 0.01; bg > /dev/null\n";
+    std::fs::write(home.join("fg"), fg.repeat(50)).unwrap();
+    std::fs::write(home.join("bg"), bg.repeat(50)).unwrap();
+
+    let out = sh.run(&format!("source {}/fg", home.display()));
+    assert_no_job_errors(&out);
+    assert_eq!(std::fs::read_to_string(codes).unwrap(), "3".repeat(50));
+    let out = sh.run(&format!("source {}/bg; wait; jobs", home.display()));
+    assert_no_job_errors(&out);
+    assert!(
+        !out.contains("Stopped") && !out.contains("Running"),
+        "{out}"
+    );
+}
+
+#[test]
+fn job_table_under_many_exits() {
+    // background jobs exit while foreground ones start and end, and `jobs` and `wait` look them up
+    let line = "declare -i i=0; while [ $i -lt 200 ]; do \
+        sh -c 'exit 3' & p=$!; sleep 0.001 | sh -c 'exit 0'; jobs > /dev/null; i=i+1; \
+        done; wait $p; echo $?; wait; echo $?; jobs";
+    run(line).assert_stdout("3\n0\n").assert_no_stderr();
+}
+
+#[test]
+fn job_table_under_many_exits_in_interactive_mode() {
+    // `SIGCHLD`s also arrive while the shell waits for the next command line
+    let mut sh = PtyShell::spawn();
+    // shorter than a line of the terminal
+    let out = sh.run("declare -i i=0; while [ $i -lt 99 ]; do sleep 0.1 & : & i=i+1; done");
+    assert_no_job_errors(&out);
+    std::thread::sleep(Duration::from_millis(500));
+    // all reaped meanwhile, and notified before the next prompt
+    let out = sh.run("jobs; wait; echo $?");
+    let (code, notices) = out.split_once('\n').unwrap();
+    assert_eq!(code, "0", "{out}");
+    assert_eq!(notices.lines().count(), 198, "{out}");
+    assert!(notices.lines().all(|line| line.contains("Done")), "{out}");
+    assert_eq!(sh.run("jobs"), "");
+}
+
+#[test]
+fn exit_ignores_stopped_jobs_without_a_terminal() {
+    // the job may be stopped before its redirections, so it must not get the pipes of the output
+    let dir = std::env::temp_dir().join(format!("zerosh-stopped-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cmd = format!(
+        "cd {}; exec > /dev/null 2> err; sleep 30 & echo $! > pid; kill -STOP $!; exit 7",
+        dir.display()
+    );
+    let out = run(&cmd);
+
+    let pid = std::fs::read_to_string(dir.join("pid")).unwrap();
+    let pid = Pid::from_raw(pid.trim().parse().unwrap());
+    let _ = signal::kill(pid, Signal::SIGKILL);
+    let err = std::fs::read_to_string(dir.join("err")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    out.assert_code(7);
+    assert_eq!(err, "");
+}
+
+/// Writes a script `job` in the directory, which appends `hup` and `cont` to `log` on `SIGHUP`
+/// and `SIGCONT`, exiting on the latter
+fn hup_logging_job(dir: &TempDir) {
+    let job = "trap 'echo hup >> log' HUP; trap 'echo cont >> log; exit' CONT\n\
+               while :; do sleep 0.05; done\n";
+    std::fs::write(dir.path().join("job"), job).unwrap();
+}
+
+#[test]
+fn exit_hangs_up_and_continues_stopped_jobs() {
+    let dir = TempDir::new("exit-stopped");
+    self::hup_logging_job(&dir);
+    // the job must not keep the pipes of the captured output open
+    dir.run("sh job > /dev/null 2>&1 & sleep 0.2; kill -STOP $!; exit 3")
+        .assert_code(3);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(dir.read("log"), "hup\ncont\n");
+}
+
+#[test]
+fn exit_hangs_up_running_jobs_with_huponexit() {
+    let dir = TempDir::new("exit-running");
+    self::hup_logging_job(&dir);
+    let start = "sh job > /dev/null 2>&1 & echo $! >> pids; sleep 0.2";
+
+    // left running, as are the jobs of `disown -h` with `set -o huponexit`
+    dir.run(&format!(
+        "{start}; {start}; set -o huponexit; disown -h %1; disown -h; jobs"
+    ))
+    .assert_stdout_contains("[2]");
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!dir.path().join("log").exists());
+    for pid in dir.read("pids").lines() {
+        let _ = signal::kill(Pid::from_raw(pid.parse().unwrap()), Signal::SIGKILL);
+    }
+
+    dir.run(&format!("{start}; set -o huponexit"))
+        .assert_success();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(dir.read("log"), "hup\ncont\n");
+}
+
+#[test]
+fn disown_removes_jobs_from_the_table() {
+    run("sleep 1 > /dev/null 2>&1 & sleep 1 > /dev/null 2>&1 & disown %1; jobs; disown -a; jobs")
+        .assert_stdout_contains("[2]  Running")
+        .assert_no_stderr();
+    run("sleep 1 > /dev/null 2>&1 & disown; jobs; wait; echo $?").assert_stdout("0\n");
+    run("disown %3")
+        .assert_stderr_contains("disown: %3: no such job")
+        .assert_code(1);
+}
+
+#[test]
+fn exit_warns_about_stopped_jobs_in_interactive_mode() {
+    let mut sh = PtyShell::spawn();
+    sh.run("sleep 30 &");
+    sh.run("kill -STOP %1");
+    sh.run("jobs");
+    let out = sh.run("exit 3");
+    assert!(out.contains("there are stopped jobs"), "{out}");
+
+    // right after the warning, or after another command
+    sh.run("true");
+    let out = sh.run("exit 3");
+    assert!(out.contains("there are stopped jobs"), "{out}");
+    sh.type_line("exit 3");
+    assert_eq!(sh.exit_code(), 3);
+}
+
+#[test]
+fn exit_without_stopped_jobs_in_interactive_mode() {
+    let mut sh = PtyShell::spawn();
+    sh.run("sleep 30 &");
+    sh.type_line("exit 4");
+    assert_eq!(sh.exit_code(), 4);
+}
+
+#[test]
+fn exec_keeps_the_jobs_if_the_command_is_not_found() {
+    run("sleep 5 > /dev/null 2>&1 & exec /nonexistent; echo $?; jobs")
+        .assert_stdout_contains("127\n[1]  Running")
+        .assert_stderr_contains("exec: /nonexistent: ");
+}
+
+#[test]
+fn exec_leaves_the_jobs_running_or_hangs_them_up() {
+    let dir = TempDir::new("exec-jobs");
+    let job = "trap 'echo hup >> hup; exit' HUP; echo $$ > pid; while :; do sleep 0.05; done";
+    std::fs::write(dir.path().join("job"), job).unwrap();
+    // the job must not keep the pipes of the captured output open
+    let start = "sh job > /dev/null 2>&1 & sleep 0.2";
+
+    // the job outlives the shell replaced by the command
+    dir.run(&format!(
+        "{start}; exec sh -c 'sleep 0.2; kill -0 $(cat pid) && echo alive'"
+    ))
+    .assert_stdout("alive\n");
+    let pid = Pid::from_raw(dir.read("pid").trim().parse().unwrap());
+    let _ = signal::kill(pid, Signal::SIGKILL);
+    assert!(!dir.path().join("hup").exists());
+
+    std::fs::remove_file(dir.path().join("pid")).unwrap();
+    dir.run(&format!("{start}; set -o huponexit; exec sleep 0.3"))
+        .assert_success();
+    assert_eq!(dir.read("hup"), "hup\n");
+}
+
+/// Returns the state of a process in `/proc/PID/stat`, e.g., `T` if it's stopped
+fn process_state(pid: Pid) -> char {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+    let (_, rest) = stat.rsplit_once(") ").unwrap();
+    rest.chars().next().unwrap()
+}
+
+#[test]
+fn pipeline_jobs_run_in_one_process_group() {
+    let mut sh = PtyShell::spawn();
+    sh.run("sleep 30 | sleep 30 | cat &");
+    let out = sh.run("jobs -l");
+    let pids = out
+        .split_whitespace()
+        .skip(1)
+        .take(3)
+        .map(|pid| Pid::from_raw(pid.parse().unwrap()))
+        .collect::<Vec<_>>();
+    for &pid in &pids {
+        assert_eq!(unistd::getpgid(Some(pid)), Ok(pids[0]), "{out}");
+    }
+    assert_eq!(sh.run("jobs -p"), format!("{}\n", pids[0]));
+
+    // `fg` takes the whole pipeline, and `Ctrl+z` stops all of it
+    sh.type_line("fg");
+    sh.expect("sleep 30 | sleep 30 | cat\n");
+    std::thread::sleep(Duration::from_millis(200));
+    sh.send(b"\x1a");
+    sh.expect("Stopped");
+    sh.expect(PROMPT);
+    assert!(pids.iter().all(|&pid| process_state(pid) == 'T'));
+    sh.run("bg");
+    assert!(pids.iter().all(|&pid| process_state(pid) != 'T'));
+
+    // and `Ctrl+c` ends all of it
+    sh.type_line("fg");
+    sh.expect("sleep 30 | sleep 30 | cat\n");
+    std::thread::sleep(Duration::from_millis(200));
+    sh.send(b"\x03");
+    sh.expect(PROMPT);
+    assert_eq!(sh.run("jobs"), "");
+    assert!(pids.iter().all(|&pid| signal::kill(pid, None).is_err()));
+}
+
+#[test]
+fn signals_of_foreground_commands_are_reported() {
+    run("sh -c 'kill -TERM $$'; echo $?")
+        .assert_stdout("143\n")
+        .assert_stderr("Terminated\n");
+    // `Ctrl+c` and closed pipes are usual
+    run("sh -c 'kill -INT $$'; sh -c 'kill -PIPE $$'; echo $?")
+        .assert_stdout("141\n")
+        .assert_no_stderr();
+}
+
+#[test]
+fn notify_prints_job_changes_above_the_prompt() {
+    let mut sh = PtyShell::spawn();
+    sh.run("set -b");
+    sh.run("sleep 0.3 &");
+    // at once, without another command line
+    sh.expect("[1]  Done                    sleep 0.3\n");
+    sh.expect(PROMPT);
+
+    sh.run("sleep 30 &");
+    let pid = sh.run("echo $!").trim().parse().unwrap();
+    signal::kill(Pid::from_raw(pid), Signal::SIGSTOP).unwrap();
+    sh.expect("[1]  Stopped                 sleep 30\n");
+    sh.expect(PROMPT);
+    signal::kill(Pid::from_raw(pid), Signal::SIGKILL).unwrap();
+    sh.expect("[1]  Killed                  sleep 30\n");
+}
+
+#[test]
+fn notify_keeps_the_half_typed_line() {
+    let mut sh = PtyShell::spawn();
+    sh.run("set -b");
+    sh.run("sleep 0.5 &");
+    sh.type_text("echo hel");
+    sh.expect("[1]  Done");
+    // the line is drawn again after the notice
+    sh.expect("echo hel");
+    sh.type_line("lo");
+    sh.expect("\nhello\n");
+}
+
+#[test]
+fn job_changes_wait_for_the_next_prompt_without_notify() {
+    let mut sh = PtyShell::spawn();
+    sh.run("sleep 0.2 &");
+    let out = sh.read_for(Duration::from_secs(1)).to_string();
+    assert!(!out.contains("Done"), "{out:?}");
+    let out = sh.run("");
+    assert_eq!(out, "[1]  Done                    sleep 0.2\n");
+
+    // an empty line is not a command
+    sh.run("false");
+    sh.run("");
+    assert_eq!(sh.run("echo $?"), "1\n");
+}
+
+#[test]
+fn last_background_pid() {
+    run("echo \"[$!]\"").assert_stdout("[]\n");
+    // the command line is read once the job has run `exec`, which may take a while on a busy
+    // machine
+    run("sleep 5 & sleep 0.2; tr '\\0' ' ' < /proc/$!/cmdline; echo; kill $!; wait $!; echo $?")
+        .assert_stdout("sleep 5 \n143\n");
+    // the last command of a pipeline
+    run("sleep 5 | sleep 6 & sleep 0.2; tr '\\0' ' ' < /proc/${!}/cmdline; kill %1")
+        .assert_stdout("sleep 6 ");
+}
+
+#[test]
+fn wait_for_the_last_background_pid() {
+    run("sh -c 'exit 7' & wait $!; echo $?").assert_stdout("7\n");
+    // already reported as done
+    run("sh -c 'exit 7' & sleep 0.2; jobs; wait $!; echo $?")
+        .assert_stdout_contains("Done")
+        .assert_stdout_contains("\n7\n");
+    // a process of a pipeline
+    run("sh -c 'exit 3' | sleep 0.1 & wait $!; echo $?").assert_stdout("0\n");
+    run("wait 1; echo $?")
+        .assert_stdout("127\n")
+        .assert_stderr_contains("wait: pid 1 is not a child of this shell");
+}
+
+#[test]
+fn job_ids_are_reused() {
+    run("sleep 0.1 & sleep 5 & wait %1; sleep 6 & jobs; kill %1 %2")
+        .assert_stdout(
+            "[1]  Running                 sleep 6\n[2]  Running                 sleep 5\n",
+        )
+        .assert_no_stderr();
+    // `fg` without a job takes the last one started
+    run("sleep 0.1 & sleep 0.2 & wait %1; sh -c 'exit 3' & sleep 0.3; jobs; fg; echo $?")
+        .assert_stdout_contains("\n3\n");
+}
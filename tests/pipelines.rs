@@ -0,0 +1,124 @@
+mod common;
+
+use common::{run, run_with_input, TestShell};
+use zerosh::Shell;
+
+#[test]
+fn pipeline_connects_commands() {
+    run("echo a | tr a b | cat")
+        .assert_stdout("b\n")
+        .assert_success();
+}
+
+#[test]
+fn pipeline_exit_code_is_the_last_one() {
+    run("true | false").assert_code(1);
+    run("false | true").assert_success();
+}
+
+#[test]
+fn pipestatus_has_each_exit_code() {
+    run("false | true; echo ${PIPESTATUS[@]}").assert_stdout("1 0\n");
+}
+
+#[test]
+fn pipefail_takes_the_last_failure() {
+    run("false | true; echo $? ${PIPESTATUS[@]}").assert_stdout("0 1 0\n");
+    run("set -o pipefail; false | true; echo $? ${PIPESTATUS[@]}").assert_stdout("1 1 0\n");
+    // the rightmost nonzero one, and 0 if all succeed
+    run("set -o pipefail; (exit 3) | false | true; echo $?; true | true; echo $?")
+        .assert_stdout("1\n0\n");
+    run("set -o pipefail; set +o pipefail; false | true").assert_success();
+}
+
+#[test]
+fn builtins_in_pipelines() {
+    run("pwd | cat").assert_success();
+    run(r#"printf '%s\n' b a | sort"#).assert_stdout("a\nb\n");
+}
+
+#[test]
+fn group_output_is_piped_together() {
+    run("{ echo a; echo b; } | wc -l").assert_stdout("2\n");
+}
+
+#[test]
+fn standard_input_reaches_the_pipeline() {
+    run_with_input("tr a-z A-Z", "abc\n").assert_stdout("ABC\n");
+}
+
+#[test]
+fn stderr_is_captured() {
+    run("echo err >&2").assert_stdout("").assert_stderr("err\n");
+}
+
+#[test]
+fn pipes_do_not_leak_into_commands() {
+    // `ls` itself has the directory open as fd 3
+    run("true | ls /proc/self/fd | cat").assert_stdout("0\n1\n2\n3\n");
+    run("echo a | cat; ls /proc/self/fd").assert_stdout("a\n0\n1\n2\n3\n");
+    run("echo $(ls /proc/self/fd)").assert_stdout("0 1 2 3\n");
+}
+
+#[test]
+fn pipes_are_closed_in_the_shell() {
+    // listed by plain commands, as the shell holds the pipe of `$(...)` in a substitution
+    let dir = std::env::temp_dir().join(format!("zerosh-fds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cmd = format!(
+        "cd {}; ls /proc/$$/fd > before; true | true | true; echo a | cat > /dev/null; \
+         ls /proc/$$/fd > after; cmp before after",
+        dir.display()
+    );
+    run(&cmd).assert_no_stderr().assert_success();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn pipeline_length_is_limited() {
+    let pipeline = |len: usize| vec!["true"; len].join(" | ");
+    run(&pipeline(1024)).assert_success();
+    run(&pipeline(100_000))
+        .assert_stderr("ZeroSh: too many commands in a pipeline (more than 1024)\n")
+        .assert_code(2);
+
+    let sh = Shell::builder().max_pipeline_len(2).max_words(3);
+    let sh = TestShell::with_builder(sh);
+    sh.run("true | true").assert_success();
+    sh.run("true | true | true").assert_code(2);
+    let err = sh.shell().parse("echo a b c").unwrap_err();
+    assert_eq!(err.to_string(), "too many words (more than 3)");
+
+    let sh = TestShell::with_builder(Shell::builder().max_line_len(10));
+    sh.run("echo 12345").assert_stdout("12345\n");
+    sh.run("echo 123456")
+        .assert_stdout("")
+        .assert_stderr("ZeroSh: command line too long (more than 10 bytes)\n")
+        .assert_code(2);
+}
+
+#[test]
+fn builtin_output_keeps_its_order_with_children() {
+    run("printf 'a\\n'; echo b | cat; pwd; printf 'c\\n'; /bin/echo d; printf 'e\\n'")
+        .assert_stdout(&format!(
+            "a\nb\n{}\nc\nd\ne\n",
+            std::env::current_dir().unwrap().display()
+        ));
+    run("{ printf 'a\\n'; sh -c 'echo b >&2'; printf 'c\\n' >&2; printf 'd\\n'; } 2>&1 | cat")
+        .assert_stdout("a\nb\nc\nd\n");
+}
+
+#[test]
+fn time_prints_the_times_in_timeformat() {
+    run("TIMEFORMAT='%0R|%%|%x'; time sleep 0.1 | (exit 3); echo $?")
+        .assert_stdout("3\n")
+        .assert_stderr("0|%|%x\n");
+    // nothing with an empty one, and `time -p` in the POSIX format
+    let out = run("TIMEFORMAT=; time :; time -p :");
+    let lines = out.stderr().lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{out:?}");
+    for (line, name) in lines.iter().zip(["real ", "user ", "sys "]) {
+        let secs = line.strip_prefix(name).unwrap_or_else(|| panic!("{out:?}"));
+        assert!(secs.parse::<f64>().is_ok(), "{out:?}");
+    }
+}
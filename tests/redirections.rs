@@ -0,0 +1,110 @@
+mod common;
+
+use common::{run, TempDir};
+
+#[test]
+fn output_redirections() {
+    let dir = TempDir::new("redirect");
+    dir.run("echo a > f; echo b >> f; cat < f")
+        .assert_stdout("a\nb\n");
+    dir.run("echo c > f").assert_success();
+    assert_eq!(dir.read("f"), "c\n");
+}
+
+#[test]
+fn noclobber_refuses_to_overwrite() {
+    let dir = TempDir::new("noclobber");
+    dir.run("echo old > f; set -o noclobber; echo new > f; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr_contains("f: cannot overwrite existing file");
+    assert_eq!(dir.read("f"), "old\n");
+
+    // `set -C` is the same, and new files and appending are fine
+    dir.run("set -C; echo new > g; echo more >> f")
+        .assert_success();
+    assert_eq!(dir.read("g"), "new\n");
+    assert_eq!(dir.read("f"), "old\nmore\n");
+}
+
+#[test]
+fn noclobber_is_forced_with_bar() {
+    let dir = TempDir::new("clobber");
+    dir.run("echo old > f; set -o noclobber; echo new >| f")
+        .assert_success();
+    assert_eq!(dir.read("f"), "new\n");
+}
+
+#[test]
+fn noclobber_allows_devices() {
+    run("set -o noclobber; echo a > /dev/null")
+        .assert_success()
+        .assert_no_stderr();
+}
+
+#[test]
+fn closed_descriptors_are_not_inherited() {
+    // `test` checks the descriptors of its own process
+    run(
+        "{ test -e /proc/self/fd/3; echo $?; test -e /proc/self/fd/3 3>&-; echo $?; } 3> /dev/null",
+    )
+    .assert_stdout("0\n1\n");
+    run("test -e /proc/self/fd/0 <&-; echo $?").assert_stdout("1\n");
+    run("cat <&-").assert_stderr_contains("Bad file descriptor");
+}
+
+#[test]
+fn closes_apply_in_order() {
+    run("test -e /proc/self/fd/3 3>&1 3>&-; echo $?").assert_stdout("1\n");
+    run("test -e /proc/self/fd/3 3>&- 3>&1; echo $?").assert_stdout("0\n");
+    run("echo a 3>&1 3>&- >&3")
+        .assert_stderr("ZeroSh: 3: bad file descriptor\n")
+        .assert_code(1);
+}
+
+#[test]
+fn closed_descriptors_must_be_in_range() {
+    run("echo a 99999999999>&-")
+        .assert_stderr("ZeroSh: 99999999999: file descriptor out of range\n")
+        .assert_code(2);
+}
+
+#[test]
+fn missing_directories_fail_by_default() {
+    let dir = TempDir::new("nomkdir");
+    dir.run("echo a > sub/f; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr_contains("No such file or directory");
+    assert!(!dir.path().join("sub").exists());
+}
+
+#[test]
+fn mkdir_on_redirect_creates_the_parents() {
+    let dir = TempDir::new("mkdir");
+    dir.run(
+        "set -o mkdir_on_redirect; echo a > sub/dir/f; echo b >> sub/new/g; echo c 2> err/f >&2",
+    )
+    .assert_success();
+    assert_eq!(dir.read("sub/dir/f"), "a\n");
+    assert_eq!(dir.read("sub/new/g"), "b\n");
+    assert_eq!(dir.read("err/f"), "c\n");
+
+    // not for input, and off again with `set +o`
+    dir.run("set -o mkdir_on_redirect; cat < in/f")
+        .assert_code(1);
+    assert!(!dir.path().join("in").exists());
+    dir.run("set -o mkdir_on_redirect; set +o mkdir_on_redirect; echo a > off/f")
+        .assert_code(1);
+    assert!(!dir.path().join("off").exists());
+}
+
+#[test]
+fn builtins_fail_on_write_errors() {
+    run("printf 'a\\n' > /dev/full; echo $?; pwd > /dev/full; echo $?")
+        .assert_stdout("1\n1\n")
+        .assert_stderr(
+            "ZeroSh: printf: write error: No space left on device (os error 28)\n\
+            ZeroSh: pwd: write error: No space left on device (os error 28)\n",
+        );
+    // the output dropped on the error doesn't reach the next command
+    run("printf 'a\\n' > /dev/full; printf 'b\\n'").assert_stdout("b\n");
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use nix::sys::signal::Signal;
+
+use common::{
+    pty::{PtyShell, PROMPT},
+    run, TempDir,
+};
+
+/// Prints the blocked and the ignored signals of the command itself
+const SIGNAL_STATUS: &str = "grep -E '^Sig(Blk|Ign)' /proc/self/status";
+
+/// Signals the shell ignores or handles itself
+const SHELL_SIGNALS: [Signal; 7] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+    Signal::SIGCHLD,
+    Signal::SIGPIPE,
+];
+
+/// Parses a signal mask such as `SigIgn:` in the output of [`SIGNAL_STATUS`]
+#[track_caller]
+fn mask(status: &str, name: &str) -> u64 {
+    let line = status.lines().find(|line| line.starts_with(name));
+    let line = line.unwrap_or_else(|| panic!("no {name} in {status:?}"));
+    u64::from_str_radix(line[name.len()..].trim(), 16).unwrap()
+}
+
+fn bit(signal: Signal) -> u64 {
+    1 << (signal as i32 - 1)
+}
+
+/// Asserts that no signal is blocked and none of [`SHELL_SIGNALS`] is ignored in the output of
+/// [`SIGNAL_STATUS`]. Others may be ignored by the test runner and inherited as they are.
+#[track_caller]
+fn assert_default_signals(status: &str) {
+    assert_eq!(mask(status, "SigBlk:"), 0, "{status:?}");
+    let ignored = mask(status, "SigIgn:");
+    for signal in SHELL_SIGNALS {
+        assert_eq!(ignored & bit(signal), 0, "{signal} ignored: {status:?}");
+    }
+}
+
+#[test]
+fn commands_get_the_default_signal_dispositions() {
+    // the test process ignores `SIGPIPE`, which the forked shell inherits
+    assert_default_signals(run(SIGNAL_STATUS).stdout());
+    assert_default_signals(run(&format!("({SIGNAL_STATUS}) | cat")).stdout());
+    assert_default_signals(run(&format!("exec {SIGNAL_STATUS}")).stdout());
+}
+
+#[test]
+fn commands_get_the_default_signal_dispositions_in_interactive_mode() {
+    let mut sh = PtyShell::spawn();
+    // the interactive shell ignores `SIGTTOU` and more for job control
+    let shell_status = sh.run("grep -E '^Sig(Blk|Ign)' /proc/$$/status");
+    let ignored = mask(&shell_status, "SigIgn:");
+    assert_ne!(ignored & bit(Signal::SIGTTOU), 0, "{shell_status:?}");
+
+    assert_default_signals(&sh.run(SIGNAL_STATUS));
+    assert_default_signals(&sh.run(&format!("{SIGNAL_STATUS} | cat")));
+}
+
+#[test]
+fn kill_lists_signals_and_converts_them() {
+    let out = run("kill -l");
+    out.assert_success();
+    assert!(
+        out.stdout().starts_with(" 1) SIGHUP\t 2) SIGINT\t"),
+        "{out:?}"
+    );
+    assert!(
+        out.stdout().contains(" 9) SIGKILL\t10) SIGUSR1\n"),
+        "{out:?}"
+    );
+
+    run("kill -l 9; kill -l KILL; kill -l sigterm").assert_stdout("KILL\n9\n15\n");
+    // the exit code of a process killed by the signal
+    run("kill -l 137").assert_stdout("KILL\n");
+    run("kill -l 99")
+        .assert_stderr("ZeroSh: kill: 99: invalid signal\n")
+        .assert_code(1);
+}
+
+#[test]
+fn kill_and_nohup_print_the_errno_text() {
+    run("kill 2147483647")
+        .assert_stderr("ZeroSh: kill: 2147483647: No such process\n")
+        .assert_code(1);
+    run("nohup zerosh-no-such-command")
+        .assert_stderr(
+            "ZeroSh: nohup: failed to run command 'zerosh-no-such-command': \
+            No such file or directory\n",
+        )
+        .assert_code(127);
+}
+
+#[test]
+fn trap_p_prints_the_traps_to_set_them_again() {
+    let traps = "trap 'echo \"it'\\''s\"' USR1; trap '' TERM; trap 'echo bye' EXIT";
+    let dump = "trap -- 'echo bye' EXIT\n\
+        trap -- 'echo \"it'\\''s\"' SIGUSR1\n\
+        trap -- '' SIGTERM\n";
+    run(&format!("{traps}; trap -p; trap - EXIT"))
+        .assert_stdout(dump)
+        .assert_no_stderr();
+    // in the order of the arguments
+    run(&format!("{traps}; trap -p USR1 0 INT; trap - EXIT")).assert_stdout(
+        "trap -- 'echo \"it'\\''s\"' SIGUSR1\n\
+        trap -- 'echo bye' EXIT\n",
+    );
+    run("trap -p FOO")
+        .assert_stderr("ZeroSh: trap: FOO: invalid signal\n")
+        .assert_code(1);
+
+    // the dump sets the same traps in another shell
+    let dir = TempDir::new("trap-dump");
+    dir.run(&format!("{traps}; trap -p > traps; trap - EXIT"))
+        .assert_success();
+    dir.run("source traps; trap -p; trap - EXIT")
+        .assert_stdout(dump);
+    dir.run("source traps; kill -USR1 $$; trap - EXIT")
+        .assert_stdout("it's\n");
+}
+
+#[test]
+fn trap_l_lists_the_signals() {
+    let out = run("trap -l");
+    out.assert_success();
+    assert_eq!(out.stdout(), run("kill -l").stdout());
+}
+
+#[test]
+fn ctrl_c_ends_loops() {
+    let mut sh = PtyShell::spawn();
+    // builtins only, where the shell itself gets `SIGINT`
+    sh.type_line("while :; do :; done");
+    sh.expect("\n");
+    thread::sleep(Duration::from_millis(200));
+    sh.send(b"\x03");
+    sh.expect(PROMPT);
+    // a command killed by `SIGINT`
+    sh.type_line("until false; do sleep 5; done");
+    sh.expect("\n");
+    thread::sleep(Duration::from_millis(200));
+    sh.send(b"\x03");
+    sh.expect(PROMPT);
+    assert_eq!(sh.run("echo $?"), "130\n");
+}
+
+#[test]
+fn ctrl_c_interrupts_read() {
+    let mut sh = PtyShell::spawn();
+    sh.type_line("read x; echo \"status $?\"");
+    sh.expect("\n");
+    thread::sleep(Duration::from_millis(200));
+    sh.send(b"\x03");
+    assert_eq!(sh.expect(PROMPT), "^Cstatus 130\n");
+    // the shell reads the terminal again
+    sh.type_line("read x; echo \"[$x]\"");
+    sh.expect("\n");
+    sh.type_line("typed");
+    assert_eq!(sh.expect(PROMPT), "typed\n[typed]\n");
+}
@@ -0,0 +1,352 @@
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    net::{TcpListener, TcpStream},
+    os::fd::{AsRawFd, OwnedFd},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use nix::sys::{socket, wait::WaitStatus};
+
+use common::{
+    pty::{PtyShell, PROMPT},
+    TempDir,
+};
+
+/// Starts a shell saving the history in `~/.zerosh_history`
+fn spawn_with_history() -> PtyShell {
+    PtyShell::spawn_with(&[], &[("ZEROSH_NO_HISTORY", ""), ("XDG_STATE_HOME", "")])
+}
+
+#[test]
+fn hang_up_exits_and_keeps_the_history() {
+    let mut sh = spawn_with_history();
+    sh.run("echo remembered");
+    sh.hang_up();
+
+    let status = sh.wait(Duration::from_secs(5));
+    assert!(
+        matches!(status, Some(WaitStatus::Exited(_, 129))),
+        "{status:?}"
+    );
+    let history = fs::read_to_string(sh.home().join(".zerosh_history")).unwrap();
+    assert!(history.contains("echo remembered"), "{history:?}");
+}
+
+#[test]
+fn lost_terminal_exits_without_sighup() {
+    // reading the closed terminal fails instead, which must not be retried forever
+    let mut sh = PtyShell::spawn();
+    sh.run("trap '' HUP");
+    sh.hang_up();
+
+    let status = sh.wait(Duration::from_secs(5));
+    assert!(
+        matches!(status, Some(WaitStatus::Exited(_, 129))),
+        "{status:?}"
+    );
+}
+
+#[test]
+fn exit_trap_runs_on_hang_up() {
+    let mut sh = PtyShell::spawn();
+    let file = sh.home().join("trapped");
+    sh.run(&format!("trap 'echo bye > {}' EXIT", file.display()));
+    sh.hang_up();
+
+    assert!(sh.wait(Duration::from_secs(5)).is_some());
+    assert_eq!(
+        fs::read_to_string(&file).ok().as_deref(),
+        Some("bye\n"),
+        "{}",
+        sh.output()
+    );
+}
+
+#[test]
+fn eof_exits_after_messages_of_the_worker() {
+    // the `EXIT` trap changes the edit mode, which the `worker` reports before quitting
+    let mut sh = PtyShell::spawn();
+    sh.run("trap 'set -o vi; echo bye' EXIT");
+    sh.send(b"\x04");
+    sh.expect("bye");
+    assert_eq!(sh.exit_code(), 0);
+}
+
+#[test]
+fn eof_warns_about_stopped_jobs() {
+    let mut sh = PtyShell::spawn();
+    sh.run("sleep 30 &");
+    sh.run("kill -STOP %1");
+    sh.run("jobs");
+    sh.send(b"\x04");
+    sh.expect("there are stopped jobs");
+    sh.expect(PROMPT);
+    // with the code of the failed `exit`
+    sh.send(b"\x04");
+    assert_eq!(sh.exit_code(), 1);
+}
+
+#[test]
+fn window_size_is_updated_on_resize() {
+    let mut sh = PtyShell::spawn();
+    assert_eq!(sh.run("echo $COLUMNS $LINES"), "80 24\n");
+    sh.run("cd $HOME; trap 'echo $COLUMNS $LINES >> sizes' WINCH");
+
+    // on `SIGWINCH` while reading a line. Keys are typed once the trap is done, as the command
+    // in it has the terminal.
+    let sizes = sh.home().join("sizes");
+    sh.resize(100, 30);
+    thread::sleep(Duration::from_millis(500));
+    sh.resize(120, 40);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(fs::read_to_string(sizes).unwrap(), "100 30\n120 40\n");
+    assert_eq!(sh.run("echo $COLUMNS $LINES"), "120 40\n");
+}
+
+#[test]
+fn option_flags_of_an_interactive_shell() {
+    let mut sh = PtyShell::spawn();
+    assert_eq!(sh.run("echo $-"), "himH\n");
+    assert_eq!(sh.run("set +H; set -f; echo $-"), "himf\n");
+    // without job control in a subshell for a command substitution
+    assert_eq!(sh.run("echo $(echo $-)"), "hif\n");
+}
+
+#[test]
+fn command_lines_are_counted_unless_empty() {
+    let mut sh = PtyShell::spawn_with(&[], &[("ZEROSH_PROMPT", &format!("[\\#]{PROMPT}"))]);
+    assert_eq!(sh.run("echo $ZEROSH_CMD_COUNT"), "1\n[2]");
+    sh.run("");
+    sh.run("   ");
+    assert_eq!(sh.run("echo $ZEROSH_CMD_COUNT"), "2\n[3]");
+    // including the ones failing to parse
+    sh.run("echo '");
+    assert_eq!(sh.run("echo $ZEROSH_CMD_COUNT"), "4\n[5]");
+}
+
+#[test]
+fn prompt_shows_the_nesting_level_and_ssh() {
+    let prompt = format!("\\s\\S{PROMPT}");
+    let mut sh = PtyShell::spawn_with(&[], &[("ZEROSH_PROMPT", &prompt), ("SHLVL", "0")]);
+    assert_eq!(sh.run("echo $SHLVL"), "1\n");
+
+    let vars = [("ZEROSH_PROMPT", prompt.as_str()), ("SHLVL", "2")];
+    let mut sh = PtyShell::spawn_with(&[], &vars);
+    assert_eq!(sh.run("echo $SHLVL"), "3\n(3) ");
+
+    let vars = [
+        ("ZEROSH_PROMPT", prompt.as_str()),
+        ("SSH_CONNECTION", "a 1 b 2"),
+    ];
+    let mut sh = PtyShell::spawn_with(&[], &vars);
+    let host = nix::unistd::gethostname().unwrap();
+    let out = sh.run("true");
+    assert!(
+        out.ends_with(&format!("@{} ", host.to_string_lossy())),
+        "{out:?}"
+    );
+}
+
+#[test]
+fn prompt_shows_the_git_branch() {
+    let vars = [("ZEROSH_RPROMPT", "<\\g>")];
+    let mut sh = PtyShell::spawn_with(&[], &vars);
+    fs::create_dir_all(sh.home().join("repo/.git")).unwrap();
+    fs::write(sh.home().join("repo/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    sh.run(&format!("cd {}", sh.home().display()));
+    assert_eq!(sh.run("cd repo"), "<main >");
+    assert_eq!(sh.run("cd .."), "<>");
+}
+
+#[test]
+fn async_prompt_shows_a_placeholder_until_the_git_branch_is_found() {
+    let vars = [
+        ("ZEROSH_RPROMPT", "<\\g>"),
+        ("ZEROSH_ASYNC_PROMPT", "1"),
+        ("ZEROSH_ASCII", "1"),
+    ];
+    let mut sh = PtyShell::spawn_with(&[], &vars);
+    fs::create_dir_all(sh.home().join("fast/.git")).unwrap();
+    fs::write(sh.home().join("fast/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    sh.run(&format!("cd {}", sh.home().display()));
+    assert_eq!(sh.run("cd fast"), "<main >");
+
+    // reading the FIFO blocks until it's written
+    fs::create_dir_all(sh.home().join("slow/.git")).unwrap();
+    let head = sh.home().join("slow/.git/HEAD");
+    nix::unistd::mkfifo(&head, nix::sys::stat::Mode::S_IRWXU).unwrap();
+    assert_eq!(sh.run("cd ../slow"), "<...>");
+    fs::write(&head, "ref: refs/heads/topic\n").unwrap();
+    sh.expect("<topic >");
+    // the input line is redrawn as well
+    sh.type_text("echo a");
+    sh.expect("<topic >%> echo a");
+}
+
+/// Runs the shell reading `input` from a TCP connection, and then resets the connection if
+/// `reset`, or closes it
+fn run_with_socket_input(input: &str, reset: bool) -> std::process::Output {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_zerosh"))
+        .arg("--norc")
+        .env("ZEROSH_NO_HISTORY", "1")
+        .stdin(OwnedFd::from(client))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    server.write_all(input.as_bytes()).unwrap();
+    // the lines are run before the end
+    thread::sleep(Duration::from_millis(300));
+    if reset {
+        let linger = nix::libc::linger {
+            l_onoff: 1,
+            l_linger: 0,
+        };
+        socket::setsockopt(server.as_raw_fd(), socket::sockopt::Linger, &linger).unwrap();
+    }
+    drop(server);
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn read_errors_of_non_terminal_input_exit_with_1() {
+    let out = run_with_socket_input("echo a\n", false);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "a\n");
+    assert_eq!(out.status.code(), Some(0), "{out:?}");
+
+    let out = run_with_socket_input("echo a\n", true);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "a\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.starts_with("ZeroSh: read error\n"), "{stderr}");
+    assert_eq!(out.status.code(), Some(1), "{out:?}");
+}
+
+#[test]
+fn histexpand_toggles_history_expansion() {
+    let mut sh = self::spawn_with_history();
+    sh.run("echo hi");
+    // the expanded line is printed before it runs
+    assert_eq!(sh.run("echo \"!!\"!"), "echo \"echo hi\"!\necho hi!\n");
+    sh.run("set +H");
+    assert_eq!(sh.run("echo \"hi!!\""), "hi!!\n");
+    sh.run("set -o histexpand");
+    assert_eq!(sh.run("echo !-2"), "echo echo \"hi!!\"\necho hi!!\n");
+}
+
+#[test]
+fn no_history_expansion_of_non_terminal_input() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zerosh"))
+        .arg("--norc")
+        .env("ZEROSH_NO_HISTORY", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // without the history, `!!` is not found once enabled
+    let input = b"echo a\necho !! \"b!\"\nset -H\necho !!\n";
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "a\n!! b!\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(stderr, "ZeroSh: !!: event not found\n");
+}
+
+#[test]
+fn too_long_lines_of_input_are_rejected() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zerosh"))
+        .arg("--norc")
+        .env("ZEROSH_NO_HISTORY", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // over 1 MiB, e.g., a file pasted by mistake
+    let input = format!("echo {}\necho $?\n", "a".repeat(1 << 20));
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()).unwrap());
+    let out = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "2\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(
+        stderr,
+        "ZeroSh: command line too long (more than 1048576 bytes)\n"
+    );
+}
+
+#[test]
+fn complete_c_runs_a_command_for_the_candidates() {
+    let mut sh = PtyShell::spawn();
+    let home = sh.home().clone();
+    // the command exits before its output is closed, with a job in the table to be reaped on
+    // `SIGCHLD` meanwhile
+    let comp = home.join("comp");
+    let script = "printf '%s\\n' \"$1|$2|$3|$COMP_CWORD\" \"$COMP_WORDS\" > \"$HOME/args\"\n\
+        echo alpha; echo beta\n\
+        sleep 0.3 &\n";
+    fs::write(&comp, script).unwrap();
+    sh.run(&format!("complete -C 'sh {}' echo", comp.display()));
+    sh.run("sleep 10 &");
+
+    sh.type_text("echo x al\t");
+    sh.expect("alpha");
+    sh.type_line("");
+    sh.expect("\n");
+    assert_eq!(sh.expect(PROMPT), "x alpha\n");
+    assert_eq!(
+        fs::read_to_string(home.join("args")).unwrap(),
+        "echo|al|x|2\necho\nx\nal\n"
+    );
+}
+
+#[test]
+fn prompt_command_keeps_the_exit_code() {
+    let mut sh = PtyShell::spawn();
+    sh.run("PROMPT_COMMAND=true");
+    sh.run("false");
+    assert_eq!(sh.run("echo $?"), "1\n");
+    // nor does a failing one change it
+    sh.run("PROMPT_COMMAND='echo pc; false'");
+    assert_eq!(sh.run("true"), "pc\n");
+    assert_eq!(sh.run("echo $?"), "0\npc\n");
+}
+
+#[test]
+fn rc_file_runs_unless_norc() {
+    let home = TempDir::new("rc-file");
+    fs::write(home.path().join(".zeroshrc"), "echo from-zeroshrc\n").unwrap();
+    fs::write(home.path().join("other"), "echo from-other\n").unwrap();
+    let vars = [("HOME", home.path().to_str().unwrap())];
+
+    let mut sh = PtyShell::start_with_rc(&[], &vars);
+    assert_eq!(sh.expect(PROMPT), "from-zeroshrc\n");
+    let mut sh = PtyShell::start_with_rc(&["--norc"], &vars);
+    assert_eq!(sh.expect(PROMPT), "");
+    let other = home.path().join("other");
+    let mut sh = PtyShell::start_with_rc(&["--rcfile", other.to_str().unwrap()], &vars);
+    assert_eq!(sh.expect(PROMPT), "from-other\n");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_zerosh"))
+        .arg("--rcfile")
+        .env("HOME", home.path())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2), "{out:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "ZeroSh: --rcfile: option requires an argument\n"
+    );
+    assert!(out.stdout.is_empty(), "{out:?}");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::{env, fs, process::Command};
+
+use zerosh::Shell;
+
+use common::{run, run_with_input, TempDir};
+
+#[test]
+fn random_changes_on_each_expansion() {
+    let out = run("echo $RANDOM $RANDOM $RANDOM $RANDOM");
+    let values = out
+        .stdout()
+        .split_whitespace()
+        .map(|value| value.parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(values.len(), 4);
+    assert!(values.iter().all(|&value| value <= 32767), "{values:?}");
+    assert!(values.windows(2).any(|w| w[0] != w[1]), "{values:?}");
+}
+
+#[test]
+fn random_is_seeded_by_assignment() {
+    let out =
+        run("RANDOM=42; a=\"$RANDOM $RANDOM\"; RANDOM=42; b=\"$RANDOM $RANDOM\"; echo $a; echo $b");
+    let lines = out.stdout().lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], lines[1]);
+}
+
+#[test]
+fn seconds_counts_from_the_assignment() {
+    let out = run("echo $SECONDS; SECONDS=100; echo $SECONDS");
+    let values = out
+        .stdout()
+        .lines()
+        .map(|value| value.parse::<i64>().unwrap())
+        .collect::<Vec<_>>();
+    assert!(values[0] <= 1, "{values:?}");
+    assert!((100..=101).contains(&values[1]), "{values:?}");
+}
+
+#[test]
+fn lineno_of_a_sourced_file() {
+    let file = env::temp_dir().join(format!("zerosh-lineno-{}.sh", std::process::id()));
+    fs::write(&file, "echo $LINENO\n\n# comment\necho $LINENO\n").unwrap();
+    let out = run(&format!("source {}; echo $LINENO", file.display()));
+    fs::remove_file(&file).unwrap();
+    // `-c` is the first line
+    out.assert_stdout("1\n4\n1\n");
+}
+
+#[test]
+fn process_ids_are_kept_in_subshells() {
+    let out = run("echo $$ $PPID $ZEROSH_PID; (echo ${$} $PPID $ZEROSH_PID); sh -c 'echo $PPID'");
+    let lines = out
+        .stdout()
+        .lines()
+        .map(|line| line.split(' ').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let [shell, subshell, child] = &lines[..] else {
+        panic!("{out:?}")
+    };
+
+    // the shell is the parent of its commands, and the test forked the shell
+    assert_eq!(shell[0], child[0]);
+    assert_eq!(shell[1], std::process::id().to_string());
+    assert_eq!(shell[0], shell[2]);
+    // `$` and `PPID` are the shell's, `ZEROSH_PID` is the subshell's
+    assert_eq!(subshell[..2], shell[..2]);
+    assert_ne!(subshell[2], shell[2]);
+}
+
+#[test]
+fn temporary_assignments_are_restored() {
+    run("FOO=bar env | grep FOO; echo \"[$FOO]\"").assert_stdout("FOO=bar\n[]\n");
+    run("X=1 Y=2 env | grep -E '^(X|Y)='; echo \"[$X$Y]\"").assert_stdout("X=1\nY=2\n[]\n");
+    // a shell variable and an exported one get their values back
+    run("FOO=1; FOO=2 sh -c 'echo $FOO'; echo $FOO; sh -c 'echo \"[$FOO]\"'")
+        .assert_stdout("2\n1\n[]\n");
+    run("export E=e; E=t sh -c 'echo $E'; echo $E; sh -c 'echo $E'").assert_stdout("t\ne\ne\n");
+}
+
+#[test]
+fn temporary_assignments_for_builtins_and_functions() {
+    run("A=a cd .; echo \"[$A]\"").assert_stdout("[]\n");
+    run("f() { echo \"in $FOO\"; }; FOO=x f; echo \"[$FOO]\"; FOO=y; FOO=z f; echo $FOO")
+        .assert_stdout("in x\n[]\nin z\ny\n");
+}
+
+#[test]
+fn option_flags_in_dollar_hyphen() {
+    run("echo $-").assert_stdout("h\n");
+    run("set -Cf; echo $-; set +C; set -e; echo $-").assert_stdout("hCf\nhef\n");
+    run("set -o noglob; set -o notify; echo $-; set +o noglob; echo $-").assert_stdout("hbf\nhb\n");
+    // in a command line, the flags of the options set so far
+    run("set -b; x=$-; set +b; echo $x $-").assert_stdout("hb h\n");
+}
+
+#[test]
+fn shlvl_is_incremented_in_nested_shells() {
+    let zerosh = env!("CARGO_BIN_EXE_zerosh");
+    let out = run(&format!(
+        "echo $SHLVL; {zerosh} -c 'echo $SHLVL; {zerosh} -c \"echo \\$SHLVL\"'"
+    ));
+    let levels = out
+        .stdout()
+        .lines()
+        .map(|level| level.parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(levels.len(), 3, "{out:?}");
+    assert_eq!(levels[1], levels[0] + 1, "{out:?}");
+    assert_eq!(levels[2], levels[0] + 2, "{out:?}");
+    // an invalid value counts as 0
+    run(&format!("SHLVL=x {zerosh} -c 'echo $SHLVL'")).assert_stdout("1\n");
+}
+
+#[test]
+fn unset_removes_variables() {
+    run("A=a; export B=b; unset A B; echo \"[$A][$B]\"; sh -c 'echo \"[$B]\"'")
+        .assert_stdout("[][]\n[]\n")
+        .assert_no_stderr();
+    // names that are not set are skipped
+    run("unset -v A; unset -- A; echo $?").assert_stdout("0\n");
+    run("unset 1a; echo $?")
+        .assert_stdout("1\n")
+        .assert_stderr("ZeroSh: unset: 1a: not a valid identifier\n");
+    // the variable of an outer scope is visible again, and exported again
+    run("export A=out; f() { local A=in; export A; unset A; echo $A; sh -c 'echo $A'; }; f")
+        .assert_stdout("out\nout\n");
+}
+
+#[test]
+fn version_of_the_shell() {
+    let version = env!("CARGO_PKG_VERSION");
+    assert_eq!(Shell::version(), version);
+    run("echo $ZEROSH_VERSION").assert_stdout(&format!("{version}\n"));
+
+    let out = Command::new(env!("CARGO_BIN_EXE_zerosh"))
+        .arg("--version")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{out:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        format!("ZeroSh {version}\n")
+    );
+}
+
+#[test]
+fn assignments_alone_set_shell_variables() {
+    run("FOO=bar; echo $FOO; sh -c 'echo \"[$FOO]\"'").assert_stdout("bar\n[]\n");
+    // only for the command before it
+    run("FOO=bar sh -c 'echo $FOO'; echo \"[$FOO]\"").assert_stdout("bar\n[]\n");
+    // the exit code of the last command substitution
+    run("A=$(exit 3) B=b; echo $? $B").assert_stdout("3 b\n");
+}
+
+#[test]
+fn allexport_exports_assigned_variables() {
+    run("set -a; echo $-; FOO=bar; sh -c 'echo $FOO'; set +a; BAR=baz; sh -c 'echo \"[$BAR]\"'")
+        .assert_stdout("ha\nbar\n[]\n");
+    // also by builtins assigning variables
+    run_with_input("set -o allexport; read X; sh -c 'echo $X'", "x\n").assert_stdout("x\n");
+    run("set -a; f() { local L=l; sh -c 'echo $L'; }; f; sh -c 'echo \"[$L]\"'")
+        .assert_stdout("l\n[]\n");
+}
+
+#[test]
+fn declare_p_prints_arrays_as_declarations() {
+    run(r#"a=(x "y z" "it's"); declare -p a"#)
+        .assert_stdout("declare -a a=('x' 'y z' 'it'\\''s')\n");
+    run("declare -ax b=(1 2); declare -a c; declare -i n=2; declare -p b c n; env | grep ^b=")
+        .assert_stdout("declare -ax b=('1' '2')\ndeclare -a c=()\ndeclare -i n='2'\nb=1\n");
+    // a value becomes the first element
+    run("d=v; declare -a d; echo ${#d[@]}; declare -p d").assert_stdout("1\ndeclare -a d=('v')\n");
+    // the dump sets the array again
+    let dir = TempDir::new("declare-arrays");
+    dir.run(
+        r#"a=(x "y z" "it's"); declare -p a > dump; unset a; source dump; echo "${a[2]}" ${#a[@]}"#,
+    )
+    .assert_stdout("it's 3\n");
+}